@@ -0,0 +1,65 @@
+//! I/O for async-std, smol and other executors built on the async-io reactor.
+//!
+//! Spawn the connection on your executor, e g:
+//!
+//! ```ignore
+//! let ctr = dbus_futures::Connection::<Io>::from_txrx(dbus::TxRx::get_private(dbus::BusType::Session)?)?;
+//! let c = ctr.handle();
+//! async_std::task::spawn(ctr);
+//! ```
+
+use ::async_io::Async;
+use crate::io::IoDriver;
+use crate::Error;
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use futures::task;
+
+#[derive(Default)]
+pub struct Io(Vec<(Async<WatchFd>, dbus::Watch)>);
+
+impl IoDriver for Io {
+    fn register(&mut self, watches: &[dbus::Watch]) -> Result<(), Error> {
+        self.0 = watches.iter().filter(|w| w.readable() || w.writable()).map(|w| {
+            let a = Async::new(WatchFd(w.fd())).map_err(|e| Error::failed(&e))?;
+            Ok((a, *w))
+        }).collect::<Result<_, Error>>()?;
+        Ok(())
+    }
+    fn after_read_write(&mut self, cx: &mut task::Context) {
+        for (a, w) in &self.0 {
+            // If the fd is ready already, there is more to read or write, so poll the connection again.
+            if w.readable() && a.poll_readable(cx).is_ready() { cx.waker().wake_by_ref() }
+            if w.writable() && a.poll_writable(cx).is_ready() { cx.waker().wake_by_ref() }
+        }
+    }
+}
+
+/// The fd is owned by libdbus, so it is not closed on drop.
+#[derive(Debug)]
+struct WatchFd(RawFd);
+
+impl AsRawFd for WatchFd {
+    fn as_raw_fd(&self) -> RawFd { self.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Io;
+    use crate::Connection;
+
+    #[test]
+    fn async_io_basic() {
+        let txrx = dbus::TxRx::get_private(dbus::BusType::Session).unwrap();
+        let ctr = Connection::<Io>::from_txrx(txrx).unwrap();
+        let c = ctr.handle();
+        let t = std::thread::spawn(move || ::async_io::block_on(ctr));
+
+        let reply = c.with_dbus_path().method_call_with_args(&"org.freedesktop.DBus".into(), &"ListNames".into(), |_| {});
+        let names: Vec<String> = ::async_io::block_on(reply).unwrap().read1().unwrap();
+        assert!(names.iter().any(|t| t == c.unique_name()));
+
+        c.quit().unwrap();
+        t.join().unwrap();
+    }
+}
@@ -3,27 +3,23 @@ use std::sync::Arc;
 use std::pin::Pin;
 
 use crate::{Error, ConnHandle, Command};
+use crate::io::IoDriver;
 
 use futures::task;
 use futures::channel::{mpsc, oneshot};
 
-/// Internal trait. Don't mess with it unless you want to use it for
-/// your own main loop.
-pub trait IoHandler {
-    fn new(watches: &[dbus::Watch]) -> Result<Self, Error> where Self: Sized;
-    fn after_read_write(&mut self, lw: &task::LocalWaker);
-}
-
 /// This is the reactor specific part of the Connection.
 ///
 /// To set this up, you need to
-///  * create a new instance of Connection, with a specific IoHandler
+///  * create a new instance of Connection, with a specific IoDriver
 ///  * grab a handle to the Connection so you can talk to it
 ///  * spawn the Connection onto a compatible reactor/executor:
-///    This must match the IoHandler you created the Connection with.
-pub struct Connection {
+///    This must match the IoDriver you created the Connection with.
+///
+/// With the "tokio_io" feature, `tokio::Connection` does all this for you.
+pub struct Connection<IO: ?Sized = dyn IoDriver> {
     txrx: Arc<dbus::TxRx>,
-    io: Box<IoHandler>,
+    io: Box<IO>,
     command_sender: mpsc::UnboundedSender<Command>,
     command_receiver: mpsc::UnboundedReceiver<Command>,
     dispatcher: dbus::MessageDispatcher<DispatcherCfg>,
@@ -35,11 +31,34 @@ impl Connection {
     /// Creates a new D-Bus connection and connects it to the Session bus.
     ///
     /// Blocking: until the connection is up and running. 
-    pub fn new_session<IO: IoHandler + 'static>() -> Result<Self, Error> {
-        let mut x = dbus::TxRx::get_private(dbus::BusType::Session)?;
+    pub fn new_session<IO: IoDriver + Default + 'static>() -> Result<Self, Error> {
+        let x = dbus::TxRx::get_private(dbus::BusType::Session)?;
+        let c = Connection::<IO>::from_txrx(x)?;
+        Ok(Connection {
+            txrx: c.txrx,
+            io: c.io,
+            command_sender: c.command_sender,
+            command_receiver: c.command_receiver,
+            dispatcher: c.dispatcher,
+            quit: c.quit,
+        })
+    }
+}
+
+impl<IO: IoDriver> Connection<IO> {
+    /// Sets up the reactor specific part for an already connected TxRx.
+    ///
+    /// Unlike new_session, the IoDriver is part of the type, so that the Connection
+    /// is Send if the IoDriver is.
+    pub fn from_txrx(x: dbus::TxRx) -> Result<Self, Error> where IO: Default {
+        Self::with_driver(x, IO::default())
+    }
+
+    /// Like from_txrx, with an IoDriver that is already set up, e g a `Readiness`.
+    pub fn with_driver(mut x: dbus::TxRx, mut io: IO) -> Result<Self, Error> {
         // TODO we don't support any changing of watches just yet
         let watches = x.watch_fds().map_err(|_| Error::failed(&"failed to get watches"))?;
-        let io = IO::new(&watches)?;
+        io.register(&watches)?;
         let (s, r) = mpsc::unbounded();
         let x = Arc::new(x);
         Ok(Connection {
@@ -47,24 +66,27 @@ impl Connection {
             io: Box::new(io),
             command_sender: s,
             command_receiver: r,
-            dispatcher: dbus::MessageDispatcher::new(DispatcherCfg { txrx: x, streams: vec!() }),
+            dispatcher: dbus::MessageDispatcher::new(DispatcherCfg { txrx: x, streams: vec!(), method_calls: None }),
             quit: false
         })
     }
+}
 
+impl<IO: IoDriver + ?Sized> Connection<IO> {
     pub fn handle(&self) -> ConnHandle { ConnHandle(self.txrx.clone(), self.command_sender.clone()) }
 
-    fn check_cmd(&mut self, lw: &task::LocalWaker) -> bool {
+    fn check_cmd(&mut self, cx: &mut task::Context) -> bool {
         use futures::Stream;
         let cmd = {
             let p = Pin::new(&mut self.command_receiver);
-            p.poll_next(lw)
+            p.poll_next(cx)
         };
         if let task::Poll::Ready(cmd) = cmd {
             match cmd {
                 None | Some(Command::Quit) =>  { self.quit = true; },
                 Some(Command::AddReply(serial, sender)) => { self.dispatcher.add_reply(serial, sender); },
                 Some(Command::AddStream(rule, sender)) => { self.dispatcher.inner_mut().streams.push((rule, sender)); },
+                Some(Command::AddMethodStream(sender)) => { self.dispatcher.inner_mut().method_calls = Some(sender); },
             };
             true
         } else { false }
@@ -78,13 +100,13 @@ impl Connection {
     }
 }
 
-impl futures::Future for Connection {
+impl<IO: IoDriver + ?Sized> futures::Future for Connection<IO> {
     type Output = ();
-    fn poll(mut self: Pin<&mut Self>, lw: &task::LocalWaker) -> task::Poll<()> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
         let mut has_rw = false;
         loop {
             if self.quit { return task::Poll::Ready(()) };
-            if self.check_cmd(lw) { continue; }
+            if self.check_cmd(cx) { continue; }
             if self.check_msg() {
                 has_rw = false;
                 continue;
@@ -92,7 +114,7 @@ impl futures::Future for Connection {
             if !has_rw {
                 self.txrx.read_write(Some(0)).unwrap(); // TODO
                 has_rw = true;
-                self.io.after_read_write(lw);
+                self.io.after_read_write(cx);
                 continue;
             }
             return task::Poll::Pending;
@@ -103,6 +125,7 @@ impl futures::Future for Connection {
 struct DispatcherCfg {
     txrx: Arc<dbus::TxRx>,
     streams: Vec<(dbus::MatchRule<'static>, mpsc::UnboundedSender<dbus::Message>)>,
+    method_calls: Option<mpsc::UnboundedSender<dbus::Message>>,
 }
 
 impl dbus::MessageDispatcherConfig for DispatcherCfg {
@@ -118,6 +141,18 @@ impl dbus::MessageDispatcherConfig for DispatcherCfg {
             }
         }
     }
+    fn on_method_call(msg: dbus::Message, cfg: &mut dbus::MessageDispatcher<Self>) {
+        let msg = match cfg.inner().method_calls {
+            Some(ref sender) => match sender.unbounded_send(msg) {
+                Ok(_) => return,
+                Err(e) => e.into_inner(), // The receiver has been dropped, fall back to the default reply.
+            },
+            None => msg,
+        };
+        if let Some(reply) = dbus::MessageDispatcher::<Self>::default_dispatch(&msg) {
+            Self::on_send(reply, cfg);
+        }
+    }
     fn on_send(msg: dbus::Message, cfg: &mut dbus::MessageDispatcher<Self>) { cfg.inner().txrx.send(msg).unwrap(); }
 }
 
@@ -0,0 +1,182 @@
+//! Glue for serving method calls from a Crossroads instance.
+
+use std::sync::Arc;
+use std::pin::Pin;
+
+use futures::{Future, FutureExt, StreamExt, TryStreamExt};
+use futures::future::ready;
+
+use dbus::crossroads::{Crossroads, Async};
+use crate::ConnHandle;
+
+/// Makes it possible to write `cr.serve(&handle)`.
+pub trait Serve {
+    /// Routes incoming method calls into Crossroads, and sends the replies back.
+    ///
+    /// Method calls are handled concurrently, and those that Crossroads cannot dispatch are
+    /// replied to with an error. The returned future resolves when the connection is closed.
+    fn serve(self, handle: &ConnHandle) -> Pin<Box<dyn Future<Output=()> + Send>>;
+}
+
+impl Serve for Arc<Crossroads<Async>> {
+    fn serve(self, handle: &ConnHandle) -> Pin<Box<dyn Future<Output=()> + Send>> {
+        let h = handle.clone();
+        // The stream only errors if the connection task is gone, so stop serving there.
+        let calls = handle.add_method_stream().into_stream().take_while(|msg| ready(msg.is_ok()));
+        // Handle the calls concurrently, so that a slow reply does not hold up the ones after it.
+        Box::pin(calls.for_each_concurrent(None, move |msg| {
+            let h = h.clone();
+            let msg = msg.unwrap();
+            let f: Pin<Box<dyn Future<Output=()> + Send>> = match self.dispatch(&msg) {
+                Some(reply) => Box::pin(reply.map(move |msgs| {
+                    // Ignore send errors - the remote might have disconnected during our processing.
+                    for r in msgs { let _ = h.send(r); }
+                })),
+                None => {
+                    if let Some(r) = dbus::MessageDispatcher::<()>::default_dispatch(&msg) { let _ = h.send(r); }
+                    Box::pin(ready(()))
+                }
+            };
+            f
+        }))
+    }
+}
+
+/// Asks polkit whether the sender of the message being handled is authorized for an action.
+///
+/// Resolves to an AccessDenied error if the caller is not authorized. Unlike a MethodReply,
+/// the future is Send, so that an Async method handler can wait for it.
+#[cfg(feature = "polkit")]
+pub fn check_authorization(handle: &ConnHandle, ctx: &dbus::crossroads::Context, action_id: &str, interactive: bool)
+-> impl Future<Output=Result<(), crate::Error>> + Send {
+    use dbus::crossroads::polkit;
+    use crate::ReplyMessage;
+    let r = polkit::check_authorization_call(ctx, action_id, interactive)
+        .and_then(|m| handle.send(m))
+        .map(|serial| ReplyMessage::new(serial, handle))
+        .unwrap_or_else(|e| ReplyMessage(Err(Some(e))));
+    r.map(|r| r.and_then(|m| polkit::parse_reply(&m)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::{thread, time::Duration};
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+    use futures::future::{join, ready};
+    use dbus::crossroads::{Crossroads, IfaceInfo, MethodInfo, PathData};
+    use crate::{Connection, ConnHandle, Readiness};
+    use super::Serve;
+
+    /// Wakes the connections up every now and then, as nobody polls their fds here.
+    struct Ticker(Arc<AtomicBool>, Option<thread::JoinHandle<()>>);
+
+    impl Drop for Ticker {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+            self.1.take().unwrap().join().unwrap();
+        }
+    }
+
+    fn connect(pool: &LocalPool, count: usize) -> (Vec<ConnHandle>, Ticker) {
+        let (mut wakers, mut handles) = (vec!(), vec!());
+        for _ in 0..count {
+            let r = Readiness::default();
+            wakers.push(r.handle());
+            let ctr = Connection::with_driver(dbus::TxRx::get_private(dbus::BusType::Session).unwrap(), r).unwrap();
+            handles.push(ctr.handle());
+            pool.spawner().spawn_local(ctr).unwrap();
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let d = done.clone();
+        let t = thread::spawn(move || while !d.load(Ordering::SeqCst) {
+            for w in &wakers { w.ready() }
+            thread::sleep(Duration::from_millis(5));
+        });
+        (handles, Ticker(done, Some(t)))
+    }
+
+    #[test]
+    fn serve() {
+        let mut pool = LocalPool::new();
+        let (handles, _ticker) = connect(&pool, 2);
+
+        struct Score(u16);
+        let mut cr = Crossroads::new_async();
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.futures.score",
+            vec!(MethodInfo::new_async("Hello", |x: &Score, msg, _| {
+                ready(Some(msg.method_return().append1(format!("Hello, my score is {}!", x.0))))
+            })), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7));
+        cr.insert("/", pdata);
+        pool.spawner().spawn_local(Arc::new(cr).serve(&handles[0])).unwrap();
+
+        let p = handles[1].with_path(handles[0].unique_name(), "/");
+        let hello = p.method_call_with_args(&"com.example.dbusrs.futures.score".into(), &"Hello".into(), |_| {});
+        let missing = p.method_call_with_args(&"com.example.dbusrs.futures.score".into(), &"Missing".into(), |_| {});
+        let (hello, missing) = pool.run_until(join(hello, missing));
+        let s: String = hello.unwrap().read1().unwrap();
+        assert_eq!(s, "Hello, my score is 7!");
+        assert_eq!(&**missing.unwrap_err().errorname(), "org.freedesktop.DBus.Error.UnknownMethod");
+
+        // Serving stops when the connection task quits, so that the pool runs out of tasks.
+        for h in &handles { h.quit().unwrap() }
+        pool.run();
+    }
+
+    #[cfg(feature = "polkit")]
+    #[test]
+    fn check_authorization() {
+        use std::collections::HashMap;
+        use dbus::arg::Variant;
+        use futures::FutureExt;
+
+        let mut pool = LocalPool::new();
+        let (handles, _ticker) = connect(&pool, 3);
+        let (server, authority, client) = (handles[0].clone(), handles[1].clone(), handles[2].clone());
+
+        // Stands in for polkit, and only lets callers do the "allowed" action.
+        struct Authority;
+        let mut cr = Crossroads::new_async();
+        cr.register::<Authority>(IfaceInfo::new("org.freedesktop.PolicyKit1.Authority",
+            vec!(MethodInfo::new_async("CheckAuthorization", |_: &Authority, msg, _| {
+                let (_, action): ((String, HashMap<String, Variant<String>>), String) = msg.read2().unwrap();
+                let authorized = action == "com.example.dbusrs.futures.allowed";
+                ready(Some(msg.method_return().append1((authorized, false, HashMap::<String, String>::new()))))
+            })), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Authority);
+        cr.insert("/org/freedesktop/PolicyKit1/Authority", pdata);
+        pool.spawner().spawn_local(Arc::new(cr).serve(&authority)).unwrap();
+        let r = pool.run_until(authority.request_name("org.freedesktop.PolicyKit1", false, false, true));
+        assert_eq!(r.unwrap(), dbus::RequestNameReply::PrimaryOwner);
+
+        struct Vault;
+        let mut cr = Crossroads::new_async();
+        let h = server.clone();
+        cr.register::<Vault>(IfaceInfo::new("com.example.dbusrs.futures.vault",
+            vec!(MethodInfo::new_async("Open", move |_: &Vault, msg, ai| {
+                let action: &str = msg.read1().unwrap();
+                let call = msg.duplicate();
+                super::check_authorization(&h, ai.ctx(), action, false).map(move |r| Some(match r {
+                    Ok(()) => call.method_return().append1("gold"),
+                    Err(e) => e.to_message(&call),
+                }))
+            })), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Vault);
+        cr.insert("/vault", pdata);
+        pool.spawner().spawn_local(Arc::new(cr).serve(&server)).unwrap();
+
+        let p = client.with_path(server.unique_name(), "/vault");
+        let open = |action: &'static str| p.method_call_with_args(&"com.example.dbusrs.futures.vault".into(), &"Open".into(),
+            move |m| { dbus::arg::IterAppend::new(m).append(action) });
+        let (allowed, denied) = pool.run_until(join(open("com.example.dbusrs.futures.allowed"), open("com.example.dbusrs.futures.denied")));
+        let s: String = allowed.unwrap().read1().unwrap();
+        assert_eq!(s, "gold");
+        assert_eq!(&**denied.unwrap_err().errorname(), "org.freedesktop.DBus.Error.AccessDenied");
+    }
+}
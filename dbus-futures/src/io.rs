@@ -0,0 +1,127 @@
+//! The interface between a Connection and the event loop that polls its file descriptors.
+
+use std::sync::{Arc, Mutex};
+
+use crate::Error;
+
+use futures::task;
+
+/// Lets a Connection know when its file descriptors are ready.
+///
+/// There are implementations for thin_main_loop and tokio, behind features. To run a
+/// Connection on an event loop of your own, either implement this, or use `Readiness`.
+pub trait IoDriver {
+    /// Starts watching the fds libdbus reads from and writes to. Called once, when the Connection is set up.
+    fn register(&mut self, watches: &[dbus::Watch]) -> Result<(), Error>;
+
+    /// Called each time the Connection has read and written what it could.
+    ///
+    /// The waker of the context must be woken when any of the fds is ready again.
+    fn after_read_write(&mut self, cx: &mut task::Context);
+}
+
+#[derive(Debug, Default)]
+struct ReadyState {
+    watches: Vec<dbus::Watch>,
+    waker: Option<task::Waker>,
+    // Set if ready was called when nobody was waiting
+    ready: bool,
+}
+
+/// An IoDriver that leaves the polling to you.
+///
+/// Poll the fds from `ReadyHandle::watches` in your event loop, and call `ReadyHandle::ready`
+/// when any of them is readable or writable, as the watch asks for. The Connection then
+/// reads and writes the next time it is polled by your executor.
+#[derive(Debug, Default)]
+pub struct Readiness(Arc<Mutex<ReadyState>>);
+
+impl Readiness {
+    /// The handle to give to the event loop.
+    pub fn handle(&self) -> ReadyHandle { ReadyHandle(self.0.clone()) }
+}
+
+impl IoDriver for Readiness {
+    fn register(&mut self, watches: &[dbus::Watch]) -> Result<(), Error> {
+        self.0.lock().unwrap().watches = watches.iter().filter(|w| w.readable() || w.writable()).cloned().collect();
+        Ok(())
+    }
+    fn after_read_write(&mut self, cx: &mut task::Context) {
+        let mut s = self.0.lock().unwrap();
+        if s.ready {
+            s.ready = false;
+            cx.waker().wake_by_ref();
+        } else { s.waker = Some(cx.waker().clone()) }
+    }
+}
+
+/// Tells a Connection using `Readiness` that its fds are ready. Can be sent to other threads.
+#[derive(Debug, Clone)]
+pub struct ReadyHandle(Arc<Mutex<ReadyState>>);
+
+impl ReadyHandle {
+    /// The fds to poll, and whether to poll them for reading, writing or both.
+    ///
+    /// Empty until the Connection is set up.
+    pub fn watches(&self) -> Vec<dbus::Watch> { self.0.lock().unwrap().watches.clone() }
+
+    /// Wakes the Connection, to read and write.
+    pub fn ready(&self) {
+        let mut s = self.0.lock().unwrap();
+        match s.waker.take() {
+            Some(w) => w.wake(),
+            None => s.ready = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use futures::task::{self, ArcWake};
+    use super::{IoDriver, Readiness};
+    use crate::Connection;
+
+    #[test]
+    fn readiness_watches() {
+        let txrx = dbus::TxRx::get_private(dbus::BusType::Session).unwrap();
+        let r = Readiness::default();
+        let h = r.handle();
+        assert!(h.watches().is_empty());
+        let _ctr = Connection::with_driver(txrx, r).unwrap();
+        let w = h.watches();
+        assert!(!w.is_empty());
+        assert!(w.iter().all(|w| w.readable() || w.writable()));
+        h.ready();
+    }
+
+    #[test]
+    fn readiness_wakes() {
+        struct Count(AtomicUsize);
+        impl ArcWake for Count {
+            fn wake_by_ref(c: &Arc<Self>) { c.0.fetch_add(1, Ordering::SeqCst); }
+        }
+        let count = Arc::new(Count(AtomicUsize::new(0)));
+        let waker = task::waker(count.clone());
+        let mut cx = task::Context::from_waker(&waker);
+        let wakes = || count.0.load(Ordering::SeqCst);
+
+        let mut r = Readiness::default();
+        let h = r.handle();
+        // Ready before the Connection waits, so it is woken right after reading and writing.
+        h.ready();
+        r.after_read_write(&mut cx);
+        assert_eq!(wakes(), 1);
+        // Now it waits until the next ready.
+        r.after_read_write(&mut cx);
+        assert_eq!(wakes(), 1);
+        h.ready();
+        assert_eq!(wakes(), 2);
+        // Nobody waits, so this only counts for the next time.
+        h.ready();
+        assert_eq!(wakes(), 2);
+        r.after_read_write(&mut cx);
+        assert_eq!(wakes(), 3);
+    }
+}
@@ -1,5 +1,3 @@
-#![feature(futures_api)]
-
 use dbus;
 use std::sync::Arc;
 use std::pin::Pin;
@@ -17,22 +15,21 @@ pub mod stdintf;
 enum Command {
     AddReply(u32, oneshot::Sender<dbus::Message>),
     AddStream(dbus::MatchRule<'static>, mpsc::UnboundedSender<dbus::Message>),
+    AddMethodStream(mpsc::UnboundedSender<dbus::Message>),
     Quit,
 }
 
 #[derive(Debug)]
 pub struct ReplyMessage(Result<oneshot::Receiver<dbus::Message>, Option<Error>>);
 
-impl futures::TryFuture for ReplyMessage {
-    type Ok = dbus::Message;
-    type Error = Error;
-    fn try_poll(mut self: Pin<&mut Self>, lw: &task::LocalWaker) -> task::Poll<Result<Self::Ok, Self::Error>> {
+impl futures::Future for ReplyMessage {
+    type Output = Result<dbus::Message, Error>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
         match &mut self.0 {
             Err(e) => task::Poll::Ready(Err(e.take().unwrap())),
             Ok(ref mut recv) => {
-                use futures::Future;
                 let p: Pin<&mut oneshot::Receiver<dbus::Message>> = Pin::new(recv);
-                let mut r: dbus::Message = futures::try_ready!(p.poll(lw).map_err(|e| { Error::failed(&e) }));
+                let mut r: dbus::Message = futures::ready!(futures::Future::poll(p, cx)).map_err(|e| { Error::failed(&e) })?;
                 task::Poll::Ready((|| { r.as_result()?; Ok(r) })())
             }
         }
@@ -51,55 +48,50 @@ impl ReplyMessage {
 }
 
 pub struct MethodReply<T> {
-    f: Pin<Box<futures::Future<Output=Result<T, Error>>>>,
+    f: Pin<Box<dyn futures::Future<Output=Result<T, Error>>>>,
 }
 
-impl<T> futures::TryFuture for MethodReply<T> {
-    type Ok = T;
-    type Error = Error;
-    fn try_poll(mut self: Pin<&mut Self>, lw: &task::LocalWaker) -> task::Poll<Result<Self::Ok, Self::Error>> {
-        let p = Pin::new(&mut self.f);
-        p.try_poll(lw)
+impl<T> futures::Future for MethodReply<T> {
+    type Output = Result<T, Error>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        self.f.as_mut().poll(cx)
     }
 }
 
 impl<T: 'static> MethodReply<T> {
     pub fn from_msg<F: FnOnce(dbus::Message) -> Result<T, Error> + 'static>(msg: ReplyMessage, parse_fn: F) -> Self {
         use futures::TryFutureExt;
-        MethodReply { f: Box::pin(msg.and_then(|m| ready(parse_fn(m))).into_future()) }
+        MethodReply { f: Box::pin(msg.and_then(|m| ready(parse_fn(m)))) }
     }
 }
 
 #[derive(Debug)]
 pub struct MessageStream(Result<mpsc::UnboundedReceiver<dbus::Message>, Option<Error>>);
 
-impl futures::TryStream for MessageStream {
-    type Ok = dbus::Message;
-    type Error = Error;
-    fn try_poll_next(mut self: Pin<&mut Self>, lw: &task::LocalWaker) -> task::Poll<Option<Result<Self::Ok, Self::Error>>> {
+impl futures::Stream for MessageStream {
+    type Item = Result<dbus::Message, Error>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Option<Self::Item>> {
         match &mut self.0 {
             Err(e) => { let e = e.take(); task::Poll::Ready(e.map(|e| Err(e))) },
             Ok(ref mut recv) => {
-                use futures::Stream;
                 let p: Pin<&mut mpsc::UnboundedReceiver<dbus::Message>> = Pin::new(recv);
-                p.poll_next(lw).map(|x| x.map(|x| Ok(x)))
+                futures::Stream::poll_next(p, cx).map(|x| x.map(|x| Ok(x)))
             }
         }
     }
 }
 
 pub struct SignalStream<T> {
-    f: Box<futures::TryStream<Ok=T, Error=Error> + Unpin>,
+    f: Box<dyn futures::Stream<Item=Result<T, Error>> + Unpin>,
     handle: ConnHandle,
     match_str: String,
 }
 
-impl<T> futures::TryStream for SignalStream<T> {
-    type Ok = T;
-    type Error = Error;
-    fn try_poll_next(mut self: Pin<&mut Self>, lw: &task::LocalWaker) -> task::Poll<Option<Result<Self::Ok, Self::Error>>> {
+impl<T> futures::Stream for SignalStream<T> {
+    type Item = Result<T, Error>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Option<Self::Item>> {
         let p = Pin::new(&mut *self.f);
-        p.try_poll_next(lw)
+        p.poll_next(cx)
     }
 }
 
@@ -200,6 +192,14 @@ impl ConnHandle {
         MessageStream(self.1.unbounded_send(Command::AddStream(rule, s)).map(|_| r).map_err(|e| Some(Error::failed(&e))))
     }
 
+    /// Incoming method calls are sent to the stream, instead of being replied to with an error.
+    ///
+    /// Only one method call stream can be active at a time; adding a new one replaces the old one.
+    pub fn add_method_stream(&self) -> MessageStream {
+        let (s, r) = mpsc::unbounded();
+        MessageStream(self.1.unbounded_send(Command::AddMethodStream(s)).map(|_| r).map_err(|e| Some(Error::failed(&e))))
+    }
+
     /// Returns a stream of corresponding signals, optionally filtered on sender and path.
     ///
     /// Makes a call to the D-Bus server to add the match as well.
@@ -252,14 +252,22 @@ impl ConnHandle {
     }
 }
 
+pub mod crossroads;
+
 #[cfg(feature = "tml_io")]
 pub mod thin_main_loop;
 
 #[cfg(feature = "tokio_io")]
 pub mod tokio;
 
+#[cfg(feature = "async_io")]
+pub mod async_io;
+
 
 mod connection;
 
+pub mod io;
+
 pub use connection::Connection;
+pub use io::{IoDriver, Readiness, ReadyHandle};
 
@@ -4,30 +4,35 @@ use thin_main_loop::future as tmlf;
 use std::pin::Pin;
 use futures::task;
 
-use crate::connection::IoHandler;
+use crate::io::IoDriver;
 use crate::Error;
 
+#[derive(Default)]
 pub struct Io(Vec<tmlf::Io>);
 
-impl IoHandler for Io {
-    fn new(watches: &[dbus::Watch]) -> Result<Self, Error> where Self: Sized {
-        Ok(Io(watches.into_iter().filter_map(|w| {
+impl IoDriver for Io {
+    fn register(&mut self, watches: &[dbus::Watch]) -> Result<(), Error> {
+        self.0 = watches.into_iter().filter_map(|w| {
             match (w.readable(), w.writable()) {
                 (false, false) => None,
                 (true, false) => Some(tmlf::io(tml::CbHandle(w.fd()), tml::IODirection::Read)),
                 _ => unimplemented!(),
             }
-        }).collect()))
+        }).collect();
+        Ok(())
     }
-    fn after_read_write(&mut self, lw: &task::LocalWaker) {
+    fn after_read_write(&mut self, cx: &mut task::Context) {
         for io in &mut self.0 {
             use futures::Stream;
             let p = Pin::new(io);
-            let _ = p.poll_next(lw);
+            let _ = p.poll_next(cx);
         }
     }
 }
 
+// On unix, thin_main_loop only polls fds with its "glib" feature, which links another
+// glib-sys than the "glib" feature of dbus, so it cannot be enabled in this workspace.
+// Hence these are ignored; run them with --ignored where thin_main_loop has "glib" enabled.
 #[cfg(test)]
 mod tests {
     use thin_main_loop::future as tmlf;
@@ -37,6 +42,7 @@ mod tests {
     use futures::{FutureExt, TryFutureExt, StreamExt, TryStreamExt};
 
     #[test]
+    #[ignore]
     fn basic_conn() {
         let ctr = Connection::new_session::<Io>().unwrap();
         let c = ctr.handle();
@@ -63,6 +69,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore]
     fn gen_conn() {
         let ctr = Connection::new_session::<Io>().unwrap();
         let c = ctr.handle();
@@ -82,6 +89,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore]
     fn request_name() {
         let ctr = Connection::new_session::<Io>().unwrap();
         let c = ctr.handle();
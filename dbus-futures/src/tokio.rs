@@ -1,24 +1,29 @@
 use mio;
 
 use tokio_reactor::PollEvented;
-use crate::connection::IoHandler;
-use crate::Error;
+use crate::io::IoDriver;
+use crate::{Error, ConnHandle};
 
-use futures::task;
+use std::ops::Deref;
+use std::sync::Arc;
+use futures::{task, Future, FutureExt};
+use futures::channel::oneshot;
 
+#[derive(Default)]
 pub struct Io(Vec<PollEvented<AWatch>>);
 
-impl IoHandler for Io {
-    fn new(watches: &[dbus::Watch]) -> Result<Self, Error> where Self: Sized {
-        Ok(Io(watches.into_iter().filter_map(|w| {
+impl IoDriver for Io {
+    fn register(&mut self, watches: &[dbus::Watch]) -> Result<(), Error> {
+        self.0 = watches.into_iter().filter_map(|w| {
             if !w.readable() && !w.writable() { return None; }
             let p = PollEvented::new(AWatch(w.clone()));
             if w.readable() { p.clear_read_ready(mio::Ready::readable()).unwrap() };
             if w.writable() { p.clear_write_ready().unwrap() };
             Some(p)
-        }).collect()))
+        }).collect();
+        Ok(())
     }
-    fn after_read_write(&mut self, _: &task::LocalWaker) {
+    fn after_read_write(&mut self, _: &mut task::Context) {
         for p in &mut self.0 {
             p.clear_read_ready(mio::Ready::readable()).unwrap(); // TODO
             p.clear_write_ready().unwrap();
@@ -27,6 +32,58 @@ impl IoHandler for Io {
 }
 
 
+/// A connection whose I/O is driven by a task spawned on the tokio runtime.
+///
+/// There is no separate future to spawn or poll: the task is spawned when the connection is
+/// set up, and it quits when the last clone of the Connection is dropped. Derefs to ConnHandle,
+/// which has the methods to make calls, receive signals, etc. ConnHandles that outlive the
+/// Connection stop working when the task has quit.
+#[derive(Clone, Debug)]
+pub struct Connection(Arc<Shutdown>);
+
+#[derive(Debug)]
+struct Shutdown(ConnHandle);
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        // If the task has already quit, e g because the runtime shut down, there is nothing to do.
+        let _ = self.0.quit();
+    }
+}
+
+impl Deref for Connection {
+    type Target = ConnHandle;
+    fn deref(&self) -> &ConnHandle { &(self.0).0 }
+}
+
+impl Connection {
+    /// Connects to the session bus.
+    ///
+    /// Must be polled inside a tokio runtime, which the connection task is spawned on.
+    pub fn new_session() -> impl Future<Output=Result<Self, Error>> { Self::new(dbus::BusType::Session) }
+
+    /// Connects to the system bus.
+    ///
+    /// Must be polled inside a tokio runtime, which the connection task is spawned on.
+    pub fn new_system() -> impl Future<Output=Result<Self, Error>> { Self::new(dbus::BusType::System) }
+
+    fn new(bus: dbus::BusType) -> impl Future<Output=Result<Self, Error>> {
+        // Connecting blocks until the bus has replied to Hello, so do that on another thread.
+        let (s, r) = oneshot::channel();
+        std::thread::spawn(move || { let _ = s.send(dbus::TxRx::get_private(bus)); });
+        r.map(|r| -> Result<Self, Error> {
+            let txrx = r.map_err(|e| Error::failed(&e))??;
+            let ctr = crate::Connection::<Io>::from_txrx(txrx)?;
+            let handle = ctr.handle();
+            tokio::spawn(futures::compat::Compat::new(ctr.map(Ok::<(), ()>)));
+            Ok(Connection(Arc::new(Shutdown(handle))))
+        })
+    }
+
+    /// A handle that does not keep the connection task alive.
+    pub fn handle(&self) -> ConnHandle { (**self).clone() }
+}
+
 #[derive(Debug)]
 struct AWatch(dbus::Watch);
 
@@ -68,28 +125,59 @@ mod tests {
     use super::Io;
     use crate::{ReplyMessage, Connection};
     use futures::{FutureExt, TryFutureExt};
+    use futures::compat::Compat;
     use tokio;
 
+    #[test]
+    fn tokio_native() {
+        let f = super::Connection::new_session().then(|c| {
+            let c = c.unwrap();
+            let reply = c.with_dbus_path().method_call_with_args(&"org.freedesktop.DBus".into(), &"ListNames".into(), |_| {});
+            reply.into_future().map(move |msg| {
+                let names: Vec<String> = msg.unwrap().read1().unwrap();
+                assert!(names.iter().any(|t| t == c.unique_name()));
+                // Dropping c here quits the connection task, so that tokio::run returns.
+            })
+        });
+        tokio::run(Compat::new(f.map(Ok::<(), ()>)));
+    }
+
+    #[test]
+    fn tokio_current_thread() {
+        use crate::stdintf::org_freedesktop::DBus;
+        let f = super::Connection::new_session().then(|c| {
+            let c = c.unwrap();
+            c.with_dbus_path().get_interfaces().into_future().map(move |reply| {
+                assert!(!reply.unwrap().is_empty());
+                drop(c);
+            })
+        });
+        tokio::runtime::current_thread::run(Compat::new(f.map(Ok::<(), ()>)));
+    }
+
     #[test]
     fn tokio_basic() {
-        let ctr = Connection::new_session::<Io>().unwrap();
-        let c = ctr.handle();
-        // let mut exec = tmlf::Executor::new().unwrap();
-        tokio::spawn(futures::compat::Compat::new(ctr));
-
-        let remote_path = c.with_path("org.freedesktop.DBus", "/");
-        let reply_future: ReplyMessage = remote_path.method_call_with_args(
-            &"org.freedesktop.DBus".into(), &"ListNames".into(), |_| {});
-        let r2: futures::future::IntoFuture<_> = reply_future.into_future();
-        let r3 = r2.then(|msg| {
-            let msg = msg.unwrap();
-            let reply: Vec<String> = msg.read1().unwrap();
-            let my_name = c.unique_name();
-            assert!(reply.len() > 0);
-            assert!(reply.iter().any(|t| t == my_name));
-            println!("{:?}, {:?}", my_name, reply);
-            futures::future::ready(())
+        let txrx = dbus::TxRx::get_private(dbus::BusType::Session).unwrap();
+        let f = futures::future::lazy(move |_| {
+            // The Io registers its fds with the reactor of the runtime, so set it up in there.
+            let ctr = Connection::<Io>::from_txrx(txrx).unwrap();
+            let c = ctr.handle();
+            tokio::spawn(Compat::new(ctr.map(Ok::<(), ()>)));
+            c
+        }).then(|c| {
+            let remote_path = c.with_path("org.freedesktop.DBus", "/");
+            let reply_future: ReplyMessage = remote_path.method_call_with_args(
+                &"org.freedesktop.DBus".into(), &"ListNames".into(), |_| {});
+            reply_future.into_future().map(move |msg| {
+                let msg = msg.unwrap();
+                let reply: Vec<String> = msg.read1().unwrap();
+                let my_name = c.unique_name();
+                assert!(!reply.is_empty());
+                assert!(reply.iter().any(|t| t == my_name));
+                println!("{:?}, {:?}", my_name, reply);
+                c.quit().unwrap();
+            })
         });
-        tokio::run(r3);
+        tokio::run(Compat::new(f.map(Ok::<(), ()>)));
     }
 }
@@ -0,0 +1,684 @@
+//! Attribute macros for declaring D-Bus interfaces for the crossroads module of the dbus crate,
+//! and derive macros for using structs and enums as D-Bus arguments.
+//!
+//! Like crossroads itself, this is unstable and experimental for now.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, TokenStream, TokenTree};
+
+/// Declares a D-Bus interface from the methods of an impl block.
+///
+/// ```ignore
+/// struct Counter(std::sync::atomic::AtomicU32);
+///
+/// #[dbus_interface(name = "com.example.Counter")]
+/// impl Counter {
+///     fn add(&self, n: u32) -> u32 { self.0.fetch_add(n, Ordering::SeqCst) + n }
+///     fn reset(&self) -> Result<(), MethodErr> { self.0.store(0, Ordering::SeqCst); Ok(()) }
+///
+///     #[dbus(property)]
+///     fn value(&self) -> u32 { self.0.load(Ordering::SeqCst) }
+///     #[dbus(property)]
+///     fn set_value(&self, v: u32) { self.0.store(v, Ordering::SeqCst) }
+/// }
+///
+/// Counter::register_dbus_interface(&mut cr);
+/// ```
+///
+/// Every method with a `&self` receiver becomes a D-Bus method, with its name in CamelCase, e g
+/// "Add" and "Reset" above. The argument types are the in arguments, and the return type is the
+/// out argument. A tuple is several out arguments, and `Result<T, E>` replies with an error if the
+/// method fails, where E is any type implementing `DBusError`.
+///
+/// The attribute adds a `register_dbus_interface` function, that registers the interface
+/// with a `Crossroads<()>`.
+///
+/// # Options
+///
+/// * `name = "..."` - the name of the interface (required).
+/// * `flavor = "mut"` - register with a `Crossroads<Mut>` instead, so that methods can take `&mut self`.
+/// * `flavor = "async"` - register with a `Crossroads<Async>` instead. This is the default if
+///   there are `async fn` methods.
+///
+/// # Async methods
+///
+/// Async methods can await other futures, e g calls to other services, before they return. The
+/// future of a method call must not borrow the interface data, so the type must implement `Clone`,
+/// and each call of an async method runs on a clone of it. Typically the type is a handle to
+/// state shared through an `Arc`. Methods that are not async run on the data directly.
+///
+/// Methods can be annotated with `#[dbus(...)]`:
+///
+/// * `property` - the method is a property getter, or if its name starts with "set_", a setter
+///   for the property of the rest of the name. Setters take `&self`, or `&mut self` with
+///   `flavor = "mut"`, one in argument, and return nothing or `Result<(), MethodErr>`.
+/// * `name = "..."` - use another D-Bus name for the method or property.
+/// * `skip` - do not make the method part of the interface.
+#[proc_macro_attribute]
+pub fn dbus_interface(attr: TokenStream, item: TokenStream) -> TokenStream {
+    match expand(attr, item) {
+        Ok(ts) => ts,
+        Err(e) => format!("compile_error!({:?});", format!("dbus_interface: {}", e)).parse().unwrap(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flavor { Sync, Mut, Async }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Receiver { Ref, RefMut }
+
+#[derive(Debug, Default)]
+struct Options {
+    property: bool,
+    skip: bool,
+    name: Option<String>,
+}
+
+#[derive(Debug)]
+struct Method {
+    fn_name: String,
+    opts: Options,
+    receiver: Receiver,
+    is_async: bool,
+    // Argument names and types
+    args: Vec<(String, String)>,
+    ret: Ret,
+}
+
+#[derive(Debug)]
+enum Ret {
+    // The types of the out arguments
+    Plain(Out),
+    // The types of the out arguments when Ok
+    Result(Out),
+}
+
+#[derive(Debug)]
+enum Out {
+    Unit,
+    Single(String),
+    Tuple(Vec<String>),
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> Result<TokenStream, String> {
+    let (iface_name, flavor) = parse_args(attr)?;
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+    let impl_pos = tokens.iter().position(|t| is_ident(t, "impl")).ok_or("can only be used on impl blocks")?;
+    let body = match tokens.last() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g.clone(),
+        _ => return Err("can only be used on impl blocks".into()),
+    };
+    let self_ty = &tokens[impl_pos+1..tokens.len()-1];
+    if self_ty.first().filter(|t| is_punct(t, '<')).is_some() { return Err("generic impl blocks are not supported".into()) }
+    if self_ty.iter().any(|t| is_ident(t, "for")) { return Err("trait impl blocks are not supported".into()) }
+    let self_ty = stream(self_ty).to_string();
+
+    let (body, methods) = parse_body(body.stream())?;
+    // Async methods need the async flavor, so make that the default when there are any.
+    let flavor = flavor.unwrap_or(if methods.iter().any(|m| m.is_async) { Flavor::Async } else { Flavor::Sync });
+    let generated = generate(&iface_name, flavor, &self_ty, &methods)?;
+
+    let mut out = stream(&tokens[..tokens.len()-1]);
+    out.extend(Some(TokenTree::Group(Group::new(Delimiter::Brace, body))));
+    out.extend(generated.parse::<TokenStream>().map_err(|e| format!("{:?}", e))?);
+    Ok(out)
+}
+
+fn stream(t: &[TokenTree]) -> TokenStream { t.iter().cloned().collect() }
+
+fn is_ident(t: &TokenTree, s: &str) -> bool { if let TokenTree::Ident(i) = t { i.to_string() == s } else { false } }
+
+fn is_punct(t: &TokenTree, c: char) -> bool { if let TokenTree::Punct(p) = t { p.as_char() == c } else { false } }
+
+fn string_lit(t: &TokenTree) -> Result<String, String> {
+    let s = t.to_string();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') { Ok(s[1..s.len()-1].into()) }
+    else { Err(format!("expected a string, found {}", s)) }
+}
+
+/// Splits at commas outside of angle brackets.
+fn split_commas(tokens: &[TokenTree]) -> Vec<&[TokenTree]> {
+    let (mut r, mut start, mut depth) = (vec!(), 0, 0i32);
+    for (i, t) in tokens.iter().enumerate() {
+        if let TokenTree::Punct(p) = t {
+            match p.as_char() {
+                '<' => depth += 1,
+                // Not the arrow of a return type, e g in Box<dyn Fn() -> u8>
+                '>' if i == 0 || !is_punct(&tokens[i-1], '-') => depth -= 1,
+                ',' if depth == 0 => { r.push(&tokens[start..i]); start = i + 1; },
+                _ => {},
+            }
+        }
+    }
+    if start < tokens.len() { r.push(&tokens[start..]) }
+    r
+}
+
+fn parse_args(attr: TokenStream) -> Result<(String, Option<Flavor>), String> {
+    let tokens: Vec<TokenTree> = attr.into_iter().collect();
+    let (mut name, mut flavor) = (None, None);
+    for chunk in split_commas(&tokens) {
+        match chunk {
+            [TokenTree::Ident(k), eq, v] if is_punct(eq, '=') => match &*k.to_string() {
+                "name" => name = Some(string_lit(v)?),
+                "flavor" => flavor = Some(match &*string_lit(v)? {
+                    "sync" => Flavor::Sync,
+                    "mut" => Flavor::Mut,
+                    "async" => Flavor::Async,
+                    f => return Err(format!("unknown flavor {:?}", f)),
+                }),
+                k => return Err(format!("unknown option {}", k)),
+            },
+            _ => return Err(format!("cannot parse option {}", stream(chunk))),
+        }
+    }
+    Ok((name.ok_or("the interface name is missing, add name = \"...\"")?, flavor))
+}
+
+fn parse_options(g: &Group) -> Result<Options, String> {
+    let tokens: Vec<TokenTree> = g.stream().into_iter().collect();
+    let mut opts = Options::default();
+    for chunk in split_commas(&tokens) {
+        match chunk {
+            [k] if is_ident(k, "property") => opts.property = true,
+            [k] if is_ident(k, "skip") => opts.skip = true,
+            [k, eq, v] if is_ident(k, "name") && is_punct(eq, '=') => opts.name = Some(string_lit(v)?),
+            _ => return Err(format!("unknown option {}", stream(chunk))),
+        }
+    }
+    Ok(opts)
+}
+
+/// Returns the impl block body without the `#[dbus(...)]` attributes, and the methods in it.
+fn parse_body(body: TokenStream) -> Result<(TokenStream, Vec<Method>), String> {
+    let tokens: Vec<TokenTree> = body.into_iter().collect();
+    let (mut out, mut methods) = (vec!(), vec!());
+    let (mut item, mut opts) = (vec!(), None);
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = &tokens[i];
+        i += 1;
+        if is_punct(t, '#') {
+            if let Some(TokenTree::Group(g)) = tokens.get(i) {
+                let inner: Vec<TokenTree> = g.stream().into_iter().collect();
+                if let [name, TokenTree::Group(args)] = &*inner {
+                    if is_ident(name, "dbus") {
+                        if opts.is_some() { return Err("use one #[dbus(...)] attribute per method".into()) }
+                        opts = Some(parse_options(args)?);
+                        i += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        out.push(t.clone());
+        item.push(t.clone());
+        let end = match t {
+            TokenTree::Group(g) => g.delimiter() == Delimiter::Brace,
+            _ => is_punct(t, ';'),
+        };
+        if end {
+            let o = opts.take();
+            if let Some(m) = parse_method(&item, o.unwrap_or_default())? { methods.push(m) }
+            item.clear();
+        }
+    }
+    if opts.is_some() { return Err("#[dbus(...)] must be on a method".into()) }
+    Ok((stream(&out), methods))
+}
+
+/// Returns None for items that are not part of the interface.
+fn parse_method(item: &[TokenTree], opts: Options) -> Result<Option<Method>, String> {
+    let has_opts = opts.property || opts.name.is_some();
+    let fn_pos = match item.iter().position(|t| is_ident(t, "fn")) {
+        Some(p) if !opts.skip => p,
+        _ if has_opts => return Err("#[dbus(...)] must be on a method".into()),
+        _ => return Ok(None),
+    };
+    let fn_name = item.get(fn_pos+1).map(|t| t.to_string()).ok_or("method name missing")?;
+    let is_async = item[..fn_pos].iter().any(|t| is_ident(t, "async"));
+    let params = match item.get(fn_pos+2) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream().into_iter().collect::<Vec<_>>(),
+        _ => return Err(format!("{}: generic methods are not supported", fn_name)),
+    };
+    let params = split_commas(&params);
+    let receiver = match params.first().map(|p| p.iter().map(|t| t.to_string()).collect::<Vec<_>>()) {
+        Some(ref r) if r.len() == 2 && r[0] == "&" && r[1] == "self" => Receiver::Ref,
+        Some(ref r) if r.len() == 3 && r[0] == "&" && r[1] == "mut" && r[2] == "self" => Receiver::RefMut,
+        Some(ref r) if r.last().map(|s| &**s) == Some("self") => return Err(format!("{}: methods must take &self or &mut self", fn_name)),
+        // Associated functions are not part of the interface
+        _ if has_opts => return Err(format!("{}: methods must take &self or &mut self", fn_name)),
+        _ => return Ok(None),
+    };
+    let args = params[1..].iter().map(|p| {
+        let colon = p.iter().position(|t| is_punct(t, ':')).ok_or_else(|| format!("{}: cannot parse argument {}", fn_name, stream(p)))?;
+        match &p[..colon] {
+            [TokenTree::Ident(n)] => Ok((n.to_string(), stream(&p[colon+1..]).to_string())),
+            [m, TokenTree::Ident(n)] if is_ident(m, "mut") => Ok((n.to_string(), stream(&p[colon+1..]).to_string())),
+            _ => Err(format!("{}: arguments must be plain names", fn_name)),
+        }
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    let body_pos = item.len() - 1;
+    let ret = match item.get(fn_pos+3) {
+        Some(a) if is_punct(a, '-') => {
+            let end = item.iter().rposition(|t| is_ident(t, "where")).unwrap_or(body_pos);
+            parse_ret(&item[fn_pos+5..end])
+        },
+        _ => Ret::Plain(Out::Unit),
+    };
+    Ok(Some(Method { fn_name, opts, receiver, is_async, args, ret }))
+}
+
+fn parse_out(ty: &[TokenTree]) -> Out {
+    match ty {
+        [TokenTree::Group(g)] if g.delimiter() == Delimiter::Parenthesis => {
+            let inner: Vec<TokenTree> = g.stream().into_iter().collect();
+            let types: Vec<String> = split_commas(&inner).into_iter().map(|t| stream(t).to_string()).collect();
+            if types.is_empty() { Out::Unit } else { Out::Tuple(types) }
+        },
+        _ => Out::Single(stream(ty).to_string()),
+    }
+}
+
+/// Result<T, E>, or an alias like anyhow::Result<T>, has the out arguments T.
+fn parse_ret(ty: &[TokenTree]) -> Ret {
+    if let Some(lt) = ty.iter().position(|t| is_punct(t, '<')) {
+        if lt > 0 && is_ident(&ty[lt-1], "Result") && ty.last().filter(|t| is_punct(t, '>')).is_some() {
+            let inner = &ty[lt+1..ty.len()-1];
+            if let Some(ok) = split_commas(inner).first() { return Ret::Result(parse_out(ok)) }
+        }
+    }
+    Ret::Plain(parse_out(ty))
+}
+
+fn camel_case(s: &str) -> String {
+    s.split('_').map(|p| {
+        let mut c = p.chars();
+        c.next().map(|f| f.to_uppercase().chain(c).collect::<String>()).unwrap_or_default()
+    }).collect()
+}
+
+/// The type and argument names of the out arguments, and how to turn the return value into them.
+fn out_args(out: &Out) -> (String, String, &'static str) {
+    let names = |n: usize| (0..n).map(|i| format!("\"arg{}\",", i)).collect::<String>();
+    match out {
+        Out::Unit => ("()".into(), "()".into(), ""),
+        Out::Single(t) => (format!("({},)", t), format!("({})", names(1)), ".map(|r| (r,))"),
+        Out::Tuple(t) => (format!("({},)", t.join(", ")), format!("({})", names(t.len())), ""),
+    }
+}
+
+fn generate(iface_name: &str, flavor: Flavor, self_ty: &str, methods: &[Method]) -> Result<String, String> {
+    let (cr_ty, info_ty, new_typed, new_ro, new_rw) = match flavor {
+        Flavor::Sync => ("()", "::dbus::crossroads::SyncInfo", "new_typed_err", "new_sync_ro", "new_sync_rw"),
+        Flavor::Mut => ("::dbus::crossroads::Mut", "::dbus::crossroads::Context", "new_mut_typed_err", "new_mut_ro", "new_mut_rw"),
+        Flavor::Async => ("::dbus::crossroads::Async", "::dbus::crossroads::AsyncInfo", "new_async_typed_err", "new_async_ro", "new_async_rw"),
+    };
+    let mut minfos = String::new();
+    // Getters and setters, by D-Bus name
+    let mut props: Vec<(String, Option<&Method>, Option<&Method>)> = vec!();
+
+    for m in methods {
+        if m.is_async && flavor != Flavor::Async {
+            return Err(format!("{}: async methods need flavor = \"async\"", m.fn_name));
+        }
+        if m.opts.property {
+            let setter = m.fn_name.starts_with("set_");
+            let base = if setter { &m.fn_name[4..] } else { &*m.fn_name };
+            let name = m.opts.name.clone().unwrap_or_else(|| camel_case(base));
+            let idx = props.iter().position(|p| p.0 == name).unwrap_or_else(|| { props.push((name, None, None)); props.len() - 1 });
+            if m.is_async { return Err(format!("{}: property getters and setters cannot be async", m.fn_name)) }
+            let slot = if setter { &mut props[idx].2 } else { &mut props[idx].1 };
+            if slot.is_some() { return Err(format!("{}: property {} is declared twice", m.fn_name, props[idx].0)) }
+            *slot = Some(m);
+            continue;
+        }
+        if m.receiver == Receiver::RefMut && flavor == Flavor::Async {
+            return Err(format!("{}: methods of the async flavor must take &self", m.fn_name));
+        }
+        if m.receiver == Receiver::RefMut && flavor != Flavor::Mut {
+            return Err(format!("{}: methods taking &mut self need flavor = \"mut\"", m.fn_name));
+        }
+        let name = m.opts.name.clone().unwrap_or_else(|| camel_case(&m.fn_name));
+        let arg_names: String = m.args.iter().map(|a| format!("{},", a.0)).collect();
+        let in_names: String = m.args.iter().map(|a| format!("\"{}\",", a.0)).collect();
+        let in_types: String = m.args.iter().map(|a| format!("{},", a.1)).collect();
+        let call = format!("x.{}({}){}", m.fn_name, arg_names, if m.is_async { ".await" } else { "" });
+        let (out_ty, out_names, body) = match &m.ret {
+            Ret::Plain(o) => {
+                let (ty, names, _) = out_args(o);
+                let v = match o { Out::Single(_) => format!("({},)", call), Out::Unit => format!("{{ {}; () }}", call), _ => call };
+                (ty, names, format!("Ok::<_, ::dbus::tree::MethodErr>({})", v))
+            },
+            Ret::Result(o) => {
+                let (ty, names, map) = out_args(o);
+                (ty, names, format!("{}{}", call, map))
+            },
+        };
+        let body = match flavor {
+            // The future must be 'static, so it gets a clone of the interface data.
+            _ if m.is_async => format!("let x = x.clone(); async move {{ {} }}", body),
+            Flavor::Async => format!("::std::future::ready({})", body),
+            _ => body,
+        };
+        let x_ty = if flavor == Flavor::Mut { "&mut Self" } else { "&Self" };
+        // new_async_typed_err also has the type of the future
+        let infer = if flavor == Flavor::Async { "_, _, _, _, _" } else { "_, _, _, _" };
+        minfos += &format!("::dbus::crossroads::MethodInfo::{}::<({}), {}, {}>({:?}, ({}), {}, |x: {}, ({}): ({})| {{ {} }}),\n",
+            new_typed, in_types, out_ty, infer, name, in_names, out_names, x_ty, arg_names, in_types, body);
+    }
+
+    let mut pinfos = String::new();
+    for (name, getter, setter) in props {
+        let getter = getter.ok_or_else(|| format!("property {} has a setter but no getter", name))?;
+        if !getter.args.is_empty() || getter.receiver != Receiver::Ref {
+            return Err(format!("{}: property getters must take only &self", getter.fn_name));
+        }
+        let get = match &getter.ret {
+            Ret::Plain(Out::Unit) => return Err(format!("{}: property getters must return a value", getter.fn_name)),
+            Ret::Plain(_) => format!("Some(x.{}())", getter.fn_name),
+            Ret::Result(_) => format!("x.{}().ok()", getter.fn_name),
+        };
+        let get = format!("|x: &Self, _: &::dbus::Message, _: &{}| {}", info_ty, get);
+        pinfos += &match setter {
+            None => format!("::dbus::crossroads::PropInfo::{}({:?}, {}),\n", new_ro, name, get),
+            Some(s) => {
+                if s.args.len() != 1 {
+                    return Err(format!("{}: property setters must take one argument", s.fn_name));
+                }
+                if s.receiver == Receiver::RefMut && flavor != Flavor::Mut {
+                    return Err(format!("{}: property setters taking &mut self need flavor = \"mut\"", s.fn_name));
+                }
+                let set = match &s.ret {
+                    Ret::Plain(Out::Unit) => format!("{{ x.{}(v); Ok(()) }}", s.fn_name),
+                    Ret::Result(Out::Unit) => format!("x.{}(v).map_err(Into::into)", s.fn_name),
+                    _ => return Err(format!("{}: property setters must return nothing or Result<(), MethodErr>", s.fn_name)),
+                };
+                let x_ty = if flavor == Flavor::Mut { "&mut Self" } else { "&Self" };
+                format!("::dbus::crossroads::PropInfo::{}({:?}, {}, |x: {}, _: &::dbus::Message, _: &{}, v: {}| {}),\n",
+                    new_rw, name, get, x_ty, info_ty, s.args[0].1, set)
+            },
+        };
+    }
+
+    Ok(format!("impl {} {{
+    /// Registers the {} interface, declared with the dbus_interface attribute.
+    pub fn register_dbus_interface(cr: &mut ::dbus::crossroads::Crossroads<{}>) {{
+        cr.register::<Self>(::dbus::crossroads::IfaceInfo::new({:?}, vec!({}), vec!({}), vec!()));
+    }}
+}}", self_ty, iface_name, cr_ty, iface_name, minfos, pinfos))
+}
+
+/// Implements `Arg`, `Append` and `Get` for a struct, as a D-Bus struct of its fields.
+///
+/// ```ignore
+/// #[derive(DBusArgs)]
+/// struct Entry { name: String, size: u32, tags: Vec<String> }
+///
+/// #[derive(DBusArgs)]
+/// struct Listing(String, Vec<Entry>, HashMap<String, Variant<Box<RefArg>>>);
+///
+/// // Listing has the signature "(sa(suas)a{sv})".
+/// let m = m.append1(Listing(dir, entries, props));
+/// let l: Listing = m.read1()?;
+/// ```
+///
+/// The fields are the struct's fields in order, so every field type must implement `Arg`,
+/// `Append` and `Get` - e g basic types, `Vec`, `HashMap`, `Variant` and other structs
+/// with `#[derive(DBusArgs)]`. Structs with named fields, tuple structs, and structs with
+/// type parameters are supported, but not lifetime parameters, unit structs or enums.
+#[proc_macro_derive(DBusArgs)]
+pub fn derive_dbus_args(item: TokenStream) -> TokenStream {
+    match expand_derive(item) {
+        Ok(ts) => ts,
+        Err(e) => format!("compile_error!({:?});", format!("DBusArgs: {}", e)).parse().unwrap(),
+    }
+}
+
+fn expand_derive(item: TokenStream) -> Result<TokenStream, String> {
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+    let pos = tokens.iter().position(|t| is_ident(t, "struct")).ok_or("can only be derived for structs")?;
+    let name = tokens.get(pos+1).map(|t| t.to_string()).ok_or("struct name missing")?;
+    let rest = &tokens[pos+2..];
+    let generics = match rest.first() {
+        Some(t) if is_punct(t, '<') => {
+            let end = generics_end(rest).ok_or("cannot parse the generics")?;
+            parse_generics(&rest[1..end])?
+        },
+        _ => vec!(),
+    };
+    let (fields, named) = match rest.iter().find(|t| if let TokenTree::Group(_) = t { true } else { false }) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => (parse_fields(g, true)?, true),
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => (parse_fields(g, false)?, false),
+        _ => return Err("unit structs have no D-Bus type".into()),
+    };
+    if fields.is_empty() { return Err("structs without fields have no D-Bus type".into()) }
+    if rest.iter().any(|t| is_ident(t, "where")) { return Err("where clauses are not supported".into()) }
+    generate_derive(&name, &generics, &fields, named).parse::<TokenStream>().map_err(|e| format!("{:?}", e))
+}
+
+/// The index of the '>' closing the generics starting at tokens[0].
+fn generics_end(tokens: &[TokenTree]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        if is_punct(t, '<') { depth += 1 }
+        if is_punct(t, '>') && (i == 0 || !is_punct(&tokens[i-1], '-')) {
+            depth -= 1;
+            if depth == 0 { return Some(i) }
+        }
+    }
+    None
+}
+
+/// The names of the type parameters; bounds are not supported, as they are added by the derive.
+fn parse_generics(tokens: &[TokenTree]) -> Result<Vec<String>, String> {
+    split_commas(tokens).into_iter().map(|p| match p {
+        [TokenTree::Ident(n)] => Ok(n.to_string()),
+        [TokenTree::Punct(q), ..] if q.as_char() == '\'' => Err("lifetime parameters are not supported".into()),
+        _ => Err(format!("cannot parse type parameter {}, bounds are not supported", stream(p))),
+    }).collect()
+}
+
+/// The field names (or indices) and types.
+fn parse_fields(g: &Group, named: bool) -> Result<Vec<(String, String)>, String> {
+    let tokens: Vec<TokenTree> = g.stream().into_iter().collect();
+    split_commas(&tokens).into_iter().enumerate().map(|(idx, f)| {
+        let mut i = 0;
+        // Attributes, e g doc comments, and visibility
+        loop {
+            match f.get(i) {
+                Some(t) if is_punct(t, '#') => i += 2,
+                Some(t) if is_ident(t, "pub") => {
+                    i += 1;
+                    if let Some(TokenTree::Group(g)) = f.get(i) { if g.delimiter() == Delimiter::Parenthesis { i += 1 } }
+                },
+                _ => break,
+            }
+        }
+        let f = &f[i.min(f.len())..];
+        if !named { return Ok((idx.to_string(), stream(f).to_string())) }
+        match f {
+            [TokenTree::Ident(n), colon, ty @ ..] if is_punct(colon, ':') && !ty.is_empty() => Ok((n.to_string(), stream(ty).to_string())),
+            _ => Err(format!("cannot parse field {}", stream(f))),
+        }
+    }).collect()
+}
+
+fn generate_derive(name: &str, generics: &[String], fields: &[(String, String)], named: bool) -> String {
+    let bounded = |b: &str| generics.iter().map(|g| format!("{}: {},", g, b)).collect::<String>();
+    let params = format!("<{}>", generics.join(", "));
+    let vars: Vec<String> = fields.iter().map(|f| format!("f_{}", f.0)).collect();
+    let pattern = if named {
+        format!("{} {{ {} }}", name, fields.iter().zip(&vars).map(|(f, v)| format!("{}: {},", f.0, v)).collect::<String>())
+    } else {
+        format!("{}({})", name, vars.iter().map(|v| format!("{},", v)).collect::<String>())
+    };
+    let sig: String = fields.iter().map(|f| format!("s.push_str(&<{} as ::dbus::arg::Arg>::signature());\n", f.1)).collect();
+    let appends: String = vars.iter().map(|v| format!("s.append({});", v)).collect();
+    // A field that is not there fails in get, so the iterator is only moved, and not checked.
+    let gets: String = fields.iter().zip(&vars).map(|(f, v)| format!("let {}: {} = si.get()?; si.next();\n", v, f.1)).collect();
+
+    format!("impl<{bounds_arg}> ::dbus::arg::Arg for {name}{params} {{
+    const ARG_TYPE: ::dbus::arg::ArgType = ::dbus::arg::ArgType::Struct;
+    fn signature() -> ::dbus::Signature<'static> {{
+        let mut s = String::from(\"(\");
+        {sig}s.push_str(\")\");
+        ::dbus::Signature::from(s)
+    }}
+}}
+
+impl<{bounds_append}> ::dbus::arg::Append for {name}{params} {{
+    fn append(self, i: &mut ::dbus::arg::IterAppend) {{
+        let {pattern} = self;
+        i.append_struct(|s| {{ {appends} }});
+    }}
+}}
+
+impl<'a, {bounds_get}> ::dbus::arg::Get<'a> for {name}{params} {{
+    fn get(i: &mut ::dbus::arg::Iter<'a>) -> Option<Self> {{
+        let mut si = i.recurse(::dbus::arg::ArgType::Struct)?;
+        {gets}Some({pattern})
+    }}
+}}
+", bounds_arg = bounded("::dbus::arg::Arg"), bounds_append = bounded("::dbus::arg::Arg + ::dbus::arg::Append"),
+        bounds_get = bounded("::dbus::arg::Arg + ::dbus::arg::Get<'a>"), name = name, params = params,
+        sig = sig, appends = appends, gets = gets, pattern = pattern)
+}
+
+/// Implements `Arg`, `Append` and `Get` for an enum without fields, as a D-Bus `u32` or string.
+///
+/// ```ignore
+/// #[derive(DBusEnum)]
+/// enum DeviceState { Unknown = 0, Unmanaged = 10, Unavailable = 20, Disconnected = 30 }
+///
+/// #[derive(DBusEnum)]
+/// #[dbus(repr = "string")]
+/// enum Transport { Auto, #[dbus(name = "bredr")] BrEdr, #[dbus(name = "le")] LowEnergy }
+/// ```
+///
+/// By default, and with `#[dbus(repr = "u32")]`, a variant is its discriminant, numbered
+/// like Rust does. With `#[dbus(repr = "string")]`, it is its name, or the name given
+/// with `#[dbus(name = "...")]` on the variant.
+///
+/// Getting a value that is not one of the variants fails, which a method of the crossroads
+/// module replies to with an InvalidArgs error. The derive also implements `TryFrom<u32>`
+/// (or `TryFrom<&str>`) with `MethodErr` as the error, and `From<Enum> for u32`
+/// (or `for &'static str`).
+#[proc_macro_derive(DBusEnum, attributes(dbus))]
+pub fn derive_dbus_enum(item: TokenStream) -> TokenStream {
+    match expand_enum(item) {
+        Ok(ts) => ts,
+        Err(e) => format!("compile_error!({:?});", format!("DBusEnum: {}", e)).parse().unwrap(),
+    }
+}
+
+/// The options of a `#[dbus(...)]` attribute at tokens[i], if there is one.
+fn dbus_attr(tokens: &[TokenTree], i: usize) -> Option<Vec<(String, String)>> {
+    if !is_punct(tokens.get(i)?, '#') { return None }
+    let g = match tokens.get(i+1)? { TokenTree::Group(g) => g, _ => return None };
+    let inner: Vec<TokenTree> = g.stream().into_iter().collect();
+    match &*inner {
+        [name, TokenTree::Group(args)] if is_ident(name, "dbus") => {
+            let args: Vec<TokenTree> = args.stream().into_iter().collect();
+            Some(split_commas(&args).into_iter().map(|a| match a {
+                [k, eq, v] if is_punct(eq, '=') => (k.to_string(), string_lit(v).unwrap_or_else(|_| v.to_string())),
+                _ => (stream(a).to_string(), String::new()),
+            }).collect())
+        },
+        _ => None,
+    }
+}
+
+fn expand_enum(item: TokenStream) -> Result<TokenStream, String> {
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+    let pos = tokens.iter().position(|t| is_ident(t, "enum")).ok_or("can only be derived for enums")?;
+    let mut as_string = false;
+    for i in 0..pos {
+        for (k, v) in dbus_attr(&tokens, i).unwrap_or_default() {
+            match (&*k, &*v) {
+                ("repr", "u32") => as_string = false,
+                ("repr", "string") => as_string = true,
+                ("repr", r) => return Err(format!("unknown repr {:?}, use \"u32\" or \"string\"", r)),
+                (k, _) => return Err(format!("unknown option {}", k)),
+            }
+        }
+    }
+    let name = tokens.get(pos+1).map(|t| t.to_string()).ok_or("enum name missing")?;
+    let body = match tokens.get(pos+2) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g.stream().into_iter().collect::<Vec<_>>(),
+        Some(t) if is_punct(t, '<') => return Err("generic enums are not supported".into()),
+        _ => return Err("cannot parse the enum".into()),
+    };
+
+    // Variant names, and their D-Bus values as Rust literals
+    let mut variants: Vec<(String, String)> = vec!();
+    let mut next = 0u32;
+    for v in split_commas(&body) {
+        let (mut i, mut dbus_name) = (0, None);
+        while v.get(i).map_or(false, |t| is_punct(t, '#')) {
+            for (k, val) in dbus_attr(v, i).unwrap_or_default() {
+                match &*k {
+                    "name" if as_string => dbus_name = Some(val),
+                    "name" => return Err("name is only for repr = \"string\"".into()),
+                    k => return Err(format!("unknown option {}", k)),
+                }
+            }
+            i += 2;
+        }
+        let vname = match v.get(i) {
+            Some(TokenTree::Ident(n)) => n.to_string(),
+            _ => return Err(format!("cannot parse variant {}", stream(v))),
+        };
+        let value = match &v[i+1..] {
+            [] => next,
+            [eq, rest @ ..] if is_punct(eq, '=') => {
+                let d = stream(rest).to_string();
+                let n = d.replace('_', "");
+                let n = if n.starts_with("0x") { u32::from_str_radix(&n[2..], 16) } else { n.trim_end_matches("u32").parse() };
+                n.map_err(|_| format!("{}: discriminants must be u32 literals, found {}", vname, d))?
+            },
+            _ => return Err(format!("{}: variants with fields have no D-Bus type", vname)),
+        };
+        next = value.wrapping_add(1);
+        let lit = if as_string { format!("{:?}", dbus_name.unwrap_or_else(|| vname.clone())) } else { format!("{}u32", value) };
+        variants.push((vname, lit));
+    }
+    if variants.is_empty() { return Err("enums without variants have no D-Bus value".into()) }
+    generate_enum(&name, as_string, &variants).parse::<TokenStream>().map_err(|e| format!("{:?}", e))
+}
+
+fn generate_enum(name: &str, as_string: bool, variants: &[(String, String)]) -> String {
+    let (arg_type, ty, get_ty) = if as_string { ("String", "&'static str", "&str") } else { ("UInt32", "u32", "u32") };
+    let to: String = variants.iter().map(|(n, l)| format!("{}::{} => {},", name, n, l)).collect();
+    let from: String = variants.iter().map(|(n, l)| format!("{} => Ok({}::{}),", l, name, n)).collect();
+    format!("impl From<{name}> for {ty} {{
+    fn from(v: {name}) -> Self {{ match v {{ {to} }} }}
+}}
+
+impl ::std::convert::TryFrom<{get_ty}> for {name} {{
+    type Error = ::dbus::tree::MethodErr;
+    fn try_from(v: {get_ty}) -> Result<Self, Self::Error> {{
+        match v {{ {from} _ => Err(::dbus::tree::MethodErr::invalid_arg(&v)) }}
+    }}
+}}
+
+impl ::dbus::arg::Arg for {name} {{
+    const ARG_TYPE: ::dbus::arg::ArgType = ::dbus::arg::ArgType::{arg_type};
+    fn signature() -> ::dbus::Signature<'static> {{ <{get_ty} as ::dbus::arg::Arg>::signature() }}
+}}
+
+impl ::dbus::arg::Append for {name} {{
+    fn append(self, i: &mut ::dbus::arg::IterAppend) {{ i.append(<{ty}>::from(self)) }}
+}}
+
+impl<'a> ::dbus::arg::Get<'a> for {name} {{
+    fn get(i: &mut ::dbus::arg::Iter<'a>) -> Option<Self> {{
+        let v: {get_ty} = i.get()?;
+        ::std::convert::TryFrom::try_from(v).ok()
+    }}
+}}
+", name = name, ty = ty, get_ty = get_ty, to = to, from = from, arg_type = arg_type)
+}
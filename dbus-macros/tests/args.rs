@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use dbus::Message;
+use dbus::arg::{Arg, RefArg, Variant};
+use dbus_macros::DBusArgs;
+
+#[derive(DBusArgs, Debug, PartialEq)]
+struct Entry {
+    /// The name of the entry
+    pub name: String,
+    pub(crate) size: u32,
+    tags: Vec<String>,
+}
+
+#[derive(DBusArgs, Debug)]
+struct Listing(String, Vec<Entry>, HashMap<String, Variant<Box<RefArg>>>);
+
+#[derive(DBusArgs, Debug, PartialEq)]
+struct Pair<T> { key: String, value: T }
+
+fn msg() -> Message { Message::new_method_call("com.example.dbusrs", "/", "com.example.dbusrs", "Args").unwrap() }
+
+#[test]
+fn derived_struct() {
+    assert_eq!(&*Entry::signature(), "(suas)");
+    let e = Entry { name: "a".into(), size: 5, tags: vec!("x".into(), "y".into()) };
+    let m = msg().append1(e);
+    let e: Entry = m.read1().unwrap();
+    assert_eq!(e, Entry { name: "a".into(), size: 5, tags: vec!("x".into(), "y".into()) });
+    assert!(m.read1::<Pair<String>>().is_err());
+}
+
+#[test]
+fn derived_nested() {
+    assert_eq!(&*Listing::signature(), "(sa(suas)a{sv})");
+    let mut props = HashMap::new();
+    props.insert("count".to_string(), Variant(Box::new(2u32) as Box<RefArg>));
+    let entries = vec!(Entry { name: "b".into(), size: 0, tags: vec!() }, Entry { name: "c".into(), size: 1, tags: vec!("z".into()) });
+    let m = msg().append2(Listing("/home".into(), entries, props), vec!(Pair { key: "k".into(), value: 3i64 }));
+    let (l, p): (Listing, Vec<Pair<i64>>) = m.read2().unwrap();
+    assert_eq!(l.0, "/home");
+    assert_eq!(l.1.len(), 2);
+    assert_eq!(l.1[1].tags, vec!("z".to_string()));
+    assert_eq!(l.2["count"].0.as_u64(), Some(2));
+    assert_eq!(p, vec!(Pair { key: "k".into(), value: 3 }));
+    assert_eq!(&*<Vec<Pair<i64>>>::signature(), "a(sx)");
+}
@@ -0,0 +1,53 @@
+use std::convert::TryFrom;
+use dbus::Message;
+use dbus::arg::Arg;
+use dbus_macros::{DBusArgs, DBusEnum};
+
+#[derive(DBusEnum, Debug, PartialEq, Clone, Copy)]
+enum DeviceState { Unknown = 0, Unmanaged = 10, Unavailable = 20, Disconnected = 30, Prepare, Config }
+
+#[derive(DBusEnum, Debug, PartialEq, Clone, Copy)]
+#[dbus(repr = "string")]
+enum Transport {
+    Auto,
+    /// Basic rate
+    #[dbus(name = "bredr")]
+    BrEdr,
+    #[dbus(name = "le")]
+    LowEnergy,
+}
+
+#[derive(DBusArgs, Debug, PartialEq)]
+struct Device { state: DeviceState, transport: Transport }
+
+fn msg() -> Message { Message::new_method_call("com.example.dbusrs", "/", "com.example.dbusrs", "Enums").unwrap() }
+
+#[test]
+fn u32_enum() {
+    assert_eq!(&*DeviceState::signature(), "u");
+    assert_eq!(u32::from(DeviceState::Config), 32);
+    assert_eq!(DeviceState::try_from(31).unwrap(), DeviceState::Prepare);
+    let e = DeviceState::try_from(5).unwrap_err();
+    assert_eq!(e.errorname(), &"org.freedesktop.DBus.Error.InvalidArgs".into());
+
+    let m = msg().append2(DeviceState::Unavailable, 11u32);
+    assert_eq!(m.get1(), Some(DeviceState::Unavailable));
+    assert_eq!(m.read2::<DeviceState, DeviceState>().unwrap_err().pos(), 1);
+}
+
+#[test]
+fn string_enum() {
+    assert_eq!(&*Transport::signature(), "s");
+    assert_eq!(<&str>::from(Transport::LowEnergy), "le");
+    assert_eq!(Transport::try_from("Auto").unwrap(), Transport::Auto);
+    assert!(Transport::try_from("BrEdr").is_err());
+
+    let m = msg().append2(Transport::BrEdr, "usb");
+    assert_eq!(m.get1(), Some(Transport::BrEdr));
+    assert!(m.read2::<Transport, Transport>().is_err());
+
+    let d = Device { state: DeviceState::Disconnected, transport: Transport::Auto };
+    assert_eq!(&*Device::signature(), "(us)");
+    let m = msg().append1(d);
+    assert_eq!(m.get1(), Some(Device { state: DeviceState::Disconnected, transport: Transport::Auto }));
+}
@@ -0,0 +1,178 @@
+use std::sync::Mutex;
+use dbus::crossroads::{Crossroads, PathData, DBusError};
+use dbus::crossroads::testing::TestCall;
+use dbus::tree::MethodErr;
+use dbus_macros::dbus_interface;
+
+struct Score {
+    points: Mutex<u32>,
+    name: Mutex<String>,
+}
+
+struct Overflow;
+
+impl DBusError for Overflow {
+    fn error_name(&self) -> dbus::ErrorName<'static> { "com.example.dbusrs.Error.Overflow".into() }
+    fn error_message(&self) -> String { "Too many points".into() }
+}
+
+#[dbus_interface(name = "com.example.dbusrs.Score")]
+impl Score {
+    fn new() -> Self { Score { points: Mutex::new(7), name: Mutex::new("Bob".into()) } }
+
+    fn add(&self, points: u32) -> Result<u32, Overflow> {
+        if points > 100 { return Err(Overflow) }
+        let mut p = self.points.lock().unwrap();
+        *p += points;
+        Ok(*p)
+    }
+
+    fn reset(&self) { *self.points.lock().unwrap() = 0 }
+
+    #[dbus(name = "Describe")]
+    fn summary(&self, prefix: String) -> (String, u32) {
+        (format!("{}{}", prefix, self.name.lock().unwrap()), *self.points.lock().unwrap())
+    }
+
+    #[dbus(skip)]
+    #[allow(dead_code)]
+    fn internal(&self) -> u32 { 5 }
+
+    #[dbus(property)]
+    fn player_name(&self) -> String { self.name.lock().unwrap().clone() }
+
+    #[dbus(property)]
+    fn set_player_name(&self, v: String) -> Result<(), MethodErr> {
+        if v.is_empty() { return Err(MethodErr::invalid_arg(&v)) }
+        *self.name.lock().unwrap() = v;
+        Ok(())
+    }
+
+    #[dbus(property)]
+    fn points(&self) -> u32 { *self.points.lock().unwrap() }
+}
+
+const IFACE: &str = "com.example.dbusrs.Score";
+
+#[test]
+fn methods() {
+    let mut cr = Crossroads::new_sync();
+    Score::register_dbus_interface(&mut cr);
+    let mut pdata = PathData::new();
+    pdata.insert(Score::new());
+    cr.insert("/", pdata);
+
+    let (total,): (u32,) = TestCall::new("/", IFACE, "Add").args((5u32,)).dispatch(&mut cr).ok();
+    assert_eq!(total, 12);
+    let r = TestCall::new("/", IFACE, "Add").args((500u32,)).dispatch(&mut cr);
+    assert_eq!(r.error().unwrap().message(), Some("Too many points"));
+    let (s, p): (String, u32) = TestCall::new("/", IFACE, "Describe").args(("Player ".to_string(),)).dispatch(&mut cr).ok();
+    assert_eq!((&*s, p), ("Player Bob", 12));
+    let _: () = TestCall::new("/", IFACE, "Reset").dispatch(&mut cr).ok();
+    assert!(TestCall::new("/", IFACE, "Internal").dispatch(&mut cr).error().is_some());
+    assert!(TestCall::new("/", IFACE, "New").dispatch(&mut cr).error().is_some());
+}
+
+#[test]
+fn properties() {
+    let mut cr = Crossroads::new_sync();
+    Score::register_dbus_interface(&mut cr);
+    let mut pdata = PathData::new();
+    pdata.insert(Score::new());
+    cr.insert("/", pdata);
+
+    let props = "org.freedesktop.DBus.Properties";
+    let get = |cr: &mut Crossroads<()>, name: &str| TestCall::new("/", props, "Get").args((IFACE.to_string(), name.to_string())).dispatch(cr);
+    let (v,): (dbus::arg::Variant<u32>,) = get(&mut cr, "Points").ok();
+    assert_eq!(v.0, 7);
+    let (v,): (dbus::arg::Variant<String>,) = get(&mut cr, "PlayerName").ok();
+    assert_eq!(v.0, "Bob");
+
+    let set = |cr: &mut Crossroads<()>, name: &str, v: String| TestCall::new("/", props, "Set")
+        .args((IFACE.to_string(), name.to_string(), dbus::arg::Variant(v))).dispatch(cr);
+    let _: () = set(&mut cr, "PlayerName", "Alice".into()).ok();
+    let (v,): (dbus::arg::Variant<String>,) = get(&mut cr, "PlayerName").ok();
+    assert_eq!(v.0, "Alice");
+    assert!(set(&mut cr, "PlayerName", "".into()).error().is_some());
+    assert!(set(&mut cr, "Points", "5".into()).error().is_some());
+}
+
+struct Counter(u32);
+
+#[dbus_interface(name = "com.example.dbusrs.Counter", flavor = "mut")]
+impl Counter {
+    fn increment(&mut self, by: u32) -> u32 { self.0 += by; self.0 }
+
+    #[dbus(property)]
+    fn value(&self) -> u32 { self.0 }
+    #[dbus(property)]
+    fn set_value(&mut self, v: u32) { self.0 = v }
+}
+
+#[test]
+fn mut_flavor() {
+    let mut cr = Crossroads::new_mut();
+    Counter::register_dbus_interface(&mut cr);
+    let mut pdata = PathData::new();
+    pdata.insert(Counter(1));
+    cr.insert("/", pdata);
+
+    let (v,): (u32,) = TestCall::new("/", "com.example.dbusrs.Counter", "Increment").args((2u32,)).dispatch(&mut cr).ok();
+    assert_eq!(v, 3);
+    let (v,): (dbus::arg::Variant<u32>,) = TestCall::new("/", "org.freedesktop.DBus.Properties", "Get")
+        .args(("com.example.dbusrs.Counter".to_string(), "Value".to_string())).dispatch(&mut cr).ok();
+    assert_eq!(v.0, 3);
+
+    TestCall::new("/", "org.freedesktop.DBus.Properties", "Set")
+        .args(("com.example.dbusrs.Counter".to_string(), "Value".to_string(), dbus::arg::Variant(10u32))).dispatch(&mut cr).ok::<()>();
+    let (v,): (u32,) = TestCall::new("/", "com.example.dbusrs.Counter", "Increment").args((2u32,)).dispatch(&mut cr).ok();
+    assert_eq!(v, 12);
+}
+
+#[derive(Clone)]
+struct Lookup(std::sync::Arc<Mutex<Vec<String>>>);
+
+async fn fetch(key: u32) -> Result<String, MethodErr> {
+    if key == 0 { Err(MethodErr::invalid_arg(&key)) } else { Ok(format!("value {}", key)) }
+}
+
+#[dbus_interface(name = "com.example.dbusrs.Lookup")]
+impl Lookup {
+    async fn get(&self, key: u32) -> Result<String, MethodErr> {
+        let v = fetch(key).await?;
+        self.0.lock().unwrap().push(v.clone());
+        Ok(v)
+    }
+
+    async fn pair(&self, a: u32, b: u32) -> (String, String) {
+        (fetch(a).await.unwrap(), fetch(b).await.unwrap())
+    }
+
+    fn count(&self) -> u32 { self.0.lock().unwrap().len() as u32 }
+
+    #[dbus(property)]
+    fn last(&self) -> String { self.0.lock().unwrap().last().cloned().unwrap_or_default() }
+}
+
+#[test]
+fn async_methods() {
+    let mut cr = Crossroads::new_async();
+    Lookup::register_dbus_interface(&mut cr);
+    let mut pdata = PathData::new();
+    let lookup = Lookup(Default::default());
+    pdata.insert(lookup.clone());
+    cr.insert("/", pdata);
+
+    let iface = "com.example.dbusrs.Lookup";
+    let (v,): (String,) = TestCall::new("/", iface, "Get").args((5u32,)).dispatch(&mut cr).ok();
+    assert_eq!(v, "value 5");
+    assert!(TestCall::new("/", iface, "Get").args((0u32,)).dispatch(&mut cr).error().is_some());
+    let (a, b): (String, String) = TestCall::new("/", iface, "Pair").args((1u32, 2u32)).dispatch(&mut cr).ok();
+    assert_eq!((&*a, &*b), ("value 1", "value 2"));
+    let (c,): (u32,) = TestCall::new("/", iface, "Count").dispatch(&mut cr).ok();
+    assert_eq!(c, 1);
+    assert_eq!(lookup.0.lock().unwrap().len(), 1);
+    let (v,): (dbus::arg::Variant<String>,) = TestCall::new("/", "org.freedesktop.DBus.Properties", "Get")
+        .args((iface.to_string(), "Last".to_string())).dispatch(&mut cr).ok();
+    assert_eq!(v.0, "value 5");
+}
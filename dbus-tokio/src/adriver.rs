@@ -1,7 +1,7 @@
 use mio::{self, unix, Ready};
 use mio::unix::UnixReady;
 use std::io;
-use dbus::{Connection, ConnMsgs, Watch, WatchEvent, Message, MessageType, Error as DBusError};
+use dbus::{Connection, ConnMsgs, is_disconnected, Watch, WatchEvent, Message, MessageType, NameWatcher, MatchRule, SignalArgs, Token, BusName, Path, Error as DBusError};
 use futures::{Async, Future, Stream, Poll};
 use futures::sync::{oneshot, mpsc};
 use tokio::reactor::Handle as CoreHandle;
@@ -12,11 +12,14 @@ use std::os::raw::c_uint;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::os::unix::io::RawFd;
+use std::marker::PhantomData;
 
 type MCallMap = Rc<RefCell<HashMap<u32, oneshot::Sender<Message>>>>;
 
 type MStream = Rc<RefCell<Option<mpsc::UnboundedSender<Message>>>>;
 
+type MDisconnect = Rc<RefCell<Vec<oneshot::Sender<()>>>>;
+
 #[derive(Debug)]
 /// A Tokio enabled D-Bus connection.
 ///
@@ -27,6 +30,7 @@ pub struct AConnection {
     quit: Option<Rc<oneshot::Sender<()>>>,
     callmap: MCallMap,
     msgstream: MStream,
+    disconnect: MDisconnect,
 }
 
 impl AConnection {
@@ -38,6 +42,7 @@ impl AConnection {
         let (tx, rx) = oneshot::channel();
         let map: MCallMap = Default::default();
         let istream: MStream = Default::default();
+        let disconnect: MDisconnect = Default::default();
         let mut d = ADriver {
             conn: c.clone(),
             fds: HashMap::new(),
@@ -45,14 +50,17 @@ impl AConnection {
             quit: rx,
             callmap: map.clone(),
             msgstream: istream.clone(),
+            disconnect: disconnect.clone(),
         };
         let i = AConnection {
             conn: c,
             quit: Some(Rc::new(tx)),
             callmap: map,
             msgstream: istream,
+            disconnect,
         };
-        i.conn.set_watch_callback(Box::new(|_| unimplemented!("Watch handling is very rare and not implemented yet")));
+        // The callback must be Send, so the watches are synced in ADriver::poll instead.
+        i.conn.set_watch_callback(Box::new(|_| {}));
         for w in i.conn.watch_fds() { d.modify_watch(w, false)?; }
         e.spawn(Box::new(d));
         Ok(i)
@@ -71,6 +79,49 @@ impl AConnection {
         Ok(mc)
     }
 
+    /// Sends a method call with the no-reply-expected flag set, and without waiting for a reply.
+    ///
+    /// Unlike `method_call`, nothing is kept waiting for a reply; see `Connection::method_call_no_reply`.
+    pub fn method_call_no_reply(&self, m: Message) -> Result<u32, &'static str> {
+        self.conn.method_call_no_reply(m).map_err(|_| "D-Bus send error")
+    }
+
+    /// Sends several method calls back to back, and returns a Future for all the replies.
+    ///
+    /// The replies are in the order of the calls. Unlike with `futures::future::join_all`, an
+    /// error reply to one call does not fail the others.
+    pub fn method_calls<I: IntoIterator<Item=Message>>(&self, msgs: I) -> Result<AMethodCalls, &'static str> {
+        let calls = msgs.into_iter().map(|m| self.method_call(m)).collect::<Result<Vec<_>, _>>()?;
+        let replies = calls.iter().map(|_| None).collect();
+        Ok(AMethodCalls { calls, replies })
+    }
+
+    /// Returns a Future for the unique name of the owner of a name, once it has one.
+    ///
+    /// This is for clients that start before the service they need, e g during boot.
+    /// To give up after a while, use `tokio::util::FutureExt::timeout` on the Future.
+    pub fn wait_for_name(&self, name: &str) -> Result<AWaitForName, DBusError> {
+        let (tx, rx) = oneshot::channel();
+        let mut tx = Some(tx);
+        let watcher = NameWatcher::new(self.conn.clone(), name, move |o| {
+            if let Some(o) = o {
+                if let Some(tx) = tx.take() { let _ = tx.send(o.to_string()); }
+            }
+        })?;
+        Ok(AWaitForName { _watcher: watcher, inner: rx })
+    }
+
+    /// Returns a Future that resolves when the connection is lost, e g because the bus daemon
+    /// restarted, so that reconnecting can start right away.
+    ///
+    /// It resolves right away if the connection is already lost, and fails if the AConnection
+    /// is dropped first. The Disconnected signal is still sent to the stream from `messages`.
+    pub fn disconnected(&self) -> ADisconnected {
+        let (tx, rx) = oneshot::channel();
+        if self.conn.is_connected() { self.disconnect.borrow_mut().push(tx) } else { let _ = tx.send(()); }
+        ADisconnected { inner: rx }
+    }
+
     /// Returns a stream of incoming messages.
     ///
     /// Creating more than one stream for the same AConnection is not supported; this function will
@@ -82,6 +133,25 @@ impl AConnection {
         *i = Some(tx);
         Ok(AMessageStream { inner: rx, stream: self.msgstream.clone(), quit: self.quit.as_ref().map(|q| q.clone()) })
     }
+
+    /// Returns a stream of incoming messages matching a rule.
+    ///
+    /// The rule is added to the bus, and removed again when the stream is dropped. Matching
+    /// messages go to this stream only, not to the one from `messages`. Unlike that one, there
+    /// can be several of these at the same time.
+    pub fn match_stream(&self, rule: MatchRule) -> Result<AMatchStream, DBusError> {
+        let (tx, rx) = mpsc::unbounded();
+        let token = self.conn.add_match_cb(rule, move |m| { let _ = tx.unbounded_send(m.duplicate()); })?;
+        Ok(AMatchStream { inner: rx, conn: self.conn.clone(), token, quit: self.quit.as_ref().map(|q| q.clone()) })
+    }
+
+    /// Returns a stream of the signal S, from a sender and/or path, or from all if None.
+    ///
+    /// Like `match_stream`, with the arguments of the signal decoded. Signals that do not
+    /// decode as S are skipped.
+    pub fn signal_stream<S: SignalArgs>(&self, sender: Option<&BusName>, path: Option<&Path>) -> Result<ASignalStream<S>, DBusError> {
+        Ok(ASignalStream { inner: self.match_stream(S::match_rule(sender, path))?, _s: PhantomData })
+    }
 }
 
 impl Drop for AConnection {
@@ -104,6 +174,7 @@ struct ADriver {
     quit: oneshot::Receiver<()>,
     callmap: MCallMap,
     msgstream: MStream,
+    disconnect: MDisconnect,
 }
 
 impl ADriver {
@@ -139,7 +210,10 @@ impl ADriver {
         let msgs = ConnMsgs { conn: &*self.conn, timeout_ms: None };
         for m in msgs {
             debug!("handle_msgs: {:?}", m);
-            if m.msg_type() == MessageType::MethodReturn {
+            if is_disconnected(&m) {
+                for tx in self.disconnect.borrow_mut().drain(..) { let _ = tx.send(()); }
+            }
+            if m.msg_type() == MessageType::MethodReturn || m.msg_type() == MessageType::Error {
                 let mut map = self.callmap.borrow_mut();
                 let serial = m.get_reply_serial().unwrap();
                 let r = map.remove(&serial);
@@ -190,6 +264,10 @@ impl Future for ADriver {
             if ur.is_readable() { w.clear_read_ready(Ready::readable()).map_err(|_| ())?; };
             if ur.is_writable() { w.clear_write_ready().map_err(|_| ())?; };
         };
+        // E g when the connection is lost, libdbus removes its watches.
+        let watches = self.conn.watch_fds();
+        self.fds.retain(|fd, _| watches.iter().any(|w| w.fd() == *fd));
+        for w in watches { self.modify_watch(w, false).map_err(|_| ())?; }
         self.handle_msgs();
         Ok(Async::NotReady)
     }
@@ -258,6 +336,60 @@ impl Drop for AMethodCall {
     }
 }
 
+#[derive(Debug)]
+/// A Future that resolves when all method calls are replied to, see `AConnection::method_calls`.
+pub struct AMethodCalls {
+    calls: Vec<AMethodCall>,
+    replies: Vec<Option<Result<Message, DBusError>>>,
+}
+
+impl Future for AMethodCalls {
+    type Item = Vec<Result<Message, DBusError>>;
+    type Error = ();
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        for (c, r) in self.calls.iter_mut().zip(self.replies.iter_mut()).filter(|(_, r)| r.is_none()) {
+            match c.poll() {
+                Ok(Async::NotReady) => {},
+                Ok(Async::Ready(m)) => *r = Some(Ok(m)),
+                Err(e) => *r = Some(Err(e)),
+            }
+        }
+        if self.replies.iter().any(|r| r.is_none()) { return Ok(Async::NotReady) }
+        Ok(Async::Ready(self.replies.iter_mut().map(|r| r.take().unwrap()).collect()))
+    }
+}
+
+/// A Future that resolves to the unique name of the owner of a name, see `AConnection::wait_for_name`.
+pub struct AWaitForName {
+    _watcher: NameWatcher<Rc<Connection>>,
+    inner: oneshot::Receiver<String>,
+}
+
+impl Future for AWaitForName {
+    type Item = String;
+    type Error = DBusError;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        self.inner.poll().map_err(|_| DBusError::new_custom("org.freedesktop.DBus.Failed", "Tokio cancelled future"))
+    }
+}
+
+#[derive(Debug)]
+/// A Future that resolves when the connection is lost, see `AConnection::disconnected`.
+pub struct ADisconnected {
+    inner: oneshot::Receiver<()>,
+}
+
+impl Future for ADisconnected {
+    type Item = ();
+    type Error = DBusError;
+
+    fn poll(&mut self) -> Result<Async<Self::Item>, Self::Error> {
+        self.inner.poll().map_err(|_| DBusError::new_custom("org.freedesktop.DBus.Failed", "Tokio cancelled future"))
+    }
+}
+
 #[derive(Debug)]
 /// A Stream of incoming messages.
 ///
@@ -291,6 +423,53 @@ impl Drop for AMessageStream {
     }
 }
 
+#[derive(Debug)]
+/// A Stream of incoming messages matching a rule, see `AConnection::match_stream`.
+pub struct AMatchStream {
+    inner: mpsc::UnboundedReceiver<Message>,
+    conn: Rc<Connection>,
+    token: Token,
+    quit: Option<Rc<oneshot::Sender<()>>>,
+}
+
+impl Stream for AMatchStream {
+    type Item = Message;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> { self.inner.poll() }
+}
+
+impl Drop for AMatchStream {
+    fn drop(&mut self) {
+        debug!("Dropping AMatchStream");
+        let _ = self.conn.remove_match_cb(self.token);
+        if let Ok(x) = Rc::try_unwrap(self.quit.take().unwrap()) {
+            debug!("AMatchStream telling ADriver to quit");
+            let _ = x.send(());
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A Stream of decoded signals, see `AConnection::signal_stream`.
+pub struct ASignalStream<S> {
+    inner: AMatchStream,
+    _s: PhantomData<S>,
+}
+
+impl<S: SignalArgs> Stream for ASignalStream<S> {
+    type Item = S;
+    type Error = ();
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.inner.poll()? {
+                Async::Ready(Some(m)) => if let Some(s) = S::from_message(&m) { return Ok(Async::Ready(Some(s))) },
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
 #[test]
 fn aconnection_test() {
     let conn = Rc::new(Connection::get_private(::dbus::BusType::Session).unwrap());
@@ -304,6 +483,33 @@ fn aconnection_test() {
     assert!(z.iter().any(|v| *v == "org.freedesktop.DBus"));
 }
 
+#[test]
+fn amethod_call_error_test() {
+    let conn = Rc::new(Connection::get_private(::dbus::BusType::Session).unwrap());
+    let mut rt = Runtime::new().unwrap();
+    let aconn = AConnection::new(conn.clone(), CoreHandle::current(), &mut rt).unwrap();
+
+    let m = ::dbus::Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetNameOwner")
+        .unwrap().append1("com.example.dbustokio.nobody");
+    let e = rt.block_on(aconn.method_call(m).unwrap()).unwrap_err();
+    assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.NameHasNoOwner"));
+}
+
+#[test]
+fn amethod_calls_test() {
+    let conn = Rc::new(Connection::get_private(::dbus::BusType::Session).unwrap());
+    let mut rt = Runtime::new().unwrap();
+    let aconn = AConnection::new(conn.clone(), CoreHandle::current(), &mut rt).unwrap();
+
+    let names = vec!(conn.unique_name(), "com.example.dbustokio.nobody".into(), "org.freedesktop.DBus".into());
+    let msgs = names.iter().map(|n| ::dbus::Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetNameOwner")
+        .unwrap().append1(n));
+    let replies = rt.block_on(aconn.method_calls(msgs).unwrap()).unwrap();
+    assert_eq!(replies[0].as_ref().unwrap().read1::<&str>().unwrap(), conn.unique_name());
+    assert_eq!(replies[1].as_ref().unwrap_err().name(), Some("org.freedesktop.DBus.Error.NameHasNoOwner"));
+    assert_eq!(replies[2].as_ref().unwrap().read1::<&str>().unwrap(), "org.freedesktop.DBus");
+}
+
 #[test]
 fn astream_test() {
     let conn = Rc::new(Connection::get_private(::dbus::BusType::Session).unwrap());
@@ -317,3 +523,57 @@ fn astream_test() {
     assert_eq!(firstsig.unwrap().msg_type(), ::dbus::MessageType::Signal);
 }
 
+
+#[test]
+fn await_name_test() {
+    let conn = Rc::new(Connection::get_private(::dbus::BusType::Session).unwrap());
+    let mut rt = Runtime::new().unwrap();
+    let aconn = AConnection::new(conn.clone(), CoreHandle::current(), &mut rt).unwrap();
+
+    let name = format!("com.example.dbustokio.waited{}", ::std::process::id());
+    let w = aconn.wait_for_name(&name).unwrap();
+    let c2 = Connection::get_private(::dbus::BusType::Session).unwrap();
+    c2.register_name(&name, 0).unwrap();
+    let owner = rt.block_on(w).unwrap();
+    assert_eq!(owner, c2.unique_name());
+}
+
+#[test]
+fn signal_stream_test() {
+    use dbus::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged as PC;
+    let conn = Rc::new(Connection::get_private(::dbus::BusType::Session).unwrap());
+    let mut rt = Runtime::new().unwrap();
+    let aconn = AConnection::new(conn.clone(), CoreHandle::current(), &mut rt).unwrap();
+
+    let c2 = Connection::get_private(::dbus::BusType::Session).unwrap();
+    let sender = c2.unique_name().into();
+    let signals = aconn.signal_stream::<PC>(Some(&sender), None).unwrap();
+    let mut pc = PC::default();
+    pc.interface_name = "com.example.dbustokio.Signals".into();
+    c2.send(pc.to_emit_message(&"/hello".into())).unwrap();
+    let first = rt.block_on(signals.into_future()).map(|(x, _)| x).map_err(|(x, _)| x).unwrap();
+    assert_eq!(first.unwrap().interface_name, "com.example.dbustokio.Signals");
+    assert!(conn.remove_match(&PC::match_str(Some(&sender), None)).is_err());
+}
+
+#[test]
+fn disconnected_test() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    let mut bus = Command::new("dbus-daemon").args(&["--session", "--nofork", "--nopidfile", "--address=unix:tmpdir=/tmp", "--print-address"])
+        .stdout(Stdio::piped()).spawn().expect("dbus-daemon not found");
+    let mut address = String::new();
+    BufReader::new(bus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    let conn = Rc::new(Connection::open_private(address.trim()).unwrap());
+    conn.register().unwrap();
+    let mut rt = Runtime::new().unwrap();
+    let aconn = AConnection::new(conn.clone(), CoreHandle::current(), &mut rt).unwrap();
+    let lost = aconn.disconnected();
+
+    bus.kill().unwrap();
+    bus.wait().unwrap();
+    rt.block_on(lost).unwrap();
+    assert!(!conn.is_connected());
+    // Already lost
+    rt.block_on(aconn.disconnected()).unwrap();
+}
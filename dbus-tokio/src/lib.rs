@@ -3,7 +3,11 @@
 //! What's currently working is:
 //!
 //!  * Client: Make method calls and wait asynchronously for them to be replied to - see `AConnection::method_call`
+//!  * Client: Send many method calls at once, and wait for all replies - see `AConnection::method_calls`
 //!  * Get a stream of incoming messages (so you can listen to signals etc) - see `AConnection::messages`
+//!  * Get a stream of the messages or signals matching a rule - see `AConnection::match_stream` and `AConnection::signal_stream`
+//!  * Client: Wait for a service to appear on the bus - see `AConnection::wait_for_name`
+//!  * Get notified when the connection is lost, e g to reconnect - see `AConnection::disconnected`
 //!  * Server: Make a tree handle that stream of incoming messages - see `tree::ATreeServer`
 //!  * Server: Add asynchronous methods to the tree - in case you cannot reply right away,
 //!    you can return a future that will reply when that future resolves - see `tree::AFactory::amethod`
@@ -22,4 +26,4 @@ pub mod tree;
 
 mod adriver;
 
-pub use adriver::{AConnection, AMessageStream, AMethodCall};
+pub use adriver::{AConnection, AMessageStream, AMatchStream, ASignalStream, AMethodCall, AMethodCalls, AWaitForName, ADisconnected};
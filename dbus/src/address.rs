@@ -0,0 +1,215 @@
+//! D-Bus server addresses, e g "unix:path=/run/dbus/system_bus_socket" or "tcp:host=localhost,port=4000".
+
+use std::{fmt, str};
+use crate::Error;
+
+const BAD_ADDRESS: &str = "org.freedesktop.DBus.Error.BadAddress";
+
+/// One D-Bus server address: a transport, and key/value pairs that tell how to connect with it.
+///
+/// Display formats the address with values escaped, so that it can be given to
+/// `Connection::open_private` or `TxRx::open_private`. libdbus does the connecting, for
+/// "tcp:" and "nonce-tcp:" addresses too, including reading the nonce file and sending the nonce.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    transport: String,
+    params: Vec<(String, String)>,
+}
+
+impl Address {
+    /// An address with no key/value pairs yet.
+    pub fn new(transport: &str) -> Self { Address { transport: transport.into(), params: vec!() } }
+
+    /// Adds a key/value pair, or replaces the value if the key is there already.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        match self.params.iter_mut().find(|p| p.0 == key) {
+            Some(p) => p.1 = value.into(),
+            None => self.params.push((key.into(), value.into())),
+        }
+        self
+    }
+
+    /// A "unix:path=" address, for a unix socket in the file system.
+    pub fn unix_path(path: &str) -> Self { Address::new("unix").with("path", path) }
+
+    /// A "unix:abstract=" address, for a unix socket in the abstract namespace (Linux only).
+    ///
+    /// The name is without the leading NUL byte that the abstract namespace uses.
+    pub fn unix_abstract(name: &str) -> Self { Address::new("unix").with("abstract", name) }
+
+    /// An "autolaunch:" address, which on Windows finds (or starts) the session bus for the scope.
+    ///
+    /// The scope is "*user" for a bus per user, "*install-path" for a bus per installation of
+    /// D-Bus, or any other string, and defaults to a bus shared by all users. On other platforms,
+    /// libdbus runs dbus-launch to find the bus of the X11 session.
+    pub fn autolaunch(scope: Option<&str>) -> Self {
+        let a = Address::new("autolaunch");
+        match scope { Some(s) => a.with("scope", s), None => a }
+    }
+
+    /// A "tcp:" address, to connect to a bus listening on a TCP port.
+    pub fn tcp(host: &str, port: u16) -> Self { Address::new("tcp").with("host", host).with("port", &port.to_string()) }
+
+    /// A "nonce-tcp:" address. The nonce file holds the secret that the bus wants to be
+    /// sent first, and must be readable by the client only.
+    pub fn nonce_tcp(host: &str, port: u16, noncefile: &str) -> Self {
+        Address::new("nonce-tcp").with("host", host).with("port", &port.to_string()).with("noncefile", noncefile)
+    }
+
+    /// The transport, e g "tcp".
+    pub fn transport(&self) -> &str { &self.transport }
+
+    /// The (unescaped) value of a key.
+    pub fn get(&self, key: &str) -> Option<&str> { self.params.iter().find(|p| p.0 == key).map(|p| &*p.1) }
+
+    /// The key/value pairs, in order.
+    pub fn params(&self) -> &[(String, String)] { &self.params }
+
+    /// Parses a list of addresses separated by ";", like in DBUS_SESSION_BUS_ADDRESS.
+    pub fn parse_list(s: &str) -> Result<Vec<Address>, Error> {
+        s.split(';').filter(|x| !x.is_empty()).map(str::parse).collect()
+    }
+
+    /// Checks the keys that the "unix:", "autolaunch:", "tcp:" and "nonce-tcp:" transports use.
+    ///
+    /// Other transports are not checked here, libdbus checks them when connecting.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.transport == "unix" {
+            // Only one of these tells where the socket is.
+            let n = ["path", "abstract", "tmpdir", "dir", "runtime"].iter().filter(|k| self.get(k).is_some()).count();
+            if n != 1 { return Err(bad_address("unix address needs exactly one of path, abstract, tmpdir, dir or runtime")) }
+            if self.get("runtime").map_or(false, |r| r != "yes") { return Err(bad_address("runtime must be \"yes\"")) }
+            return Ok(())
+        }
+        if self.transport == "autolaunch" {
+            if let Some((k, _)) = self.params.iter().find(|p| p.0 != "scope" && p.0 != "guid") {
+                return Err(bad_address(&format!("Unknown key {:?} for autolaunch", k)))
+            }
+            return Ok(())
+        }
+        if self.transport != "tcp" && self.transport != "nonce-tcp" { return Ok(()) }
+        if let Some(p) = self.get("port") {
+            p.parse::<u16>().map_err(|_| bad_address(&format!("Invalid port {:?}", p)))?;
+        }
+        match self.get("family") {
+            None | Some("ipv4") | Some("ipv6") => {},
+            Some(f) => return Err(bad_address(&format!("Invalid address family {:?}", f))),
+        }
+        if self.transport == "nonce-tcp" && self.get("noncefile").is_none() {
+            return Err(bad_address("nonce-tcp address without noncefile"));
+        }
+        Ok(())
+    }
+}
+
+fn bad_address(msg: &str) -> Error { Error::new_custom(BAD_ADDRESS, msg) }
+
+fn unescape(s: &str) -> Result<String, Error> {
+    let b = s.as_bytes();
+    let mut r = vec!();
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'%' {
+            let hex = b.get(i+1..i+3).and_then(|h| str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+            r.push(hex.ok_or_else(|| bad_address(&format!("Invalid escape in {:?}", s)))?);
+            i += 3;
+        } else { r.push(b[i]); i += 1; }
+    }
+    String::from_utf8(r).map_err(|_| bad_address(&format!("Value {:?} is not UTF-8", s)))
+}
+
+fn escape(s: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    for &c in s.as_bytes() {
+        match c {
+            b'-' | b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'_' | b'/' | b'.' | b'\\' | b'*' => write!(f, "{}", c as char)?,
+            _ => write!(f, "%{:02x}", c)?,
+        }
+    }
+    Ok(())
+}
+
+impl str::FromStr for Address {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let colon = s.find(':').ok_or_else(|| bad_address(&format!("Address {:?} has no transport", s)))?;
+        let mut a = Address::new(&s[..colon]);
+        if a.transport.is_empty() { return Err(bad_address(&format!("Address {:?} has no transport", s))) }
+        for kv in s[colon+1..].split(',').filter(|x| !x.is_empty()) {
+            let eq = kv.find('=').ok_or_else(|| bad_address(&format!("Missing value for {:?}", kv)))?;
+            let (k, v) = (&kv[..eq], unescape(&kv[eq+1..])?);
+            if k.is_empty() { return Err(bad_address(&format!("Missing key in {:?}", kv))) }
+            if a.get(k).is_some() { return Err(bad_address(&format!("Key {:?} given twice", k))) }
+            a.params.push((k.into(), v));
+        }
+        a.check()?;
+        Ok(a)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.transport)?;
+        for (i, (k, v)) in self.params.iter().enumerate() {
+            if i > 0 { write!(f, ",")? }
+            write!(f, "{}=", k)?;
+            escape(v, f)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_address() {
+    let a: Address = "tcp:host=localhost,port=4000,family=ipv4".parse().unwrap();
+    assert_eq!(a.transport(), "tcp");
+    assert_eq!(a.get("port"), Some("4000"));
+    assert_eq!(a, Address::tcp("localhost", 4000).with("family", "ipv4"));
+    assert_eq!(a.to_string(), "tcp:host=localhost,port=4000,family=ipv4");
+
+    let a = Address::nonce_tcp("10.0.0.1", 5000, "/tmp/dbus nonce");
+    assert_eq!(a.to_string(), "nonce-tcp:host=10.0.0.1,port=5000,noncefile=/tmp/dbus%20nonce");
+    assert_eq!(a.to_string().parse::<Address>().unwrap(), a);
+
+    let l = Address::parse_list("unix:path=/run/dbus/bus;tcp:host=example.com,port=1234;").unwrap();
+    assert_eq!(l.len(), 2);
+    assert_eq!(l[0].get("path"), Some("/run/dbus/bus"));
+
+    let a: Address = "unix:abstract=/tmp/dbus-%00x".parse().unwrap();
+    assert_eq!(a.get("abstract"), Some("/tmp/dbus-\0x"));
+    assert_eq!(a, Address::unix_abstract("/tmp/dbus-\0x"));
+    assert_eq!(Address::unix_abstract("dbus test").to_string(), "unix:abstract=dbus%20test");
+
+    assert_eq!("autolaunch:".parse::<Address>().unwrap(), Address::autolaunch(None));
+    assert_eq!(Address::autolaunch(Some("*user")).to_string(), "autolaunch:scope=*user");
+    assert_eq!("autolaunch:scope=*install-path".parse::<Address>().unwrap().get("scope"), Some("*install-path"));
+
+    for bad in &["tcp", ":host=x", "unix:", "autolaunch:path=x", "unix:path=/a,abstract=b", "unix:runtime=no", "tcp:host", "tcp:port=99999", "tcp:family=ipx", "nonce-tcp:host=x,port=1", "tcp:host=%zz", "tcp:host=a,host=b"] {
+        let e = bad.parse::<Address>().unwrap_err();
+        assert_eq!(e.name(), Some(BAD_ADDRESS), "{}", bad);
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn abstract_socket() {
+    use crate::{Connection, Server};
+    let name = format!("dbus-rs-test-{}", ::std::process::id());
+    let server = Server::listen(&Address::unix_abstract(&name).to_string()).unwrap();
+    let a: Address = server.address().parse().unwrap();
+    assert_eq!(a.get("abstract"), Some(&*name));
+    let c = Connection::open_peer(&server.address()).unwrap();
+    assert!(c.is_connected());
+    assert!(server.accept(2000).unwrap().is_some());
+}
+
+#[test]
+fn connect_tcp() {
+    use std::net::TcpListener;
+    // Nothing listens on the port, so libdbus fails to connect, but it should understand the address.
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = l.local_addr().unwrap().port();
+    drop(l);
+    let e = crate::TxRx::open_private(&Address::tcp("127.0.0.1", port).to_string()).unwrap_err();
+    assert!(e.name().is_some());
+    assert_ne!(e.name(), Some(BAD_ADDRESS));
+}
@@ -1,12 +1,15 @@
 use super::*;
-use {Signature, Path, Message, ffi, OwnedFd};
+use {Signature, Path, Message, ffi};
+#[cfg(unix)]
+use OwnedFd;
 use std::marker::PhantomData;
 use std::{ptr, mem, any, fmt};
 use super::check;
 use std::ffi::{CString};
 use std::os::raw::{c_void, c_int};
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use std::hash::Hash;
+use std::iter::FromIterator;
 
 // Map DBus-Type -> Alignment. Copied from _dbus_marshal_write_fixed_multi in
 // http://dbus.freedesktop.org/doc/api/html/dbus-marshal-basic_8c_source.html#l01020
@@ -180,11 +183,14 @@ impl<K: DictKey + Append + Eq + Hash, V: Arg + Append> Append for HashMap<K, V>
     }
 }
 
+// Reads a dict into a map, if the entries have the signature of (K, V).
+fn get_dict<'a, K: DictKey + Get<'a>, V: Arg + Get<'a>, C: FromIterator<(K, V)>>(i: &mut Iter<'a>) -> Option<C> {
+    if i.signature() != <Dict<K, V, Iter>>::signature() { return None }
+    Dict::get(i).map(|d: Dict<K, V, Iter>| d.collect())
+}
+
 impl<'a, K: DictKey + Get<'a> + Eq + Hash, V: Arg + Get<'a>> Get<'a> for HashMap<K, V> {
-    fn get(i: &mut Iter<'a>) -> Option<Self> {
-        // TODO: Full element signature is not verified.
-        Dict::get(i).map(|d| d.into_iter().collect())
-    }
+    fn get(i: &mut Iter<'a>) -> Option<Self> { get_dict(i) }
 }
 
 impl<K: DictKey + RefArg + Eq + Hash, V: RefArg + Arg> RefArg for HashMap<K, V> {
@@ -215,6 +221,51 @@ impl<K: DictKey + RefArg + Eq + Hash, V: RefArg + Arg> RefArg for HashMap<K, V>
     }
 }
 
+/// BTreeMaps are D-Bus dicts, like HashMaps, with the entries appended in key order.
+impl<K: DictKey, V: Arg> Arg for BTreeMap<K, V> {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> {
+        Signature::from(format!("a{{{}{}}}", K::signature(), V::signature())) }
+}
+
+impl<K: DictKey + Append + Ord, V: Arg + Append> Append for BTreeMap<K, V> {
+    fn append(self, i: &mut IterAppend) {
+        Dict::new(self.into_iter()).append(i);
+    }
+}
+
+impl<'a, K: DictKey + Get<'a> + Ord, V: Arg + Get<'a>> Get<'a> for BTreeMap<K, V> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> { get_dict(i) }
+}
+
+impl<K: DictKey + RefArg + Ord, V: RefArg + Arg> RefArg for BTreeMap<K, V> {
+    fn arg_type(&self) -> ArgType { ArgType::Array }
+    fn signature(&self) -> Signature<'static> { format!("a{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature()).into() }
+    fn append(&self, i: &mut IterAppend) {
+        let sig = CString::new(format!("{{{}{}}}", <K as Arg>::signature(), <V as Arg>::signature())).unwrap();
+        i.append_container(ArgType::Array, Some(&sig), |s| for (k, v) in self {
+            s.append_container(ArgType::DictEntry, None, |ss| {
+                k.append(ss);
+                v.append(ss);
+            })
+        });
+    }
+    #[inline]
+    fn as_any(&self) -> &any::Any where Self: 'static { self }
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut any::Any where Self: 'static { self }
+    fn as_iter<'b>(&'b self) -> Option<Box<Iterator<Item=&'b RefArg> + 'b>> {
+        Some(Box::new(self.iter().flat_map(|(k, v)| vec![k as &RefArg, v as &RefArg].into_iter())))
+    }
+    #[inline]
+    fn box_clone(&self) -> Box<RefArg + 'static> {
+        Box::new(InternalDict {
+            outer_sig: self.signature(),
+            data: self.iter().map(|(k, v)| (k.box_clone(), v.box_clone())).collect(),
+        })
+    }
+}
+
 impl<T: Arg> Arg for Vec<T> {
     const ARG_TYPE: ArgType = ArgType::Array;
     fn signature() -> Signature<'static> { Signature::from(format!("a{}", T::signature())) }
@@ -326,7 +377,8 @@ fn get_dict_refarg<'a, K, F: FnMut(&mut Iter<'a>) -> Option<K>>(i: &mut Iter<'a>
     while let Some(mut d) = si.recurse(ArgType::DictEntry) {
         let k = f(&mut d).unwrap();
         d.next();
-        data.push((k, d.get_refarg().unwrap()));
+        // Values that cannot be read on this platform (file descriptors) are skipped.
+        if let Some(v) = d.get_refarg() { data.push((k, v)) }
         si.next();
     }
     Box::new(InternalDict { data, outer_sig })
@@ -433,7 +485,8 @@ impl RefArg for InternalArray {
     }
 }
 
-pub fn get_array_refarg<'a>(i: &mut Iter<'a>) -> Box<RefArg> {
+/// Returns None for arrays of file descriptors where libdbus cannot pass them.
+pub fn get_array_refarg<'a>(i: &mut Iter<'a>) -> Option<Box<RefArg>> {
     debug_assert!(i.arg_type() == ArgType::Array);
     let etype = ArgType::from_i32(unsafe { ffi::dbus_message_iter_get_element_type(&mut i.0) } as i32).unwrap();
 
@@ -465,19 +518,26 @@ pub fn get_array_refarg<'a>(i: &mut Iter<'a>) -> Box<RefArg> {
                 ArgType::UInt64 => get_dict_refarg::<u64, _>(i, |si| si.get()),
                 ArgType::Double => get_dict_refarg::<f64, _>(i, |si| si.get()),
                 ArgType::Boolean => get_dict_refarg::<bool, _>(i, |si| si.get()),
-                // ArgType::UnixFd => get_dict_refarg::<OwnedFd, _>(i, |si| si.get()),
+                #[cfg(unix)]
+                ArgType::UnixFd => get_dict_refarg::<OwnedFd, _>(i, |si| si.get()),
+                #[cfg(not(unix))]
+                ArgType::UnixFd => return None,
                 ArgType::String => get_dict_refarg::<String, _>(i, |si| si.get()),
                 ArgType::ObjectPath => get_dict_refarg::<Path<'static>, _>(i, |si| si.get::<Path>().map(|s| s.into_static())),
                 ArgType::Signature => get_dict_refarg::<Signature<'static>, _>(i, |si| si.get::<Signature>().map(|s| s.into_static())),
                 _ => panic!("Array with invalid dictkey ({:?})", key),
             }
         }
+        #[cfg(unix)]
         ArgType::UnixFd => get_var_array_refarg::<OwnedFd, _>(i, |si| si.get()),
+        // libdbus only passes file descriptors on unix.
+        #[cfg(not(unix))]
+        ArgType::UnixFd => return None,
         ArgType::Struct => get_internal_array(i),
     };
 
     debug_assert_eq!(i.signature(), x.signature());
-    x
+    Some(x)
 }
 
 
@@ -1,7 +1,9 @@
 use ffi;
 use super::*;
 use super::check;
-use {Signature, Path, OwnedFd};
+use {Signature, Path};
+#[cfg(unix)]
+use OwnedFd;
 use std::{ptr, any, mem};
 use std::ffi::CStr;
 use std::os::raw::{c_void, c_char, c_int};
@@ -234,25 +236,78 @@ impl<'a> Get<'a> for &'a CStr {
     fn get(i: &mut Iter<'a>) -> Option<&'a CStr> { unsafe { arg_get_str(&mut i.0, Self::ARG_TYPE) }}
 }
 
+#[cfg(unix)]
 impl Arg for OwnedFd {
     const ARG_TYPE: ArgType = ArgType::UnixFd;
     fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"h\0") } }
 }
+#[cfg(unix)]
 impl Append for OwnedFd {
     fn append(self, i: &mut IterAppend) {
         use std::os::unix::io::AsRawFd;
         arg_append_basic(&mut i.0, ArgType::UnixFd, self.as_raw_fd())
     }
 }
+#[cfg(unix)]
 impl DictKey for OwnedFd {}
+#[cfg(unix)]
 impl<'a> Get<'a> for OwnedFd {
     fn get(i: &mut Iter) -> Option<Self> {
         arg_get_basic(&mut i.0, ArgType::UnixFd).map(|q| OwnedFd::new(q)) 
     }
 }
 
+#[cfg(unix)]
 refarg_impl!(OwnedFd, _i, { use std::os::unix::io::AsRawFd; Some(_i.as_raw_fd() as i64) }, None, None, None);
 
+// libdbus duplicates file descriptors when appending them, so the originals are closed as usual.
+#[cfg(unix)]
+impl Arg for ::std::fs::File {
+    const ARG_TYPE: ArgType = ArgType::UnixFd;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"h\0") } }
+}
+#[cfg(unix)]
+impl Append for ::std::fs::File {
+    fn append(self, i: &mut IterAppend) {
+        use std::os::unix::io::AsRawFd;
+        arg_append_basic(&mut i.0, ArgType::UnixFd, self.as_raw_fd())
+    }
+}
+#[cfg(unix)]
+impl<'a> Get<'a> for ::std::fs::File {
+    fn get(i: &mut Iter) -> Option<Self> { OwnedFd::get(i).map(Into::into) }
+}
+
+#[cfg(unix)]
+impl Arg for ::std::os::unix::io::OwnedFd {
+    const ARG_TYPE: ArgType = ArgType::UnixFd;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"h\0") } }
+}
+#[cfg(unix)]
+impl Append for ::std::os::unix::io::OwnedFd {
+    fn append(self, i: &mut IterAppend) {
+        use std::os::unix::io::AsRawFd;
+        arg_append_basic(&mut i.0, ArgType::UnixFd, self.as_raw_fd())
+    }
+}
+#[cfg(unix)]
+impl<'a> Get<'a> for ::std::os::unix::io::OwnedFd {
+    fn get(i: &mut Iter) -> Option<Self> { OwnedFd::get(i).map(Into::into) }
+}
+
+#[cfg(unix)]
+impl<'a> Arg for ::std::os::unix::io::BorrowedFd<'a> {
+    const ARG_TYPE: ArgType = ArgType::UnixFd;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"h\0") } }
+}
+#[cfg(unix)]
+impl<'a> Append for ::std::os::unix::io::BorrowedFd<'a> {
+    fn append(self, i: &mut IterAppend) {
+        use std::os::unix::io::AsRawFd;
+        arg_append_basic(&mut i.0, ArgType::UnixFd, self.as_raw_fd())
+    }
+}
+
 macro_rules! string_impl {
     ($t: ident, $s: ident, $f: expr) => {
 
@@ -25,6 +25,8 @@
 //!
 //! `Dict<K, V, I> where K: Append + DictKey, V: Append, I: Iterator<Item=(&K, &V)>` - A D-Bus dict (array of dict entries).
 //!
+//! `Vec<T>`, `HashMap<K, V>`, `BTreeMap<K, V>` - a D-Bus array, or a D-Bus dict where K is a basic type.
+//!
 //! `Path` - a D-Bus object path.
 //!
 //! `Signature` - a D-Bus signature.
@@ -51,24 +53,43 @@
 //! `Dict<K, V, Iter> where K: Get + DictKey, V: Get` - A D-Bus dict (array of dict entries). Implements Iterator so you can easily
 //! collect it into, e g, a `HashMap`.
 //!
+//! `Vec<T>`, `HashMap<K, V>`, `BTreeMap<K, V>` - a D-Bus array, or a D-Bus dict. A map is only read
+//! if the signature of the dict matches K and V.
+//!
 //! `Path` - a D-Bus object path.
 //!
 //! `Signature` - a D-Bus signature.
 //!
 //! `OwnedFd` - a file descriptor sent from the remote side.
 //!
+//! `PropMap` - a dict of properties ("a{sv}"), with typed getters for its values.
+//!
+//! **Serde**:
+//!
+//! With the "serde" feature, `Serde<T>` appends and gets any `T: Serialize + Deserialize`,
+//! e g a struct with derived impls, as a D-Bus struct. `serialize`, `deserialize` and
+//! `serde_signature` do the same with errors instead of panics or `None`.
+//!
 
 mod msgarg;
 mod basic_impl;
 mod variantstruct_impl;
 mod array_impl;
+mod propmap;
+#[cfg(feature = "serde")]
+mod serde_impl;
 
-pub use self::msgarg::{Arg, FixedArray, Get, DictKey, Append, RefArg, cast, cast_mut};
+pub use self::msgarg::{Arg, FixedArray, Get, DictKey, Append, RefArg, AppendAll, ReadAll, cast, cast_mut};
 pub use self::array_impl::{Array, Dict};
 pub use self::variantstruct_impl::Variant;
+pub use self::propmap::PropMap;
+#[cfg(feature = "serde")]
+pub use self::serde_impl::{Serde, serialize, deserialize, serde_signature};
 
 use std::{fmt, mem, ptr, error};
-use {ffi, Message, Signature, Path, OwnedFd};
+use {ffi, Message, Signature, Path};
+#[cfg(unix)]
+use OwnedFd;
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_void, c_int};
 
@@ -77,6 +98,22 @@ fn check(f: &str, i: u32) { if i == 0 { panic!("D-Bus error: '{}' failed", f) }}
 
 fn ffi_iter() -> ffi::DBusMessageIter { unsafe { mem::zeroed() }} 
 
+// Appends an argument to a scratch message, and reads it back, e g as another type than it was appended as.
+fn read_appended<T, A: FnOnce(&mut IterAppend), R: FnOnce(&mut Iter) -> Result<T, TypeMismatchError>>(append: A, read: R) -> Result<T, TypeMismatchError> {
+    let mut m = Message::new_signal("/", "org.freedesktop.DBus.Properties", "Scratch").unwrap();
+    append(&mut IterAppend::new(&mut m));
+    read(&mut m.iter_init())
+}
+
+// Like Iter::read, but fails unless the argument has the full signature of T. Getting an array or dict
+// only checks the outer type, and stops at the first element of another type.
+fn read_exact<'a, T: Arg + Get<'a>>(i: &mut Iter<'a>) -> Result<T, TypeMismatchError> {
+    if i.signature() != T::signature() {
+        return Err(TypeMismatchError { expected: T::ARG_TYPE, found: i.arg_type(), position: i.2 })
+    }
+    i.read()
+}
+
 #[derive(Clone, Copy)]
 /// Helper struct for appending one or more arguments to a Message. 
 pub struct IterAppend<'a>(ffi::DBusMessageIter, &'a Message);
@@ -187,8 +224,8 @@ impl<'a> Iter<'a> {
     /// paths are `Path<'static>`, signatures are `Signature<'static>`, Int32 are `i32s` and so on.
     pub fn get_refarg(&mut self) -> Option<Box<RefArg + 'static>> {
         Some(match self.arg_type() {
-	    ArgType::Array => array_impl::get_array_refarg(self),
-	    ArgType::Variant => Box::new(Variant::new_refarg(self).unwrap()),
+	    ArgType::Array => return array_impl::get_array_refarg(self),
+	    ArgType::Variant => Box::new(Variant::new_refarg(self)?),
 	    ArgType::Boolean => Box::new(self.get::<bool>().unwrap()),
 	    ArgType::Invalid => return None,
 	    ArgType::String => Box::new(self.get::<String>().unwrap()),
@@ -201,7 +238,11 @@ impl<'a> Iter<'a> {
 	    ArgType::Int64 => Box::new(self.get::<i64>().unwrap()),
 	    ArgType::UInt64 => Box::new(self.get::<u64>().unwrap()),
 	    ArgType::Double => Box::new(self.get::<f64>().unwrap()),
+	    #[cfg(unix)]
 	    ArgType::UnixFd => Box::new(self.get::<OwnedFd>().unwrap()),
+	    // libdbus only passes file descriptors on unix.
+	    #[cfg(not(unix))]
+	    ArgType::UnixFd => return None,
 	    ArgType::Struct => Box::new(self.recurse(ArgType::Struct).unwrap().collect::<Vec<_>>()),
 	    ArgType::ObjectPath => Box::new(self.get::<Path>().unwrap().into_static()),
 	    ArgType::Signature => Box::new(self.get::<Signature>().unwrap().into_static()),
@@ -5,7 +5,7 @@ use std::{fmt, any};
 use std::sync::Arc;
 use std::rc::Rc;
 
-use super::{Iter, IterAppend, ArgType};
+use super::{Iter, IterAppend, ArgType, PropMap, read_appended, read_exact};
 
 /// Types that can represent a D-Bus message argument implement this trait.
 ///
@@ -81,6 +81,22 @@ pub trait RefArg: fmt::Debug {
     /// Works for: Array/Dict, Struct, Variant.
     #[inline]
     fn as_iter<'a>(&'a self) -> Option<Box<Iterator<Item=&'a RefArg> + 'a>> { None }
+    /// Try to read the argument as a Vec of strings.
+    ///
+    /// Works for: Array of String, e g the value of a property of type "as".
+    fn as_strs(&self) -> Option<Vec<String>> { read_appended(|i| self.append(i), |i| read_exact(i)).ok() }
+    /// Try to read the argument as a Vec of bytes.
+    ///
+    /// Works for: Array of Byte.
+    fn as_byte_array(&self) -> Option<Vec<u8>> { read_appended(|i| self.append(i), |i| read_exact(i)).ok() }
+    /// Try to read the argument as a dict of properties.
+    ///
+    /// Works for: Dict of String to Variant ("a{sv}").
+    fn as_props(&self) -> Option<PropMap> { read_appended(|i| self.append(i), |i| read_exact(i)).ok() }
+    /// Try to read the argument as a Vec of string pairs.
+    ///
+    /// Works for: Array of Struct of two Strings ("a(ss)").
+    fn as_str_pairs(&self) -> Option<Vec<(String, String)>> { read_appended(|i| self.append(i), |i| read_exact(i)).ok() }
     /// Deep clone of the RefArg, causing the result to be 'static.
     ///
     /// Usable as an escape hatch in case of lifetime problems with RefArg.
@@ -115,6 +131,22 @@ pub unsafe trait FixedArray: Arg + 'static + Clone + Copy {}
 /// Types that can be used as keys in a dict type implement this trait. 
 pub trait DictKey: Arg {}
 
+/// Tuples of arguments, appended to a message one after another (rather than as a struct).
+///
+/// Implemented for tuples of up to eight elements, and `()` for no arguments.
+pub trait AppendAll: Sized {
+    /// Appends all arguments.
+    fn append(self, i: &mut IterAppend);
+}
+
+/// Tuples of arguments, read from a message one after another (rather than as a struct).
+///
+/// Implemented for tuples of up to eight elements, and `()` for no arguments.
+pub trait ReadAll: Sized {
+    /// Reads all arguments.
+    fn read(i: &mut Iter) -> Result<Self, super::TypeMismatchError>;
+}
+
 
 
 /// Simple lift over reference to value - this makes some iterators more ergonomic to use
@@ -147,6 +179,14 @@ impl<'a, T: RefArg + ?Sized> RefArg for &'a T {
     #[inline]
     fn as_str(&self) -> Option<&str> { (&**self).as_str() }
     #[inline]
+    fn as_strs(&self) -> Option<Vec<String>> { (&**self).as_strs() }
+    #[inline]
+    fn as_byte_array(&self) -> Option<Vec<u8>> { (&**self).as_byte_array() }
+    #[inline]
+    fn as_props(&self) -> Option<PropMap> { (&**self).as_props() }
+    #[inline]
+    fn as_str_pairs(&self) -> Option<Vec<(String, String)>> { (&**self).as_str_pairs() }
+    #[inline]
     fn as_iter<'b>(&'b self) -> Option<Box<Iterator<Item=&'b RefArg> + 'b>> { (&**self).as_iter() }
     #[inline]
     fn box_clone(&self) -> Box<RefArg + 'static> { (&**self).box_clone() }
@@ -177,6 +217,14 @@ impl<T: RefArg + ?Sized> RefArg for $t<T> {
     #[inline]
     fn as_str(&self) -> Option<&str> { (&**self).as_str() }
     #[inline]
+    fn as_strs(&self) -> Option<Vec<String>> { (&**self).as_strs() }
+    #[inline]
+    fn as_byte_array(&self) -> Option<Vec<u8>> { (&**self).as_byte_array() }
+    #[inline]
+    fn as_props(&self) -> Option<PropMap> { (&**self).as_props() }
+    #[inline]
+    fn as_str_pairs(&self) -> Option<Vec<(String, String)>> { (&**self).as_str_pairs() }
+    #[inline]
     fn as_iter<'a>(&'a self) -> Option<Box<Iterator<Item=&'a RefArg> + 'a>> { (&**self).as_iter() }
     #[inline]
     fn box_clone(&self) -> Box<RefArg + 'static> { (&**self).box_clone() }
@@ -202,6 +250,37 @@ deref_impl!(Box, self, &mut **self );
 deref_impl!(Rc, self, Rc::get_mut(self).unwrap());
 deref_impl!(Arc, self, Arc::get_mut(self).unwrap());
 
+macro_rules! all_impl {
+    ($($n: ident $t: ident),*) => {
+
+impl<$($t: Append),*> AppendAll for ($($t,)*) {
+    #[allow(unused_variables)]
+    fn append(self, i: &mut IterAppend) {
+        let ($($n,)*) = self;
+        $( i.append($n); )*
+    }
+}
+
+impl<$($t: Arg + for<'z> Get<'z>),*> ReadAll for ($($t,)*) {
+    #[allow(unused_variables)]
+    fn read(i: &mut Iter) -> Result<Self, super::TypeMismatchError> {
+        Ok(($( i.read::<$t>()?, )*))
+    }
+}
+
+    }
+}
+
+all_impl!();
+all_impl!(a A);
+all_impl!(a A, b B);
+all_impl!(a A, b B, c C);
+all_impl!(a A, b B, c C, d D);
+all_impl!(a A, b B, c C, d D, e E);
+all_impl!(a A, b B, c C, d D, e E, f F);
+all_impl!(a A, b B, c C, d D, e E, f F, g G);
+all_impl!(a A, b B, c C, d D, e E, f F, g G, h H);
+
 #[cfg(test)]
 mod test {
     extern crate tempdir;
@@ -333,4 +412,51 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn maps() {
+        use std::collections::BTreeMap;
+        use arg::Arg;
+        let mut b = BTreeMap::new();
+        b.insert("b".to_string(), vec!(1u8, 2));
+        b.insert("a".into(), vec!());
+        let mut h = HashMap::new();
+        h.insert(7u32, Variant(true));
+        assert_eq!(&*<BTreeMap<String, Vec<u8>> as Arg>::signature(), "a{say}");
+
+        let m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap().append3(b.clone(), h.clone(), Dict::new(vec!((1u16, "x"))));
+        let (b2, h2): (BTreeMap<String, Vec<u8>>, HashMap<u32, Variant<bool>>) = m.read2().unwrap();
+        assert_eq!((b2, h2), (b, h));
+        let mut i = m.iter_init();
+        assert_eq!(&*i.signature(), "a{say}");
+        assert_eq!(i.get::<BTreeMap<String, String>>(), None);
+        assert!(i.next() && i.next());
+        // Not a HashMap<u16, u16>, even though the first element is a u16
+        assert_eq!(i.get::<HashMap<u16, u16>>(), None);
+        assert_eq!(i.get::<BTreeMap<u16, &str>>().unwrap()[&1], "x");
+    }
+
+    #[test]
+    fn container_casts() {
+        let mut props: HashMap<String, Variant<Box<RefArg>>> = HashMap::new();
+        props.insert("Id".into(), Variant(Box::new(5u32)));
+        let m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap()
+            .append3(Variant(vec!("a", "b")), Variant(&[1u8, 2][..]), Variant(props))
+            .append2(Variant(vec!(("k".to_string(), "v".to_string()))), vec!(7i32));
+        let v: Vec<Box<RefArg>> = m.iter_init().collect();
+        let vs: Vec<&Variant<Box<RefArg>>> = v[..4].iter().map(|a| cast(a).unwrap()).collect();
+        assert_eq!(vs[0].as_strs(), Some(vec!("a".to_string(), "b".into())));
+        assert_eq!(vs[1].as_byte_array(), Some(vec!(1, 2)));
+        assert_eq!(vs[2].as_props().unwrap().get::<u32>("Id").unwrap(), Some(5));
+        assert_eq!(vs[3].as_str_pairs(), Some(vec!(("k".into(), "v".into()))));
+        // Also through Box<RefArg>, and the inner value
+        assert_eq!(v[0].as_strs().map(|s| s.len()), Some(2));
+        assert_eq!(vs[1].0.as_byte_array(), Some(vec!(1, 2)));
+
+        assert_eq!(vs[0].as_byte_array(), None);
+        assert_eq!(vs[1].as_strs(), None);
+        assert!(vs[3].as_props().is_none());
+        assert_eq!(v[4].as_byte_array(), None);
+        assert_eq!(v[4].as_strs(), None);
+    }
 }
@@ -0,0 +1,137 @@
+use super::{Arg, Append, Get, Iter, IterAppend, RefArg, Variant, TypeMismatchError, ArgType, read_appended, read_exact};
+use {Signature, Path};
+use std::collections::HashMap;
+use std::ops;
+
+/// A dict of properties, i e the "a{sv}" returned by `Properties.GetAll` and
+/// `ObjectManager.GetManagedObjects`, with typed getters for its values.
+///
+/// ```rust,no_run
+/// # use dbus::{Connection, BusType, Message};
+/// # use dbus::arg::PropMap;
+/// # let c = Connection::get_private(BusType::System).unwrap();
+/// let m = Message::new_method_call("org.freedesktop.NetworkManager", "/org/freedesktop/NetworkManager",
+///     "org.freedesktop.DBus.Properties", "GetAll").unwrap().append1("org.freedesktop.NetworkManager");
+/// let props: PropMap = c.send_with_reply_and_block(m, 2000).unwrap().read1().unwrap();
+/// let version: Option<String> = props.get("Version").unwrap();
+/// let devices = props.get_paths("Devices").unwrap().unwrap_or_default();
+/// let state: u32 = props.get("State").unwrap().unwrap_or(0);
+/// ```
+///
+/// The getters return `Ok(None)` if the property is missing, and a `TypeMismatchError` if it has
+/// another type than the one asked for. The position of the error is always 0. The map itself is
+/// available through `Deref`, e g for iterating over all properties.
+#[derive(Debug, Default)]
+pub struct PropMap(pub HashMap<String, Variant<Box<RefArg>>>);
+
+impl PropMap {
+    /// Creates an empty PropMap.
+    pub fn new() -> Self { Default::default() }
+
+    /// Gets the value of a property, if it is a T.
+    ///
+    /// T can be any type that does not borrow from the value, e g `u32`, `String`, `Vec<String>`
+    /// or a `HashMap`. Use `get_path` and `get_paths` for object paths.
+    pub fn get<T: Arg + for<'b> Get<'b>>(&self, key: &str) -> Result<Option<T>, TypeMismatchError> {
+        self.read(key, |i| read_exact(i))
+    }
+
+    /// Gets the value of a property, if it is a string.
+    pub fn get_str(&self, key: &str) -> Result<Option<String>, TypeMismatchError> { self.get(key) }
+
+    /// Gets the value of a property, if it is an array of strings.
+    pub fn get_strs(&self, key: &str) -> Result<Option<Vec<String>>, TypeMismatchError> { self.get(key) }
+
+    /// Gets the value of a property, if it is an object path.
+    pub fn get_path(&self, key: &str) -> Result<Option<Path<'static>>, TypeMismatchError> {
+        self.read(key, |i| i.read::<Path>().map(|p| p.into_static()))
+    }
+
+    /// Gets the value of a property, if it is an array of object paths.
+    pub fn get_paths(&self, key: &str) -> Result<Option<Vec<Path<'static>>>, TypeMismatchError> {
+        self.read(key, |i| read_exact::<Vec<Path>>(i).map(|v| v.into_iter().map(|p| p.into_static()).collect()))
+    }
+
+    /// Gets the value of a property as a u64, if it is of any unsigned integer type (or a byte).
+    pub fn get_u64(&self, key: &str) -> Result<Option<u64>, TypeMismatchError> {
+        self.read(key, |i| match i.arg_type() {
+            ArgType::Byte => i.read::<u8>().map(u64::from),
+            ArgType::UInt16 => i.read::<u16>().map(u64::from),
+            ArgType::UInt32 => i.read::<u32>().map(u64::from),
+            _ => i.read::<u64>(),
+        })
+    }
+
+    /// Gets the value of a property as an i64, if it is of any signed integer type.
+    pub fn get_i64(&self, key: &str) -> Result<Option<i64>, TypeMismatchError> {
+        self.read(key, |i| match i.arg_type() {
+            ArgType::Int16 => i.read::<i16>().map(i64::from),
+            ArgType::Int32 => i.read::<i32>().map(i64::from),
+            _ => i.read::<i64>(),
+        })
+    }
+
+    // The value is read back with the Get impls of the arg module.
+    fn read<T, F: FnOnce(&mut Iter) -> Result<T, TypeMismatchError>>(&self, key: &str, f: F) -> Result<Option<T>, TypeMismatchError> {
+        let v = match self.0.get(key) { Some(v) => v, None => return Ok(None) };
+        read_appended(|i| v.0.append(i), f).map(Some)
+    }
+}
+
+impl From<HashMap<String, Variant<Box<RefArg>>>> for PropMap {
+    fn from(m: HashMap<String, Variant<Box<RefArg>>>) -> Self { PropMap(m) }
+}
+
+impl ops::Deref for PropMap {
+    type Target = HashMap<String, Variant<Box<RefArg>>>;
+    fn deref(&self) -> &Self::Target { &self.0 }
+}
+
+impl ops::DerefMut for PropMap {
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
+}
+
+impl Arg for PropMap {
+    const ARG_TYPE: ArgType = ArgType::Array;
+    fn signature() -> Signature<'static> { unsafe { Signature::from_slice_unchecked(b"a{sv}\0") } }
+}
+
+impl<'a> Append for &'a PropMap {
+    fn append(self, i: &mut IterAppend) { RefArg::append(&self.0, i) }
+}
+
+impl Append for PropMap {
+    fn append(self, i: &mut IterAppend) { (&self).append(i) }
+}
+
+impl<'a> Get<'a> for PropMap {
+    fn get(i: &mut Iter<'a>) -> Option<Self> { i.get().map(PropMap) }
+}
+
+#[test]
+fn propmap() {
+    use Message;
+    let mut p = PropMap::new();
+    p.insert("Name".into(), Variant(Box::new("eth0".to_string())));
+    p.insert("Mtu".into(), Variant(Box::new(1500u32)));
+    p.insert("Paths".into(), Variant(Box::new(vec!(Path::new("/a").unwrap(), Path::new("/b").unwrap()))));
+    p.insert("Tags".into(), Variant(Box::new(vec!("x".to_string()))));
+
+    let m = Message::new_signal("/", "com.example.dbusrs", "Props").unwrap().append1(&p);
+    let p: PropMap = m.read1().unwrap();
+    assert_eq!(p.len(), 4);
+    assert_eq!(p.get_str("Name").unwrap().as_ref().map(|s| &**s), Some("eth0"));
+    assert_eq!(p.get::<u32>("Mtu").unwrap(), Some(1500));
+    assert_eq!(p.get_u64("Mtu").unwrap(), Some(1500));
+    assert_eq!(p.get::<u32>("Missing").unwrap(), None);
+    let e = p.get::<u16>("Mtu").unwrap_err();
+    assert_eq!((e.expected_arg_type(), e.found_arg_type()), (ArgType::UInt16, ArgType::UInt32));
+    assert!(p.get_i64("Mtu").is_err());
+    assert!(p.get_str("Mtu").is_err());
+    assert_eq!(p.get_paths("Paths").unwrap().unwrap(), vec!(Path::new("/a").unwrap(), Path::new("/b").unwrap()));
+    assert!(p.get_path("Paths").is_err());
+    assert_eq!(p.get_strs("Tags").unwrap().unwrap(), vec!("x".to_string()));
+    // Not an empty Vec, although the array is of another type
+    assert!(p.get::<Vec<u32>>("Tags").is_err());
+    assert!(p.get_strs("Paths").is_err());
+}
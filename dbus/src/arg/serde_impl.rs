@@ -0,0 +1,533 @@
+use super::{Append, Get, Iter, IterAppend, ArgType};
+use {Error, MessageItem, MessageItemArray, Signature};
+use serde::{ser, de, Serialize, Deserialize};
+use serde::de::{DeserializeOwned, IntoDeserializer, Visitor};
+use std::fmt;
+
+/// Appends and gets T with Serde, e g a struct with `#[derive(Serialize, Deserialize)]`.
+///
+/// Structs and tuples (and fixed size arrays) are D-Bus structs, sequences are arrays, maps are
+/// dicts, and enums with only unit variants are strings. Options, units and enum variants with
+/// data have no D-Bus type, and fail. As D-Bus needs to know the types of empty arrays up front,
+/// the signature is found from the `Deserialize` impl of T, see `serde_signature`.
+///
+/// Appending panics if T can not be represented in D-Bus; use `serialize` for an Err instead.
+/// Variants are read as their inner value, so e g a dict of variants can be read into a struct
+/// with a matching field type for every entry.
+///
+/// This needs the "serde" feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Serde<T>(pub T);
+
+impl<T: Serialize + DeserializeOwned> Append for Serde<T> {
+    fn append(self, i: &mut IterAppend) {
+        i.append(serialize(&self.0).unwrap_or_else(|e| panic!("Serde: {}", e)))
+    }
+}
+
+impl<'a, T: Deserialize<'a>> Get<'a> for Serde<T> {
+    fn get(i: &mut Iter<'a>) -> Option<Self> {
+        T::deserialize(Des { i }).ok().map(Serde)
+    }
+}
+
+fn serde_error(msg: &str) -> Error { Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", msg) }
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self { serde_error(&msg.to_string()) }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self { serde_error(&msg.to_string()) }
+}
+
+/// The D-Bus signature of T, with the types described in `Serde`.
+///
+/// This calls the `Deserialize` impl of T with made-up values, so it fails for types that
+/// do not accept zeroes and empty strings, or that deserialize differently depending on the
+/// input. Recursive types fail too, as they have no signature.
+pub fn serde_signature<T: DeserializeOwned>() -> Result<Signature<'static>, Error> {
+    let mut s = String::new();
+    T::deserialize(Probe { sig: &mut s, depth: 0 })?;
+    Signature::new(s).map_err(|e| serde_error(&e))
+}
+
+/// Serializes a value into a MessageItem, with the types described in `Serde`.
+pub fn serialize<T: Serialize + DeserializeOwned>(value: &T) -> Result<MessageItem, Error> {
+    let sig = serde_signature::<T>()?;
+    value.serialize(Ser { sig: &sig })
+}
+
+/// Deserializes the current argument, and moves to the next one, like `Iter::read`.
+pub fn deserialize<'a, T: Deserialize<'a>>(i: &mut Iter<'a>) -> Result<T, Error> {
+    let r = T::deserialize(Des { i })?;
+    i.next();
+    Ok(r)
+}
+
+// The length of the first complete type of a signature.
+fn first_type_len(sig: &str) -> usize {
+    let (mut len, mut depth) = (0, 0);
+    for c in sig.bytes() {
+        len += 1;
+        match c {
+            b'a' => continue,
+            b'(' | b'{' => depth += 1,
+            b')' | b'}' => depth -= 1,
+            _ => {},
+        }
+        if depth == 0 { break }
+    }
+    len
+}
+
+fn split_first(sig: &str) -> Result<(&str, &str), Error> {
+    let len = first_type_len(sig);
+    if len == 0 { return Err(serde_error("More values than in the signature")) }
+    Ok((&sig[..len], &sig[len..]))
+}
+
+// Serializes a value of this signature.
+struct Ser<'s> { sig: &'s str }
+
+impl<'s> Ser<'s> {
+    fn basic(&self, code: u8, item: MessageItem) -> Result<MessageItem, Error> {
+        if self.sig.as_bytes().first() == Some(&code) { return Ok(item) }
+        Err(serde_error(&format!("Serialized as '{}', but deserialized as '{}'", code as char, self.sig)))
+    }
+
+    fn starting(&self, prefix: &str) -> Result<&'s str, Error> {
+        if self.sig.starts_with(prefix) { return Ok(&self.sig[prefix.len()..]) }
+        Err(serde_error(&format!("Serialized as '{}', but deserialized as '{}'", prefix, self.sig)))
+    }
+
+    fn array(self) -> Result<Compound<'s>, Error> {
+        let elem = self.starting("a")?;
+        if elem.starts_with('{') { return Err(serde_error("Serialized as an array, but deserialized as a map")) }
+        Ok(Compound { kind: Kind::Array(self.sig, elem), items: vec!() })
+    }
+
+    fn structure(self) -> Result<Compound<'s>, Error> {
+        let fields = &self.starting("(")?[..self.sig.len() - 2];
+        Ok(Compound { kind: Kind::Struct(fields), items: vec!() })
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T, Error> { Err(serde_error(&format!("{} can not be represented in D-Bus", what))) }
+
+impl<'s> ser::Serializer for Ser<'s> {
+    type Ok = MessageItem;
+    type Error = Error;
+    type SerializeSeq = Compound<'s>;
+    type SerializeTuple = Compound<'s>;
+    type SerializeTupleStruct = Compound<'s>;
+    type SerializeTupleVariant = ser::Impossible<MessageItem, Error>;
+    type SerializeMap = Compound<'s>;
+    type SerializeStruct = Compound<'s>;
+    type SerializeStructVariant = ser::Impossible<MessageItem, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<MessageItem, Error> { self.basic(b'b', MessageItem::Bool(v)) }
+    fn serialize_i8(self, v: i8) -> Result<MessageItem, Error> { self.basic(b'n', MessageItem::Int16(v.into())) }
+    fn serialize_i16(self, v: i16) -> Result<MessageItem, Error> { self.basic(b'n', MessageItem::Int16(v)) }
+    fn serialize_i32(self, v: i32) -> Result<MessageItem, Error> { self.basic(b'i', MessageItem::Int32(v)) }
+    fn serialize_i64(self, v: i64) -> Result<MessageItem, Error> { self.basic(b'x', MessageItem::Int64(v)) }
+    fn serialize_u8(self, v: u8) -> Result<MessageItem, Error> { self.basic(b'y', MessageItem::Byte(v)) }
+    fn serialize_u16(self, v: u16) -> Result<MessageItem, Error> { self.basic(b'q', MessageItem::UInt16(v)) }
+    fn serialize_u32(self, v: u32) -> Result<MessageItem, Error> { self.basic(b'u', MessageItem::UInt32(v)) }
+    fn serialize_u64(self, v: u64) -> Result<MessageItem, Error> { self.basic(b't', MessageItem::UInt64(v)) }
+    fn serialize_f32(self, v: f32) -> Result<MessageItem, Error> { self.basic(b'd', MessageItem::Double(v.into())) }
+    fn serialize_f64(self, v: f64) -> Result<MessageItem, Error> { self.basic(b'd', MessageItem::Double(v)) }
+    fn serialize_char(self, v: char) -> Result<MessageItem, Error> { self.basic(b's', MessageItem::Str(v.to_string())) }
+    fn serialize_str(self, v: &str) -> Result<MessageItem, Error> { self.basic(b's', MessageItem::Str(v.into())) }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<MessageItem, Error> {
+        self.starting("ay")?;
+        let a = MessageItemArray::new(v.iter().map(|&b| MessageItem::Byte(b)).collect(), "ay".into()).unwrap();
+        Ok(MessageItem::Array(a))
+    }
+
+    fn serialize_none(self) -> Result<MessageItem, Error> { unsupported("An Option") }
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<MessageItem, Error> { unsupported("An Option") }
+    fn serialize_unit(self) -> Result<MessageItem, Error> { unsupported("A unit") }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<MessageItem, Error> { unsupported(name) }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<MessageItem, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, value: &T) -> Result<MessageItem, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, name: &'static str, _: u32, _: &'static str, _: &T) -> Result<MessageItem, Error> {
+        unsupported(name)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Compound<'s>, Error> { self.array() }
+    fn serialize_tuple(self, _: usize) -> Result<Compound<'s>, Error> { self.structure() }
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Compound<'s>, Error> { self.structure() }
+
+    fn serialize_tuple_variant(self, name: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeTupleVariant, Error> {
+        unsupported(name)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Compound<'s>, Error> {
+        let entry = self.starting("a{")?;
+        let (key, value) = split_first(&entry[..entry.len() - 1])?;
+        Ok(Compound { kind: Kind::Dict(self.sig, key, value, None), items: vec!() })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Compound<'s>, Error> { self.structure() }
+
+    fn serialize_struct_variant(self, name: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeStructVariant, Error> {
+        unsupported(name)
+    }
+}
+
+enum Kind<'s> {
+    // Signature of the array, and of its elements
+    Array(&'s str, &'s str),
+    // Signatures of the fields not serialized yet
+    Struct(&'s str),
+    // Signature of the dict, of its keys and of its values, and the key waiting for its value
+    Dict(&'s str, &'s str, &'s str, Option<MessageItem>),
+}
+
+struct Compound<'s> {
+    kind: Kind<'s>,
+    items: Vec<MessageItem>,
+}
+
+impl<'s> Compound<'s> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let item = match self.kind {
+            Kind::Array(_, elem) => value.serialize(Ser { sig: elem })?,
+            Kind::Struct(ref mut rest) => {
+                let (field, r) = split_first(rest)?;
+                *rest = r;
+                value.serialize(Ser { sig: field })?
+            },
+            Kind::Dict(..) => unreachable!(),
+        };
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MessageItem, Error> {
+        match self.kind {
+            Kind::Array(sig, _) | Kind::Dict(sig, _, _, None) => MessageItemArray::new(self.items, Signature::new(sig).unwrap())
+                .map(MessageItem::Array).map_err(|e| serde_error(&format!("{:?}", e))),
+            Kind::Struct("") if !self.items.is_empty() => Ok(MessageItem::Struct(self.items)),
+            Kind::Struct("") => unsupported("An empty struct"),
+            Kind::Struct(_) => Err(serde_error("Fewer values than in the signature")),
+            Kind::Dict(..) => Err(serde_error("A key without a value")),
+        }
+    }
+}
+
+impl<'s> ser::SerializeSeq for Compound<'s> {
+    type Ok = MessageItem;
+    type Error = Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> { self.element(value) }
+    fn end(self) -> Result<MessageItem, Error> { self.finish() }
+}
+
+impl<'s> ser::SerializeTuple for Compound<'s> {
+    type Ok = MessageItem;
+    type Error = Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> { self.element(value) }
+    fn end(self) -> Result<MessageItem, Error> { self.finish() }
+}
+
+impl<'s> ser::SerializeTupleStruct for Compound<'s> {
+    type Ok = MessageItem;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> { self.element(value) }
+    fn end(self) -> Result<MessageItem, Error> { self.finish() }
+}
+
+impl<'s> ser::SerializeStruct for Compound<'s> {
+    type Ok = MessageItem;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _: &'static str, value: &T) -> Result<(), Error> { self.element(value) }
+    fn end(self) -> Result<MessageItem, Error> { self.finish() }
+}
+
+impl<'s> ser::SerializeMap for Compound<'s> {
+    type Ok = MessageItem;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        if let Kind::Dict(_, ksig, _, ref mut k) = self.kind { *k = Some(key.serialize(Ser { sig: ksig })?) }
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        if let Kind::Dict(_, _, vsig, ref mut k) = self.kind {
+            let key = k.take().ok_or_else(|| serde_error("A value without a key"))?;
+            let value = value.serialize(Ser { sig: vsig })?;
+            self.items.push(MessageItem::DictEntry(Box::new(key), Box::new(value)));
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<MessageItem, Error> { self.finish() }
+}
+
+// Deserializes the current argument, without moving to the next one.
+struct Des<'a: 'b, 'b> { i: &'b mut Iter<'a> }
+
+fn get<'a, T: Get<'a>>(i: &mut Iter<'a>) -> Result<T, Error> {
+    i.get().ok_or_else(|| serde_error("Unexpected type"))
+}
+
+impl<'de, 'b> de::Deserializer<'de> for Des<'de, 'b> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let i = self.i;
+        match i.arg_type() {
+            ArgType::Boolean => visitor.visit_bool(get(i)?),
+            ArgType::Byte => visitor.visit_u8(get(i)?),
+            ArgType::Int16 => visitor.visit_i16(get(i)?),
+            ArgType::UInt16 => visitor.visit_u16(get(i)?),
+            ArgType::Int32 => visitor.visit_i32(get(i)?),
+            ArgType::UInt32 => visitor.visit_u32(get(i)?),
+            ArgType::Int64 => visitor.visit_i64(get(i)?),
+            ArgType::UInt64 => visitor.visit_u64(get(i)?),
+            ArgType::Double => visitor.visit_f64(get(i)?),
+            ArgType::String => visitor.visit_borrowed_str(get(i)?),
+            ArgType::ObjectPath => visitor.visit_string(get::<::Path>(i)?.to_string()),
+            ArgType::Signature => visitor.visit_string(get::<Signature>(i)?.to_string()),
+            ArgType::Variant => {
+                let mut inner = i.recurse(ArgType::Variant).unwrap();
+                Des { i: &mut inner }.deserialize_any(visitor)
+            },
+            ArgType::Struct => visitor.visit_seq(Seq(i.recurse(ArgType::Struct).unwrap())),
+            ArgType::DictEntry => visitor.visit_seq(Seq(i.recurse(ArgType::DictEntry).unwrap())),
+            ArgType::Array => {
+                let is_dict = i.signature().as_bytes().get(1) == Some(&b'{');
+                let inner = i.recurse(ArgType::Array).unwrap();
+                if is_dict { visitor.visit_map(Map(inner, None)) } else { visitor.visit_seq(Seq(inner)) }
+            },
+            ArgType::UnixFd => unsupported("A file descriptor"),
+            ArgType::Invalid => Err(serde_error("Fewer arguments than expected")),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _: &'static str, _: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        let s: &'de str = get(self.i)?;
+        visitor.visit_enum(s.into_deserializer())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> { unsupported("An Option") }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Seq<'a>(Iter<'a>);
+
+impl<'de> de::SeqAccess<'de> for Seq<'de> {
+    type Error = Error;
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        if self.0.arg_type() == ArgType::Invalid { return Ok(None) }
+        let r = seed.deserialize(Des { i: &mut self.0 })?;
+        self.0.next();
+        Ok(Some(r))
+    }
+}
+
+// The dict entries, and the current entry, after its key
+struct Map<'a>(Iter<'a>, Option<Iter<'a>>);
+
+impl<'de> de::MapAccess<'de> for Map<'de> {
+    type Error = Error;
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        let mut entry = match self.0.recurse(ArgType::DictEntry) { Some(e) => e, None => return Ok(None) };
+        let r = seed.deserialize(Des { i: &mut entry })?;
+        entry.next();
+        self.1 = Some(entry);
+        Ok(Some(r))
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let mut entry = self.1.take().ok_or_else(|| serde_error("A value without a key"))?;
+        let r = seed.deserialize(Des { i: &mut entry })?;
+        self.0.next();
+        Ok(r)
+    }
+}
+
+// Finds the signature of a type, by deserializing it from made-up values.
+struct Probe<'s> { sig: &'s mut String, depth: u32 }
+
+// D-Bus allows 32 levels of arrays and 32 levels of structs.
+const MAX_DEPTH: u32 = 64;
+
+impl<'s> Probe<'s> {
+    fn basic(self, code: char) -> &'s mut String { self.sig.push(code); self.sig }
+
+    fn inner(&mut self, open: &str) -> Result<Probe<'_>, Error> {
+        if self.depth >= MAX_DEPTH { return Err(serde_error("Too deeply nested, or a recursive type")) }
+        self.sig.push_str(open);
+        Ok(Probe { sig: self.sig, depth: self.depth + 1 })
+    }
+
+    fn fields<'de, V: Visitor<'de>>(mut self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        if len == 0 { return unsupported("An empty struct") }
+        let r = visitor.visit_seq(ProbeSeq(self.inner("(")?, len))?;
+        self.sig.push(')');
+        Ok(r)
+    }
+}
+
+impl<'de, 's> de::Deserializer<'de> for Probe<'s> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> {
+        Err(serde_error("The signature depends on the value"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('b'); visitor.visit_bool(false) }
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('n'); visitor.visit_i16(0) }
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('n'); visitor.visit_i16(0) }
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('i'); visitor.visit_i32(0) }
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('x'); visitor.visit_i64(0) }
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('y'); visitor.visit_u8(0) }
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('q'); visitor.visit_u16(0) }
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('u'); visitor.visit_u32(0) }
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('t'); visitor.visit_u64(0) }
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('d'); visitor.visit_f64(0.0) }
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('d'); visitor.visit_f64(0.0) }
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('s'); visitor.visit_char(' ') }
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('s'); visitor.visit_str("") }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.basic('s'); visitor.visit_str("") }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.sig.push_str("ay");
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.deserialize_bytes(visitor) }
+
+    fn deserialize_option<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> { unsupported("An Option") }
+    fn deserialize_unit<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Error> { unsupported("A unit") }
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, _: V) -> Result<V::Value, Error> { unsupported(name) }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(ProbeSeq(self.inner("a")?, 1))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> { self.fields(len, visitor) }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _: &'static str, len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.fields(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+        let r = visitor.visit_map(ProbeMap(self.inner("a{")?, 1))?;
+        self.sig.push('}');
+        Ok(r)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        self.fields(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        let first = match variants.first() { Some(v) => *v, None => return unsupported(name) };
+        self.basic('s');
+        visitor.visit_enum(first.into_deserializer())
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.deserialize_any(visitor) }
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> { self.deserialize_any(visitor) }
+}
+
+// Gives this many made-up elements.
+struct ProbeSeq<'s>(Probe<'s>, usize);
+
+impl<'de, 's> de::SeqAccess<'de> for ProbeSeq<'s> {
+    type Error = Error;
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        if self.1 == 0 { return Ok(None) }
+        self.1 -= 1;
+        seed.deserialize(Probe { sig: self.0.sig, depth: self.0.depth }).map(Some)
+    }
+    fn size_hint(&self) -> Option<usize> { Some(self.1) }
+}
+
+struct ProbeMap<'s>(Probe<'s>, usize);
+
+impl<'de, 's> de::MapAccess<'de> for ProbeMap<'s> {
+    type Error = Error;
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        if self.1 == 0 { return Ok(None) }
+        self.1 -= 1;
+        seed.deserialize(Probe { sig: self.0.sig, depth: self.0.depth }).map(Some)
+    }
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(Probe { sig: self.0.sig, depth: self.0.depth })
+    }
+    fn size_hint(&self) -> Option<usize> { Some(self.1) }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate serde_derive;
+    use self::serde_derive::{Serialize, Deserialize};
+    use super::*;
+    use std::collections::HashMap;
+    use Message;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+    enum Color { Red, Green }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Inner { c: Color, pos: (i32, f64) }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Outer {
+        name: String,
+        id: u32,
+        tags: Vec<String>,
+        empty: Vec<Inner>,
+        props: HashMap<String, u8>,
+        inner: Inner,
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        let mut props = HashMap::new();
+        props.insert("x".to_string(), 5u8);
+        props.insert("y".into(), 7);
+        let o = Outer { name: "Hello".into(), id: 42, tags: vec!("a".into(), "b".into()), empty: vec!(),
+            props, inner: Inner { c: Color::Green, pos: (-3, 2.5) } };
+        assert_eq!(&*serde_signature::<Outer>().unwrap(), "(suasa(s(id))a{sy}(s(id)))");
+
+        let mut m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap().append1("Hello");
+        m.append_items(&[serialize(&o).unwrap()]);
+        let mut i = m.iter_init();
+        assert_eq!(i.read::<&str>().unwrap(), "Hello");
+        assert_eq!(&*i.signature(), "(suasa(s(id))a{sy}(s(id)))");
+        assert_eq!(deserialize::<Outer>(&mut i).unwrap(), o);
+        assert!(deserialize::<Inner>(&mut i).is_err());
+
+        let m = Message::new_method_call("a.b", "/", "a.b", "c").unwrap().append1(Serde((Color::Red, 1u16)));
+        assert_eq!(m.get1::<Serde<(Color, u16)>>(), Some(Serde((Color::Red, 1))));
+        assert_eq!(m.get1::<Serde<(Color, String)>>(), None);
+        assert!(serialize(&Some(5)).is_err());
+    }
+}
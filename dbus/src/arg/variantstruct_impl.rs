@@ -94,6 +94,14 @@ impl<T: RefArg> RefArg for Variant<T> {
     #[inline]
     fn as_str(&self) -> Option<&str> { self.0.as_str() }
     #[inline]
+    fn as_strs(&self) -> Option<Vec<String>> { self.0.as_strs() }
+    #[inline]
+    fn as_byte_array(&self) -> Option<Vec<u8>> { self.0.as_byte_array() }
+    #[inline]
+    fn as_props(&self) -> Option<PropMap> { self.0.as_props() }
+    #[inline]
+    fn as_str_pairs(&self) -> Option<Vec<(String, String)>> { self.0.as_str_pairs() }
+    #[inline]
     fn as_iter<'a>(&'a self) -> Option<Box<Iterator<Item=&'a RefArg> + 'a>> {
         use std::iter;
         let z: &RefArg = &self.0;
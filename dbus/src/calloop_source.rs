@@ -0,0 +1,117 @@
+use crate::{Connection, ConnectionItem};
+use calloop::{EventSource, EventDispatcher};
+use calloop::mio::{self, Evented, Poll, PollOpt, Ready, Token};
+use calloop::mio::unix::{EventedFd, UnixReady};
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+/// A calloop event source for a connection, e g to handle D-Bus in a Wayland compositor.
+///
+/// The events are the incoming items of the connection:
+///
+/// ```rust,no_run
+/// use std::rc::Rc;
+/// use dbus::{Connection, BusType, ConnectionItem, CalloopSource};
+///
+/// let c = Rc::new(Connection::get_private(BusType::Session).unwrap());
+/// let mut event_loop = calloop::EventLoop::<()>::new().unwrap();
+/// event_loop.handle().insert_source(CalloopSource::new(c.clone()), |item, _| {
+///     if let ConnectionItem::Signal(s) = item { println!("{:?}", s) }
+/// }).unwrap();
+/// loop { event_loop.dispatch(None, &mut ()).unwrap() }
+/// ```
+///
+/// libdbus's timeouts, e g for method calls not replied to, are handled whenever the connection
+/// is ready. To have them handled on time, call `Connection::process_io` from a calloop timer
+/// at `Connection::next_timeout`.
+///
+/// This needs the "calloop" feature.
+pub struct CalloopSource {
+    conn: Rc<Connection>,
+}
+
+impl CalloopSource {
+    /// Creates an event source for the connection.
+    pub fn new(conn: Rc<Connection>) -> Self { CalloopSource { conn } }
+
+    /// The connection.
+    pub fn connection(&self) -> &Rc<Connection> { &self.conn }
+
+    fn fds(&self) -> Vec<i32> {
+        let mut fds: Vec<_> = self.conn.watch_fds().iter().map(|w| w.fd()).collect();
+        fds.sort();
+        fds.dedup();
+        fds
+    }
+}
+
+impl Evented for CalloopSource {
+    fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        for fd in self.fds() { EventedFd(&fd).register(poll, token, interest, opts)? }
+        Ok(())
+    }
+
+    fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        for fd in self.fds() { EventedFd(&fd).reregister(poll, token, interest, opts)? }
+        Ok(())
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        for fd in self.fds() { EventedFd(&fd).deregister(poll)? }
+        Ok(())
+    }
+}
+
+impl EventSource for CalloopSource {
+    type Event = ConnectionItem;
+
+    // Outgoing messages are written when sent, so only incoming ones need waiting for.
+    fn interest(&self) -> Ready { Ready::readable() }
+
+    fn pollopts(&self) -> PollOpt { PollOpt::level() }
+
+    fn make_dispatcher<Data: 'static, F: FnMut(ConnectionItem, &mut Data) + 'static>(&self, callback: F)
+        -> Rc<RefCell<EventDispatcher<Data>>> {
+        Rc::new(RefCell::new(Dispatcher { conn: self.conn.clone(), callback }))
+    }
+}
+
+struct Dispatcher<F> {
+    conn: Rc<Connection>,
+    callback: F,
+}
+
+impl<Data, F: FnMut(ConnectionItem, &mut Data)> EventDispatcher<Data> for Dispatcher<F> {
+    fn ready(&mut self, ready: mio::Ready, data: &mut Data) {
+        let u = UnixReady::from(ready);
+        let items: Vec<_> = self.conn.process_io(ready.is_readable() || u.is_hup() || u.is_error(), ready.is_writable()).collect();
+        for item in items { (self.callback)(item, data) }
+    }
+}
+
+#[test]
+fn calloop_method_call() {
+    use crate::{BusType, Message};
+    use std::time::{Duration, Instant};
+    let c = Rc::new(Connection::get_private(BusType::Session).unwrap());
+    c.register_object_path("/calloop").unwrap();
+    let mut event_loop = calloop::EventLoop::<bool>::new().unwrap();
+    let c2 = c.clone();
+    let source = event_loop.handle().insert_source(CalloopSource::new(c.clone()), move |item, got| {
+        if let ConnectionItem::MethodCall(m) = item {
+            assert_eq!(&*m.member().unwrap(), "Calloop");
+            c2.send(m.method_return()).unwrap();
+            *got = true;
+        }
+    }).map_err(|e| e.error).unwrap();
+
+    let m = Message::new_method_call(c.unique_name(), "/calloop", "com.example.dbusrs.Calloop", "Calloop").unwrap();
+    c.send(m).unwrap();
+    let (start, mut got) = (Instant::now(), false);
+    while !got {
+        assert!(start.elapsed().as_secs() < 5, "No method call from the event loop");
+        event_loop.dispatch(Some(Duration::from_millis(100)), &mut got).unwrap();
+    }
+    source.remove();
+}
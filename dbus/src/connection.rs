@@ -1,10 +1,13 @@
-use super::{Error, ffi, to_c_str, c_str_to_slice, Watch, Message, MessageType, BusName, Path, ConnPath};
-use super::{RequestNameReply, ReleaseNameReply, BusType};
-use super::watch::WatchList;
+use super::{Error, ffi, to_c_str, c_str_to_slice, Watch, WatchEvent, Message, MessageType, BusName, Path, ConnPath};
+use super::{RequestNameReply, ReleaseNameReply, StartReply, BusType, MatchRule};
+use std::rc::Rc;
+use super::watch::{WatchList, RawFd};
+use super::timeout::TimeoutList;
+use super::stats::{Stats, ConnectionStats};
+use std::time::Instant;
 use std::{fmt, mem, ptr, thread, panic, ops};
 use std::collections::VecDeque;
 use std::cell::{Cell, RefCell};
-use std::os::unix::io::RawFd;
 use std::os::raw::{c_void, c_char, c_int, c_uint};
 
 /// The type of function to use for replacing the message callback.
@@ -185,12 +188,35 @@ struct IConnection {
     conn: Cell<*mut ffi::DBusConnection>,
     pending_items: RefCell<VecDeque<Message>>,
     watches: Option<Box<WatchList>>,
+    timeouts: Option<Box<TimeoutList>>,
     handlers: RefCell<MsgHandlerList>,
+    is_bus: bool,
+    default_timeout: Cell<i32>,
+    match_cbs: RefCell<Vec<MatchCallback>>,
+    next_token: Cell<usize>,
+    names: RefCell<Vec<String>>,
+    disconnect_cb: RefCell<Option<DisconnectCallback>>,
+    stats: Stats,
 
     filter_cb: RefCell<Option<MessageCallback>>,
     filter_cb_panic: RefCell<thread::Result<()>>,
 }
 
+type DisconnectCallback = Box<FnMut(&Connection)>;
+
+// Rc, so that the callback can be called while the list is not borrowed
+type MatchFn = Rc<RefCell<FnMut(&Message)>>;
+
+struct MatchCallback {
+    token: Token,
+    rule: MatchRule<'static>,
+    cb: MatchFn,
+}
+
+/// Identifies a callback added with `Connection::add_match_cb`, to remove it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token(usize);
+
 /// A D-Bus connection. Start here if you want to get on the D-Bus!
 pub struct Connection {
     i: Box<IConnection>,
@@ -200,6 +226,22 @@ pub fn conn_handle(c: &Connection) -> *mut ffi::DBusConnection {
     c.i.conn.get()
 }
 
+pub (crate) fn conn_stats(c: &Connection) -> &Stats { &c.i.stats }
+
+/// The error name for replies cancelled by `Connection::close`.
+pub const CLOSING_ERROR: &str = "com.github.diwic.dbus.Error.Closing";
+
+/// Whether this is the signal libdbus makes when the connection is lost,
+/// i e "Disconnected" on the "org.freedesktop.DBus.Local" interface.
+pub fn is_disconnected(m: &Message) -> bool {
+    m.msg_type() == MessageType::Signal && m.interface() == Some("org.freedesktop.DBus.Local".into())
+        && m.member() == Some("Disconnected".into())
+}
+
+pub (crate) fn not_a_bus() -> Error {
+    Error::new_custom("org.freedesktop.DBus.Error.NotSupported", "Not connected to a message bus")
+}
+
 extern "C" fn filter_message_cb(conn: *mut ffi::DBusConnection, msg: *mut ffi::DBusMessage,
     user_data: *mut c_void) -> ffi::DBusHandlerResult {
 
@@ -216,8 +258,10 @@ extern "C" fn filter_message_cb(conn: *mut ffi::DBusConnection, msg: *mut ffi::D
     }
 
     let fcb = panic::AssertUnwindSafe(&i.filter_cb);
+    let stats = panic::AssertUnwindSafe(&i.stats);
     let r = panic::catch_unwind(|| {
         let m = Message::from_ptr(msg, true);
+        stats.received(&m);
         let mut cb = fcb.borrow_mut().take().unwrap(); // Take the callback out while we call it.
         let r = cb(connref.0, m);
         let mut cb2 = fcb.borrow_mut(); // If the filter callback has not been replaced, put it back in.
@@ -254,12 +298,20 @@ impl Connection {
         self.i.conn.get()
     }
 
-    fn conn_from_ptr(conn: *mut ffi::DBusConnection) -> Result<Connection, Error> {
+    pub (crate) fn conn_from_ptr(conn: *mut ffi::DBusConnection, is_bus: bool) -> Result<Connection, Error> {
         let mut c = Connection { i: Box::new(IConnection {
             conn: Cell::new(conn),
             pending_items: RefCell::new(VecDeque::new()),
             watches: None,
+            timeouts: None,
             handlers: RefCell::new(vec!()),
+            is_bus,
+            default_timeout: Cell::new(-1),
+            match_cbs: RefCell::new(vec!()),
+            next_token: Cell::new(1),
+            names: RefCell::new(vec!()),
+            disconnect_cb: RefCell::new(None),
+            stats: Default::default(),
             filter_cb: RefCell::new(Some(Box::new(default_filter_callback))),
             filter_cb_panic: RefCell::new(Ok(())),
         })};
@@ -271,6 +323,7 @@ impl Connection {
         } != 0);
 
         c.i.watches = Some(WatchList::new(&c, Box::new(|_| {})));
+        c.i.timeouts = Some(TimeoutList::new(conn));
         Ok(c)
     }
 
@@ -281,7 +334,7 @@ impl Connection {
         if conn == ptr::null_mut() {
             return Err(e)
         }
-        Self::conn_from_ptr(conn)
+        Self::conn_from_ptr(conn, true)
     }
 
     /// Creates a new D-Bus connection to a remote address.
@@ -293,13 +346,42 @@ impl Connection {
         if conn == ptr::null_mut() {
             return Err(e)
         }
-        Self::conn_from_ptr(conn)
+        Self::conn_from_ptr(conn, true)
+    }
+
+    /// Creates a direct connection to a peer at the address, e g "unix:path=/run/foo", that is not a message bus.
+    ///
+    /// Messages go straight to and from the peer, so there is no Hello, no unique name, and no
+    /// destination or sender needed on messages. The methods that talk to the bus, like `register`,
+    /// `register_name` and `add_match`, return an error.
+    pub fn open_peer(address: &str) -> Result<Connection, Error> {
+        let mut e = Error::empty();
+        let conn = unsafe { ffi::dbus_connection_open_private(to_c_str(address).as_ptr(), e.get_mut()) };
+        if conn == ptr::null_mut() {
+            return Err(e)
+        }
+        Self::conn_from_ptr(conn, false)
+    }
+
+    /// Lets clients authenticate with the ANONYMOUS mechanism, on the server side of a peer connection.
+    ///
+    /// Off by default. Must be set before the connection has authenticated, see `Server::set_allow_anonymous`.
+    pub fn set_allow_anonymous(&self, value: bool) {
+        unsafe { ffi::dbus_connection_set_allow_anonymous(self.conn(), value as u32) }
+    }
+
+    /// Whether the other end is a message bus, i e false for connections made with `open_peer`.
+    pub fn is_bus(&self) -> bool { self.i.is_bus }
+
+    fn check_bus(&self) -> Result<(), Error> {
+        if self.i.is_bus { Ok(()) } else { Err(not_a_bus()) }
     }
 
     /// Registers a new D-Bus connection with the bus.
     ///
     /// Note: `get_private` does this automatically, useful with `open_private`
     pub fn register(&self) -> Result<(), Error> {
+        self.check_bus()?;
         let mut e = Error::empty();
         if unsafe { ffi::dbus_bus_register(self.conn(), e.get_mut()) == 0 } {
             Err(e)
@@ -313,18 +395,52 @@ impl Connection {
         unsafe { ffi::dbus_connection_get_is_connected(self.conn()) != 0 }
     }
 
+    /// Whether file descriptors can be sent over the connection, e g as `OwnedFd` or `File` arguments.
+    ///
+    /// libdbus negotiates this with the other side during authentication, when the transport
+    /// is a unix socket and both sides support it. Sending a message with file descriptors
+    /// fails if this is false.
+    pub fn can_send_unix_fd(&self) -> bool {
+        unsafe { ffi::dbus_connection_can_send_type(self.conn(), ffi::DBUS_TYPE_UNIX_FD) != 0 }
+    }
+
+    /// The timeout in milliseconds for blocking method calls made with a timeout of -1.
+    ///
+    /// This is -1 by default, which means the libdbus default of 25 seconds.
+    pub fn default_timeout(&self) -> i32 { self.i.default_timeout.get() }
+
+    /// Sets the timeout used for blocking method calls made with a timeout of -1, e g by
+    /// `ConnPath`s made with that timeout and by helpers that do not take a timeout.
+    ///
+    /// This also applies to calls already set up, as the timeout is looked up when calling.
+    pub fn set_default_timeout(&self, timeout_ms: i32) { self.i.default_timeout.set(timeout_ms) }
+
     /// Sends a message over the D-Bus and waits for a reply.
     /// This is usually used for method calls.
+    ///
+    /// A timeout_ms of -1 means the `default_timeout` of the connection, and `TIMEOUT_INFINITE` means no timeout.
     pub fn send_with_reply_and_block(&self, msg: Message, timeout_ms: i32) -> Result<Message, Error> {
-        let mut e = Error::empty();
+        let timeout_ms = if timeout_ms == -1 { self.i.default_timeout.get() } else { timeout_ms };
+        // As dbus_connection_send_with_reply_and_block does it, but with the reply counted in the stats.
+        let mut p = ptr::null_mut();
+        if unsafe { ffi::dbus_connection_send_with_reply(self.conn(), msg.ptr(), &mut p, timeout_ms as c_int) } == 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Sending method call failed"))
+        }
+        if p.is_null() { return Err(Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection is closed")) }
+        self.i.stats.sent(&msg, None);
+        self.i.stats.call_started();
         let response = unsafe {
-            ffi::dbus_connection_send_with_reply_and_block(self.conn(), msg.ptr(),
-                timeout_ms as c_int, e.get_mut())
+            ffi::dbus_pending_call_block(p);
+            let r = ffi::dbus_pending_call_steal_reply(p);
+            ffi::dbus_pending_call_unref(p);
+            r
         };
-        if response == ptr::null_mut() {
-            return Err(e);
-        }
-        Ok(Message::from_ptr(response, false))
+        self.i.stats.call_finished();
+        if response.is_null() { return Err(Error::new_custom("org.freedesktop.DBus.Error.NoReply", "No reply")) }
+        let mut m = Message::from_ptr(response, false);
+        self.i.stats.received(&m);
+        m.as_result()?;
+        Ok(m)
     }
 
     /// Sends a message over the D-Bus without waiting. Useful for sending signals and method call replies.
@@ -332,10 +448,21 @@ impl Connection {
         let mut serial = 0u32;
         let r = unsafe { ffi::dbus_connection_send(self.conn(), msg.ptr(), &mut serial) };
         if r == 0 { return Err(()); }
+        let reply = msg.msg_type() == MessageType::MethodCall && !msg.get_no_reply();
+        self.i.stats.sent(&msg, if reply { Some(serial) } else { None });
         unsafe { ffi::dbus_connection_flush(self.conn()) };
         Ok(serial)
     }
 
+    /// Sends a method call with the no-reply-expected flag set, and without waiting for a reply.
+    ///
+    /// This is for notification-like calls at a high rate: the receiver does not send any reply,
+    /// not even an error, and nothing is kept around waiting for one. Returns the serial.
+    pub fn method_call_no_reply(&self, msg: Message) -> Result<u32, Error> {
+        msg.set_no_reply(true);
+        self.send(msg).map_err(|_| Error::new_custom("org.freedesktop.DBus.Error.Failed", "Sending method call failed"))
+    }
+
     /// Sends a message over the D-Bus, returning a MessageReply.
     ///
     /// Call add_handler on the result to start waiting for reply. This should be done before next call to `incoming` or `iter`.
@@ -344,6 +471,54 @@ impl Connection {
         Ok(MessageReply(Some(f), serial))
     }
 
+    /// Sends a method call without waiting, and calls `f` with the reply, or with a NoReply error
+    /// if none arrives within `timeout_ms`.
+    ///
+    /// The callback is called while handling incoming messages, e g from `iter` or `process_io`,
+    /// which also handle the timeout. A timeout_ms of -1 means the `default_timeout` of the
+    /// connection, and `TIMEOUT_INFINITE` means no timeout.
+    pub fn send_with_reply_timeout<F: FnOnce(Result<&Message, Error>) + 'static>(&self, msg: Message, timeout_ms: i32, f: F) -> Result<(), Error> {
+        struct Pending<F> { i: *const IConnection, f: Option<F> }
+        extern "C" fn notify<F: FnOnce(Result<&Message, Error>)>(p: *mut ffi::DBusPendingCall, user_data: *mut c_void) {
+            let d = unsafe { &mut *(user_data as *mut Pending<F>) };
+            let i = unsafe { &*d.i };
+            let (f, r) = (d.f.take(), unsafe { ffi::dbus_pending_call_steal_reply(p) });
+            let f = match f { Some(f) => panic::AssertUnwindSafe(f), None => return };
+            let stats = panic::AssertUnwindSafe(&i.stats);
+            // Like in filter_message_cb, a panic is resumed after dispatching.
+            let r = panic::catch_unwind(move || {
+                if r.is_null() { return (f.0)(Err(Error::new_custom("org.freedesktop.DBus.Error.NoReply", "No reply"))) }
+                let m = Message::from_ptr(r, false);
+                stats.received(&m);
+                (f.0)(if m.msg_type() == MessageType::Error { Err(m.set_error_from_msg().unwrap_err()) } else { Ok(&m) })
+            });
+            if let Err(e) = r { *i.filter_cb_panic.borrow_mut() = Err(e) }
+        }
+        extern "C" fn free<F>(user_data: *mut c_void) {
+            let _ = unsafe { Box::from_raw(user_data as *mut Pending<F>) };
+        }
+
+        let timeout_ms = if timeout_ms == -1 { self.i.default_timeout.get() } else { timeout_ms };
+        let mut p = ptr::null_mut();
+        if unsafe { ffi::dbus_connection_send_with_reply(self.conn(), msg.ptr(), &mut p, timeout_ms as c_int) } == 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Sending method call failed"))
+        }
+        if p.is_null() { return Err(Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection is closed")) }
+        self.i.stats.sent(&msg, None);
+        let user_data = Box::into_raw(Box::new(Pending { i: &*self.i, f: Some(f) })) as *mut c_void;
+        // The connection keeps the pending call until it completes or times out.
+        let ok = unsafe {
+            let ok = ffi::dbus_pending_call_set_notify(p, Some(notify::<F>), user_data, Some(free::<F>));
+            ffi::dbus_pending_call_unref(p);
+            ok
+        };
+        if ok == 0 {
+            free::<F>(user_data);
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Setting up the reply callback failed"))
+        }
+        Ok(())
+    }
+
     /// Adds a message handler to the connection.
     ///
     /// # Example
@@ -403,6 +578,15 @@ impl Connection {
 
     /// Register an object path.
     pub fn register_object_path(&self, path: &str) -> Result<(), Error> {
+        self.register_path(path, false)
+    }
+
+    /// Register an object path, and all paths below it.
+    pub fn register_fallback_path(&self, path: &str) -> Result<(), Error> {
+        self.register_path(path, true)
+    }
+
+    fn register_path(&self, path: &str, fallback: bool) -> Result<(), Error> {
         let mut e = Error::empty();
         let p = to_c_str(path);
         let vtable = ffi::DBusObjectPathVTable {
@@ -415,7 +599,11 @@ impl Connection {
         };
         let r = unsafe {
             let user_data: *mut c_void = mem::transmute(&*self.i);
-            ffi::dbus_connection_try_register_object_path(self.conn(), p.as_ptr(), &vtable, user_data, e.get_mut())
+            if fallback {
+                ffi::dbus_connection_try_register_fallback(self.conn(), p.as_ptr(), &vtable, user_data, e.get_mut())
+            } else {
+                ffi::dbus_connection_try_register_object_path(self.conn(), p.as_ptr(), &vtable, user_data, e.get_mut())
+            }
         };
         if r == 0 { Err(e) } else { Ok(()) }
     }
@@ -450,18 +638,108 @@ impl Connection {
 
     /// Register a name.
     pub fn register_name(&self, name: &str, flags: u32) -> Result<RequestNameReply, Error> {
+        self.check_bus()?;
         let mut e = Error::empty();
         let n = to_c_str(name);
         let r = unsafe { ffi::dbus_bus_request_name(self.conn(), n.as_ptr(), flags, e.get_mut()) };
-        if r == -1 { Err(e) } else { Ok(unsafe { mem::transmute(r) }) }
+        if r == -1 { return Err(e) }
+        let r: RequestNameReply = unsafe { mem::transmute(r) };
+        if r != RequestNameReply::Exists {
+            let mut names = self.i.names.borrow_mut();
+            if !names.iter().any(|n| n == name) { names.push(name.into()) }
+        }
+        Ok(r)
     }
 
     /// Release a name.
     pub fn release_name(&self, name: &str) -> Result<ReleaseNameReply, Error> {
+        self.check_bus()?;
         let mut e = Error::empty();
         let n = to_c_str(name);
         let r = unsafe { ffi::dbus_bus_release_name(self.conn(), n.as_ptr(), e.get_mut()) };
-        if r == -1 { Err(e) } else { Ok(unsafe { mem::transmute(r) }) }
+        if r == -1 { return Err(e) }
+        self.i.names.borrow_mut().retain(|n| n != name);
+        Ok(unsafe { mem::transmute(r) })
+    }
+
+    /// Counters of the messages sent and received on this connection.
+    ///
+    /// Replies are counted as pending from the method call is sent until they are processed,
+    /// e g by `iter`; so a method call sent with `send`, that is never replied to, stays pending.
+    pub fn stats(&self) -> ConnectionStats { self.i.stats.get() }
+
+    /// Asks the bus to start the service that can own a name, i e bus activation.
+    ///
+    /// Blocking: until the service has started and owns the name, or failed to.
+    pub fn start_service_by_name(&self, name: &str) -> Result<StartReply, Error> {
+        self.check_bus()?;
+        let mut e = Error::empty();
+        let n = to_c_str(name);
+        let mut r = 0;
+        if unsafe { ffi::dbus_bus_start_service_by_name(self.conn(), n.as_ptr(), 0, &mut r, e.get_mut()) } == 0 { return Err(e) }
+        Ok(unsafe { mem::transmute(r) })
+    }
+
+    /// The names that the bus can start a service for, with `start_service_by_name`.
+    pub fn list_activatable_names(&self) -> Result<Vec<String>, Error> {
+        self.check_bus()?;
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "ListActivatableNames").unwrap();
+        let r = self.send_with_reply_and_block(m, -1)?;
+        r.read1().map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()))
+    }
+
+    /// Makes sure a service is running, starting it if needed, and returns the unique name of
+    /// the owner of the name.
+    ///
+    /// A name that already has an owner is not started, so it does not need to be activatable.
+    pub fn ensure_service_running(&self, name: &str) -> Result<String, Error> {
+        if let Ok(owner) = self.name_owner(name) { return Ok(owner) }
+        // AlreadyRunning, if it got an owner since.
+        self.start_service_by_name(name)?;
+        self.name_owner(name)
+    }
+
+    fn name_owner(&self, name: &str) -> Result<String, Error> {
+        self.check_bus()?;
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetNameOwner").unwrap().append1(name);
+        let r = self.send_with_reply_and_block(m, -1)?;
+        r.read1().map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()))
+    }
+
+    /// Closes the connection, instead of leaving that to drop.
+    ///
+    /// First the names registered with `register_name` are released, so that other
+    /// connections can take them over, and the outgoing messages are written. Then the replies
+    /// still waited for with `add_handler` are cancelled: their handlers get an error
+    /// reply named `CLOSING_ERROR`. Blocking calls and `Pipeline` replies get libdbus's
+    /// Disconnected error instead.
+    ///
+    /// Once closed, the connection can not be used to send or receive messages.
+    pub fn close(&self) -> Result<(), Error> {
+        let mut r = Ok(());
+        if self.i.is_bus && self.is_connected() {
+            let names = self.i.names.borrow().clone();
+            for name in names {
+                if let Err(e) = self.release_name(&name) { if r.is_ok() { r = Err(e) } }
+            }
+        }
+        unsafe { ffi::dbus_connection_flush(self.conn()) };
+
+        let replies: Vec<_> = self.i.handlers.borrow().iter().filter_map(|h| match h.handler_type() {
+            MsgHandlerType::Reply(serial) => Some(serial),
+            _ => None,
+        }).collect();
+        for serial in replies {
+            let msg = crate::message::message_error_reply(serial, CLOSING_ERROR, "The connection is closing");
+            let mut v: MsgHandlerList = mem::replace(&mut *self.i.handlers.borrow_mut(), vec!());
+            msghandler_process(&mut v, &msg, self);
+            let mut v2 = self.i.handlers.borrow_mut();
+            v.append(&mut *v2);
+            *v2 = v;
+        }
+
+        unsafe { ffi::dbus_connection_close(self.conn()) };
+        r
     }
 
     /// Add a match rule to match messages on the message bus.
@@ -469,6 +747,7 @@ impl Connection {
     /// See the `unity_focused_window` example for how to use this to catch signals.
     /// (The syntax of the "rule" string is specified in the [D-Bus specification](https://dbus.freedesktop.org/doc/dbus-specification.html#message-bus-routing-match-rules).)
     pub fn add_match(&self, rule: &str) -> Result<(), Error> {
+        self.check_bus()?;
         let mut e = Error::empty();
         let n = to_c_str(rule);
         unsafe { ffi::dbus_bus_add_match(self.conn(), n.as_ptr(), e.get_mut()) };
@@ -477,12 +756,39 @@ impl Connection {
 
     /// Remove a match rule to match messages on the message bus.
     pub fn remove_match(&self, rule: &str) -> Result<(), Error> {
+        self.check_bus()?;
         let mut e = Error::empty();
         let n = to_c_str(rule);
         unsafe { ffi::dbus_bus_remove_match(self.conn(), n.as_ptr(), e.get_mut()) };
         if e.name().is_some() { Err(e) } else { Ok(()) }
     }
 
+    /// Calls the closure for incoming messages matching the rule, until `remove_match_cb` is called.
+    ///
+    /// On a bus, this also adds the rule to the bus, so that the bus sends us the messages. The
+    /// closure is called while processing incoming messages, e g in `iter`, and the messages it
+    /// gets are not returned from there.
+    pub fn add_match_cb<F: FnMut(&Message) + 'static>(&self, rule: MatchRule, f: F) -> Result<Token, Error> {
+        if self.is_bus() { self.add_match(&rule.match_str())? }
+        let token = Token(self.i.next_token.get());
+        self.i.next_token.set(token.0 + 1);
+        self.i.match_cbs.borrow_mut().push(MatchCallback { token, rule: rule.into_static(), cb: Rc::new(RefCell::new(f)) });
+        Ok(token)
+    }
+
+    /// Removes a callback added with `add_match_cb`, and its rule from the bus.
+    ///
+    /// It is fine to call this from inside the callback.
+    pub fn remove_match_cb(&self, token: Token) -> Result<(), Error> {
+        let mut cbs = self.i.match_cbs.borrow_mut();
+        let i = cbs.iter().position(|m| m.token == token)
+            .ok_or_else(|| Error::new_custom("org.freedesktop.DBus.Error.MatchRuleNotFound", "No match callback with this token"))?;
+        let m = cbs.remove(i);
+        drop(cbs);
+        if self.is_bus() { self.remove_match(&m.rule.match_str())? }
+        Ok(())
+    }
+
     /// Async I/O: Get an up-to-date list of file descriptors to watch.
     ///
     /// See the `Watch` struct for an example.
@@ -501,6 +807,31 @@ impl Connection {
     }
 
 
+    /// Async I/O: When libdbus next needs to be called, even if no fd is ready, or None if it does not.
+    ///
+    /// This is for its timeouts, e g for method calls not replied to. Call `process_io` at this time,
+    /// with readable and writable false if no fd is ready.
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.i.timeouts.as_ref().unwrap().next_deadline()
+    }
+
+    /// Async I/O: Reads and/or writes, handles the timeouts that are due, and returns pending items.
+    ///
+    /// This is an alternative to `watch_handle` for event loops that only know that the
+    /// connection is ready, e g from mio or epoll: wait for the fds of `watch_fds` to be readable
+    /// or writable as the `Watch` asks for, or for `next_timeout`, and then call this. The
+    /// returned iterator never blocks for new events.
+    pub fn process_io(&self, readable: bool, writable: bool) -> ConnectionItems<'_> {
+        let flags = if readable { WatchEvent::Readable as c_uint } else { 0 } +
+            if writable { WatchEvent::Writable as c_uint } else { 0 };
+        if flags != 0 {
+            let watches = self.i.watches.as_ref().unwrap();
+            for w in self.watch_fds() { watches.watch_handle(w.fd(), flags) }
+        }
+        self.i.timeouts.as_ref().unwrap().handle_due();
+        ConnectionItems::new(self, None, true)
+    }
+
     /// Create a convenience struct for easier calling of many methods on the same destination and path.
     pub fn with_path<'a, D: Into<BusName<'a>>, P: Into<Path<'a>>>(&'a self, dest: D, path: P, timeout_ms: i32) ->
         ConnPath<'a, &'a Connection> {
@@ -593,6 +924,17 @@ impl Connection {
     /// see https://github.com/diwic/dbus-rs/issues/99 for additional info.)
     pub fn set_watch_callback(&self, f: Box<Fn(Watch) + Send>) { self.i.watches.as_ref().unwrap().set_on_update(f); }
 
+    /// Sets a callback to be called once the connection is lost, e g because the bus daemon
+    /// restarted or the socket failed, so that a service can reconnect right away.
+    ///
+    /// libdbus notices this while reading or writing, e g in `iter` or `process_io`, and the
+    /// callback is called while processing incoming messages, before the Disconnected signal
+    /// from "org.freedesktop.DBus.Local" is returned. It is also called after `close`, if
+    /// messages are processed after that. Setting a callback replaces the previous one.
+    pub fn set_disconnect_callback<F: FnMut(&Connection) + 'static>(&self, f: F) {
+        *self.i.disconnect_cb.borrow_mut() = Some(Box::new(f));
+    }
+
     fn check_panic(&self) {
         let p = mem::replace(&mut *self.i.filter_cb_panic.borrow_mut(), Ok(()));
         if let Err(perr) = p { panic::resume_unwind(perr); }
@@ -600,12 +942,23 @@ impl Connection {
 
     fn next_msg(&self) -> Option<Message> {
         while let Some(msg) = self.i.pending_items.borrow_mut().pop_front() {
+            if is_disconnected(&msg) {
+                // Taken out, so that the callback can set another one.
+                let cb = self.i.disconnect_cb.borrow_mut().take();
+                if let Some(mut cb) = cb { cb(self) };
+            }
             let mut v: MsgHandlerList = mem::replace(&mut *self.i.handlers.borrow_mut(), vec!());
             let b = msghandler_process(&mut v, &msg, self);
-            let mut v2 = self.i.handlers.borrow_mut();
-            v.append(&mut *v2);
-            *v2 = v;
-            if !b { return Some(msg) };
+            {
+                let mut v2 = self.i.handlers.borrow_mut();
+                v.append(&mut *v2);
+                *v2 = v;
+            }
+            if b { continue };
+            // Callbacks can add and remove callbacks, so do not keep the list borrowed.
+            let cbs: Vec<_> = self.i.match_cbs.borrow().iter().filter(|m| m.rule.matches(&msg)).map(|m| m.cb.clone()).collect();
+            if cbs.is_empty() { return Some(msg) };
+            for cb in cbs { (*cb.borrow_mut())(&msg) };
         };
         None
     }
@@ -731,3 +1084,224 @@ fn message_reply() {
     assert!(false);
 }
 
+
+#[test]
+fn open_peer() {
+    extern crate tempdir;
+    use std::os::unix::net::UnixListener;
+    let dir = tempdir::TempDir::new("dbus-rs-peer").unwrap();
+    let path = dir.path().join("socket");
+    let _l = UnixListener::bind(&path).unwrap();
+    let c = Connection::open_peer(&format!("unix:path={}", path.display())).unwrap();
+    assert!(!c.is_bus());
+    assert_eq!(c.register_name("com.example.dbusrs.peer", 0).unwrap_err().name(), Some("org.freedesktop.DBus.Error.NotSupported"));
+    assert!(c.add_match("type='signal'").is_err());
+    assert!(c.register().is_err());
+    assert!(Connection::get_private(BusType::Session).unwrap().is_bus());
+}
+
+#[test]
+fn default_timeout() {
+    let c = Connection::get_private(BusType::Session).unwrap();
+    assert_eq!(c.default_timeout(), -1);
+    c.set_default_timeout(20);
+    assert_eq!(c.default_timeout(), 20);
+    // Nobody answers this, so only the timeout ends the call.
+    let m = Message::new_method_call(c.unique_name(), "/", "com.example.dbusrs.Slow", "Wait").unwrap();
+    let start = ::std::time::Instant::now();
+    assert!(c.send_with_reply_and_block(m, -1).is_err());
+    assert!(start.elapsed() < ::std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn match_callbacks() {
+    use std::{cell, rc};
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let c2 = Connection::get_private(BusType::Session).unwrap();
+    let got = rc::Rc::new(cell::RefCell::new(vec!()));
+    let g2 = got.clone();
+    let rule = MatchRule::new_signal("com.example.dbusrs.Token", "Tick").with_sender(c2.unique_name());
+    let t = c.add_match_cb(rule, move |m| g2.borrow_mut().push(m.read1::<u32>().unwrap())).unwrap();
+    let tick = |n: u32| c2.send(Message::new_signal("/", "com.example.dbusrs.Token", "Tick").unwrap().append1(n)).unwrap();
+    let process = || for _ in 0..5 { for _ in c.incoming(100) {} };
+
+    tick(1);
+    process();
+    assert_eq!(*got.borrow(), vec!(1));
+    c.remove_match_cb(t).unwrap();
+    assert!(c.remove_match_cb(t).is_err());
+    tick(2);
+    process();
+    assert_eq!(*got.borrow(), vec!(1));
+}
+
+#[test]
+fn no_reply_calls() {
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let m = Message::new_method_call(c.unique_name(), "/notify", "com.example.dbusrs.Notify", "Tick").unwrap();
+    let serial = c.method_call_no_reply(m).unwrap();
+    let m = c.incoming(1000).find(|m| m.msg_type() == MessageType::MethodCall).unwrap();
+    assert_eq!(m.get_serial(), serial);
+    assert!(m.get_no_reply());
+}
+
+#[test]
+fn process_io_timeouts() {
+    use std::{cell, rc};
+    let c = Connection::get_private(BusType::Session).unwrap();
+    assert!(c.next_timeout().is_none());
+    // c2 never reads its messages, so the call is received but nobody answers it.
+    let c2 = Connection::get_private(BusType::Session).unwrap();
+    let m = Message::new_method_call(c2.unique_name(), "/timeout", "com.example.dbusrs.Timeout", "Ignored").unwrap();
+    let reply: rc::Rc<cell::RefCell<Option<Error>>> = Default::default();
+    let reply2 = reply.clone();
+    let start = Instant::now();
+    c.send_with_reply_timeout(m, 200, move |r| { *reply2.borrow_mut() = Some(r.unwrap_err()) }).unwrap();
+    assert!(c.next_timeout().unwrap() >= start);
+
+    while reply.borrow().is_none() {
+        assert!(start.elapsed().as_secs() < 5);
+        let mut fds: Vec<_> = c.watch_fds().iter().map(|w| w.to_pollfd()).collect();
+        let wait = c.next_timeout().map(|t| t.saturating_duration_since(Instant::now()).as_millis() as c_int).unwrap_or(1000);
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, wait) };
+        let (r, w) = (fds.iter().any(|f| f.revents & libc::POLLIN != 0), fds.iter().any(|f| f.revents & libc::POLLOUT != 0));
+        for _ in c.process_io(r, w) {}
+    }
+    assert!(start.elapsed().as_millis() >= 200);
+    assert_eq!(reply.borrow().as_ref().unwrap().name(), Some("org.freedesktop.DBus.Error.NoReply"));
+    assert!(c.next_timeout().is_none());
+}
+
+#[test]
+fn close_connection() {
+    use std::{cell, rc};
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let c2 = Connection::get_private(BusType::Session).unwrap();
+    let name = format!("com.example.dbusrs.close{}", ::std::process::id());
+    c.register_name(&name, 0).unwrap();
+    c.register_object_path("/close").unwrap();
+
+    // A method call to ourselves, which is never replied to.
+    let m = Message::new_method_call(c.unique_name(), "/close", "com.example.dbusrs.Close", "Ignored").unwrap();
+    let got = rc::Rc::new(cell::RefCell::new(None));
+    let got2 = got.clone();
+    c.add_handler(c.send_with_reply(m, move |r| { *got2.borrow_mut() = Some(r.unwrap_err()) }).unwrap());
+    let m = Message::new_method_call(c2.unique_name(), "/close", "com.example.dbusrs.Close", "Flushed").unwrap();
+    c.method_call_no_reply(m).unwrap();
+
+    c.close().unwrap();
+    assert!(!c.is_connected());
+    assert_eq!(got.borrow().as_ref().unwrap().name(), Some(CLOSING_ERROR));
+    assert!(c.i.handlers.borrow().is_empty());
+
+    let m = c2.incoming(1000).find(|m| m.msg_type() == MessageType::MethodCall).unwrap();
+    assert_eq!(&*m.member().unwrap(), "Flushed");
+    let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetNameOwner").unwrap().append1(&*name);
+    assert!(c2.send_with_reply_and_block(m, 2000).is_err());
+}
+
+#[test]
+fn disconnect_callback() {
+    extern crate tempdir;
+    use std::{cell, rc};
+    let dir = tempdir::TempDir::new("dbus-rs-disconnect").unwrap();
+    let server = crate::Server::listen(&format!("unix:tmpdir={}", dir.path().display())).unwrap();
+    let address = server.address();
+    let client = thread::spawn(move || {
+        let c = Connection::open_peer(&address).unwrap();
+        let m = Message::new_method_call("com.example.dbusrs.server", "/hello", "com.example.dbusrs.Server", "Hello").unwrap();
+        c.send_with_reply_and_block(m, 2000).unwrap();
+        // The client's end is closed when c is dropped.
+    });
+
+    let c = server.accept(2000).unwrap().unwrap();
+    c.register_object_path("/hello").unwrap();
+    let lost = rc::Rc::new(cell::Cell::new(0));
+    let lost2 = lost.clone();
+    c.set_disconnect_callback(move |c| { assert!(!c.is_connected()); lost2.set(lost2.get() + 1) });
+    let mut got_signal = false;
+    for (i, item) in c.iter(100).enumerate() {
+        assert!(i < 50, "Disconnect not noticed");
+        match item {
+            ConnectionItem::MethodCall(m) => { c.send(m.method_return()).unwrap(); },
+            ConnectionItem::Signal(ref m) if is_disconnected(m) => {
+                assert_eq!(lost.get(), 1);
+                got_signal = true;
+                break;
+            },
+            _ => {},
+        }
+    }
+    assert!(got_signal);
+    client.join().unwrap();
+}
+
+#[test]
+fn bus_activation() {
+    extern crate tempdir;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::fs;
+    let dir = tempdir::TempDir::new("dbus-rs-activation").unwrap();
+    fs::write(dir.path().join("bus.conf"), format!(r#"<busconfig>
+        <type>session</type><listen>unix:tmpdir={0}</listen><servicedir>{0}</servicedir>
+        <policy context="default"><allow send_destination="*" eavesdrop="true"/><allow eavesdrop="true"/><allow own="*"/></policy>
+        </busconfig>"#, dir.path().display())).unwrap();
+    // Starting it just fails, but it is activatable.
+    fs::write(dir.path().join("com.example.dbusrs.Activated.service"),
+        "[D-BUS Service]\nName=com.example.dbusrs.Activated\nExec=/bin/false\n").unwrap();
+    let mut bus = Command::new("dbus-daemon").arg("--nofork").arg("--print-address")
+        .arg(format!("--config-file={}", dir.path().join("bus.conf").display()))
+        .stdout(Stdio::piped()).spawn().expect("dbus-daemon not found");
+    let mut address = String::new();
+    BufReader::new(bus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    let connect = || { let c = Connection::open_private(address.trim()).unwrap(); c.register().unwrap(); c };
+
+    let (c, c2) = (connect(), connect());
+    assert!(c.list_activatable_names().unwrap().iter().any(|n| n == "com.example.dbusrs.Activated"));
+    assert_eq!(c.ensure_service_running("org.freedesktop.DBus").unwrap(), "org.freedesktop.DBus");
+    assert!(c.start_service_by_name("com.example.dbusrs.Activated").is_err());
+    assert_eq!(c.ensure_service_running("com.example.dbusrs.Unknown").unwrap_err().name(), Some("org.freedesktop.DBus.Error.ServiceUnknown"));
+
+    c2.register_name("com.example.dbusrs.Activated", 0).unwrap();
+    assert_eq!(c.start_service_by_name("com.example.dbusrs.Activated").unwrap(), StartReply::AlreadyRunning);
+    assert_eq!(c.ensure_service_running("com.example.dbusrs.Activated").unwrap(), c2.unique_name());
+    bus.kill().unwrap();
+    bus.wait().unwrap();
+}
+
+#[test]
+fn connection_stats() {
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let s0 = c.stats();
+    let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetId").unwrap();
+    c.send_with_reply_and_block(m, 2000).unwrap();
+    let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "NoSuchMethod").unwrap();
+    assert!(c.send_with_reply_and_block(m, 2000).is_err());
+    let s1 = c.stats();
+    assert_eq!(s1.sent.method_calls - s0.sent.method_calls, 2);
+    assert_eq!(s1.received.method_returns - s0.received.method_returns, 1);
+    assert_eq!(s1.received.errors - s0.received.errors, 1);
+    assert!(s1.sent.bytes > s0.sent.bytes && s1.received.bytes > s0.received.bytes);
+    assert_eq!(s1.pending_replies, 0);
+    assert!(s1.max_pending_replies >= 1);
+
+    // Three calls waiting at the same time, replied to and processed by the handlers.
+    for _ in 0..3 {
+        let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "GetId").unwrap();
+        c.add_handler(c.send_with_reply(m, |r| { r.unwrap(); }).unwrap());
+    }
+    assert_eq!(c.stats().pending_replies, 3);
+    for (i, _) in c.iter(100).enumerate() {
+        assert!(i < 50, "Replies not processed");
+        if c.stats().pending_replies == 0 { break }
+    }
+    let s2 = c.stats();
+    assert_eq!(s2.max_pending_replies, 3);
+    assert_eq!(s2.received.method_returns - s1.received.method_returns, 3);
+
+    let sig = Message::new_signal("/stats", "com.example.dbusrs.Stats", "Counted").unwrap();
+    c.send(sig).unwrap();
+    assert_eq!(c.stats().sent.signals, s2.sent.signals + 1);
+    assert_eq!(c.stats().sent.messages, s2.sent.messages + 1);
+}
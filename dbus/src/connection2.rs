@@ -2,6 +2,8 @@ use crate::{BusType, Error, Message, to_c_str, Watch};
 use std::{ptr, str};
 use std::ffi::CStr;
 use std::os::raw::{c_void};
+use std::sync::{Mutex, MutexGuard};
+use std::collections::VecDeque;
 
 #[derive(Debug)]
 pub struct ConnHandle(*mut ffi::DBusConnection);
@@ -18,6 +20,40 @@ impl Drop for ConnHandle {
     }
 }
 
+/// What `TxRx::send` does with a message when the outgoing queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Write queued messages until there is room. Blocking: for as long as the peer does not read.
+    Block,
+    /// Do not queue the message, and return an error.
+    Error,
+    /// Queue the message, and drop the oldest messages that are not yet handed to libdbus to
+    /// make room. This is for signals and other messages that are fine to lose when the peer
+    /// cannot keep up.
+    ///
+    /// libdbus cannot take back a message once it has it, so messages that do not fit are held
+    /// back by the TxRx, and handed over as the peer reads. Only these can be dropped, so if
+    /// libdbus alone fills the limits, the queue can exceed them by one message.
+    DropOldest,
+}
+
+impl Default for QueuePolicy {
+    fn default() -> Self { QueuePolicy::Block }
+}
+
+/// Limits for the outgoing queue of a TxRx, see `TxRx::set_outgoing_limits`.
+///
+/// Without limits, a peer that does not read lets the queue grow without bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueLimits {
+    /// Most bytes queued
+    pub max_bytes: Option<usize>,
+    /// Most messages queued
+    pub max_messages: Option<usize>,
+    /// What to do when sending a message would exceed the limits
+    pub policy: QueuePolicy,
+}
+
 /// Experimental rewrite of Connection [unstable / experimental]
 ///
 /// Slightly lower level, with better support for async operations.
@@ -33,6 +69,32 @@ impl Drop for ConnHandle {
 #[derive(Debug)]
 pub struct TxRx {
     handle: ConnHandle,
+    is_bus: bool,
+    limits: QueueLimits,
+    queue: Mutex<OutQueue>,
+}
+
+#[derive(Debug)]
+struct OutQueue {
+    // Sizes of the messages libdbus has not written yet, oldest first.
+    sizes: VecDeque<usize>,
+    // Messages held back by QueuePolicy::DropOldest, with their sizes.
+    held: VecDeque<(Message, usize)>,
+    // The TxRx sets the serials, so that held back messages get theirs when sent. libdbus only
+    // uses serial 1 itself, for the Hello of get_private or register.
+    last_serial: u32,
+}
+
+impl OutQueue {
+    // libdbus writes messages in order, so those not in its outgoing size anymore are the oldest.
+    fn written(&mut self, libdbus_bytes: usize) {
+        let mut sum: usize = self.sizes.iter().sum();
+        while sum > libdbus_bytes {
+            sum -= self.sizes.pop_front().unwrap();
+        }
+    }
+
+    fn held_bytes(&self) -> usize { self.held.iter().map(|h| h.1).sum() }
 }
 
 impl TxRx {
@@ -41,13 +103,14 @@ impl TxRx {
         self.handle.0
     }
 
-    fn conn_from_ptr(ptr: *mut ffi::DBusConnection) -> Result<TxRx, Error> {
+    fn conn_from_ptr(ptr: *mut ffi::DBusConnection, is_bus: bool) -> Result<TxRx, Error> {
         let handle = ConnHandle(ptr);
 
         /* No, we don't want our app to suddenly quit if dbus goes down */
         unsafe { ffi::dbus_connection_set_exit_on_disconnect(ptr, 0) };
 
-        let c = TxRx { handle };
+        let c = TxRx { handle, is_bus, limits: Default::default(),
+            queue: Mutex::new(OutQueue { sizes: VecDeque::new(), held: VecDeque::new(), last_serial: 1 }) };
 
         Ok(c)
     }
@@ -62,7 +125,7 @@ impl TxRx {
         if conn == ptr::null_mut() {
             return Err(e)
         }
-        Self::conn_from_ptr(conn)
+        Self::conn_from_ptr(conn, true)
     }
 
     /// Creates a new D-Bus connection to a remote address.
@@ -76,9 +139,24 @@ impl TxRx {
         if conn == ptr::null_mut() {
             return Err(e)
         }
-        Self::conn_from_ptr(conn)
+        Self::conn_from_ptr(conn, true)
+    }
+
+    /// Creates a direct connection to a peer that is not a message bus, see `Connection::open_peer`.
+    ///
+    /// Blocking: until the connection is established.
+    pub fn open_peer(address: &str) -> Result<TxRx, Error> {
+        let mut e = Error::empty();
+        let conn = unsafe { ffi::dbus_connection_open_private(to_c_str(address).as_ptr(), e.get_mut()) };
+        if conn == ptr::null_mut() {
+            return Err(e)
+        }
+        Self::conn_from_ptr(conn, false)
     }
 
+    /// Whether the other end is a message bus, i e false for connections made with `open_peer`.
+    pub fn is_bus(&self) -> bool { self.is_bus }
+
     /// Registers a new D-Bus connection with the bus.
     ///
     /// Note: `get_private` does this automatically, useful with `open_private`
@@ -86,6 +164,7 @@ impl TxRx {
     /// Blocking: until a "Hello" response is received from the server.
     pub fn register(&mut self) -> Result<(), Error> {
         // This function needs to take &mut self, because it changes unique_name and unique_name takes a &self
+        if !self.is_bus { return Err(crate::connection::not_a_bus()) }
         let mut e = Error::empty();
         if unsafe { ffi::dbus_bus_register(self.conn(), e.get_mut()) == 0 } {
             Err(e)
@@ -99,6 +178,15 @@ impl TxRx {
         unsafe { ffi::dbus_connection_get_is_connected(self.conn()) != 0 }
     }
 
+    /// Whether file descriptors can be sent over the connection, e g as `OwnedFd` or `File` arguments.
+    ///
+    /// libdbus negotiates this with the other side during authentication, when the transport
+    /// is a unix socket and both sides support it. Sending a message with file descriptors
+    /// fails if this is false.
+    pub fn can_send_unix_fd(&self) -> bool {
+        unsafe { ffi::dbus_connection_can_send_type(self.conn(), ffi::DBUS_TYPE_UNIX_FD) != 0 }
+    }
+
     /// Get the connection's unique name.
     ///
     /// It's usually something like ":1.54"
@@ -112,18 +200,99 @@ impl TxRx {
 
     /// Puts a message into libdbus out queue. Use "flush" or "read_write" to make sure it is sent over the wire.
     ///
-    /// Returns a serial number than can be used to match against a reply.
-    pub fn send(&self, msg: Message) -> Result<u32, ()> {
-        let mut serial = 0u32;
-        let r = unsafe { ffi::dbus_connection_send(self.conn(), msg.ptr(), &mut serial) };
-        if r == 0 { return Err(()); }
+    /// Returns a serial number than can be used to match against a reply. If the queue is full,
+    /// what happens depends on the policy of the `set_outgoing_limits`.
+    pub fn send(&self, mut msg: Message) -> Result<u32, ()> {
+        let size = crate::message::message_marshalled_len(&msg);
+        let mut q = self.locked_queue();
+        if self.limits.policy == QueuePolicy::Block {
+            while !self.has_room_for(&q, size, false) {
+                drop(q);
+                self.read_write(None)?;
+                q = self.locked_queue();
+            }
+        } else if !self.has_room_for(&q, size, true) {
+            if self.limits.policy == QueuePolicy::Error { return Err(()) }
+            while !q.held.is_empty() && !self.has_room_for(&q, size, true) { q.held.pop_front(); }
+        }
+        let serial = msg.get_serial();
+        let serial = if serial != 0 { serial } else {
+            q.last_serial = q.last_serial.checked_add(1).unwrap_or(2);
+            crate::message::message_set_serial(&mut msg, q.last_serial);
+            q.last_serial
+        };
+        if !q.held.is_empty() || !self.has_room_for(&q, size, false) {
+            q.held.push_back((msg, size));
+        } else {
+            self.hand_over(&mut q, msg, size)?;
+        }
         Ok(serial)
     }
 
+    fn hand_over(&self, q: &mut OutQueue, msg: Message, size: usize) -> Result<(), ()> {
+        if unsafe { ffi::dbus_connection_send(self.conn(), msg.ptr(), ptr::null_mut()) } == 0 { return Err(()) }
+        q.sizes.push_back(size);
+        Ok(())
+    }
+
+    // The queue, with what libdbus has written taken out and held back messages handed over.
+    fn locked_queue(&self) -> MutexGuard<'_, OutQueue> {
+        let mut q = self.queue.lock().unwrap();
+        q.written(self.libdbus_bytes());
+        while let Some(size) = q.held.front().map(|h| h.1) {
+            if !self.has_room_for(&q, size, false) { break }
+            let msg = q.held.pop_front().unwrap().0;
+            if self.hand_over(&mut q, msg, size).is_err() { break }
+        }
+        q
+    }
+
+    fn has_room_for(&self, q: &OutQueue, size: usize, with_held: bool) -> bool {
+        let QueueLimits { max_bytes, max_messages, .. } = self.limits;
+        let (held_bytes, held) = if with_held { (q.held_bytes(), q.held.len()) } else { (0, 0) };
+        let bytes = self.libdbus_bytes() + held_bytes;
+        // A message bigger than max_bytes is let through when the queue is empty, not blocked forever.
+        (max_bytes.is_none() || bytes == 0 || bytes + size <= max_bytes.unwrap()) &&
+            max_messages.filter(|&m| q.sizes.len() + held >= m).is_none()
+    }
+
+    fn libdbus_bytes(&self) -> usize {
+        unsafe { ffi::dbus_connection_get_outgoing_size(self.conn()) as usize }
+    }
+
+    /// Limits the outgoing queue, from the next `send` on.
+    pub fn set_outgoing_limits(&mut self, limits: QueueLimits) {
+        self.limits = limits;
+        // Held back messages that fit now are handed over.
+        drop(self.locked_queue());
+    }
+
+    /// The limits of the outgoing queue.
+    pub fn outgoing_limits(&self) -> QueueLimits { self.limits }
+
+    /// The number of bytes in the outgoing queue, i e not yet written to the peer.
+    pub fn outgoing_bytes(&self) -> usize {
+        let q = self.locked_queue();
+        self.libdbus_bytes() + q.held_bytes()
+    }
+
+    /// The number of messages in the outgoing queue.
+    ///
+    /// libdbus only keeps track of bytes, so the TxRx counts the messages by their sizes.
+    pub fn outgoing_messages(&self) -> usize {
+        let q = self.locked_queue();
+        q.sizes.len() + q.held.len()
+    }
+
     /// Flush the queue of outgoing messages.
     /// 
     /// Blocking: until the outgoing queue is empty.
-    pub fn flush(&self) { unsafe { ffi::dbus_connection_flush(self.conn()) } }
+    pub fn flush(&self) {
+        loop {
+            unsafe { ffi::dbus_connection_flush(self.conn()) };
+            if self.locked_queue().held.is_empty() { return }
+        }
+    }
 
     /// Read and write to the connection.
     ///
@@ -136,6 +305,8 @@ impl TxRx {
         if unsafe { ffi::dbus_connection_read_write(self.conn(), t) == 0 } {
             Err(())
         } else {
+            // Makes room for held back messages
+            drop(self.locked_queue());
             Ok(())
         }
     }
@@ -183,6 +354,40 @@ fn test_txrx_send_sync() {
     is_sync(&c);
 }
 
+#[test]
+fn txrx_outgoing_limits() {
+    extern crate tempdir;
+    let dir = tempdir::TempDir::new("dbus-rs-queue").unwrap();
+    // The server never reads or writes, so the client cannot even finish authenticating.
+    let server = crate::Server::listen(&format!("unix:tmpdir={}", dir.path().display())).unwrap();
+    let mut c = TxRx::open_peer(&server.address()).unwrap();
+    let msg = || Message::new_signal("/", "com.example.dbusrs.Queue", "Tick").unwrap().append1(vec!(0u8; 1000));
+
+    // Counted also without limits
+    for _ in 0..2 { assert!(c.send(msg()).unwrap() > 0) }
+    assert_eq!(c.outgoing_messages(), 2);
+    let bytes = c.outgoing_bytes();
+    assert!(bytes > 2000);
+
+    c.set_outgoing_limits(QueueLimits { max_bytes: None, max_messages: Some(3), policy: QueuePolicy::Error });
+    assert!(c.send(msg()).unwrap() > 0);
+    assert_eq!(c.outgoing_messages(), 3);
+    let bytes = c.outgoing_bytes();
+    assert!(bytes > 3000);
+    assert!(c.send(msg()).is_err());
+
+    // Held back messages get serials, and the oldest of them is dropped for the next one.
+    c.set_outgoing_limits(QueueLimits { max_bytes: Some(bytes + 100), max_messages: None, policy: QueuePolicy::DropOldest });
+    let s1 = c.send(msg()).unwrap();
+    assert_eq!(c.outgoing_messages(), 4);
+    let bytes4 = c.outgoing_bytes();
+    let s2 = c.send(msg()).unwrap();
+    assert!(s2 > s1 && s1 > 0);
+    assert_eq!(c.outgoing_messages(), 4);
+    assert_eq!(c.outgoing_bytes(), bytes4);
+    assert_eq!(c.outgoing_limits().policy, QueuePolicy::DropOldest);
+}
+
 #[test]
 fn txrx_simple_test() {
     let mut c = TxRx::get_private(BusType::Session).unwrap();
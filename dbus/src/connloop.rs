@@ -0,0 +1,125 @@
+use crate::{Connection, ConnectionItem, Error, WatchEvent};
+use crate::connection::conn_handle;
+use std::cmp;
+use std::os::raw::c_int;
+use std::time::Instant;
+
+type ItemCallback = Box<FnMut(&Connection, ConnectionItem)>;
+
+/// Runs several connections, e g to both the system and the session bus, from one thread.
+///
+/// Each connection has a callback for its incoming items. The loop waits until any of the
+/// connections is ready, and gives the items of that connection to its callback:
+///
+/// ```rust,no_run
+/// use dbus::{Connection, BusType, ConnectionItem, ConnectionLoop};
+///
+/// let mut l = ConnectionLoop::new();
+/// l.add(Connection::get_private(BusType::System).unwrap(), |_, item| {
+///     if let ConnectionItem::Signal(s) = item { println!("System bus: {:?}", s) }
+/// });
+/// l.add(Connection::get_private(BusType::Session).unwrap(), |c, item| {
+///     if let ConnectionItem::MethodCall(m) = item { c.send(m.method_return()).unwrap(); }
+/// });
+/// l.run().unwrap();
+/// ```
+#[derive(Default)]
+pub struct ConnectionLoop {
+    conns: Vec<(Connection, ItemCallback)>,
+}
+
+impl ConnectionLoop {
+    /// Creates a loop without connections.
+    pub fn new() -> Self { Default::default() }
+
+    /// Adds a connection, with a callback for its incoming items.
+    ///
+    /// Returns the index of the connection, for `connection`.
+    pub fn add<F: FnMut(&Connection, ConnectionItem) + 'static>(&mut self, c: Connection, f: F) -> usize {
+        self.conns.push((c, Box::new(f)));
+        self.conns.len() - 1
+    }
+
+    /// The connection added with this index.
+    pub fn connection(&self, idx: usize) -> &Connection { &self.conns[idx].0 }
+
+    /// The number of connections.
+    pub fn len(&self) -> usize { self.conns.len() }
+
+    /// Whether there are no connections.
+    pub fn is_empty(&self) -> bool { self.conns.is_empty() }
+
+    /// Waits until any connection is ready, for up to timeout_ms milliseconds (or forever
+    /// if -1), and processes the connections that are.
+    ///
+    /// This also handles libdbus's timeouts, e g for method calls not replied to, when they are due.
+    pub fn process(&mut self, timeout_ms: i32) -> Result<(), Error> {
+        // The index of the connection for each fd.
+        let (mut owners, mut fds) = (vec!(), vec!());
+        let mut wait = timeout_ms;
+        for (i, (c, _)) in self.conns.iter().enumerate() {
+            for w in c.watch_fds() { owners.push(i); fds.push(w.to_pollfd()) }
+            if let Some(t) = c.next_timeout() {
+                let ms = t.saturating_duration_since(Instant::now()).as_millis() as i32;
+                wait = if wait < 0 { ms } else { cmp::min(wait, ms) };
+            }
+            // Messages already read, e g while waiting for a blocking call, are processed right away.
+            if unsafe { ffi::dbus_connection_get_dispatch_status(conn_handle(c)) } != ffi::DBusDispatchStatus::Complete { wait = 0 }
+        }
+        let r = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, wait as c_int) };
+        if r < 0 {
+            let e = ::std::io::Error::last_os_error();
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.IOError", &e.to_string()))
+        }
+
+        for (i, (c, cb)) in self.conns.iter_mut().enumerate() {
+            let mut items = vec!();
+            for (_, pfd) in owners.iter().zip(&fds).filter(|(&o, pfd)| o == i && pfd.revents != 0) {
+                items.extend(c.watch_handle(pfd.fd, WatchEvent::from_revents(pfd.revents)));
+            }
+            // Timeouts that are due, and messages already read.
+            items.extend(c.process_io(false, false));
+            for item in items { cb(c, item) }
+        }
+        Ok(())
+    }
+
+    /// Processes the connections, until none of them is connected.
+    pub fn run(&mut self) -> Result<(), Error> {
+        while self.conns.iter().any(|(c, _)| c.is_connected()) { self.process(-1)? }
+        Ok(())
+    }
+}
+
+#[test]
+fn two_connections() {
+    use crate::{BusType, Message};
+    use std::rc::Rc;
+    use std::cell::Cell;
+    let got = Rc::new(Cell::new(0));
+    let mut l = ConnectionLoop::new();
+    for _ in 0..2 {
+        let got2 = got.clone();
+        let c = Connection::get_private(BusType::Session).unwrap();
+        c.register_object_path("/loop").unwrap();
+        l.add(c, move |c, item| {
+            if let ConnectionItem::MethodCall(m) = item {
+                assert_eq!(&*m.destination().unwrap(), &*c.unique_name());
+                c.send(m.method_return()).unwrap();
+                got2.set(got2.get() + 1);
+            }
+        });
+    }
+    assert_eq!(l.len(), 2);
+
+    let c = Connection::get_private(BusType::Session).unwrap();
+    for i in 0..2 {
+        let m = Message::new_method_call(l.connection(i).unique_name(), "/loop", "com.example.dbusrs.Loop", "Hello").unwrap();
+        c.send(m).unwrap();
+    }
+    let start = Instant::now();
+    while got.get() < 2 {
+        assert!(start.elapsed().as_secs() < 5, "Method calls not processed");
+        l.process(100).unwrap();
+    }
+}
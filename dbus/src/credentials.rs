@@ -0,0 +1,94 @@
+use crate::{Connection, Message, Error, arg};
+#[cfg(unix)]
+use crate::OwnedFd;
+use std::collections::HashMap;
+
+/// What the bus knows about the process behind a connection, from GetConnectionCredentials.
+///
+/// Fields are None when the bus does not know them, e g linux_security_label without an LSM,
+/// or process_fd with buses older than dbus-daemon 1.15.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionCredentials {
+    /// UnixUserID
+    pub unix_uid: Option<u32>,
+    /// UnixGroupIDs: the primary and supplementary groups
+    pub unix_gids: Option<Vec<u32>>,
+    /// ProcessID
+    pub pid: Option<u32>,
+    /// ProcessFD: a pidfd for the process, which unlike the pid cannot be reused
+    #[cfg(unix)]
+    pub process_fd: Option<OwnedFd>,
+    /// LinuxSecurityLabel, e g an SELinux context or AppArmor profile, without the trailing NUL
+    pub linux_security_label: Option<Vec<u8>>,
+    /// WindowsSID
+    pub windows_sid: Option<String>,
+}
+
+impl ConnectionCredentials {
+    /// Parses the a{sv} returned by GetConnectionCredentials. Unknown keys are ignored.
+    pub fn from_dict(d: &HashMap<String, arg::Variant<Box<arg::RefArg>>>) -> Self {
+        let u32s = |v: &arg::RefArg| v.as_iter().map(|i| i.filter_map(|x| x.as_u64()).map(|x| x as u32).collect::<Vec<_>>());
+        let mut c = ConnectionCredentials::default();
+        for (k, v) in d {
+            let v = &*v.0;
+            match &**k {
+                "UnixUserID" => c.unix_uid = v.as_u64().map(|x| x as u32),
+                "UnixGroupIDs" => c.unix_gids = u32s(v),
+                "ProcessID" => c.pid = v.as_u64().map(|x| x as u32),
+                #[cfg(unix)]
+                "ProcessFD" => c.process_fd = arg::cast::<OwnedFd>(v).cloned(),
+                "LinuxSecurityLabel" => c.linux_security_label = v.as_iter().map(|i| {
+                    let mut l: Vec<u8> = i.filter_map(|x| x.as_u64()).map(|x| x as u8).collect();
+                    if l.last() == Some(&0) { l.pop(); }
+                    l
+                }),
+                "WindowsSID" => c.windows_sid = v.as_str().map(String::from),
+                _ => {},
+            }
+        }
+        c
+    }
+
+    /// The GetConnectionCredentials method call for a name, to send without blocking.
+    pub fn method_call(name: &str) -> Message {
+        Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetConnectionCredentials")
+            .unwrap().append1(name)
+    }
+
+    /// Parses the reply to `method_call`.
+    pub fn from_reply(reply: &Message) -> Result<Self, Error> {
+        let mut reply = reply.duplicate();
+        reply.as_result()?;
+        let d: HashMap<String, arg::Variant<Box<arg::RefArg>>> = reply.read1()
+            .map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()))?;
+        Ok(Self::from_dict(&d))
+    }
+}
+
+impl Connection {
+    /// Asks the bus for the credentials of the connection owning a name, e g the sender of a method call.
+    ///
+    /// Prefer the unique name of the sender over a well-known name, which can change owner.
+    pub fn connection_credentials(&self, name: &str) -> Result<ConnectionCredentials, Error> {
+        let r = self.send_with_reply_and_block(ConnectionCredentials::method_call(name), -1)?;
+        ConnectionCredentials::from_reply(&r)
+    }
+}
+
+#[test]
+fn own_credentials() {
+    use crate::BusType;
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let cr = c.connection_credentials(&c.unique_name()).unwrap();
+    assert_eq!(cr.pid, Some(::std::process::id()));
+    assert_eq!(cr.unix_uid, Some(unsafe { libc::getuid() }));
+    if let Some(g) = cr.unix_gids { assert!(g.contains(&unsafe { libc::getgid() })) }
+    assert!(c.connection_credentials("com.example.dbusrs.nobody").is_err());
+
+    let mut m = Message::new_method_call("com.example.dbusrs.credentials", "/", "com.example.dbusrs.Credentials", "Test").unwrap();
+    let ctx = crate::crossroads::Context::new(&m);
+    assert!(ctx.caller_credentials(&c).is_err());
+    crate::message::message_set_sender(&mut m, &c.unique_name().into());
+    let ctx = crate::crossroads::Context::new(&m);
+    assert_eq!(ctx.caller_credentials(&c).unwrap().pid, Some(::std::process::id()));
+}
@@ -0,0 +1,71 @@
+use crate::{Signature, Message, arg};
+use crate::tree::MethodErr;
+
+/// Tuples of D-Bus arguments, used for typed method handlers and signal declarations.
+///
+/// Implemented for tuples of up to eight elements.
+pub trait ArgAll: Sized {
+    /// A tuple of argument names, of the same size.
+    type Names;
+    /// Calls cb once for every argument, with its name and signature.
+    fn strs_sigs<CB: FnMut(&'static str, Signature<'static>)>(names: Self::Names, cb: CB);
+    /// Reads all arguments.
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError>;
+    /// Appends all arguments.
+    fn append(self, ia: &mut arg::IterAppend);
+}
+
+/// Turns a failure to read the arguments into an InvalidArgs error.
+pub (crate) fn invalid_args(e: arg::TypeMismatchError) -> MethodErr {
+    ("org.freedesktop.DBus.Error.InvalidArgs", e.to_string()).into()
+}
+
+/// The signature of all arguments of a message.
+pub (crate) fn msg_signature(msg: &Message) -> String {
+    let mut i = msg.iter_init();
+    let mut r = String::new();
+    while i.arg_type() != arg::ArgType::Invalid {
+        r += &i.signature();
+        i.next();
+    }
+    r
+}
+
+macro_rules! name_of { ($t: ident) => { &'static str } }
+
+macro_rules! argall_impl {
+    ($($n: ident $t: ident),*) => {
+
+impl<$($t: arg::Arg + arg::Append + for<'z> arg::Get<'z>),*> ArgAll for ($($t,)*) {
+    type Names = ($(name_of!($t),)*);
+
+    #[allow(unused_variables, unused_mut)]
+    fn strs_sigs<CB: FnMut(&'static str, Signature<'static>)>(names: Self::Names, mut cb: CB) {
+        let ($($n,)*) = names;
+        $( cb($n, <$t as arg::Arg>::signature()); )*
+    }
+
+    #[allow(unused_variables)]
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(($( i.read::<$t>()?, )*))
+    }
+
+    #[allow(unused_variables)]
+    fn append(self, ia: &mut arg::IterAppend) {
+        let ($($n,)*) = self;
+        $( ia.append($n); )*
+    }
+}
+
+    }
+}
+
+argall_impl!();
+argall_impl!(a A);
+argall_impl!(a A, b B);
+argall_impl!(a A, b B, c C);
+argall_impl!(a A, b B, c C, d D);
+argall_impl!(a A, b B, c C, d D, e E);
+argall_impl!(a A, b B, c C, d D, e E, f F);
+argall_impl!(a A, b B, c C, d D, e E, f F, g G);
+argall_impl!(a A, b B, c C, d D, e E, f F, g G, h H);
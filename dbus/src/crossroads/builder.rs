@@ -0,0 +1,123 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use crate::{Interface as IfaceName, Member as MemberName, Message, arg};
+use crate::tree::MethodErr;
+use super::args::ArgAll;
+use super::crossroads::Crossroads;
+use super::handlers::Handlers;
+use super::info::{IfaceInfo, MethodInfo, PropInfo, SignalInfo};
+
+/// Builds an IfaceInfo for interface data of type T, taking signatures from Rust types.
+///
+/// ```ignore
+/// IfaceBuilder::<Score>::new("com.example.dbusrs.score")
+///     .method("Hello", ("name",), ("reply",), |score: &Score, (name,): (String,)| {
+///         Ok((format!("Hello {}, my score is {}!", name, score.0),))
+///     })
+///     .property("Score", |score: &Score| Some(score.0))
+///     .signal::<(u16,), _>("ScoreChanged", ("score",))
+///     .register(&mut cr);
+/// ```
+#[derive(Debug)]
+pub struct IfaceBuilder<T, H: Handlers = ()> {
+    info: IfaceInfo<'static, H>,
+    _dummy: PhantomData<fn(&T)>,
+}
+
+impl<T: Any + Send + Sync + 'static, H: Handlers> IfaceBuilder<T, H> {
+    /// Adds a signal, with argument names and the argument types as a tuple.
+    pub fn signal<A: ArgAll, N: Into<MemberName<'static>>>(mut self, name: N, arg_names: A::Names) -> Self {
+        let mut s = SignalInfo::new(name);
+        let mut args = vec!();
+        A::strs_sigs(arg_names, |n, sig| args.push((n, sig)));
+        for (n, sig) in args { s = s.arg(n, sig) }
+        self.info.signals.push(s);
+        self
+    }
+
+    /// Returns the finished IfaceInfo.
+    pub fn build(self) -> IfaceInfo<'static, H> { self.info }
+
+    /// Registers the finished IfaceInfo for T.
+    pub fn register(self, cr: &mut Crossroads<H>) { cr.register::<T>(self.info); }
+}
+
+impl<T: Any + Send + Sync + 'static> IfaceBuilder<T, ()> {
+    pub fn new<N: Into<IfaceName<'static>>>(name: N) -> Self {
+        IfaceBuilder { info: IfaceInfo::new(name, vec!(), vec!(), vec!()), _dummy: PhantomData }
+    }
+
+    /// Adds a method with typed arguments, see MethodInfo::new_typed.
+    pub fn method<IA, OA, N, F>(mut self, name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll,
+    N: Into<MemberName<'static>>,
+    F: Fn(&T, IA) -> Result<OA, MethodErr> + Send + Sync + 'static,
+    {
+        self.info.methods.push(MethodInfo::new_typed(name, in_names, out_names, f));
+        self
+    }
+
+    /// Adds a read-only property.
+    pub fn property<P, N, G>(mut self, name: N, getf: G) -> Self where
+    P: arg::Append + arg::Arg,
+    N: Into<MemberName<'static>>,
+    G: Fn(&T) -> Option<P> + Send + Sync + 'static,
+    {
+        self.info.props.push(PropInfo::new_sync_ro(name, move |t: &T, _, _| getf(t)));
+        self
+    }
+
+    /// Adds a read-write property.
+    pub fn property_rw<P, N, G, S>(mut self, name: N, getf: G, setf: S) -> Self where
+    P: arg::Append + arg::Arg + for<'z> arg::Get<'z>,
+    N: Into<MemberName<'static>>,
+    G: Fn(&T) -> Option<P> + Send + Sync + 'static,
+    S: Fn(&T, P) -> Result<(), MethodErr> + Send + Sync + 'static,
+    {
+        self.info.props.push(PropInfo::new_sync_rw(name, move |t: &T, _, _| getf(t), move |t: &T, _, _, p| setf(t, p)));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::crossroads::PathData;
+    use super::super::stdimpl::DBusProperties;
+    use super::super::testing::TestCall;
+
+    #[test]
+    fn builder() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        IfaceBuilder::<Score>::new("com.example.dbusrs.crossroads.score")
+            .method("Hello", ("name",), ("reply",), |score: &Score, (name,): (String,)| {
+                Ok((format!("Hello {}, my score is {}!", name, score.0),))
+            })
+            .method("Fail", (), (), |_: &Score, _: ()| -> Result<(), MethodErr> { Err(MethodErr::failed(&"Nope")) })
+            .property("Score", |score: &Score| Some(score.0))
+            .signal::<(u16,), _>("ScoreChanged", ("score",))
+            .register(&mut cr);
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(DBusProperties);
+        cr.insert("/", pdata);
+
+        let hello = || TestCall::new("/", "com.example.dbusrs.crossroads.score", "Hello");
+        let (s,): (String,) = hello().append1("Bob").dispatch(&mut cr).ok();
+        assert_eq!(s, "Hello Bob, my score is 7!");
+
+        let e = hello().append1(5u32).dispatch(&mut cr).error().unwrap();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+
+        let xml = cr.introspect(&"/".into(), cr.get_data("/"));
+        assert!(xml.contains(r#"<method name="Hello">
+      <arg name="name" type="s" direction="in"/>
+      <arg name="reply" type="s" direction="out"/>
+    </method>"#));
+        assert!(xml.contains(r#"<property name="Score" type="q" access="read"/>"#));
+        assert!(xml.contains(r#"<arg name="score" type="q"/>"#));
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, BusName, Message, SignalArgs, arg};
+use crate::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use crate::tree::MethodErr;
+
+/// The message being handled, and somewhere to put messages that should be sent after the reply.
+#[derive(Debug)]
+pub struct Context<'a> {
+    msg: &'a Message,
+    outgoing: RefCell<Vec<Message>>,
+    fallback: Option<(PathName<'static>, String)>,
+    // Elements of the path matched by a path template
+    params: Vec<(String, String)>,
+    app_data: Option<&'a (dyn Any + Send + Sync)>,
+}
+
+impl<'a> Context<'a> {
+    pub (crate) fn new(msg: &'a Message) -> Self { Context { msg, outgoing: Default::default(), fallback: None, params: vec!(), app_data: None } }
+
+    pub (crate) fn with_app_data(mut self, app_data: Option<&'a (dyn Any + Send + Sync)>) -> Self {
+        self.app_data = app_data;
+        self
+    }
+
+    /// Sets the path of the object handling the call, if it differs from the path of the message.
+    pub (crate) fn with_object_path(mut self, object: &CStr) -> Self {
+        let p = match self.msg.path() { Some(p) => p, None => return self };
+        let (o, p) = (object.to_bytes(), p.as_cstr().to_bytes());
+        if o == p { return self }
+        // Path templates have elements with braces, which are not allowed in object paths.
+        if o.contains(&b'{') {
+            self.params = super::crossroads::template_params(o, p).unwrap_or_default();
+            return self
+        }
+        let sub = if o == b"/" { &p[1..] } else { &p[o.len()+1..] };
+        let object = PathName::from_slice(object.to_bytes_with_nul()).unwrap().into_static();
+        self.fallback = Some((object, String::from_utf8_lossy(sub).into_owned()));
+        self
+    }
+
+    /// The message being handled.
+    pub fn message(&self) -> &'a Message { self.msg }
+
+    pub fn sender(&self) -> Option<BusName<'a>> { self.msg.sender() }
+    pub fn destination(&self) -> Option<BusName<'a>> { self.msg.destination() }
+    pub fn path(&self) -> Option<PathName<'a>> { self.msg.path() }
+    pub fn interface(&self) -> Option<IfaceName<'a>> { self.msg.interface() }
+    pub fn member(&self) -> Option<MemberName<'a>> { self.msg.member() }
+    pub fn serial(&self) -> u32 { self.msg.get_serial() }
+
+    /// The application data set with `Crossroads::set_app_data`, if it is of type T.
+    pub fn app_data<T: Any>(&self) -> Option<&'a T> { self.app_data.and_then(|a| a.downcast_ref()) }
+
+    /// If the call was dispatched to a fallback path, that path.
+    pub fn fallback_path(&self) -> Option<&PathName<'static>> { self.fallback.as_ref().map(|f| &f.0) }
+    /// If the call was dispatched to a fallback path, the rest of the path of the message,
+    /// e g "track123" for a call to "/org/example/player/track123" handled by "/org/example/player".
+    pub fn sub_path(&self) -> Option<&str> { self.fallback.as_ref().map(|f| &*f.1) }
+
+    /// If the call was dispatched to a path template, the path element matched by "{name}".
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|x| x.0 == name).map(|x| &*x.1)
+    }
+
+    /// Asks the bus for the credentials of the sender, e g to check its uid.
+    ///
+    /// Blocks until the bus replies. c is the connection the method call came from.
+    pub fn caller_credentials(&self, c: &crate::Connection) -> Result<crate::ConnectionCredentials, MethodErr> {
+        let sender = self.sender().ok_or_else(|| MethodErr::access_denied(&"Method call has no sender"))?;
+        Ok(c.connection_credentials(&sender)?)
+    }
+
+    /// True if the caller has set the NO_REPLY_EXPECTED flag.
+    pub fn no_reply_expected(&self) -> bool { self.msg.get_no_reply() }
+    /// True if the caller allows the bus to auto-start the destination.
+    pub fn auto_start(&self) -> bool { self.msg.get_auto_start() }
+
+    /// Replies to the method call later.
+    ///
+    /// Return None from the handler, then complete the call with `Crossroads::reply`
+    /// or a `Replier`, possibly from another thread.
+    pub fn defer(&self) -> DeferredReply { DeferredReply(self.msg.duplicate()) }
+
+    /// Queues a message (typically a signal), to be sent after the reply.
+    pub fn push_msg(&self, m: Message) { self.outgoing.borrow_mut().push(m) }
+
+    /// Starts recording property changes on the path of the message, to be signalled together.
+    ///
+    /// When committed, one PropertiesChanged signal per interface is queued with `push_msg`,
+    /// instead of one per property. Nothing is signalled if it is dropped without committing.
+    pub fn begin_prop_changes(&self) -> PropChanges<'_, 'a> { PropChanges { ctx: self, changes: BTreeMap::new() } }
+
+    pub (crate) fn into_msgs(self) -> Vec<Message> { self.outgoing.into_inner() }
+}
+
+/// Property changes to signal together, see `Context::begin_prop_changes`.
+#[derive(Debug)]
+pub struct PropChanges<'c, 'a> {
+    ctx: &'c Context<'a>,
+    // Changed and invalidated properties, by interface
+    changes: BTreeMap<String, (arg::PropMap, Vec<String>)>,
+}
+
+impl PropChanges<'_, '_> {
+    /// Records the new value of a property. If it was already recorded, the latest value is signalled.
+    ///
+    /// The value is broadcast, so use `invalidated` for properties with `PropInfo::read_access`.
+    pub fn changed<V: arg::RefArg + 'static>(&mut self, iface: &str, name: &str, value: V) -> &mut Self {
+        let c = self.changes.entry(iface.into()).or_insert_with(Default::default);
+        c.1.retain(|x| x != name);
+        c.0.insert(name.into(), arg::Variant(Box::new(value)));
+        self
+    }
+
+    /// Records that a property has changed, without signalling its value.
+    pub fn invalidated(&mut self, iface: &str, name: &str) -> &mut Self {
+        let c = self.changes.entry(iface.into()).or_insert_with(Default::default);
+        c.0.remove(name);
+        if !c.1.iter().any(|x| x == name) { c.1.push(name.into()) }
+        self
+    }
+
+    /// Queues the PropertiesChanged signals.
+    pub fn commit(self) {
+        let path = match self.ctx.path() { Some(p) => p, None => return };
+        for (iface, (changed, invalidated)) in self.changes {
+            let s = PropertiesPropertiesChanged { interface_name: iface, changed_properties: changed.0, invalidated_properties: invalidated };
+            self.ctx.push_msg(s.to_emit_message(&path));
+        }
+    }
+}
+
+/// A method call that will be replied to later. Obtained from `Context::defer`.
+///
+/// Complete it with `Crossroads::reply` or `Replier::reply`, or send a reply made from it on
+/// the connection the call came from.
+#[derive(Debug)]
+pub struct DeferredReply(Message);
+
+impl DeferredReply {
+    /// The serial of the method call.
+    pub fn serial(&self) -> u32 { self.0.get_serial() }
+    /// The unique name of the caller.
+    pub fn sender(&self) -> Option<BusName> { self.0.sender() }
+    /// Makes a method return. Append the out arguments to it before replying.
+    pub fn method_return(&self) -> Message { self.0.method_return() }
+    /// Makes an error reply.
+    pub fn error(&self, e: &MethodErr) -> Message { e.to_message(&self.0) }
+}
+
+/// Completes deferred method calls from other threads. Obtained from `Crossroads::replier`.
+///
+/// The replies are queued on the Crossroads, and sent by the serve loop like `take_signals`.
+#[derive(Debug, Clone)]
+pub struct Replier(pub (crate) Arc<Mutex<Vec<Message>>>);
+
+impl Replier {
+    /// Queues `msg` as the reply to the deferred call, typically made with
+    /// `token.method_return()` or `token.error()`.
+    ///
+    /// If `msg` is not already a reply to the call, its reply serial and destination are set.
+    pub fn reply(&self, token: DeferredReply, mut msg: Message) {
+        if msg.get_reply_serial() != Some(token.serial()) {
+            crate::message::message_set_reply_serial(&mut msg, token.serial());
+            msg.set_destination(token.sender());
+        }
+        self.0.lock().unwrap().push(msg)
+    }
+}
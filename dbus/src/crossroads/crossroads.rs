@@ -1,25 +1,101 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::any::{TypeId, Any};
 use std::ffi::{CString, CStr};
 use std::fmt;
-use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, Signature, Message, MessageType};
-use super::info::{IfaceInfo, MethodInfo, PropInfo};
-use super::handlers::{Handlers, SyncInfo};
-use super::stdimpl::DBusProperties;
+use std::future::Future;
+use std::pin::Pin;
+use std::task;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, Signature, Message, MessageType, SignalArgs, arg};
+use crate::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use super::info::{IfaceInfo, MethodInfo, PropInfo, SignalInfo, EmitsChangedSignal};
+use super::handlers::{self, Handlers, SyncInfo, Mut, MutMethod, Async, AsyncInfo, AsyncReply, Local, LocalInfo, Par, ParInfo, AnyIface, BoxIface, RefIface};
+use super::context::{Context, DeferredReply, Replier};
+use super::middleware::{Middleware, Middlewares};
+use crate::tree::MethodErr;
+use super::stdimpl::{self, DBusProperties, DBusIntrospectable, DBusObjectManager, DBusMetrics, DBusPeer};
+use super::metrics::{Metrics, MetricsCollector};
+use super::order::{ReplyOrder, Ticket};
+use super::trace::{DispatchSpan, traced};
 
 // The key is an IfaceName, but if we have that we bump into https://github.com/rust-lang/rust/issues/59732
 // so we use CString as a workaround.
 #[derive(Default, Debug)]
 struct IfaceReg<H: Handlers>(BTreeMap<CString, (TypeId, IfaceInfo<'static, H>)>);
 
-#[derive(Default)]
-pub struct PathData<H: Handlers>(Vec<(TypeId, H::Iface)>);
+/// The interfaces of an object path.
+pub struct PathData<H: Handlers> {
+    ifaces: Vec<(TypeId, H::Iface)>,
+    // Interfaces with their own IfaceInfo, not looked up through the TypeId.
+    instances: Vec<(IfaceInfo<'static, H>, H::Iface)>,
+    fallback: bool,
+    std_ifaces: bool,
+}
+
+impl<H: Handlers> Default for PathData<H> {
+    fn default() -> Self { PathData::new() }
+}
+
+impl<H: Handlers> PathData<H> where H::Iface: AnyIface {
+    pub fn insert<I: Any + 'static>(&mut self, i: I) where H::Iface: BoxIface<I> {
+        let id = TypeId::of::<I>();
+        let t = H::Iface::box_iface(i);
+        self.ifaces.push((id, t));
+    }
+
+    /// The interface data of type I, if present.
+    pub fn get_mut<I: Any + 'static>(&mut self) -> Option<&mut I> {
+        let id = TypeId::of::<I>();
+        self.ifaces.iter_mut().find(|x| x.0 == id).and_then(|x| x.1.downcast_mut())
+    }
+
+    /// Inserts interface data together with an IfaceInfo for this path only.
+    ///
+    /// The interface does not need to be registered with `Crossroads::register`, so the same
+    /// interface can have different handlers on different paths, and the same type can back
+    /// several interfaces. It takes precedence over a registered interface with the same name.
+    pub fn insert_instance<I: Any + 'static>(&mut self, info: IfaceInfo<'static, H>, i: I) where H::Iface: BoxIface<I> {
+        self.instances.retain(|x| x.0.name != info.name);
+        self.instances.push((info, H::Iface::box_iface(i)));
+    }
+
+    /// Removes the interface data of type I, if present.
+    pub fn remove<I: Any + 'static>(&mut self) -> Option<I> {
+        let id = TypeId::of::<I>();
+        let idx = self.ifaces.iter().position(|x| x.0 == id)?;
+        self.ifaces.remove(idx).1.into_inner()
+    }
+
+    /// Replaces the interface data of type I, returning the old data.
+    /// If there was none, it is inserted.
+    pub fn replace<I: Any + 'static>(&mut self, i: I) -> Option<I> where H::Iface: BoxIface<I> {
+        let id = TypeId::of::<I>();
+        match self.ifaces.iter_mut().find(|x| x.0 == id) {
+            Some(x) => {
+                let old = std::mem::replace(&mut x.1, H::Iface::box_iface(i));
+                old.into_inner()
+            },
+            None => { self.insert(i); None },
+        }
+    }
+}
+
+impl<H: Handlers> PathData<H> where H::Iface: RefIface {
+    /// The interface data of type I, if present.
+    ///
+    /// For the Par flavor, where the data is behind a Mutex, use `lock` instead.
+    pub fn get<I: Any + 'static>(&self) -> Option<&I> {
+        let id = TypeId::of::<I>();
+        self.ifaces.iter().find(|x| x.0 == id).and_then(|x| x.1.downcast_ref())
+    }
+}
 
-impl PathData<()> {
-    pub fn insert<I: Any + 'static + Send + Sync>(&mut self, i: I) {
+impl PathData<Par> {
+    /// Locks the interface data of type I, if present. This is `get` of the other flavors.
+    pub fn lock<I: Any + 'static>(&self) -> Option<MutexGuard<I>> {
         let id = TypeId::of::<I>();
-        let t = Box::new(i);
-        self.0.push((id, t));
+        self.ifaces.iter().find(|x| x.0 == id).and_then(|x| x.1.lock())
     }
 }
 
@@ -28,28 +104,95 @@ impl<H: Handlers> fmt::Debug for PathData<H> {
 }
 
 impl<H: Handlers> PathData<H> {
-    fn new() -> Self { PathData(vec!()) }
+    pub fn new() -> Self { PathData { ifaces: vec!(), instances: vec!(), fallback: false, std_ifaces: true } }
+
+    /// Makes the standard interfaces (Introspectable, Peer and Properties) available on this path
+    /// only if they are inserted explicitly. By default, they are available on every path.
+    pub fn disable_std_ifaces(&mut self) { self.std_ifaces = false }
+
+    /// True if the path was inserted with `Crossroads::insert_fallback`.
+    pub fn is_fallback(&self) -> bool { self.fallback }
+
+    /// Removes an interface inserted with `insert_instance`.
+    pub fn remove_instance(&mut self, iface: &str) -> Option<(IfaceInfo<'static, H>, H::Iface)> {
+        let idx = self.instances.iter().position(|x| &*x.0.name == iface)?;
+        Some(self.instances.remove(idx))
+    }
 }
 
+// Inserted paths, and path templates (which are not valid object paths).
 #[derive(Debug)]
-struct IfacePaths<H: Handlers>(BTreeMap<CString, PathData<H>>);
+struct IfacePaths<H: Handlers>(BTreeMap<CString, PathData<H>>, BTreeMap<CString, PathData<H>>);
 
 impl<H: Handlers> Default for IfacePaths<H> {
-    fn default() -> Self { IfacePaths(BTreeMap::new()) }
+    fn default() -> Self { IfacePaths(BTreeMap::new(), BTreeMap::new()) }
+}
+
+impl<H: Handlers> IfacePaths<H> {
+    /// The data of an inserted path or template, as returned from `Crossroads::find_path`.
+    fn data(&self, key: &CStr) -> &PathData<H> { self.0.get(key).or_else(|| self.1.get(key)).unwrap() }
+
+    fn data_mut(&mut self, key: &CStr) -> &mut PathData<H> {
+        match self.0.get_mut(key) { Some(data) => data, None => self.1.get_mut(key).unwrap() }
+    }
+}
+
+/// Checks that a path template is an object path, where some elements can be "{name}".
+fn check_template(t: &str) -> Result<(), String> {
+    let elem_ok = |e: &str| !e.is_empty() && e.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_');
+    if !t.starts_with('/') || t.len() < 2 || !t.contains('{') {
+        return Err(format!("Path template '{}' must start with '/' and have at least one parameter", t));
+    }
+    for e in t[1..].split('/') {
+        let name = if e.starts_with('{') && e.ends_with('}') { &e[1..e.len()-1] } else { e };
+        if !elem_ok(name) { return Err(format!("Path template '{}' has an invalid element '{}'", t, e)) }
+    }
+    Ok(())
+}
+
+/// The parameters of a path template, if the path matches it.
+pub (super) fn template_params(template: &[u8], path: &[u8]) -> Option<Vec<(String, String)>> {
+    let (mut t, mut p) = (template.split(|&c| c == b'/'), path.split(|&c| c == b'/'));
+    let mut r = vec!();
+    loop {
+        match (t.next(), p.next()) {
+            (None, None) => return Some(r),
+            (Some(te), Some(pe)) if te.first() == Some(&b'{') => {
+                if pe.is_empty() { return None }
+                let name = String::from_utf8_lossy(&te[1..te.len()-1]).into_owned();
+                r.push((name, String::from_utf8_lossy(pe).into_owned()));
+            },
+            (Some(te), Some(pe)) if te == pe => {},
+            _ => return None,
+        }
+    }
 }
 
 struct MsgHeaders<'a> {
+    msg: &'a Message,
     m: MemberName<'a>,
-    i: IfaceName<'a>,
+    // Method calls without an interface are dispatched to the only interface with the method.
+    i: Option<IfaceName<'a>>,
     p: PathName<'a>,
 }
 
+pub (super) type IfacePropMap = HashMap<String, arg::PropMap>;
+
+fn is_descendant(parent: &[u8], child: &[u8]) -> bool {
+    if parent == b"/" { return child.len() > 1 }
+    child.len() > parent.len() + 1 && child.starts_with(parent) && child[parent.len()] == b'/'
+}
+
+fn error_reply(msg: &Message, e: MethodErr) -> Option<Message> {
+    if msg.get_no_reply() { None } else { Some(e.to_message(msg)) }
+}
+
 fn msg_headers(msg: &Message) -> Option<MsgHeaders> {
     if msg.msg_type() != MessageType::MethodCall { return None };
     let p = msg.path()?;
-    let i = msg.interface()?;
+    let i = msg.interface();
     let m = msg.member()?;
-    Some(MsgHeaders { m, i, p })
+    Some(MsgHeaders { msg, m, i, p })
 }
 
 pub (super) struct MLookup<'a, H: Handlers> {
@@ -61,64 +204,896 @@ pub (super) struct MLookup<'a, H: Handlers> {
 //    pub (super) pinfo: Option<&'a PropInfo<'static, H>>,
 }
 
+struct Authorize(Box<dyn Fn(&Context) -> Result<(), MethodErr> + Send + Sync + 'static>);
+
+impl fmt::Debug for Authorize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "Authorize") }
+}
+
+struct CatchAll(Box<dyn Fn(&Context) -> Option<Message> + Send + Sync + 'static>);
+
+impl fmt::Debug for CatchAll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "CatchAll") }
+}
+
 #[derive(Debug)]
 pub struct Crossroads<H: Handlers> {
     reg: IfaceReg<H>,
     paths: IfacePaths<H>,
+    authorize: Option<Authorize>,
+    catch_all: Option<CatchAll>,
+    allowed_senders: Option<BTreeSet<String>>,
+    app_data: Option<Box<dyn Any + Send + Sync>>,
+    middleware: Middlewares,
+    metrics: Option<Arc<MetricsCollector>>,
+    reply_order: Option<Arc<ReplyOrder>>,
+    // Also deferred replies, shared with the Repliers.
+    signals: Arc<Mutex<Vec<Message>>>,
+    // Interface data for the standard interfaces on paths where they are not inserted.
+    no_data: H::Iface,
+    introspect_cache: IntrospectCache,
 }
 
+/// Statistics of the cache of introspection XML, see `Crossroads::introspect_cache_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntrospectCacheStats {
+    /// Number of times the XML was taken from the cache.
+    pub hits: u64,
+    /// Number of times the XML had to be generated.
+    pub misses: u64,
+    /// Number of paths currently cached.
+    pub entries: usize,
+}
+
+/// Generated introspection XML, by path.
+#[derive(Debug, Default)]
+struct IntrospectCache(Mutex<(HashMap<CString, String>, IntrospectCacheStats)>);
+
+impl IntrospectCache {
+    fn get(&self, p: &CStr) -> Option<String> {
+        let mut c = self.0.lock().unwrap();
+        let xml = c.0.get(p).cloned();
+        if xml.is_some() { c.1.hits += 1 } else { c.1.misses += 1 }
+        xml
+    }
+
+    fn put(&self, p: &CStr, xml: String) { self.0.lock().unwrap().0.insert(p.into(), xml); }
+
+    /// Forgets the XML of a path, the paths above it (which list it as a child node)
+    /// and the paths below it (which might be dispatched to it, if it is a fallback path).
+    fn invalidate(&self, p: &CStr) {
+        let p = p.to_bytes();
+        self.0.lock().unwrap().0.retain(|k, _| {
+            let k = k.as_bytes();
+            k != p && !is_descendant(k, p) && !is_descendant(p, k)
+        });
+    }
+
+    fn clear(&self) { self.0.lock().unwrap().0.clear() }
+
+    fn stats(&self) -> IntrospectCacheStats {
+        let c = self.0.lock().unwrap();
+        IntrospectCacheStats { entries: c.0.len(), ..c.1 }
+    }
+}
+
+/// Interfaces that are available on every path, unless disabled with `PathData::disable_std_ifaces`.
+const STD_IFACES: [&str; 3] = ["org.freedesktop.DBus.Introspectable", "org.freedesktop.DBus.Peer", "org.freedesktop.DBus.Properties"];
+
 impl<H: Handlers> Crossroads<H> {
 
     pub fn register<I: 'static>(&mut self, info: IfaceInfo<'static, H>) -> Option<IfaceInfo<'static, H>> {
+        self.introspect_cache.clear();
         self.reg.0.insert(info.name.clone().into_cstring(), (TypeId::of::<I>(), info)).map(|x| x.1)
     }
-    pub fn insert<N: Into<PathName<'static>>>(&mut self, name: N, data: PathData<H>) {
-        self.paths.0.insert(name.into().into_cstring(), data);
+    /// Replaces the IfaceInfo of a registered interface, keeping the type it was registered for.
+    ///
+    /// Calls dispatched after this use the new handlers. Returns the old IfaceInfo, or gives back
+    /// `info` if no interface with that name is registered.
+    pub fn replace_iface(&mut self, info: IfaceInfo<'static, H>) -> Result<IfaceInfo<'static, H>, IfaceInfo<'static, H>> {
+        match self.reg.0.get_mut(info.name.as_cstr()) {
+            Some(entry) => {
+                self.introspect_cache.clear();
+                Ok(std::mem::replace(&mut entry.1, info))
+            },
+            None => Err(info),
+        }
+    }
+    /// Adds a method to the interface registered for I, replacing a method with the same name.
+    ///
+    /// Calls dispatched after this can call the method, and it is included in introspection.
+    /// Gives back the method if there is no interface, or more than one interface, registered for I.
+    pub fn add_method<I: 'static>(&mut self, m: MethodInfo<'static, H>) -> Result<(), MethodInfo<'static, H>> {
+        let id = TypeId::of::<I>();
+        let mut found = self.reg.0.values_mut().filter(|(typeid, _)| *typeid == id);
+        let iinfo = match (found.next(), found.next()) {
+            (Some((_, iinfo)), None) => iinfo,
+            _ => return Err(m),
+        };
+        iinfo.methods.retain(|x| x.name != m.name);
+        iinfo.methods.push(m);
+        self.introspect_cache.clear();
+        Ok(())
+    }
+    /// Inserts a path.
+    ///
+    /// If there is an ObjectManager on a path above it, an InterfacesAdded signal is queued,
+    /// see `take_signals`.
+    pub fn insert<N: Into<PathName<'static>>>(&mut self, name: N, mut data: PathData<H>) {
+        data.fallback = false;
+        self.insert_path(name.into(), data);
+    }
+    /// Inserts a fallback path.
+    ///
+    /// Method calls on paths below it that are not inserted themselves, are dispatched to
+    /// the nearest fallback path above them. The handler can get the rest of the path from
+    /// `Context::sub_path`.
+    pub fn insert_fallback<N: Into<PathName<'static>>>(&mut self, name: N, mut data: PathData<H>) {
+        data.fallback = true;
+        self.insert_path(name.into(), data);
+    }
+    fn insert_path(&mut self, name: PathName<'static>, data: PathData<H>) {
+        self.introspect_cache.invalidate(name.as_cstr());
+        let old = self.paths.0.insert(name.clone().into_cstring(), data);
+        if let Some(om) = self.object_manager_above(name.as_cstr()) { self.queue_path_inserted(&om, &name, old) }
+    }
+    /// Inserts many paths below `prefix` in one call, e g BlueZ style "hci0/dev_00_11_22_33_44_55" below "/org/bluez".
+    ///
+    /// All paths are checked before any of them is inserted, so if one of them is not a valid
+    /// object path, nothing is inserted. If there is an ObjectManager above the paths, one
+    /// InterfacesAdded signal is queued per path, like `insert` does.
+    pub fn insert_tree<N, S, I>(&mut self, prefix: N, children: I) -> Result<(), String>
+    where N: Into<PathName<'static>>, S: AsRef<str>, I: IntoIterator<Item=(S, PathData<H>)> {
+        let prefix = prefix.into();
+        let base = if &*prefix == "/" { "" } else { &*prefix };
+        let children = children.into_iter().map(|(sub, mut data)| {
+            data.fallback = false;
+            PathName::new(format!("{}/{}", base, sub.as_ref())).map(|name| (name, data))
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        // Invalidating once for the prefix covers all paths below it.
+        self.introspect_cache.invalidate(prefix.as_cstr());
+        let inserted: Vec<_> = children.into_iter().map(|(name, data)| {
+            let old = self.paths.0.insert(name.clone().into_cstring(), data);
+            (name, old)
+        }).collect();
+        // Many paths usually share a parent, so look up its ObjectManager only once.
+        let mut oms: HashMap<Vec<u8>, Option<PathName<'static>>> = HashMap::new();
+        for (name, old) in inserted {
+            let b = name.as_cstr().to_bytes();
+            let parent = b[..b.iter().rposition(|&c| c == b'/').unwrap_or(0)].to_vec();
+            let om = oms.entry(parent).or_insert_with(|| self.object_manager_above(name.as_cstr())).clone();
+            if let Some(om) = om { self.queue_path_inserted(&om, &name, old) }
+        }
+        Ok(())
+    }
+    /// Inserts a path template, such as "/org/example/Player/{id}".
+    ///
+    /// Method calls on paths matching the template, that are not inserted themselves, are dispatched
+    /// to it. The handler can get the matched path elements from `Context::path_param`.
+    /// If several templates match, the first one in sorted order is used. Paths matching a template are not
+    /// listed in introspection or by ObjectManagers, as they are not known in advance.
+    pub fn insert_template(&mut self, template: &str, data: PathData<H>) -> Result<(), String> {
+        check_template(template)?;
+        self.introspect_cache.clear();
+        self.paths.1.insert(CString::new(template).unwrap(), data);
+        Ok(())
+    }
+    pub fn remove_template(&mut self, template: &str) -> Option<PathData<H>> {
+        self.introspect_cache.clear();
+        self.paths.1.remove(&*CString::new(template).ok()?)
     }
     pub fn get_data<N: Into<PathName<'static>>>(&self, name: N) -> Option<&PathData<H>> {
         self.paths.0.get(name.into().as_cstr())
     }
+    pub fn get_data_mut<N: Into<PathName<'static>>>(&mut self, name: N) -> Option<&mut PathData<H>> {
+        let name = name.into();
+        // The interfaces might be changed.
+        self.introspect_cache.invalidate(name.as_cstr());
+        self.paths.0.get_mut(name.as_cstr())
+    }
+    /// Removes a path.
+    ///
+    /// If there is an ObjectManager on a path above it, an InterfacesRemoved signal is queued,
+    /// see `take_signals`.
+    pub fn remove<N: Into<PathName<'static>>>(&mut self, name: N) -> Option<PathData<H>> {
+        let name = name.into();
+        let data = self.paths.0.remove(name.as_cstr())?;
+        self.introspect_cache.invalidate(name.as_cstr());
+        if let Some(om) = self.object_manager_above(name.as_cstr()) { self.queue_interfaces_removed(&om, &name, &data) }
+        Some(data)
+    }
+    /// Removes a path and all paths below it.
+    pub fn remove_subtree<N: Into<PathName<'static>>>(&mut self, prefix: N) -> Vec<(PathName<'static>, PathData<H>)> {
+        let prefix = prefix.into();
+        self.introspect_cache.invalidate(prefix.as_cstr());
+        let p = prefix.as_cstr().to_bytes();
+        let keys: Vec<CString> = self.paths.0.keys()
+            .filter(|k| k.as_bytes() == p || is_descendant(p, k.as_bytes())).cloned().collect();
+        let removed: Vec<_> = keys.into_iter().map(|k| {
+            let data = self.paths.0.remove(&k).unwrap();
+            (PathName::from_slice(k.as_bytes_with_nul()).unwrap().into_static(), data)
+        }).collect();
+        for (name, data) in &removed {
+            if let Some(om) = self.object_manager_above(name.as_cstr()) { self.queue_interfaces_removed(&om, name, data) }
+        }
+        removed
+    }
+
+    /// The nearest path above `path` that has an ObjectManager.
+    fn object_manager_above(&self, path: &CStr) -> Option<PathName<'static>> {
+        let mut b = path.to_bytes();
+        while b.len() > 1 {
+            let idx = b.iter().rposition(|&c| c == b'/')?;
+            b = if idx == 0 { b"/" } else { &b[..idx] };
+            let k = CString::new(b).unwrap();
+            if let Some(data) = self.paths.0.get(&k) {
+                if self.path_ifaces(data).any(|(iinfo, _)| &*iinfo.name == "org.freedesktop.DBus.ObjectManager") {
+                    return Some(PathName::from_slice(k.as_bytes_with_nul()).unwrap().into_static())
+                }
+            }
+        }
+        None
+    }
+
+    fn queue_path_inserted(&self, om: &PathName, name: &PathName, old: Option<PathData<H>>) {
+        if let Some(old) = old { self.queue_interfaces_removed(om, name, &old) }
+        if self.path_ifaces(&self.paths.0[name.as_cstr()]).next().is_some() {
+            if let Some(m) = DBusObjectManager::interfaces_added(self, om, name) { self.queue_signal(m) }
+        }
+    }
+
+    fn queue_interfaces_removed(&self, om: &PathName, name: &PathName, data: &PathData<H>) {
+        if self.path_ifaces(data).next().is_none() { return }
+        self.queue_signal(DBusObjectManager::interfaces_removed(self, om, name, data))
+    }
+
+    fn queue_signal(&self, m: Message) { self.signals.lock().unwrap().push(m) }
+
+    /// Completes a method call that was deferred with `Context::defer`.
+    ///
+    /// The reply is queued and sent like the signals of `take_signals`; see `Replier::reply`.
+    pub fn reply(&self, token: DeferredReply, msg: Message) { self.replier().reply(token, msg) }
+
+    /// A handle for completing deferred method calls from threads that do not have the Crossroads.
+    pub fn replier(&self) -> Replier { Replier(self.signals.clone()) }
+
+    /// Takes the signals queued by Crossroads itself, such as ObjectManager signals when
+    /// paths are inserted or removed, and deferred replies. These should be sent on
+    /// the connection; `serve` does that.
+    pub fn take_signals(&self) -> Vec<Message> {
+        std::mem::replace(&mut *self.signals.lock().unwrap(), vec!())
+    }
+
+    /// Sets data shared by all handlers, e g configuration. Handlers get it from `Context::app_data`.
+    pub fn set_app_data<T: Any + Send + Sync>(&mut self, data: T) {
+        self.app_data = Some(Box::new(data));
+    }
+
+    /// The data set with `set_app_data`, if it is of type T.
+    pub fn app_data<T: Any>(&self) -> Option<&T> { self.raw_app_data().and_then(|a| a.downcast_ref()) }
+
+    pub (super) fn raw_app_data(&self) -> Option<&(dyn Any + Send + Sync)> { self.app_data.as_ref().map(|a| &**a) }
+
+    /// Starts collecting call counts, error counts and handler times for each method.
+    ///
+    /// Insert `DBusMetrics` on a path to make them available over D-Bus.
+    pub fn enable_metrics(&mut self) {
+        if self.metrics.is_none() { self.metrics = Some(Default::default()) }
+    }
+
+    /// The metrics collected so far. Empty unless `enable_metrics` has been called.
+    pub fn metrics(&self) -> Metrics { self.metrics.as_ref().map(|m| m.get()).unwrap_or_default() }
+
+    fn record_call(&self, iface: &str, headers: &MsgHeaders, reply: Option<&Message>, start: Instant) {
+        if let Some(m) = &self.metrics { m.record(iface, &headers.m, reply, start.elapsed()) }
+    }
+
+    /// Adds middleware to run around every dispatched method call, after the authorization checks.
+    pub fn add_middleware<M: Middleware>(&mut self, m: M) { self.middleware.push(m) }
+
+    /// Sets a function that is called before every method call is dispatched.
+    ///
+    /// If it returns an error, that is replied instead of calling the method handler.
+    /// Use `MethodErr::access_denied` to reject callers.
+    pub fn set_authorize<F>(&mut self, f: F) where F: Fn(&Context) -> Result<(), MethodErr> + Send + Sync + 'static {
+        self.authorize = Some(Authorize(Box::new(f)));
+    }
+
+    /// Sets a function that gets the method calls that could not be dispatched, because the path,
+    /// interface or method is not found, e g for forwarding them somewhere else.
+    ///
+    /// It does not get calls rejected by `set_authorize` or `set_allowed_senders`, and middleware
+    /// is not run around it. Its reply, and messages pushed to the context, are returned from `dispatch`.
+    /// To reply later, e g when a forwarded call has been replied to, use `Context::defer`.
+    pub fn set_catch_all<F>(&mut self, f: F) where F: Fn(&Context) -> Option<Message> + Send + Sync + 'static {
+        self.catch_all = Some(CatchAll(Box::new(f)));
+    }
+
+    /// Only accepts method calls from these unique names. Other callers get an AccessDenied error.
+    ///
+    /// Unique names are what senders are identified by, see `allow_sender` for well-known names.
+    pub fn set_allowed_senders<I: IntoIterator<Item=S>, S: Into<String>>(&mut self, names: I) {
+        self.allowed_senders = Some(names.into_iter().map(|s| s.into()).collect());
+    }
+
+    /// Accepts method calls from this unique name, in addition to the already allowed ones.
+    pub (super) fn add_allowed_sender(&mut self, name: String) {
+        self.allowed_senders.get_or_insert_with(BTreeSet::new).insert(name);
+    }
+
+    /// Accepts method calls from everyone again.
+    pub fn clear_allowed_senders(&mut self) { self.allowed_senders = None }
+
+    fn check_authorized(&self, ctx: &Context) -> Result<(), MethodErr> {
+        if let Some(allowed) = &self.allowed_senders {
+            let sender = ctx.sender();
+            if !sender.as_ref().map(|s| allowed.contains(&**s)).unwrap_or(false) {
+                return Err(MethodErr::access_denied(&format!("Sender {} is not allowed",
+                    sender.as_ref().map(|s| &**s).unwrap_or("(none)"))));
+            }
+        }
+        self.authorize.as_ref().map_or(Ok(()), |f| (f.0)(ctx))
+    }
+
+    /// Finds the path, or the nearest fallback path above it.
+    fn find_path(&self, p: &CStr) -> Option<(&CStr, &PathData<H>)> {
+        if let Some((k, data)) = self.paths.0.get_key_value(p) { return Some((k, data)) }
+        if let Some((k, data)) = self.paths.1.iter().find(|(k, _)| template_params(k.as_bytes(), p.to_bytes()).is_some()) {
+            return Some((k, data))
+        }
+        let mut b = p.to_bytes();
+        while b.len() > 1 {
+            let idx = b.iter().rposition(|&c| c == b'/')?;
+            b = if idx == 0 { b"/" } else { &b[..idx] };
+            let k = CString::new(b).unwrap();
+            if let Some((k, data)) = self.paths.0.get_key_value(&k) {
+                if data.fallback { return Some((k, data)) }
+            }
+        }
+        None
+    }
 
-    fn reg_lookup(&self, headers: &MsgHeaders) -> Option<(MLookup<H>, &MethodInfo<'static, H>)> {
-       let (typeid, iinfo) = self.reg.0.get(headers.i.as_cstr())?;
-       let minfo = iinfo.methods.iter().find(|x| x.name == headers.m)?;
-       let data = self.paths.0.get(headers.p.as_cstr())?;
-       let (_, iface) = data.0.iter().find(|x| x.0 == *typeid)?;
-       Some((MLookup { cr: self, data, iface, iinfo }, minfo))
+    fn reg_lookup(&self, headers: &MsgHeaders) -> Result<(MLookup<H>, &MethodInfo<'static, H>, &CStr), MethodErr> {
+       let (path, data) = self.find_path(headers.p.as_cstr()).ok_or_else(|| MethodErr::no_path(&headers.p))?;
+       let (iinfo, iface) = match &headers.i {
+           Some(i) => self.iface_lookup(data, i.as_cstr()).ok_or_else(|| MethodErr::no_interface(i))?,
+           None => self.member_lookup(data, &headers.m)?,
+       };
+       let minfo = iinfo.methods.iter().find(|x| x.name == headers.m).ok_or_else(|| MethodErr::no_method(&headers.m))?;
+       minfo.check_in_args(headers.msg)?;
+       Ok((MLookup { cr: self, data, iface, iinfo }, minfo, path))
     }
 
+    /// Finds the interface with a method, for method calls without an interface.
+    fn member_lookup<'a>(&'a self, data: &'a PathData<H>, m: &MemberName) -> Result<(&'a IfaceInfo<'static, H>, &'a H::Iface), MethodErr> {
+        let mut found: Vec<_> = self.path_ifaces(data).filter(|(iinfo, _)| iinfo.methods.iter().any(|x| x.name == *m)).collect();
+        match found.len() {
+            0 => Err(MethodErr::no_method(m)),
+            1 => Ok(found.remove(0)),
+            _ => Err(("org.freedesktop.DBus.Error.UnknownMethod", format!("Method {} exists on several interfaces ({}), specify one",
+                m, found.iter().map(|x| &*x.0.name).collect::<Vec<_>>().join(", "))).into()),
+        }
+    }
+
+    /// Finds a property. An empty interface name searches all interfaces on the path,
+    /// and then the property name must be unique among them.
     pub (super) fn reg_prop_lookup<'a>(&'a self, data: &'a PathData<H>, iname: &CStr, propname: &CStr) ->
-    Option<(MLookup<'a, H>, &PropInfo<'static, H>)> {
-       let (typeid, iinfo) = self.reg.0.get(iname)?;
-       let pinfo = iinfo.props.iter().find(|x| x.name.as_cstr() == propname)?;
-       let (_, iface) = data.0.iter().find(|x| x.0 == *typeid)?;
-       Some((MLookup { cr: self, data, iface, iinfo}, pinfo))       
+    Result<(MLookup<'a, H>, &PropInfo<'static, H>), MethodErr> {
+        let no_prop = || MethodErr::no_property(&propname.to_string_lossy());
+        let find = |iinfo: &'a IfaceInfo<'static, H>| iinfo.props.iter().find(|x| x.name.as_cstr() == propname);
+        if !iname.to_bytes().is_empty() {
+            let (iinfo, iface) = self.iface_lookup(data, iname).ok_or_else(no_prop)?;
+            let pinfo = find(iinfo).ok_or_else(no_prop)?;
+            return Ok((MLookup { cr: self, data, iface, iinfo}, pinfo))
+        }
+        let mut found: Vec<_> = self.path_ifaces(data).filter_map(|(iinfo, iface)| find(iinfo).map(|p| (iinfo, iface, p))).collect();
+        match found.len() {
+            0 => Err(no_prop()),
+            1 => { let (iinfo, iface, pinfo) = found.remove(0); Ok((MLookup { cr: self, data, iface, iinfo}, pinfo)) },
+            _ => Err(("org.freedesktop.DBus.Error.InvalidArgs", format!("Property {} exists on several interfaces ({}), specify one",
+                propname.to_string_lossy(), found.iter().map(|x| &*x.0.name).collect::<Vec<_>>().join(", "))).into()),
+        }
     }
-}
 
-impl Crossroads<()> {
-    pub fn dispatch(&self, msg: &Message) -> Option<Vec<Message>> {
-        let headers = msg_headers(msg)?;
-        let (lookup, minfo) = self.reg_lookup(&headers)?;
-        let handler = &minfo.handler.0;
-        let mut si = SyncInfo { cr: lookup.cr, pd: lookup.data };
-        let r = (handler)(&**lookup.iface, msg, &mut si);
-        Some(r.into_iter().collect())
+    /// All inserted paths, in sorted order.
+    pub fn paths<'a>(&'a self) -> impl Iterator<Item=PathName<'a>> + 'a {
+        self.paths.0.keys().map(|k| PathName::from_slice(k.as_bytes_with_nul()).unwrap())
+    }
+
+    /// The interfaces on a path, sorted by name. Empty if the path is not inserted.
+    pub fn interfaces<'a, N: Into<PathName<'a>>>(&'a self, path: N) -> impl Iterator<Item=(IfaceName<'a>, &'a IfaceInfo<'static, H>)> + 'a {
+        let data = self.paths.0.get(path.into().as_cstr());
+        data.into_iter().flat_map(move |data| self.path_ifaces(data)).map(|(iinfo, _)| (iinfo.name.clone(), iinfo))
+    }
+
+    /// The interfaces on a path, together with their registration info, sorted by interface name.
+    pub (super) fn path_ifaces<'a>(&'a self, data: &'a PathData<H>) -> impl Iterator<Item=(&'a IfaceInfo<'static, H>, &'a H::Iface)> + 'a {
+        let mut v: Vec<_> = self.reg.0.values().filter_map(|(typeid, iinfo)| {
+            if data.instances.iter().any(|x| x.0.name == iinfo.name) { return None }
+            data.ifaces.iter().find(|x| x.0 == *typeid).map(|(_, iface)| (iinfo, iface))
+        }).chain(data.instances.iter().map(|(iinfo, iface)| (iinfo, iface))).collect();
+        if data.std_ifaces {
+            for name in &STD_IFACES {
+                if v.iter().any(|(iinfo, _)| &*iinfo.name == *name) { continue }
+                if let Some((_, iinfo)) = self.reg.0.get(&*CString::new(*name).unwrap()) { v.push((iinfo, &self.no_data)) }
+            }
+        }
+        v.sort_by(|a, b| a.0.name.as_cstr().cmp(b.0.name.as_cstr()));
+        v.into_iter()
+    }
+
+    /// An interface on a path, together with its info.
+    fn iface_lookup<'a>(&'a self, data: &'a PathData<H>, iname: &CStr) -> Option<(&'a IfaceInfo<'static, H>, &'a H::Iface)> {
+        if let Some((iinfo, iface)) = data.instances.iter().find(|x| x.0.name.as_cstr() == iname) {
+            return Some((iinfo, iface))
+        }
+        let (typeid, iinfo) = self.reg.0.get(iname)?;
+        if let Some((_, iface)) = data.ifaces.iter().find(|x| x.0 == *typeid) { return Some((iinfo, iface)) }
+        let name = iname.to_str().ok()?;
+        if data.std_ifaces && STD_IFACES.contains(&name) { Some((iinfo, &self.no_data)) } else { None }
+    }
+
+    /// Calls the getter of a property. Returns None if the property is write-only or the getter failed.
+    pub (super) fn get_prop(&self, data: &PathData<H>, iface: &H::Iface, pinfo: &PropInfo<'static, H>, ctx: &Context) -> Option<Box<arg::RefArg>> {
+        let getf = pinfo.handlers.0.as_ref()?;
+        if !pinfo.readable(ctx) { return None; }
+        // Use a scratch message, so that a failing getter does not leave a half-written variant behind.
+        let mut m = Message::signal(&"/".into(), &"org.freedesktop.DBus.Properties".into(), &"Scratch".into());
+        let ok = {
+            let mut ia = arg::IterAppend::new(&mut m);
+            H::call_getprop(getf, iface, ctx, &mut ia, self, data)
+        };
+        if !ok { return None; }
+        arg::Iter::new(&m).get_refarg()
+    }
+
+    pub (super) fn iface_props(&self, data: &PathData<H>, iinfo: &IfaceInfo<'static, H>, iface: &H::Iface, ctx: &Context) -> arg::PropMap {
+        arg::PropMap(iinfo.props.iter().filter_map(|pinfo| {
+            self.get_prop(data, iface, pinfo, ctx).map(|v| (pinfo.name.to_string(), arg::Variant(v)))
+        }).collect())
+    }
+
+    pub (super) fn path_props(&self, data: &PathData<H>, ctx: &Context) -> IfacePropMap {
+        self.path_ifaces(data).map(|(iinfo, iface)| {
+            (iinfo.name.to_string(), self.iface_props(data, iinfo, iface, ctx))
+        }).collect()
     }
 
-    pub fn new_sync() -> Self { 
+    /// All objects below a path, with their interfaces and properties, as returned from GetManagedObjects.
+    pub (super) fn managed_objects(&self, parent: &PathName, ctx: &Context) -> HashMap<PathName<'static>, IfacePropMap> {
+        let parent = parent.as_cstr().to_bytes();
+        self.paths.0.iter().filter(|(k, _)| is_descendant(parent, k.as_bytes())).map(|(k, data)| {
+            let name = PathName::from_slice(k.as_bytes_with_nul()).unwrap().into_static();
+            (name, self.path_props(data, ctx))
+        }).collect()
+    }
+
+    /// Makes a PropertiesChanged signal for some properties of an interface on a path.
+    ///
+    /// Depending on the EmitsChangedSignal setting of each property, its new value is included,
+    /// it is listed as invalidated, or it is left out. Properties with `PropInfo::read_access`
+    /// are listed as invalidated instead of included. Returns None if there is nothing to signal.
+    pub fn properties_changed<'a, I>(&self, path: &PathName, iface: &IfaceName, props: I) -> Option<Message>
+    where I: IntoIterator<Item=&'a str> {
+        let data = self.paths.0.get(path.as_cstr())?;
+        let (iinfo, idata) = self.path_ifaces(data).find(|(iinfo, _)| *iinfo.name == **iface)?;
+        self.iface_props_changed(path, data, iinfo, idata, props)
+    }
+
+    pub (super) fn iface_props_changed<'a, I>(&self, path: &PathName, data: &PathData<H>, iinfo: &IfaceInfo<'static, H>,
+    idata: &H::Iface, props: I) -> Option<Message> where I: IntoIterator<Item=&'a str> {
+        // Property getters need a message; the signal itself will have to do.
+        let m = Message::signal(path, &PropertiesPropertiesChanged::INTERFACE.into(), &PropertiesPropertiesChanged::NAME.into());
+        let ctx = Context::new(&m).with_app_data(self.raw_app_data());
+        let mut s = PropertiesPropertiesChanged {
+            interface_name: iinfo.name.to_string(),
+            changed_properties: HashMap::new(),
+            invalidated_properties: vec!(),
+        };
+        for name in props {
+            let pinfo = match iinfo.props.iter().find(|p| &*p.name == name) { Some(p) => p, None => continue };
+            match pinfo.emits {
+                EmitsChangedSignal::True if pinfo.read_restricted() => s.invalidated_properties.push(name.into()),
+                EmitsChangedSignal::True => if let Some(v) = self.get_prop(data, idata, pinfo, &ctx) {
+                    s.changed_properties.insert(name.into(), arg::Variant(v));
+                },
+                EmitsChangedSignal::Invalidates => s.invalidated_properties.push(name.into()),
+                EmitsChangedSignal::Const | EmitsChangedSignal::False => {},
+            }
+        }
+        if s.changed_properties.is_empty() && s.invalidated_properties.is_empty() { return None; }
+        Some(s.to_emit_message(path))
+    }
+
+    /// Makes a signal declared in the IfaceInfo of an interface on a path.
+    ///
+    /// Returns None if the path does not have the interface, the interface has no such signal,
+    /// or the arguments do not match the signal's declaration.
+    pub fn emit_signal(&self, path: &PathName, iface: &IfaceName, name: &str, args: &[&arg::RefArg]) -> Option<Message> {
+        let data = self.paths.0.get(path.as_cstr())?;
+        let (iinfo, _) = self.iface_lookup(data, iface.as_cstr())?;
+        let sinfo = iinfo.signals.iter().find(|s| &*s.name == name)?;
+        if !sinfo.matches_args(args) { return None; }
+        let mut m = Message::signal(path, iface, &sinfo.name);
+        {
+            let mut ia = arg::IterAppend::new(&mut m);
+            for a in args { a.append(&mut ia); }
+        }
+        Some(m)
+    }
+
+    /// A Crossroads with the standard interfaces registered.
+    fn with_std_ifaces() -> Self {
         let mut cr = Crossroads {
             reg: IfaceReg(BTreeMap::new()),
-            paths: IfacePaths(BTreeMap::new()),
+            paths: Default::default(),
+            authorize: None,
+            catch_all: None,
+            allowed_senders: None,
+            app_data: None,
+            middleware: Default::default(),
+            metrics: None,
+            reply_order: None,
+            signals: Default::default(),
+            no_data: H::no_data(),
+            introspect_cache: Default::default(),
         };
         DBusProperties::register(&mut cr);
+        DBusIntrospectable::register(&mut cr);
+        DBusObjectManager::register(&mut cr);
+        DBusMetrics::register(&mut cr);
+        DBusPeer::register(&mut cr);
         cr
     }
+
+    /// Introspection XML for the interfaces registered on a path.
+    ///
+    /// The XML is cached until the path, or the paths or interfaces it depends on, are changed.
+    pub (super) fn introspect(&self, name: &PathName, data: Option<&PathData<H>>) -> String {
+        if let Some(xml) = self.introspect_cache.get(name.as_cstr()) { return xml }
+        let xml = self.make_introspect(name, data);
+        self.introspect_cache.put(name.as_cstr(), xml.clone());
+        xml
+    }
+
+    /// Statistics of the cache of introspection XML, for diagnostics.
+    pub fn introspect_cache_stats(&self) -> IntrospectCacheStats { self.introspect_cache.stats() }
+
+    fn make_introspect(&self, name: &PathName, data: Option<&PathData<H>>) -> String {
+        let ifacestr = data.into_iter().flat_map(|data| self.path_ifaces(data))
+            .fold(String::new(), |a, (iinfo, _)| a + &iinfo.introspect("  "));
+        let childstr = self.child_nodes(name).iter()
+            .fold(String::new(), |a, c| format!("{}  <node name=\"{}\"/>\n", a, c));
+        format!(r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node name="{}">
+{}{}</node>"##, name, ifacestr, childstr)
+    }
+
+    /// The introspection XML document for a path, the same as replied to an Introspect call.
+    ///
+    /// Works without a connection, so the XML can be e g saved and given to dbus-codegen.
+    /// Returns None if there is nothing to introspect at the path.
+    pub fn to_xml<N: Into<PathName<'static>>>(&self, path: N) -> Option<String> {
+        let path = path.into();
+        let data = self.find_path(path.as_cstr()).map(|(_, data)| data);
+        if data.is_none() && self.child_nodes(&path).is_empty() { return None }
+        Some(self.introspect(&path, data))
+    }
+
+    /// An introspection XML document with all registered interfaces, sorted by name.
+    ///
+    /// Interfaces inserted as instances on a single path are not included, use `to_xml` for those.
+    pub fn to_xml_all(&self) -> String {
+        let ifacestr = self.reg.0.values().fold(String::new(), |a, (_, iinfo)| a + &iinfo.introspect("  "));
+        format!(r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+{}</node>"##, ifacestr)
+    }
+
+    /// The names of the path elements directly below a path, that lead to inserted paths.
+    fn child_nodes(&self, name: &PathName) -> BTreeSet<String> {
+        let p = name.as_cstr().to_bytes();
+        let skip = if p == b"/" { 1 } else { p.len() + 1 };
+        self.paths.0.keys().filter(|k| is_descendant(p, k.as_bytes())).map(|k| {
+            let rest = &k.as_bytes()[skip..];
+            let end = rest.iter().position(|&c| c == b'/').unwrap_or(rest.len());
+            String::from_utf8_lossy(&rest[..end]).into_owned()
+        }).collect()
+    }
+
+    /// Dispatch for the flavors where method handlers are called with shared access to the tree.
+    fn dispatch_ref<F>(&self, msg: &Message, call: F) -> Option<Vec<Message>>
+    where F: FnOnce(&MLookup<H>, &MethodInfo<'static, H>, &Context) -> Option<Message> {
+        traced(msg, || self.dispatch_ref_untraced(msg, call))
+    }
+
+    fn dispatch_ref_untraced<F>(&self, msg: &Message, call: F) -> Option<Vec<Message>>
+    where F: FnOnce(&MLookup<H>, &MethodInfo<'static, H>, &Context) -> Option<Message> {
+        let headers = msg_headers(msg)?;
+        let (lookup, minfo, path) = match self.reg_lookup(&headers) {
+            Ok(x) => x,
+            Err(e) => { let (r, extra) = self.lookup_failed(&headers, e); return Some(r.into_iter().chain(extra).collect()) },
+        };
+        let ctx = Context::new(msg).with_object_path(path).with_app_data(self.raw_app_data());
+        if let Err(e) = self.check_authorized(&ctx) { return Some(error_reply(msg, e).into_iter().collect()) }
+        let (ran, rejected) = self.middleware.before(&ctx);
+        let mut r = match rejected {
+            Some(e) => Some(e.to_message(msg)),
+            None => {
+                let start = Instant::now();
+                let r = call(&lookup, minfo, &ctx);
+                self.record_call(&lookup.iinfo.name, &headers, r.as_ref(), start);
+                r
+            }
+        };
+        self.middleware.after(ran, msg, &mut r);
+        let r = if minfo.no_reply() || msg.get_no_reply() { None } else { r };
+        Some(r.into_iter().chain(ctx.into_msgs()).collect())
+    }
+
+    /// Makes the reply when a method call could not be dispatched, unless the caller expects no reply,
+    /// and messages pushed to the context by the catch-all handler.
+    ///
+    /// Paths that are not inserted, but have inserted paths below them, can still be introspected.
+    fn lookup_failed(&self, headers: &MsgHeaders, e: MethodErr) -> (Option<Message>, Vec<Message>) {
+        let no_reply = headers.msg.get_no_reply();
+        if headers.i.as_ref().map_or(true, |i| &**i == "org.freedesktop.DBus.Introspectable") && &*headers.m == "Introspect"
+        && self.find_path(headers.p.as_cstr()).is_none() && !self.child_nodes(&headers.p).is_empty() {
+            if no_reply { return (None, vec!()) }
+            return (Some(headers.msg.method_return().append1(self.introspect(&headers.p, None))), vec!());
+        }
+        if let Some(f) = &self.catch_all {
+            let ctx = Context::new(headers.msg).with_app_data(self.raw_app_data());
+            if let Err(e) = self.check_authorized(&ctx) { return (error_reply(headers.msg, e), vec!()) }
+            let r = (f.0)(&ctx);
+            return (r.filter(|_| !no_reply), ctx.into_msgs())
+        }
+        (error_reply(headers.msg, e), vec!())
+    }
+}
+
+impl Crossroads<()> {
+    /// Returns the reply (if any) and messages pushed to the context. Lookup failures are replied to
+    /// with an error. Returns None if the message is not a method call.
+    pub fn dispatch(&self, msg: &Message) -> Option<Vec<Message>> {
+        self.dispatch_ref(msg, |lookup, minfo, ctx| {
+            (minfo.handler.0)(&**lookup.iface, ctx.message(), &mut SyncInfo { cr: lookup.cr, pd: lookup.data, ctx })
+        })
+    }
+
+    pub fn new_sync() -> Self { Self::with_std_ifaces() }
+}
+
+impl Crossroads<Mut> {
+    /// See `Crossroads<()>::dispatch`.
+    pub fn dispatch(&mut self, msg: &Message) -> Option<Vec<Message>> {
+        traced(msg, || self.dispatch_untraced(msg))
+    }
+
+    fn dispatch_untraced(&mut self, msg: &Message) -> Option<Vec<Message>> {
+        let headers = msg_headers(msg)?;
+        let (iname, path) = match self.reg_lookup(&headers) {
+            Ok((lookup, _, path)) => (lookup.iinfo.name.clone(), path.to_owned()),
+            Err(e) => { let (r, extra) = self.lookup_failed(&headers, e); return Some(r.into_iter().chain(extra).collect()) },
+        };
+        // The lookup succeeded, so do it again, but this time with mutable access to the path data.
+        let ctx = Context::new(msg).with_object_path(&path).with_app_data(self.app_data.as_ref().map(|a| &**a));
+        if let Err(e) = self.check_authorized(&ctx) { return Some(error_reply(msg, e).into_iter().collect()) }
+        let find_method = |iinfo: &IfaceInfo<'static, Mut>| iinfo.methods.iter().position(|x| x.name == headers.m).unwrap();
+        let (ran, rejected) = self.middleware.before(&ctx);
+        let instance = self.paths.data(&path).instances.iter().position(|x| x.0.name == iname);
+        let (start, handled) = (Instant::now(), rejected.is_none());
+        let is_set = &*iname == "org.freedesktop.DBus.Properties" && &*headers.m == "Set";
+        let (mut r, no_reply) = match (rejected, instance) {
+            (Some(e), _) => (Some(e.to_message(msg)), false),
+            (None, None) if is_set => {
+                // The context borrows the app data, so the interface data is borrowed field by field.
+                let set = match self.prop_set_target(&ctx, &path) {
+                    Ok((iname, pname)) => prop_set_mut(&self.reg, &mut self.paths, &ctx, &path, &iname, &pname),
+                    Err(e) => Err(e),
+                };
+                let r = set.and_then(|_| stdimpl::prop_lookup(&ctx, self, self.paths.data(&path)))
+                    .map(|(lookup, pinfo)| stdimpl::prop_was_set(&ctx, &lookup, pinfo));
+                (Some(r.unwrap_or_else(|e| e.to_message(msg))), false)
+            },
+            (None, None) => {
+                let (typeid, iinfo) = &self.reg.0[iname.as_cstr()];
+                let minfo = &iinfo.methods[find_method(iinfo)];
+                let r = match minfo.handler.0 {
+                    MutMethod::Ref(ref f) => f(&ctx, self, self.paths.data(&path)),
+                    MutMethod::Mut(ref f) => {
+                        let data = self.paths.data_mut(&path);
+                        let (_, iface) = data.ifaces.iter_mut().find(|x| x.0 == *typeid).unwrap();
+                        f(&mut **iface, msg, &ctx)
+                    },
+                };
+                (r, minfo.no_reply())
+            },
+            (None, Some(idx)) => {
+                let data = self.paths.data(&path);
+                let midx = find_method(&data.instances[idx].0);
+                let minfo = &data.instances[idx].0.methods[midx];
+                let no_reply = minfo.no_reply();
+                let r = match minfo.handler.0 {
+                    MutMethod::Ref(ref f) => f(&ctx, self, data),
+                    MutMethod::Mut(_) => {
+                        let (iinfo, iface) = &mut self.paths.data_mut(&path).instances[idx];
+                        match iinfo.methods[midx].handler.0 {
+                            MutMethod::Mut(ref f) => f(&mut **iface, msg, &ctx),
+                            MutMethod::Ref(_) => unreachable!(),
+                        }
+                    },
+                };
+                (r, no_reply)
+            },
+        };
+        if handled { self.record_call(&iname, &headers, r.as_ref(), start) }
+        self.middleware.after(ran, msg, &mut r);
+        let r = if no_reply || msg.get_no_reply() { None } else { r };
+        Some(r.into_iter().chain(ctx.into_msgs()).collect())
+    }
+
+    /// The interface and property of a Properties.Set call, if the property can be set to the new value.
+    fn prop_set_target(&self, ctx: &Context, path: &CStr) -> Result<(IfaceName<'static>, MemberName<'static>), MethodErr> {
+        let (lookup, pinfo) = stdimpl::prop_lookup(ctx, self, self.paths.data(path))?;
+        stdimpl::prop_setter(ctx.message(), pinfo)?;
+        Ok((lookup.iinfo.name.clone(), pinfo.name.clone()))
+    }
+
+    pub fn new_mut() -> Self { Self::with_std_ifaces() }
+}
+
+// Properties.Set, with mutable access to the interface data, so that setters get &mut T.
+fn prop_set_mut(reg: &IfaceReg<Mut>, paths: &mut IfacePaths<Mut>, ctx: &Context, path: &CStr,
+    iname: &IfaceName, pname: &MemberName) -> Result<(), MethodErr> {
+    let data = paths.data_mut(path);
+    let (iinfo, iface) = match data.instances.iter_mut().find(|x| x.0.name == *iname) {
+        Some((iinfo, iface)) => (&*iinfo, iface),
+        None => {
+            let (typeid, iinfo) = &reg.0[iname.as_cstr()];
+            // The standard interfaces have no data, and no properties to set.
+            let (_, iface) = data.ifaces.iter_mut().find(|x| x.0 == *typeid).ok_or_else(|| MethodErr::ro_property(pname))?;
+            (iinfo, iface)
+        },
+    };
+    let pinfo = iinfo.props.iter().find(|p| p.name == *pname).unwrap();
+    let (setf, mut subiter) = stdimpl::prop_setter(ctx.message(), pinfo)?;
+    setf(&mut **iface, ctx.message(), &mut subiter, ctx)
+}
+
+/// Future returned from `Crossroads<Async>::dispatch`.
+pub struct AsyncDispatch {
+    reply: AsyncReply,
+    extra: Vec<Message>,
+    no_reply: bool,
+    // Middleware to run when the reply is ready, and the method call
+    after: Option<(Middlewares, Message)>,
+    // Where to record the call, the interface and method names, and when the handler was called
+    metrics: Option<(Arc<MetricsCollector>, String, String, Instant)>,
+    // If replies are ordered, our place in the order, and the messages waiting for their turn
+    ticket: Option<Ticket>,
+    ready: Option<Vec<Message>>,
+    span: DispatchSpan,
+}
+
+impl AsyncDispatch {
+    fn new(reply: AsyncReply, extra: Vec<Message>, no_reply: bool) -> Self {
+        AsyncDispatch { reply, extra, no_reply, after: None, metrics: None, ticket: None, ready: None, span: Default::default() }
+    }
+}
+
+impl Future for AsyncDispatch {
+    type Output = Vec<Message>;
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let s = self.get_mut();
+        if s.ready.is_none() {
+            let span = s.span.clone();
+            let mut r = match span.in_scope(|| s.reply.as_mut().poll(cx)) {
+                task::Poll::Ready(r) => r,
+                task::Poll::Pending => return task::Poll::Pending,
+            };
+            if let Some((m, i, name, start)) = &s.metrics { m.record(i, name, r.as_ref(), start.elapsed()) }
+            if let Some((m, call)) = &s.after { m.after(m.len(), call, &mut r) }
+            let no_reply = s.no_reply;
+            let msgs: Vec<_> = r.filter(|_| !no_reply).into_iter().chain(s.extra.drain(..)).collect();
+            span.replied(&msgs);
+            s.ready = Some(msgs);
+        }
+        if let Some(t) = &mut s.ticket {
+            if !t.try_finish(cx.waker()) { return task::Poll::Pending }
+        }
+        task::Poll::Ready(s.ready.take().unwrap())
+    }
+}
+
+impl fmt::Debug for AsyncDispatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "AsyncDispatch") }
+}
+
+impl Crossroads<Async> {
+    /// Returns a future that resolves to the reply, followed by messages pushed to the context,
+    /// or None if the message could not be dispatched.
+    pub fn dispatch(&self, msg: &Message) -> Option<AsyncDispatch> {
+        let span = DispatchSpan::new(msg);
+        let mut d = span.in_scope(|| self.dispatch_unordered(msg))?;
+        d.span = span;
+        if let Some(order) = &self.reply_order {
+            d.ticket = Some(order.ticket(msg.sender().as_ref().map(|s| &**s).unwrap_or("")));
+        }
+        Some(d)
+    }
+
+    /// Makes replies to the same sender resolve in the order `dispatch` was called, even if
+    /// a later method call is handled faster than an earlier one. Some clients rely on that.
+    ///
+    /// A future returned from `dispatch` then stays pending until the futures returned before
+    /// it, for the same sender, have resolved or been dropped. So they must be polled concurrently,
+    /// e g by spawning each of them.
+    pub fn set_ordered_replies(&mut self, ordered: bool) {
+        self.reply_order = if ordered { Some(Default::default()) } else { None };
+    }
+
+    fn dispatch_unordered(&self, msg: &Message) -> Option<AsyncDispatch> {
+        let headers = msg_headers(msg)?;
+        let (lookup, minfo, path) = match self.reg_lookup(&headers) {
+            Ok(x) => x,
+            Err(e) => {
+                let (r, extra) = self.lookup_failed(&headers, e);
+                return Some(AsyncDispatch::new(Box::pin(handlers::ready(r)), extra, false))
+            },
+        };
+        let handler = &minfo.handler.0;
+        let ctx = Context::new(msg).with_object_path(path).with_app_data(self.raw_app_data());
+        if let Err(e) = self.check_authorized(&ctx) {
+            return Some(AsyncDispatch::new(Box::pin(handlers::ready(error_reply(msg, e))), vec!(), false))
+        }
+        let (ran, rejected) = self.middleware.before(&ctx);
+        let metrics = match (&self.metrics, &rejected) {
+            (Some(m), None) => Some((m.clone(), lookup.iinfo.name.to_string(), headers.m.to_string(), Instant::now())),
+            _ => None,
+        };
+        let reply = match rejected {
+            Some(e) => Box::pin(handlers::ready(Some(e.to_message(msg)))),
+            None => {
+                let mut ai = AsyncInfo { cr: lookup.cr, pd: lookup.data, ctx: &ctx };
+                (handler)(&**lookup.iface, msg, &mut ai)
+            }
+        };
+        let after = if ran > 0 { Some((self.middleware.take(ran), msg.duplicate())) } else { None };
+        let no_reply = minfo.no_reply() || msg.get_no_reply();
+        Some(AsyncDispatch { after, metrics, ..AsyncDispatch::new(reply, ctx.into_msgs(), no_reply) })
+    }
+
+    pub fn new_async() -> Self { Self::with_std_ifaces() }
+}
+
+impl Crossroads<Local> {
+    /// See `Crossroads<()>::dispatch`.
+    pub fn dispatch(&self, msg: &Message) -> Option<Vec<Message>> {
+        self.dispatch_ref(msg, |lookup, minfo, ctx| {
+            (minfo.handler.0)(&**lookup.iface, ctx.message(), &mut LocalInfo { cr: lookup.cr, pd: lookup.data, ctx })
+        })
+    }
+
+    pub fn new_local() -> Self { Self::with_std_ifaces() }
+}
+
+impl Crossroads<Par> {
+    /// See `Crossroads<()>::dispatch`. Can be called from several threads at once.
+    pub fn dispatch(&self, msg: &Message) -> Option<Vec<Message>> {
+        self.dispatch_ref(msg, |lookup, minfo, ctx| {
+            (minfo.handler.0)(lookup.iface, ctx.message(), &mut ParInfo { cr: lookup.cr, pd: lookup.data, ctx })
+        })
+    }
+
+    pub fn new_par() -> Self { Self::with_std_ifaces() }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use super::super::testing::{TestCall, TestReply};
 
     #[test]
     fn test_send_sync() {
@@ -165,7 +1140,1400 @@ mod test {
         crate::message::message_set_serial(&mut msg, 57);
         let r = cr.dispatch(&msg).unwrap();
         assert_eq!(r.len(), 1);
-        let z: u16 = r[0].read1().unwrap();
-        assert_eq!(z, 7u16);
+        let z: arg::Variant<u16> = r[0].read1().unwrap();
+        assert_eq!(z.0, 7u16);
+
+        let r = TestCall::new("/", "org.freedesktop.DBus.Properties", "GetAll").append1("com.example.dbusrs.crossroads.score").dispatch(&mut cr);
+        assert!(r.signals().is_empty());
+        let (z,): (arg::PropMap,) = r.ok();
+        assert_eq!(z.len(), 1);
+        assert_eq!(z["Score"].0.as_u64(), Some(7));
+    }
+
+    #[test]
+    fn prop_read_access() {
+        let mut cr = Crossroads::new_sync();
+        struct Device;
+        cr.register::<Device>(IfaceInfo::new("com.example.dbusrs.crossroads.device", vec!(), vec!(
+            PropInfo::new_sync_ro("Name", |_: &Device, _, _| Some("thing")),
+            PropInfo::new_sync_ro("Serial", |_: &Device, _, _| Some("1234"))
+                .read_access(|ctx| ctx.app_data::<&str>() == Some(&"admin")),
+        ), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Device);
+        cr.insert("/", pdata);
+
+        let get = |cr: &mut Crossroads<()>, prop: &str| TestCall::new("/", "org.freedesktop.DBus.Properties", "Get")
+            .append1("com.example.dbusrs.crossroads.device").append1(prop).dispatch(cr);
+        let get_all = |cr: &mut Crossroads<()>| TestCall::new("/", "org.freedesktop.DBus.Properties", "GetAll")
+            .append1("com.example.dbusrs.crossroads.device").dispatch(cr).ok::<(arg::PropMap,)>().0;
+        let e = get(&mut cr, "Serial").error().unwrap();
+        assert_eq!(e.message(), Some("Not allowed to read property Serial"));
+        assert_eq!(get(&mut cr, "Name").ok::<(arg::Variant<String>,)>().0 .0, "thing");
+        let z = get_all(&mut cr);
+        assert_eq!(z.len(), 1);
+        assert_eq!(z["Name"].0.as_str(), Some("thing"));
+
+        cr.set_app_data("admin");
+        assert_eq!(get(&mut cr, "Serial").ok::<(arg::Variant<String>,)>().0 .0, "1234");
+        assert_eq!(get_all(&mut cr).len(), 2);
+
+        // Even when f allows it, the value is not broadcast
+        let m = cr.properties_changed(&"/".into(), &"com.example.dbusrs.crossroads.device".into(), vec!("Name", "Serial")).unwrap();
+        let s = PropertiesPropertiesChanged::from_message(&m).unwrap();
+        assert_eq!(s.changed_properties.len(), 1);
+        assert_eq!(s.changed_properties["Name"].0.as_str(), Some("thing"));
+        assert_eq!(s.invalidated_properties, vec!("Serial".to_string()));
+    }
+
+    #[test]
+    fn prop_changes() {
+        let mut cr = Crossroads::new_sync();
+        struct Device;
+        cr.register::<Device>(IfaceInfo::new("com.example.dbusrs.crossroads.device", vec!(
+            MethodInfo::new_sync("Update", |_: &Device, msg, info| {
+                let mut changes = info.ctx().begin_prop_changes();
+                changes.changed("com.example.dbusrs.crossroads.device", "Name", "thing".to_string())
+                    .changed("com.example.dbusrs.crossroads.device", "Count", 1u32)
+                    .invalidated("com.example.dbusrs.crossroads.device", "Count")
+                    .changed("com.example.dbusrs.crossroads.device", "Count", 2u32)
+                    .invalidated("com.example.dbusrs.crossroads.other", "Secret");
+                changes.commit();
+                // Not committed
+                info.ctx().begin_prop_changes().changed("com.example.dbusrs.crossroads.device", "Name", "other".to_string());
+                Some(msg.method_return())
+            }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Device);
+        cr.insert("/device", pdata);
+
+        let r = TestCall::new("/device", "com.example.dbusrs.crossroads.device", "Update").dispatch(&mut cr);
+        r.ok::<()>();
+        let r = r.signals();
+        assert_eq!(r.len(), 2);
+        let s = PropertiesPropertiesChanged::from_message(&r[0]).unwrap();
+        assert_eq!(&*r[0].path().unwrap(), "/device");
+        assert_eq!(s.interface_name, "com.example.dbusrs.crossroads.device");
+        assert_eq!(s.changed_properties.len(), 2);
+        assert_eq!(s.changed_properties["Count"].0.as_u64(), Some(2));
+        assert!(s.invalidated_properties.is_empty());
+        let s = PropertiesPropertiesChanged::from_message(&r[1]).unwrap();
+        assert_eq!(s.interface_name, "com.example.dbusrs.crossroads.other");
+        assert_eq!(s.invalidated_properties, vec!("Secret"));
+    }
+
+    #[test]
+    fn prop_empty_iface() {
+        let mut cr = Crossroads::new_sync();
+        struct Device;
+        struct Battery;
+        cr.register::<Device>(IfaceInfo::new("com.example.dbusrs.crossroads.device", vec!(), vec!(
+            PropInfo::new_sync_ro("Name", |_: &Device, _, _| Some("thing")),
+            PropInfo::new_sync_ro("Level", |_: &Device, _, _| Some(1u8)),
+        ), vec!()));
+        cr.register::<Battery>(IfaceInfo::new("com.example.dbusrs.crossroads.battery", vec!(), vec!(
+            PropInfo::new_sync_ro("Level", |_: &Battery, _, _| Some(2u8)),
+        ), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Device);
+        pdata.insert(Battery);
+        cr.insert("/", pdata);
+
+        let mut get = |iface: &str, prop: &str| TestCall::new("/", "org.freedesktop.DBus.Properties", "Get").append1(iface).append1(prop).dispatch(&mut cr);
+        assert_eq!(get("", "Name").ok::<(arg::Variant<String>,)>().0 .0, "thing");
+        assert_eq!(get("com.example.dbusrs.crossroads.battery", "Level").ok::<(arg::Variant<u8>,)>().0 .0, 2);
+        let e = get("", "Level").error().unwrap();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+        assert_eq!(e.message(), Some("Property Level exists on several interfaces \
+            (com.example.dbusrs.crossroads.battery, com.example.dbusrs.crossroads.device), specify one"));
+        assert!(get("", "Nothing").error().is_some());
+    }
+
+    #[test]
+    fn no_interface() {
+        let mut cr = Crossroads::new_sync();
+        struct Device;
+        cr.register::<Device>(IfaceInfo::new("com.example.dbusrs.crossroads.device", vec!(
+            MethodInfo::new_sync("Reset", |_: &Device, msg, _| Some(msg.method_return().append1("device"))),
+            MethodInfo::new_sync("Hello", |_: &Device, msg, _| Some(msg.method_return().append1("hello"))),
+        ), vec!(), vec!()));
+        struct Battery;
+        cr.register::<Battery>(IfaceInfo::new("com.example.dbusrs.crossroads.battery", vec!(
+            MethodInfo::new_sync("Reset", |_: &Battery, msg, _| Some(msg.method_return().append1("battery"))),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Device);
+        cr.insert("/device", pdata);
+        let mut pdata = PathData::new();
+        pdata.insert(Device);
+        pdata.insert(Battery);
+        cr.insert("/both", pdata);
+
+        let call = |path: &str, method: &str| {
+            let mut msg = TestCall::new(path, "com.example.dbusrs.crossroads.device", method).message().duplicate();
+            crate::message::message_clear_interface(&mut msg);
+            assert!(msg.interface().is_none());
+            cr.dispatch(&msg).unwrap().remove(0)
+        };
+        assert_eq!(call("/device", "Reset").read1::<&str>().unwrap(), "device");
+        assert_eq!(call("/both", "Hello").read1::<&str>().unwrap(), "hello");
+        let mut r = call("/both", "Reset");
+        assert_eq!(&*r.as_result().unwrap_err().name().unwrap(), "org.freedesktop.DBus.Error.UnknownMethod");
+        assert_eq!(call("/device", "Nothing").msg_type(), MessageType::Error);
+        // The standard interfaces are searched, too
+        assert!(call("/device", "Introspect").read1::<&str>().unwrap().contains("com.example.dbusrs.crossroads.device"));
+    }
+
+    #[test]
+    fn set_prop() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::tree::MethodErr;
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(AtomicUsize);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(),
+            vec!(PropInfo::new_sync_rw("Score", |x: &Score, _, _| Some(x.0.load(Ordering::SeqCst) as u16), |x: &Score, _, _, v: u16| {
+                if v > 100 { return Err(MethodErr::failed(&"Score too high")) }
+                x.0.store(v as usize, Ordering::SeqCst);
+                Ok(())
+            }),
+            PropInfo::new_sync_ro("Max", |_: &Score, _, _| Some(100u16))), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(AtomicUsize::new(7)));
+        pdata.insert(DBusProperties);
+        cr.insert("/", pdata);
+
+        let set = |cr: &mut Crossroads<()>, prop: &str, v: arg::Variant<Box<arg::RefArg>>| TestCall::new("/", "org.freedesktop.DBus.Properties", "Set")
+            .append1("com.example.dbusrs.crossroads.score").append1(prop).append1(v).dispatch(cr);
+        let errname = |r: TestReply| r.error().and_then(|e| e.name().map(|s| s.to_string()));
+
+        set(&mut cr, "Score", arg::Variant(Box::new(9u16))).ok::<()>();
+        let score = |cr: &Crossroads<()>| cr.get_data("/").unwrap().ifaces.iter().find_map(|x| x.1.downcast_ref::<Score>()).unwrap().0.load(Ordering::SeqCst);
+        assert_eq!(score(&cr), 9);
+
+        assert_eq!(errname(set(&mut cr, "Score", arg::Variant(Box::new(9u32)))), Some("org.freedesktop.DBus.Error.InvalidArgs".into()));
+        assert_eq!(errname(set(&mut cr, "Max", arg::Variant(Box::new(9u16)))), Some("org.freedesktop.DBus.Error.PropertyReadOnly".into()));
+        assert_eq!(errname(set(&mut cr, "Score", arg::Variant(Box::new(200u16)))), Some("org.freedesktop.DBus.Error.Failed".into()));
+        assert_eq!(score(&cr), 9);
+    }
+
+    #[test]
+    fn set_prop_signal() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(AtomicUsize);
+        let rw = |name: &'static str, emits| PropInfo::new_sync_rw(name, |x: &Score, _, _| Some(x.0.load(Ordering::SeqCst) as u16),
+            |x: &Score, _, _, v: u16| { x.0.store(v as usize, Ordering::SeqCst); Ok(()) }).emits_changed(emits);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(), vec!(
+            rw("Score", EmitsChangedSignal::True),
+            rw("Rank", EmitsChangedSignal::Invalidates),
+            rw("Hidden", EmitsChangedSignal::False),
+        ), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(AtomicUsize::new(7)));
+        pdata.insert(DBusProperties);
+        cr.insert("/", pdata);
+
+        let mut set = |prop: &str| TestCall::new("/", "org.freedesktop.DBus.Properties", "Set")
+            .append1("com.example.dbusrs.crossroads.score").append1(prop).append1(arg::Variant(9u16)).dispatch(&mut cr);
+        let r = set("Score");
+        r.ok::<()>();
+        assert_eq!(r.signals().len(), 1);
+        let s = PropertiesPropertiesChanged::from_message(&r.signals()[0]).unwrap();
+        assert_eq!(s.interface_name, "com.example.dbusrs.crossroads.score");
+        assert_eq!(s.changed_properties["Score"].0.as_u64(), Some(9));
+
+        let r = set("Rank");
+        let s = PropertiesPropertiesChanged::from_message(&r.signals()[0]).unwrap();
+        assert!(s.changed_properties.is_empty());
+        assert_eq!(s.invalidated_properties, vec!("Rank".to_string()));
+
+        assert!(set("Hidden").signals().is_empty());
+    }
+
+    #[test]
+    fn typed() {
+        use crate::tree::MethodErr;
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_typed("Add", ("who", "points"), ("reply", "total"), |x: &Score, (a, b): (String, u32)| {
+                Ok((format!("{} scored", a), x.0 as u32 + b))
+            })), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let add = || TestCall::new("/", "com.example.dbusrs.crossroads.score", "Add").append1("Alice");
+        let r: (String, u32) = add().append1(5u32).dispatch(&mut cr).ok();
+        assert_eq!(r, ("Alice scored".into(), 12));
+
+        let e = add().dispatch(&mut cr).error().unwrap();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+
+        let xml = cr.introspect(&"/".into(), cr.get_data("/"));
+        assert!(xml.contains(r#"<arg name="points" type="u" direction="in"/>"#));
+        assert!(xml.contains(r#"<arg name="total" type="u" direction="out"/>"#));
+    }
+
+    #[test]
+    fn mutable() {
+        let mut cr = Crossroads::new_mut();
+
+        struct Counter(u32, u32);
+        cr.register::<Counter>(IfaceInfo::new("com.example.dbusrs.crossroads.counter",
+            vec!(MethodInfo::new_mut("Increment", |c: &mut Counter, msg, _| {
+                c.0 += c.1;
+                Some(msg.method_return().append1(c.0))
+            })),
+            vec!(PropInfo::new_mut_ro("Count", |c: &Counter, _, _| Some(c.0)),
+                PropInfo::new_mut_rw("Step", |c: &Counter, _, _| Some(c.1), |c: &mut Counter, _, _, v: u32| { c.1 = v; Ok(()) })),
+            vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Counter(0, 1));
+        pdata.insert(DBusProperties);
+        cr.insert("/", pdata);
+
+        for i in 1..3 {
+            let (r,): (u32,) = TestCall::new("/", "com.example.dbusrs.crossroads.counter", "Increment").dispatch(&mut cr).ok();
+            assert_eq!(r, i);
+        }
+
+        let (r,): (arg::Variant<u32>,) = TestCall::new("/", "org.freedesktop.DBus.Properties", "Get")
+            .append1("com.example.dbusrs.crossroads.counter").append1("Count").dispatch(&mut cr).ok();
+        assert_eq!(r.0, 2);
+
+        let set = |cr: &mut Crossroads<Mut>, prop: &str, v: arg::Variant<Box<arg::RefArg>>| TestCall::new("/", "org.freedesktop.DBus.Properties", "Set")
+            .append1("com.example.dbusrs.crossroads.counter").append1(prop).append1(v).dispatch(cr);
+        let r = set(&mut cr, "Step", arg::Variant(Box::new(5u32)));
+        r.ok::<()>();
+        let s = PropertiesPropertiesChanged::from_message(&r.signals()[0]).unwrap();
+        assert_eq!(s.changed_properties["Step"].0.as_u64(), Some(5));
+        assert!(set(&mut cr, "Step", arg::Variant(Box::new("five".to_string()))).error().is_some());
+        assert!(set(&mut cr, "Count", arg::Variant(Box::new(7u32))).error().is_some());
+        let (r,): (u32,) = TestCall::new("/", "com.example.dbusrs.crossroads.counter", "Increment").dispatch(&mut cr).ok();
+        assert_eq!(r, 7);
+    }
+
+    #[test]
+    fn typed_tuple_out() {
+        let mut cr = Crossroads::new_mut();
+        struct Counter(u32);
+        cr.register::<Counter>(IfaceInfo::new("com.example.dbusrs.crossroads.counter",
+            vec!(MethodInfo::new_mut_typed("Info", ("step",), ("name", "count", "bytes"), |c: &mut Counter, (step,): (u32,)| {
+                c.0 += step;
+                Ok(("counter".to_string(), c.0, vec!(1u8, 2, 3)))
+            })), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Counter(0));
+        cr.insert("/", pdata);
+
+        let r = TestCall::new("/", "com.example.dbusrs.crossroads.counter", "Info").args((5u32,)).dispatch(&mut cr);
+        assert_eq!(super::super::args::msg_signature(r.reply().unwrap()), "suay");
+        assert_eq!(r.ok::<(String, u32, Vec<u8>)>(), ("counter".into(), 5, vec!(1, 2, 3)));
+        let xml = cr.introspect(&"/".into(), cr.get_data("/"));
+        assert!(xml.contains(r#"<arg name="bytes" type="ay" direction="out"/>"#));
+    }
+
+    #[test]
+    fn context() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync("Hello", |x: &Score, msg, si| {
+                let ctx = si.ctx();
+                assert_eq!(ctx.serial(), msg.get_serial());
+                assert_eq!(&*ctx.member().unwrap(), "Hello");
+                assert!(!ctx.no_reply_expected());
+                ctx.push_msg(Message::signal(&ctx.path().unwrap(), &ctx.interface().unwrap(), &"Greeted".into()).append1(x.0));
+                Some(msg.method_return())
+            })), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let r = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Hello").dispatch(&mut cr);
+        r.ok::<()>();
+        assert_eq!(r.signals().len(), 1);
+        assert_eq!(r.signals()[0].msg_type(), MessageType::Signal);
+        assert_eq!(r.signals()[0].read1::<u16>().unwrap(), 7);
+    }
+
+    #[test]
+    fn no_reply_expected() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(AtomicUsize);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Hello", |x: &Score, msg, si| {
+                assert!(si.ctx().no_reply_expected());
+                x.0.fetch_add(1, Ordering::SeqCst);
+                Some(msg.method_return())
+            }),
+            MethodInfo::new_typed("Typed", (), (), |x: &Score, ()| { x.0.fetch_add(1, Ordering::SeqCst); Ok(()) }),
+        ), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(AtomicUsize::new(0)));
+        cr.insert("/", pdata);
+
+        for member in &["Hello", "Typed", "Unknown"] {
+            let r = TestCall::new("/", "com.example.dbusrs.crossroads.score", *member).no_reply().dispatch(&mut cr);
+            assert!(r.reply().is_none() && r.signals().is_empty());
+        }
+        let score: &Score = cr.get_data("/").unwrap().ifaces.iter().find_map(|x| x.1.downcast_ref()).unwrap();
+        assert_eq!(score.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn deferred() {
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(Mutex<Option<DeferredReply>>);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync("Slow", |x: &Score, _, si| {
+                *x.0.lock().unwrap() = Some(si.ctx().defer());
+                None
+            })), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(Mutex::new(None)));
+        cr.insert("/", pdata);
+
+        let call = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Slow");
+        let r = call.dispatch(&mut cr);
+        assert!(r.reply().is_none() && r.signals().is_empty());
+
+        let score = cr.get_data("/").unwrap().ifaces.iter().find_map(|x| x.1.downcast_ref::<Score>()).unwrap();
+        let token = score.0.lock().unwrap().take().unwrap();
+        let serial = call.message().get_serial();
+        assert_eq!(token.serial(), serial);
+        let replier = cr.replier();
+        thread::spawn(move || {
+            let m = token.method_return().append1(5u32);
+            replier.reply(token, m)
+        }).join().unwrap();
+        let replies = cr.take_signals();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].get_reply_serial(), Some(serial));
+        assert_eq!(replies[0].read1::<u32>().unwrap(), 5);
+
+        // A message that is not made from the token becomes the reply to the call
+        let r = call.dispatch(&mut cr);
+        assert!(r.reply().is_none());
+        let score = cr.get_data("/").unwrap().ifaces.iter().find_map(|x| x.1.downcast_ref::<Score>()).unwrap();
+        let token = score.0.lock().unwrap().take().unwrap();
+        let other = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Other");
+        cr.reply(token, other.message().method_return().append1(6u32));
+        let replies = cr.take_signals();
+        assert_eq!(replies[0].get_reply_serial(), Some(serial));
+        assert_eq!(replies[0].read1::<u32>().unwrap(), 6);
+    }
+
+    #[test]
+    fn remove() {
+        let mut cr = Crossroads::new_sync();
+        for p in &["/a", "/a/b", "/a/b/c", "/ab", "/b"] {
+            cr.insert(*p, PathData::new());
+        }
+        assert!(cr.remove("/b").is_some());
+        assert!(cr.remove("/b").is_none());
+        let removed: Vec<_> = cr.remove_subtree("/a").into_iter().map(|(p, _)| p.to_string()).collect();
+        assert_eq!(removed, vec!("/a", "/a/b", "/a/b/c"));
+        assert!(cr.get_data("/ab").is_some());
+    }
+
+    #[test]
+    fn authorize() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Public", |_: &Score, msg, _| Some(msg.method_return())),
+            MethodInfo::new_sync("Secret", |_: &Score, msg, _| Some(msg.method_return())),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+        cr.set_authorize(|ctx| {
+            if ctx.member().map(|m| &*m == "Secret").unwrap_or(false) {
+                Err(MethodErr::access_denied(&"Secret is secret"))
+            } else { Ok(()) }
+        });
+
+        let mut call = |member: &str| TestCall::new("/", "com.example.dbusrs.crossroads.score", member).dispatch(&mut cr);
+        call("Public").ok::<()>();
+        assert_eq!(call("Secret").error().unwrap().name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+    }
+
+    #[test]
+    fn allowed_senders() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Hello", |_: &Score, msg, _| Some(msg.method_return())),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let call = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Hello");
+        call.dispatch(&mut cr).ok::<()>();
+        cr.set_allowed_senders(vec!(":1.5"));
+        // The call is from TEST_SENDER
+        assert_eq!(call.dispatch(&mut cr).error().unwrap().name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+        TestCall::new("/", "com.example.dbusrs.crossroads.score", "Hello").sender(":1.5").dispatch(&mut cr).ok::<()>();
+        cr.clear_allowed_senders();
+        call.dispatch(&mut cr).ok::<()>();
+    }
+
+    #[test]
+    fn middleware() {
+        use std::sync::{Arc, Mutex};
+        struct Log(&'static str, Arc<Mutex<Vec<String>>>);
+        impl Middleware for Log {
+            fn before(&self, ctx: &Context) -> Result<(), MethodErr> {
+                let member = ctx.member().unwrap().to_string();
+                self.1.lock().unwrap().push(format!("{} before {}", self.0, member));
+                if member == "Rejected" && self.0 == "inner" { Err(MethodErr::access_denied(&"Rejected")) } else { Ok(()) }
+            }
+            fn after(&self, _: &Message, reply: &mut Option<Message>) {
+                let t = reply.as_ref().map(|r| r.msg_type());
+                self.1.lock().unwrap().push(format!("{} after {:?}", self.0, t));
+            }
+        }
+
+        let log = Arc::new(Mutex::new(vec!()));
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Hello", |_: &Score, msg, _| Some(msg.method_return())),
+            MethodInfo::new_sync("Rejected", |_: &Score, _, _| unreachable!()),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+        cr.add_middleware(Log("outer", log.clone()));
+        cr.add_middleware(Log("inner", log.clone()));
+        cr.add_middleware(Log("innermost", log.clone()));
+
+        let mut call = |member: &str| TestCall::new("/", "com.example.dbusrs.crossroads.score", member).dispatch(&mut cr).reply().unwrap().msg_type();
+        assert_eq!(call("Hello"), MessageType::MethodReturn);
+        assert_eq!(call("Rejected"), MessageType::Error);
+        assert_eq!(*log.lock().unwrap(), vec!(
+            "outer before Hello", "inner before Hello", "innermost before Hello",
+            "innermost after Some(MethodReturn)", "inner after Some(MethodReturn)", "outer after Some(MethodReturn)",
+            "outer before Rejected", "inner before Rejected", "outer after Some(Error)",
+        ));
+    }
+
+    #[test]
+    fn metrics() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Hello", |_: &Score, msg, _| Some(msg.method_return())),
+            MethodInfo::new_sync_result("Fail", |_: &Score, _, _| Err(MethodErr::failed(&"Fail"))),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(DBusMetrics);
+        cr.insert("/", pdata);
+
+        let call = |cr: &mut Crossroads<()>, iface: &str, member: &str| TestCall::new("/", iface, member).dispatch(cr);
+        call(&mut cr, "com.example.dbusrs.crossroads.score", "Hello");
+        assert!(cr.metrics().is_empty());
+
+        cr.enable_metrics();
+        call(&mut cr, "com.example.dbusrs.crossroads.score", "Hello");
+        call(&mut cr, "com.example.dbusrs.crossroads.score", "Hello");
+        call(&mut cr, "com.example.dbusrs.crossroads.score", "Fail");
+        call(&mut cr, "com.example.dbusrs.crossroads.score", "Unknown");
+        let m = cr.metrics();
+        assert_eq!(m.len(), 2);
+        let hello = &m[&("com.example.dbusrs.crossroads.score".into(), "Hello".into())];
+        assert_eq!((hello.calls, hello.errors), (2, 0));
+        assert!(hello.max_time <= hello.total_time);
+        let fail = &m[&("com.example.dbusrs.crossroads.score".into(), "Fail".into())];
+        assert_eq!((fail.calls, fail.errors), (1, 1));
+
+        let (v,): (Vec<(String, String, u64, u64, u64, u64)>,) = call(&mut cr, "com.github.diwic.dbusrs.Metrics", "GetMetrics").ok();
+        assert_eq!(v.len(), 2);
+        assert_eq!((&*v[0].1, v[0].2, v[0].3), ("Fail", 1, 1));
+    }
+
+    #[test]
+    fn fallback() {
+        let mut cr = Crossroads::new_sync();
+        struct Player;
+        cr.register::<Player>(IfaceInfo::new("com.example.dbusrs.crossroads.player", vec!(
+            MethodInfo::new_sync("Which", |_: &Player, msg, si| {
+                let ctx = si.ctx();
+                Some(msg.method_return().append2(ctx.fallback_path().map(|p| p.to_string()).unwrap_or_default(), ctx.sub_path().unwrap_or("")))
+            }),
+        ), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Player);
+        cr.insert_fallback("/org/example/player", pdata);
+        let mut pdata = PathData::new();
+        pdata.insert(Player);
+        cr.insert("/org/example/player/current", pdata);
+        cr.insert("/org/example/player/current/other", PathData::new());
+
+        let mut call = |path: &str| {
+            let r = TestCall::new(path, "com.example.dbusrs.crossroads.player", "Which").dispatch(&mut cr);
+            if r.error().is_some() { None } else { Some(r.ok::<(String, String)>()) }
+        };
+        assert_eq!(call("/org/example/player/track123"), Some(("/org/example/player".into(), "track123".into())));
+        assert_eq!(call("/org/example/player/a/b"), Some(("/org/example/player".into(), "a/b".into())));
+        assert_eq!(call("/org/example/player"), Some(("".into(), "".into())));
+        assert_eq!(call("/org/example/player/current"), Some(("".into(), "".into())));
+        // Has no Player interface, and is not a fallback to anything else
+        assert_eq!(call("/org/example/player/current/other"), None);
+        assert_eq!(call("/org/example/player/current/other/x"), Some(("/org/example/player".into(), "current/other/x".into())));
+        assert_eq!(call("/org/example"), None);
+        assert!(cr.get_data("/org/example/player").unwrap().is_fallback());
+    }
+
+    #[test]
+    fn instances() {
+        let mut cr = Crossroads::new_sync();
+        let info = |name: &'static str| IfaceInfo::new("com.example.dbusrs.crossroads.named", vec!(
+            MethodInfo::new_sync("Name", move |_: &u8, msg, _| Some(msg.method_return().append1(name))),
+        ), vec!(), vec!());
+        let mut pdata = PathData::new();
+        pdata.insert_instance(info("first"), 0u8);
+        pdata.insert_instance(IfaceInfo::new("com.example.dbusrs.crossroads.other", vec!(
+            MethodInfo::new_sync("Value", |x: &u8, msg, _| Some(msg.method_return().append1(*x))),
+        ), vec!(), vec!()), 5u8);
+        pdata.insert(DBusIntrospectable);
+        cr.insert("/first", pdata);
+        let mut pdata = PathData::new();
+        pdata.insert_instance(info("second"), 0u8);
+        cr.insert("/second", pdata);
+
+        let call = |cr: &mut Crossroads<()>, path: &str, iface: &str, member: &str| TestCall::new(path, iface, member).dispatch(cr);
+        let name = |cr: &mut Crossroads<()>, path| call(cr, path, "com.example.dbusrs.crossroads.named", "Name").ok::<(String,)>().0;
+        assert_eq!(name(&mut cr, "/first"), "first");
+        assert_eq!(name(&mut cr, "/second"), "second");
+        assert_eq!(call(&mut cr, "/first", "com.example.dbusrs.crossroads.other", "Value").ok::<(u8,)>().0, 5);
+        assert!(call(&mut cr, "/second", "com.example.dbusrs.crossroads.other", "Value").error().is_some());
+
+        let (xml,): (String,) = call(&mut cr, "/first", "org.freedesktop.DBus.Introspectable", "Introspect").ok();
+        assert!(xml.contains("com.example.dbusrs.crossroads.named"));
+        assert!(xml.contains("com.example.dbusrs.crossroads.other"));
+
+        let mut pdata = cr.remove("/second").unwrap();
+        assert!(pdata.remove_instance("com.example.dbusrs.crossroads.named").is_some());
+        assert!(pdata.remove_instance("com.example.dbusrs.crossroads.named").is_none());
+    }
+
+    #[test]
+    fn std_ifaces() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(),
+            vec!(PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0))), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+        let mut pdata = PathData::new();
+        pdata.insert(Score(8u16));
+        pdata.disable_std_ifaces();
+        cr.insert("/private", pdata);
+
+        let mut call = |path: &str, iface: &str, member: &str| {
+            let call = TestCall::new(path, iface, member);
+            let call = if member == "Get" { call.append1("com.example.dbusrs.crossroads.score").append1("Score") } else { call };
+            call.dispatch(&mut cr)
+        };
+        let (r,): (arg::Variant<u16>,) = call("/", "org.freedesktop.DBus.Properties", "Get").ok();
+        assert_eq!(r.0, 7);
+        call("/", "org.freedesktop.DBus.Peer", "Ping").ok::<()>();
+        let (xml,): (String,) = call("/", "org.freedesktop.DBus.Introspectable", "Introspect").ok();
+        assert!(xml.contains("org.freedesktop.DBus.Properties"));
+
+        let e = call("/private", "org.freedesktop.DBus.Properties", "Get").error().unwrap();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.UnknownInterface"));
+    }
+
+    #[test]
+    fn introspect_cache() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(), vec!(), vec!()));
+        cr.insert("/a", PathData::new());
+        cr.insert("/b", PathData::new());
+
+        let call = |cr: &mut Crossroads<()>, path: &str| -> String {
+            TestCall::new(path, "org.freedesktop.DBus.Introspectable", "Introspect").dispatch(cr).ok::<(String,)>().0
+        };
+        let stats = |h, m, e| IntrospectCacheStats { hits: h, misses: m, entries: e };
+        assert!(!call(&mut cr, "/a").contains("crossroads.score"));
+        call(&mut cr, "/a");
+        call(&mut cr, "/b");
+        assert_eq!(cr.introspect_cache_stats(), stats(1, 2, 2));
+
+        // Changing /a does not affect /b
+        cr.get_data_mut("/a").unwrap().insert(Score(7));
+        assert_eq!(cr.introspect_cache_stats().entries, 1);
+        assert!(call(&mut cr, "/a").contains("crossroads.score"));
+        assert_eq!(cr.introspect_cache_stats(), stats(1, 3, 2));
+
+        // The parent lists the new child
+        call(&mut cr, "/");
+        cr.insert("/c", PathData::new());
+        assert!(call(&mut cr, "/").contains(r#"<node name="c"/>"#));
+        assert_eq!(cr.introspect_cache_stats().misses, 5);
+
+        cr.register::<u32>(IfaceInfo::new("com.example.dbusrs.crossroads.other", vec!(), vec!(), vec!()));
+        assert_eq!(cr.introspect_cache_stats().entries, 0);
+    }
+
+    #[test]
+    fn iterate() {
+        let mut cr = Crossroads::new_sync();
+        let mut pdata = PathData::new();
+        pdata.insert(DBusProperties);
+        pdata.insert(DBusIntrospectable);
+        cr.insert("/b", pdata);
+        let mut pdata = PathData::new();
+        pdata.disable_std_ifaces();
+        cr.insert("/a", pdata);
+        let paths: Vec<_> = cr.paths().map(|p| p.to_string()).collect();
+        assert_eq!(paths, vec!("/a", "/b"));
+        let ifaces: Vec<_> = cr.interfaces("/b").map(|(n, _)| n.to_string()).collect();
+        assert_eq!(ifaces, vec!("org.freedesktop.DBus.Introspectable", "org.freedesktop.DBus.Peer", "org.freedesktop.DBus.Properties"));
+        assert_eq!(cr.interfaces("/a").count(), 0);
+        assert_eq!(cr.interfaces("/c").count(), 0);
+    }
+
+    #[test]
+    fn app_data() {
+        struct Config { greeting: String }
+        let mut cr = Crossroads::new_mut();
+        cr.set_app_data(Config { greeting: "Hello".into() });
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_mut("Greet", |_: &mut Score, msg, ctx| {
+                let c: &Config = ctx.app_data().unwrap();
+                assert!(ctx.app_data::<u32>().is_none());
+                Some(msg.method_return().append1(&*c.greeting))
+            }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+        assert_eq!(cr.app_data::<Config>().unwrap().greeting, "Hello");
+
+        let (r,): (String,) = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Greet").dispatch(&mut cr).ok();
+        assert_eq!(r, "Hello");
+    }
+
+    #[test]
+    fn replace_iface() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        let info = |debug: bool| {
+            let mut m = vec!(MethodInfo::new_sync("Get", |x: &Score, msg, _| Some(msg.method_return().append1(x.0))));
+            if debug { m.push(MethodInfo::new_sync("Debug", |_: &Score, msg, _| Some(msg.method_return().append1("Debug")))) }
+            IfaceInfo::new("com.example.dbusrs.crossroads.score", m, vec!(), vec!())
+        };
+        cr.register::<Score>(info(false));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let call = |cr: &mut Crossroads<()>, member: &str| TestCall::new("/", "com.example.dbusrs.crossroads.score", member).dispatch(cr);
+        assert!(call(&mut cr, "Debug").error().is_some());
+        let old = cr.replace_iface(info(true)).unwrap();
+        assert_eq!(old.methods.len(), 1);
+        call(&mut cr, "Debug").ok::<(String,)>();
+        call(&mut cr, "Get").ok::<(u16,)>();
+        assert!(cr.replace_iface(IfaceInfo::new("com.example.dbusrs.crossroads.other", vec!(), vec!(), vec!())).is_err());
+    }
+
+    #[test]
+    fn add_method() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Get", |x: &Score, msg, _| Some(msg.method_return().append1(x.0))),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let call = |cr: &mut Crossroads<()>, member: &str| TestCall::new("/", "com.example.dbusrs.crossroads.score", member).dispatch(cr);
+        assert!(call(&mut cr, "Reset").error().is_some());
+        assert!(!cr.to_xml("/").unwrap().contains("Reset"));
+
+        cr.add_method::<Score>(MethodInfo::new_sync("Reset", |_: &Score, msg, _| Some(msg.method_return().append1("reset")))).unwrap();
+        assert_eq!(call(&mut cr, "Reset").ok::<(String,)>().0, "reset");
+        assert_eq!(call(&mut cr, "Get").ok::<(u16,)>().0, 7);
+        assert!(cr.to_xml("/").unwrap().contains(r#"<method name="Reset"/>"#));
+
+        // Replaces the method with the same name
+        cr.add_method::<Score>(MethodInfo::new_sync("Get", |_: &Score, msg, _| Some(msg.method_return().append1(0u16)))).unwrap();
+        assert_eq!(call(&mut cr, "Get").ok::<(u16,)>().0, 0);
+        assert!(cr.add_method::<u32>(MethodInfo::new_sync("Get", |_: &u32, msg, _| Some(msg.method_return()))).is_err());
+    }
+
+    #[test]
+    fn templates() {
+        let mut cr = Crossroads::new_mut();
+        struct Player(u32);
+        cr.register::<Player>(IfaceInfo::new("com.example.dbusrs.crossroads.player", vec!(
+            MethodInfo::new_mut("Play", |p: &mut Player, msg, ctx| {
+                p.0 += 1;
+                let id = ctx.path_param("id").unwrap_or("none");
+                let track = ctx.path_param("track").unwrap_or("none");
+                Some(msg.method_return().append3(id, track, p.0))
+            }),
+        ), vec!(), vec!()));
+        for t in &["/org/example/Player/{id}", "/org/example/Player/{id}/Track/{track}"] {
+            let mut pdata = PathData::new();
+            pdata.insert(Player(0));
+            cr.insert_template(t, pdata).unwrap();
+        }
+        let mut pdata = PathData::new();
+        pdata.insert(Player(100));
+        cr.insert("/org/example/Player/special", pdata);
+
+        let mut call = |path: &str| TestCall::new(path, "com.example.dbusrs.crossroads.player", "Play").dispatch(&mut cr);
+        let play = |r: TestReply| r.ok::<(String, String, u32)>();
+        assert_eq!(play(call("/org/example/Player/p7")), ("p7".into(), "none".into(), 1));
+        assert_eq!(play(call("/org/example/Player/p8")), ("p8".into(), "none".into(), 2));
+        assert_eq!(play(call("/org/example/Player/p7/Track/t1")), ("p7".into(), "t1".into(), 1));
+        // Inserted paths take precedence
+        assert_eq!(play(call("/org/example/Player/special")), ("none".into(), "none".into(), 101));
+        assert!(call("/org/example/Player/p7/Other/t1").error().is_some());
+
+        assert!(cr.insert_template("/org/example/{id", PathData::new()).is_err());
+        assert!(cr.insert_template("/org/example", PathData::new()).is_err());
+        assert!(cr.insert_template("org/{example}", PathData::new()).is_err());
+        assert!(cr.remove_template("/org/example/Player/{id}").is_some());
+        assert_eq!(template_params(b"/a/{x}/c", b"/a/b/c"), Some(vec!(("x".into(), "b".into()))));
+        assert_eq!(template_params(b"/a/{x}", b"/a/b/c"), None);
+    }
+
+    #[test]
+    fn pathdata_get() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        struct Bonus(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Total", |x: &Score, msg, si| {
+                let bonus = si.path_data().get::<Bonus>().map(|b| b.0).unwrap_or(0);
+                Some(msg.method_return().append1(x.0 + bonus))
+            }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(Bonus(2u16));
+        cr.insert("/", pdata);
+
+        cr.get_data_mut("/").unwrap().get_mut::<Bonus>().unwrap().0 = 3;
+        assert_eq!(cr.get_data("/").unwrap().get::<Score>().unwrap().0, 7);
+        assert!(cr.get_data("/").unwrap().get::<u32>().is_none());
+
+        let (r,): (u16,) = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Total").dispatch(&mut cr).ok();
+        assert_eq!(r, 10);
+    }
+
+    #[test]
+    fn pathdata_remove_replace() {
+        let mut pdata: PathData<()> = PathData::new();
+        pdata.insert(5u16);
+        pdata.insert(DBusProperties);
+        assert_eq!(pdata.replace(7u16), Some(5u16));
+        assert_eq!(pdata.replace(9u32), None);
+        assert_eq!(pdata.remove::<u16>(), Some(7u16));
+        assert_eq!(pdata.remove::<u16>(), None);
+        assert!(pdata.remove::<DBusProperties>().is_some());
+        assert_eq!(pdata.remove::<u32>(), Some(9u32));
+    }
+
+    #[test]
+    fn unknown() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(DBusProperties);
+        pdata.disable_std_ifaces();
+        cr.insert("/", pdata);
+
+        let mut call = |path: &str, iface: &str, member: &str| {
+            let r = TestCall::new(path, iface, member).dispatch(&mut cr);
+            assert!(r.signals().is_empty());
+            r.error().unwrap().name().unwrap().to_string()
+        };
+        assert_eq!(call("/nope", "com.example.dbusrs.crossroads.score", "Hello"), "org.freedesktop.DBus.Error.UnknownObject");
+        assert_eq!(call("/", "com.example.dbusrs.crossroads.nope", "Hello"), "org.freedesktop.DBus.Error.UnknownInterface");
+        assert_eq!(call("/", "org.freedesktop.DBus.Introspectable", "Introspect"), "org.freedesktop.DBus.Error.UnknownInterface");
+        assert_eq!(call("/", "com.example.dbusrs.crossroads.score", "Hello"), "org.freedesktop.DBus.Error.UnknownMethod");
+        assert_eq!(call("/", "org.freedesktop.DBus.Properties", "Get"), "org.freedesktop.DBus.Error.InvalidArgs");
+
+        let sig = Message::signal(&"/".into(), &"com.example.dbusrs.crossroads.score".into(), &"Hello".into());
+        assert!(cr.dispatch(&sig).is_none());
+    }
+
+    #[test]
+    fn result() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync_result("Check", |x: &Score, msg, _| {
+                let limit: u16 = msg.read1()?;
+                if x.0 > limit { Err(MethodErr::failed(&"Score too high")) }
+                else { Ok(msg.method_return()) }
+            })), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let mut call = |limit: u16| TestCall::new("/", "com.example.dbusrs.crossroads.score", "Check").args((limit,)).dispatch(&mut cr);
+        call(10).ok::<()>();
+        let e = call(5).error().unwrap();
+        assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.Failed"));
+        assert_eq!(e.message(), Some("Score too high"));
+    }
+
+    #[test]
+    fn check_args() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_sync("Checked", |_: &Score, msg, _| Some(msg.method_return())).in_args::<(u32, String)>(("a", "b")),
+            MethodInfo::new_sync("Unchecked", |_: &Score, msg, _| Some(msg.method_return())),
+        ), vec!(), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let call = |member: &str| TestCall::new("/", "com.example.dbusrs.crossroads.score", member);
+        let mut dispatch = |call: TestCall| call.dispatch(&mut cr).reply().unwrap().msg_type();
+        assert_eq!(dispatch(call("Checked").append1(5u32).append1("Hello")), MessageType::MethodReturn);
+        assert_eq!(dispatch(call("Checked").append1(5u32)), MessageType::Error);
+        assert_eq!(dispatch(call("Checked").append1("Hello").append1(5u32)), MessageType::Error);
+        assert_eq!(dispatch(call("Unchecked").append1(5u32)), MessageType::MethodReturn);
+    }
+
+    #[test]
+    fn to_xml() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync("Hello", |_: &Score, msg, _| Some(msg.method_return()))
+                .in_arg("name", "s").out_arg("reply", "s")),
+            vec!(), vec!()));
+        cr.register::<u32>(IfaceInfo::new("com.example.dbusrs.crossroads.unused", vec!(), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/example/score", pdata);
+
+        let xml = cr.to_xml("/example/score").unwrap();
+        assert!(xml.contains(r#"<node name="/example/score">"#));
+        assert!(xml.contains(r#"<arg name="name" type="s" direction="in"/>"#));
+        assert!(!xml.contains("com.example.dbusrs.crossroads.unused"));
+        assert!(cr.to_xml("/example").unwrap().contains(r#"<node name="score"/>"#));
+        assert!(cr.to_xml("/other").is_none());
+
+        let all = cr.to_xml_all();
+        let (score, unused) = (all.find("crossroads.score").unwrap(), all.find("crossroads.unused").unwrap());
+        assert!(score < unused);
+        assert!(all.contains("<node>\n"));
+    }
+
+    #[test]
+    fn introspect() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+
+        let info = IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync("Hello", |_: &Score, msg, _| Some(msg.method_return()))
+                .in_arg("name", "s").out_arg("reply", "s")),
+            vec!(PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0))),
+            vec!(),
+        );
+        cr.register::<Score>(info);
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(DBusIntrospectable);
+        pdata.disable_std_ifaces();
+        cr.insert("/example", pdata);
+
+        let (xml,): (String,) = TestCall::new("/example", "org.freedesktop.DBus.Introspectable", "Introspect").dispatch(&mut cr).ok();
+        assert_eq!(xml, r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node name="/example">
+  <interface name="com.example.dbusrs.crossroads.score">
+    <method name="Hello">
+      <arg name="name" type="s" direction="in"/>
+      <arg name="reply" type="s" direction="out"/>
+    </method>
+    <property name="Score" type="q" access="read"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>"##);
+    }
+
+    #[test]
+    fn annotations() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync("Ping", |_: &Score, msg, _| Some(msg.method_return()))
+                .annotate("org.freedesktop.DBus.Method.NoReply", "true")),
+            vec!(PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0)).deprecated()
+                .annotate("org.freedesktop.DBus.Property.EmitsChangedSignal", "const")),
+            vec!(),
+        ).deprecated());
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let r = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Ping").dispatch(&mut cr);
+        assert!(r.reply().is_none() && r.signals().is_empty());
+
+        let data = cr.paths.0.get(CStr::from_bytes_with_nul(b"/\0").unwrap()).unwrap();
+        let xml = cr.introspect(&"/".into(), Some(data));
+        assert!(xml.contains(r##"
+    <method name="Ping">
+      <annotation name="org.freedesktop.DBus.Method.NoReply" value="true"/>
+    </method>
+    <property name="Score" type="q" access="read">
+      <annotation name="org.freedesktop.DBus.Deprecated" value="true"/>
+      <annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" value="const"/>
+    </property>
+    <annotation name="org.freedesktop.DBus.Deprecated" value="true"/>
+  </interface>"##));
+    }
+
+    #[test]
+    fn introspect_children() {
+        let mut cr = Crossroads::new_sync();
+        let mut pdata = PathData::new();
+        pdata.insert(DBusIntrospectable);
+        cr.insert("/org/example/player", pdata);
+        cr.insert("/org/example/player/track1", PathData::new());
+        cr.insert("/org/example/recorder/track1", PathData::new());
+        cr.insert("/org/examples", PathData::new());
+
+        let mut call = |path: &str| {
+            let r = TestCall::new(path, "org.freedesktop.DBus.Introspectable", "Introspect").dispatch(&mut cr);
+            if r.error().is_some() { None } else { Some(r.ok::<(String,)>().0) }
+        };
+        assert_eq!(call("/org/example").unwrap(), r##"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node name="/org/example">
+  <node name="player"/>
+  <node name="recorder"/>
+</node>"##);
+        let xml = call("/org/example/player").unwrap();
+        assert!(xml.contains("<interface name=\"org.freedesktop.DBus.Introspectable\">"));
+        assert!(xml.contains("  <node name=\"track1\"/>\n</node>"));
+        assert!(call("/").unwrap().contains("<node name=\"org\"/>"));
+        assert_eq!(call("/org/example/nothing"), None);
+    }
+
+    #[test]
+    fn object_manager() {
+        use crate::SignalArgs;
+        use crate::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesAdded;
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(),
+            vec!(PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0))), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(DBusObjectManager);
+        cr.insert("/", pdata);
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/score", pdata);
+
+        let (objects,): (HashMap<PathName<'static>, IfacePropMap>,) = TestCall::new("/", "org.freedesktop.DBus.ObjectManager", "GetManagedObjects")
+            .dispatch(&mut cr).ok();
+        assert_eq!(objects.len(), 1);
+        let props = &objects[&PathName::from("/score")]["com.example.dbusrs.crossroads.score"];
+        assert_eq!(props["Score"].0.as_u64(), Some(7));
+
+        let m = DBusObjectManager::interfaces_added(&cr, &"/".into(), &"/score".into()).unwrap();
+        let s = ObjectManagerInterfacesAdded::from_message(&m).unwrap();
+        assert_eq!(&*s.object, "/score");
+        assert!(s.interfaces.contains_key("com.example.dbusrs.crossroads.score"));
+    }
+
+    #[test]
+    fn object_manager_signals() {
+        use crate::stdintf::org_freedesktop_dbus::{ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved};
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(),
+            vec!(PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0))), vec!()));
+        let score = |s| { let mut pdata = PathData::new(); pdata.insert(Score(s)); pdata };
+
+        // No ObjectManager yet
+        cr.insert("/outside", score(1));
+        let mut pdata = PathData::new();
+        pdata.insert(DBusObjectManager);
+        cr.insert("/manager", pdata);
+        assert!(cr.take_signals().is_empty());
+
+        cr.insert("/manager/score", score(7));
+        cr.insert("/manager/score/sub", score(8));
+        let signals = cr.take_signals();
+        assert_eq!(signals.len(), 2);
+        assert_eq!(&*signals[0].path().unwrap(), "/manager");
+        let s = ObjectManagerInterfacesAdded::from_message(&signals[1]).unwrap();
+        assert_eq!(&*s.object, "/manager/score/sub");
+        let props = &s.interfaces["com.example.dbusrs.crossroads.score"];
+        assert_eq!(props["Score"].0.as_u64(), Some(8));
+        assert!(cr.take_signals().is_empty());
+
+        cr.remove("/manager/score");
+        cr.remove("/outside");
+        let signals = cr.take_signals();
+        assert_eq!(signals.len(), 1);
+        let s = ObjectManagerInterfacesRemoved::from_message(&signals[0]).unwrap();
+        assert_eq!(&*s.object, "/manager/score");
+        assert_eq!(s.interfaces, vec!("com.example.dbusrs.crossroads.score", "org.freedesktop.DBus.Introspectable",
+            "org.freedesktop.DBus.Peer", "org.freedesktop.DBus.Properties"));
+
+        cr.remove_subtree("/manager");
+        assert!(cr.take_signals().is_empty());
+    }
+
+    #[test]
+    fn catch_all() {
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_sync("Get", |x: &Score, msg, _| Some(msg.method_return().append1(x.0)))), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7));
+        cr.insert("/score", pdata);
+        cr.set_catch_all(|ctx| {
+            let forwarded = Message::new_method_call("com.example.dbusrs.elsewhere", ctx.path().unwrap(),
+                ctx.interface().unwrap(), ctx.member().unwrap()).unwrap();
+            ctx.push_msg(forwarded);
+            Some(ctx.message().method_return().append1("forwarded"))
+        });
+
+        let call = |cr: &mut Crossroads<()>, path: &str, method: &str| TestCall::new(path, "com.example.dbusrs.crossroads.score", method).dispatch(cr);
+        assert_eq!(call(&mut cr, "/score", "Get").ok::<(u16,)>().0, 7);
+        for (path, method) in &[("/score", "Unknown"), ("/nothing", "Get")] {
+            let r = call(&mut cr, path, method);
+            assert_eq!(r.reply().unwrap().read1::<&str>().unwrap(), "forwarded");
+            assert_eq!(r.signals().len(), 1);
+            assert_eq!(&*r.signals()[0].destination().unwrap(), "com.example.dbusrs.elsewhere");
+            assert_eq!(&*r.signals()[0].member().unwrap(), *method);
+        }
+
+        cr.set_allowed_senders(vec!(":1.57"));
+        let r = call(&mut cr, "/nothing", "Get");
+        assert!(r.error().is_some());
+        assert!(r.signals().is_empty());
+    }
+
+    #[test]
+    fn insert_tree() {
+        use crate::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesAdded;
+        let mut cr = Crossroads::new_sync();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(),
+            vec!(PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0))), vec!()));
+        let score = |s| { let mut pdata = PathData::new(); pdata.insert(Score(s)); pdata };
+        let mut pdata = PathData::new();
+        pdata.insert(DBusObjectManager);
+        cr.insert("/manager", pdata);
+
+        let bad = vec!(("hci0/dev_1", score(1)), ("hci0/", score(2)));
+        assert!(cr.insert_tree("/manager", bad).is_err());
+        assert!(cr.get_data("/manager/hci0/dev_1").is_none());
+
+        cr.insert_tree("/manager", (0..100).map(|i| (format!("hci0/dev_{}", i), score(i)))).unwrap();
+        let signals = cr.take_signals();
+        assert_eq!(signals.len(), 100);
+        let s = ObjectManagerInterfacesAdded::from_message(&signals[57]).unwrap();
+        assert_eq!(&*s.object, "/manager/hci0/dev_57");
+        assert_eq!(s.interfaces["com.example.dbusrs.crossroads.score"]["Score"].0.as_u64(), Some(57));
+        assert_eq!(cr.paths.0.len(), 101);
+
+        cr.insert_tree("/", vec!(("outside/a", score(1)))).unwrap();
+        assert!(cr.get_data("/outside/a").is_some());
+        assert!(cr.take_signals().is_empty());
+    }
+
+    #[test]
+    fn properties_changed() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(), vec!(
+            PropInfo::new_sync_ro("Score", |x: &Score, _, _| Some(x.0)),
+            PropInfo::new_sync_ro("Rank", |_: &Score, _, _| Some("high")).emits_changed(EmitsChangedSignal::Invalidates),
+            PropInfo::new_sync_ro("Max", |_: &Score, _, _| Some(100u16)).emits_changed(EmitsChangedSignal::Const),
+        ), vec!()));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let iface = "com.example.dbusrs.crossroads.score".into();
+        let m = cr.properties_changed(&"/".into(), &iface, vec!("Score", "Rank", "Max")).unwrap();
+        let s = PropertiesPropertiesChanged::from_message(&m).unwrap();
+        assert_eq!(s.interface_name, "com.example.dbusrs.crossroads.score");
+        assert_eq!(s.changed_properties.len(), 1);
+        assert_eq!(s.changed_properties["Score"].0.as_u64(), Some(7));
+        assert_eq!(s.invalidated_properties, vec!("Rank".to_string()));
+
+        assert!(cr.properties_changed(&"/".into(), &iface, vec!("Max")).is_none());
+    }
+
+    #[test]
+    fn emit_signal() {
+        let mut cr = Crossroads::new_sync();
+
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(), vec!(),
+            vec!(SignalInfo::new("ScoreChanged").arg("old", "q").arg("new", "q"))));
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(DBusIntrospectable);
+        cr.insert("/", pdata);
+
+        let (p, i) = ("/".into(), "com.example.dbusrs.crossroads.score".into());
+        let m = cr.emit_signal(&p, &i, "ScoreChanged", &[&7u16, &8u16]).unwrap();
+        assert_eq!(m.msg_type(), MessageType::Signal);
+        assert_eq!(&*m.member().unwrap(), "ScoreChanged");
+        assert_eq!(m.get2::<u16, u16>(), (Some(7), Some(8)));
+
+        assert!(cr.emit_signal(&p, &i, "ScoreChanged", &[&7u16]).is_none());
+        assert!(cr.emit_signal(&p, &i, "ScoreChanged", &[&7u16, &"8".to_string()]).is_none());
+        assert!(cr.emit_signal(&p, &i, "NoSuchSignal", &[]).is_none());
+
+        let xml = cr.introspect(&p, cr.get_data("/"));
+        assert!(xml.contains(r#"<signal name="ScoreChanged">
+      <arg name="old" type="q"/>
+      <arg name="new" type="q"/>
+    </signal>"#));
+    }
+
+    #[test]
+    fn local() {
+        use std::rc::Rc;
+        use std::cell::RefCell;
+        let mut cr = Crossroads::new_local();
+
+        struct Counter(u32);
+        cr.register::<Rc<RefCell<Counter>>>(IfaceInfo::new("com.example.dbusrs.crossroads.counter",
+            vec!(MethodInfo::new_local("Increment", |c: &Rc<RefCell<Counter>>, msg, _| {
+                let mut c = c.borrow_mut();
+                c.0 += 1;
+                Some(msg.method_return().append1(c.0))
+            })),
+            vec!(PropInfo::new_local_rw("Count", |c: &Rc<RefCell<Counter>>, _, _| Some(c.borrow().0),
+                |c: &Rc<RefCell<Counter>>, _, _, v: u32| { c.borrow_mut().0 = v; Ok(()) })),
+            vec!()));
+
+        // Shared with the application
+        let counter = Rc::new(RefCell::new(Counter(0)));
+        let mut pdata = PathData::new();
+        pdata.insert(counter.clone());
+        pdata.insert(DBusProperties);
+        cr.insert("/", pdata);
+
+        let call = TestCall::new("/", "com.example.dbusrs.crossroads.counter", "Increment");
+        assert_eq!(call.dispatch(&mut cr).ok::<(u32,)>().0, 1);
+        counter.borrow_mut().0 = 10;
+        assert_eq!(call.dispatch(&mut cr).ok::<(u32,)>().0, 11);
+
+        TestCall::new("/", "org.freedesktop.DBus.Properties", "Set")
+            .append1("com.example.dbusrs.crossroads.counter").append1("Count").append1(arg::Variant(5u32)).dispatch(&mut cr).ok::<()>();
+        assert_eq!(counter.borrow().0, 5);
+        assert!(cr.get_data("/").unwrap().get::<Rc<RefCell<Counter>>>().is_some());
+    }
+
+    #[test]
+    fn par() {
+        use std::thread;
+        let mut cr = Crossroads::new_par();
+
+        struct Counter(u32);
+        cr.register::<Counter>(IfaceInfo::new("com.example.dbusrs.crossroads.counter",
+            vec!(MethodInfo::new_par("Increment", |c: &mut Counter, msg, _| {
+                c.0 += 1;
+                Some(msg.method_return().append1(c.0))
+            })),
+            vec!(PropInfo::new_par_rw("Count", |c: &Counter, _, _| Some(c.0), |c: &mut Counter, _, _, v: u32| { c.0 = v; Ok(()) })),
+            vec!()));
+        for p in &["/a", "/b"] {
+            let mut pdata = PathData::new();
+            pdata.insert(Counter(0));
+            pdata.insert(DBusProperties);
+            cr.insert(*p, pdata);
+        }
+
+        let cr = Arc::new(cr);
+        let threads: Vec<_> = (0..4).map(|i| {
+            let cr = cr.clone();
+            thread::spawn(move || {
+                let path = if i % 2 == 0 { "/a" } else { "/b" };
+                for _ in 0..100 {
+                    // Shared between threads, so dispatched through &self
+                    let call = TestCall::new(path, "com.example.dbusrs.crossroads.counter", "Increment");
+                    assert_eq!(cr.dispatch(call.message()).unwrap()[0].msg_type(), MessageType::MethodReturn);
+                }
+            })
+        }).collect();
+        for t in threads { t.join().unwrap() }
+        assert_eq!(cr.get_data("/a").unwrap().lock::<Counter>().unwrap().0, 200);
+        assert_eq!(cr.get_data("/b").unwrap().lock::<Counter>().unwrap().0, 200);
+        assert!(cr.get_data("/a").unwrap().lock::<String>().is_none());
+
+        let mut cr = Arc::try_unwrap(cr).ok().unwrap();
+        TestCall::new("/a", "org.freedesktop.DBus.Properties", "Set")
+            .append1("com.example.dbusrs.crossroads.counter").append1("Count").append1(arg::Variant(5u32)).dispatch(&mut cr).ok::<()>();
+        let pdata = cr.get_data_mut("/a").unwrap();
+        assert_eq!(pdata.get_mut::<Counter>().unwrap().0, 5);
+        assert_eq!(pdata.remove::<Counter>().unwrap().0, 5);
+    }
+
+    fn poll_once<F: Future + Unpin>(f: &mut F) -> Option<F::Output> {
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+        fn noop_raw() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker { noop_raw() }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let waker = unsafe { Waker::from_raw(noop_raw()) };
+        match Pin::new(f).poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(r) => Some(r),
+            Poll::Pending => None,
+        }
+    }
+
+    #[test]
+    fn ordered_replies() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        // Resolves to the reply when the gate is opened.
+        struct Gate(Arc<AtomicBool>, Option<Message>);
+        impl Future for Gate {
+            type Output = Option<Message>;
+            fn poll(mut self: Pin<&mut Self>, _: &mut task::Context) -> task::Poll<Self::Output> {
+                if self.0.load(Ordering::SeqCst) { task::Poll::Ready(self.1.take()) } else { task::Poll::Pending }
+            }
+        }
+        let mut cr = Crossroads::new_async();
+        struct Gates(Vec<Arc<AtomicBool>>);
+        cr.register::<Gates>(IfaceInfo::new("com.example.dbusrs.crossroads.gates",
+            vec!(MethodInfo::new_async("Wait", |g: &Gates, msg, _| {
+                let i: u32 = msg.read1().unwrap();
+                Gate(g.0[i as usize].clone(), Some(msg.method_return().append1(i)))
+            })), vec!(), vec!()));
+        let gates: Vec<_> = (0..4).map(|_| Arc::new(AtomicBool::new(false))).collect();
+        let mut pdata = PathData::new();
+        pdata.insert(Gates(gates.clone()));
+        cr.insert("/", pdata);
+        // The replies are polled one by one, so the calls are dispatched without TestCall::dispatch
+        let call = |cr: &Crossroads<Async>, i: u32| cr.dispatch(TestCall::new("/", "com.example.dbusrs.crossroads.gates", "Wait").args((i,)).message()).unwrap();
+
+        // Unordered, the second call can be replied to first
+        let (mut d0, mut d1) = (call(&cr, 0), call(&cr, 1));
+        gates[1].store(true, Ordering::SeqCst);
+        assert!(poll_once(&mut d1).is_some());
+        assert!(poll_once(&mut d0).is_none());
+        gates[1].store(false, Ordering::SeqCst);
+
+        cr.set_ordered_replies(true);
+        let mut d: Vec<_> = (0..4).map(|i| call(&cr, i)).collect();
+        gates[1].store(true, Ordering::SeqCst);
+        gates[2].store(true, Ordering::SeqCst);
+        assert!(poll_once(&mut d[1]).is_none());
+        // Dropping the first call lets the others go.
+        d.remove(0);
+        assert_eq!(poll_once(&mut d[0]).unwrap()[0].read1::<u32>().unwrap(), 1);
+        assert_eq!(poll_once(&mut d[1]).unwrap()[0].read1::<u32>().unwrap(), 2);
+        assert!(poll_once(&mut d[2]).is_none());
+        gates[3].store(true, Ordering::SeqCst);
+        assert!(poll_once(&mut d[2]).is_some());
+        assert_eq!(cr.reply_order.as_ref().unwrap().pending_senders(), 0);
+    }
+
+    #[test]
+    fn simple_async() {
+        let mut cr = Crossroads::new_async();
+
+        struct Score(u16);
+
+        let info = IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_async("Hello", |x: &Score, msg, _| {
+                let mret = msg.method_return().append1(format!("Hello, my score is {}!", x.0));
+                handlers::ready(Some(mret))
+            })),
+            vec!(PropInfo::new_async_ro("Score", |x: &Score, _, _| Some(x.0))),
+            vec!(),
+        );
+        cr.register::<Score>(info);
+
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        pdata.insert(DBusProperties);
+        cr.insert("/", pdata);
+
+        let (s,): (String,) = TestCall::new("/", "com.example.dbusrs.crossroads.score", "Hello").dispatch(&mut cr).ok();
+        assert_eq!(s, "Hello, my score is 7!");
+
+        let (z,): (arg::Variant<u16>,) = TestCall::new("/", "org.freedesktop.DBus.Properties", "Get")
+            .append1("com.example.dbusrs.crossroads.score").append1("Score").dispatch(&mut cr).ok();
+        assert_eq!(z.0, 7u16);
+    }
+
+    #[test]
+    fn typed_async() {
+        use crate::tree::MethodErr;
+        let mut cr = Crossroads::new_async();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score",
+            vec!(MethodInfo::new_async_typed("Add", ("points",), ("name", "total"), |x: &Score, (b,): (u32,)| {
+                handlers::ready(if b > 100 { Err(MethodErr::failed(&"Too many points")) }
+                    else { Ok(("score".to_string(), x.0 as u32 + b)) })
+            })), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7u16));
+        cr.insert("/", pdata);
+
+        let mut call = |points: u32| TestCall::new("/", "com.example.dbusrs.crossroads.score", "Add").args((points,)).dispatch(&mut cr);
+        assert_eq!(call(5).ok::<(String, u32)>(), ("score".into(), 12));
+        assert!(call(500).error().is_some());
     }
 }
@@ -0,0 +1,108 @@
+use std::ffi::CString;
+use crate::{ErrorName, Message, arg};
+use crate::tree::MethodErr;
+
+/// Errors that can be replied to a method call, see `MethodInfo::new_typed_err`.
+///
+/// Implement this for an error type of your service, to return it from typed handlers
+/// without converting it to a MethodErr at every return site.
+pub trait DBusError {
+    /// The D-Bus error name, e g "org.freedesktop.DBus.Error.Failed".
+    fn error_name(&self) -> ErrorName<'static>;
+
+    /// The human-readable description, which is the first argument of the error reply.
+    fn error_message(&self) -> String;
+
+    /// Appends additional arguments to the error reply, after the description.
+    fn append_args(&self, _ia: &mut arg::IterAppend) {}
+
+    /// Creates an error reply to a method call.
+    fn to_error_message(&self, call: &Message) -> Message {
+        let desc = self.error_message().replace('\0', "");
+        let mut m = call.error(&self.error_name(), &CString::new(desc).unwrap());
+        self.append_args(&mut arg::IterAppend::new(&mut m));
+        m
+    }
+}
+
+impl DBusError for MethodErr {
+    fn error_name(&self) -> ErrorName<'static> { self.errorname().clone() }
+    fn error_message(&self) -> String { self.description().into() }
+}
+
+impl DBusError for crate::Error {
+    fn error_name(&self) -> ErrorName<'static> {
+        self.name().unwrap_or("org.freedesktop.DBus.Error.Failed").to_string().into()
+    }
+    fn error_message(&self) -> String { self.message().unwrap_or("Unknown error").into() }
+}
+
+/// Any error is replied as org.freedesktop.DBus.Error.Failed, with the chain of causes as description.
+#[cfg(feature = "anyhow")]
+impl DBusError for anyhow::Error {
+    fn error_name(&self) -> ErrorName<'static> { "org.freedesktop.DBus.Error.Failed".into() }
+    fn error_message(&self) -> String { format!("{:#}", self) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MessageType;
+    use crate::crossroads::testing::TestCall;
+
+    enum ScoreError { TooHigh(u32), Locked }
+
+    impl DBusError for ScoreError {
+        fn error_name(&self) -> ErrorName<'static> {
+            match self {
+                ScoreError::TooHigh(_) => "com.example.dbusrs.Error.TooHigh".into(),
+                ScoreError::Locked => "com.example.dbusrs.Error.Locked".into(),
+            }
+        }
+        fn error_message(&self) -> String {
+            match self {
+                ScoreError::TooHigh(max) => format!("The maximum score is {}", max),
+                ScoreError::Locked => "The score is locked".into(),
+            }
+        }
+        fn append_args(&self, ia: &mut arg::IterAppend) {
+            if let ScoreError::TooHigh(max) = self { ia.append(max) }
+        }
+    }
+
+    #[test]
+    fn error_reply() {
+        let call = TestCall::new("/", "com.example.dbusrs", "Set");
+        let call = call.message();
+        let m = ScoreError::TooHigh(100).to_error_message(call);
+        assert_eq!(m.msg_type(), MessageType::Error);
+        assert_eq!(m.get_reply_serial(), Some(call.get_serial()));
+        assert_eq!(m.read2::<&str, u32>().unwrap(), ("The maximum score is 100", 100));
+        let mut m = ScoreError::Locked.to_error_message(call);
+        assert_eq!(m.as_result().unwrap_err().name(), Some("com.example.dbusrs.Error.Locked"));
+
+        let m = MethodErr::no_arg().to_error_message(call);
+        assert_eq!(m.read1::<&str>().unwrap(), "Not enough arguments");
+    }
+
+    #[test]
+    fn typed_err() {
+        use crate::crossroads::{Crossroads, IfaceInfo, MethodInfo, PathData};
+        let mut cr = Crossroads::new_sync();
+        struct Score;
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs", vec!(
+            MethodInfo::new_typed_err("Set", ("score",), (), |_: &Score, (s,): (u32,)| {
+                if s > 100 { Err(ScoreError::TooHigh(100)) } else { Ok(()) }
+            }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score);
+        cr.insert("/", pdata);
+
+        let mut call = |s: u32| TestCall::new("/", "com.example.dbusrs", "Set").args((s,)).dispatch(&mut cr);
+        call(5).ok::<()>();
+        let r = call(500);
+        assert_eq!(r.reply().unwrap().read2::<&str, u32>().unwrap(), ("The maximum score is 100", 100));
+        assert_eq!(r.error().unwrap().name(), Some("com.example.dbusrs.Error.TooHigh"));
+    }
+}
@@ -1,8 +1,16 @@
 use std::fmt;
 use std::any::Any;
+use std::pin::Pin;
+use std::future::Future;
+use std::task;
+use std::sync::{Mutex, MutexGuard};
 use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, Signature, Message, arg};
 use super::crossroads::{Crossroads, PathData};
-use super::info::{MethodInfo, PropInfo};
+use super::info::{MethodInfo, PropInfo, Access};
+use super::args::{ArgAll, invalid_args};
+use super::context::Context;
+use super::error::DBusError;
+use crate::tree::MethodErr;
 
 pub struct DebugMethod<H: Handlers>(pub H::Method);
 impl<H: Handlers> fmt::Debug for DebugMethod<H> {
@@ -14,12 +22,69 @@ impl<H: Handlers> fmt::Debug for DebugProp<H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "...") }
 }
 
-pub trait Handlers {
+pub trait Handlers: Sized {
     type Method;
     type GetProp;
     type SetProp;
     type DynHandlers: Handlers;
     type Iface;
+
+    /// For internal use: makes a method handler that does not need the interface data.
+    fn make_method<F>(f: F) -> Self::Method
+    where F: Fn(&Context, &Crossroads<Self>, &PathData<Self>) -> Option<Message> + Send + Sync + 'static;
+
+    /// For internal use: interface data for interfaces that don't need any.
+    fn no_data() -> Self::Iface;
+
+    /// For internal use.
+    fn call_getprop(p: &Self::GetProp, iface: &Self::Iface, ctx: &Context, ia: &mut arg::IterAppend,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> bool;
+
+    /// For internal use. The iter points to the value inside the variant.
+    fn call_setprop(p: &Self::SetProp, iface: &Self::Iface, ctx: &Context, i: &mut arg::Iter,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> Result<(), MethodErr>;
+}
+
+/// For internal use: the boxed interface data of a handler flavor.
+pub trait AnyIface: Sized + 'static {
+    fn downcast_mut<I: Any>(&mut self) -> Option<&mut I>;
+    fn into_inner<I: Any>(self) -> Option<I>;
+}
+
+/// For internal use: interface data of type I can be used with this handler flavor.
+pub trait BoxIface<I>: AnyIface {
+    fn box_iface(i: I) -> Self;
+}
+
+/// For internal use: interface data that can be borrowed without locking, i e of every flavor but Par.
+pub trait RefIface: AnyIface {
+    fn downcast_ref<I: Any>(&self) -> Option<&I>;
+}
+
+impl AnyIface for Box<dyn Any + Send + Sync> {
+    fn downcast_mut<I: Any>(&mut self) -> Option<&mut I> { (**self).downcast_mut() }
+    fn into_inner<I: Any>(self) -> Option<I> { (self as Box<dyn Any>).downcast().ok().map(|b| *b) }
+}
+
+impl RefIface for Box<dyn Any + Send + Sync> {
+    fn downcast_ref<I: Any>(&self) -> Option<&I> { (**self).downcast_ref() }
+}
+
+impl<I: Any + Send + Sync> BoxIface<I> for Box<dyn Any + Send + Sync> {
+    fn box_iface(i: I) -> Self { Box::new(i) }
+}
+
+impl AnyIface for Box<dyn Any> {
+    fn downcast_mut<I: Any>(&mut self) -> Option<&mut I> { (**self).downcast_mut() }
+    fn into_inner<I: Any>(self) -> Option<I> { self.downcast().ok().map(|b| *b) }
+}
+
+impl RefIface for Box<dyn Any> {
+    fn downcast_ref<I: Any>(&self) -> Option<&I> { (**self).downcast_ref() }
+}
+
+impl<I: Any> BoxIface<I> for Box<dyn Any> {
+    fn box_iface(i: I) -> Self { Box::new(i) }
 }
 
 //// Sync tree - ()
@@ -27,14 +92,39 @@ pub trait Handlers {
 pub struct SyncInfo<'a> {
     pub (crate) cr: &'a Crossroads<()>,
     pub (crate) pd: &'a PathData<()>,
+    pub (crate) ctx: &'a Context<'a>,
+}
+
+impl<'a> SyncInfo<'a> {
+    pub fn ctx(&self) -> &'a Context<'a> { self.ctx }
+    /// All interfaces on the path being called, e g to reach the data of other interfaces.
+    pub fn path_data(&self) -> &'a PathData<()> { self.pd }
+    pub fn crossroads(&self) -> &'a Crossroads<()> { self.cr }
 }
 
 impl Handlers for () {
     type Method = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut SyncInfo) -> Option<Message> + Send + Sync + 'static>;
     type GetProp = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut arg::IterAppend, &mut SyncInfo) -> bool + Send + Sync + 'static>;
-    type SetProp = ();
+    type SetProp = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut arg::Iter, &mut SyncInfo) -> Result<(), MethodErr> + Send + Sync + 'static>;
     type DynHandlers = ();   
     type Iface = Box<dyn Any + 'static + Send + Sync>;
+
+    fn no_data() -> Self::Iface { Box::new(()) }
+
+    fn make_method<F>(f: F) -> Self::Method
+    where F: Fn(&Context, &Crossroads<Self>, &PathData<Self>) -> Option<Message> + Send + Sync + 'static {
+        Box::new(move |_, _, si| f(si.ctx, si.cr, si.pd))
+    }
+
+    fn call_getprop(p: &Self::GetProp, iface: &Self::Iface, ctx: &Context, ia: &mut arg::IterAppend,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> bool {
+        p(&**iface, ctx.message(), ia, &mut SyncInfo { cr, pd, ctx })
+    }
+
+    fn call_setprop(p: &Self::SetProp, iface: &Self::Iface, ctx: &Context, i: &mut arg::Iter,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> Result<(), MethodErr> {
+        p(&**iface, ctx.message(), i, &mut SyncInfo { cr, pd, ctx })
+    }
 }
 
 impl MethodInfo<'_, ()> {
@@ -50,10 +140,65 @@ impl MethodInfo<'_, ()> {
     }
 }
 
+impl MethodInfo<'_, ()> {
+    /// Like new_sync, but an error is turned into an error reply.
+    pub fn new_sync_result<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&T, &Message, &mut SyncInfo) -> Result<Message, MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_sync(name, move |x: &T, msg, si| Some(f(x, msg, si).unwrap_or_else(|e| e.to_message(msg))))
+    }
+}
+
+impl MethodInfo<'static, ()> {
+    /// A method with typed in and out arguments.
+    ///
+    /// The in arguments are read from the message before the handler is called, and the out arguments
+    /// are appended to the method return. The argument types are also used for introspection.
+    pub fn new_typed<IA, OA, N, F, T>(name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll,
+    F: Fn(&T, IA) -> Result<OA, MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_typed_err(name, in_names, out_names, f)
+    }
+
+    /// Like new_typed, but the handler can return any error type implementing DBusError.
+    pub fn new_typed_err<IA, OA, E, N, F, T>(name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll, E: DBusError,
+    F: Fn(&T, IA) -> Result<OA, E> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_sync(name, move |t: &T, msg, _| {
+            match IA::read(&mut msg.iter_init()) {
+                Ok(ia) => typed_reply(msg, f(t, ia)),
+                Err(e) => typed_reply::<OA, _>(msg, Err(invalid_args(e))),
+            }
+        }).in_args::<IA>(in_names).out_args::<OA>(out_names)
+    }
+}
+
+/// Appends the out arguments of a typed method to the method return, as separate arguments in order.
+fn typed_reply<OA: ArgAll, E: DBusError>(msg: &Message, r: Result<OA, E>) -> Option<Message> {
+    if msg.get_no_reply() { return None }
+    Some(match r {
+        Ok(oa) => {
+            let mut mret = msg.method_return();
+            oa.append(&mut arg::IterAppend::new(&mut mret));
+            mret
+        },
+        Err(e) => e.to_error_message(msg),
+    })
+}
+
 impl PropInfo<'_, ()> {
+    /// A read-only property. The getter only gets shared access to the interface data, so
+    /// values that change are typically kept in atomics or behind a Mutex.
     pub fn new_sync_ro<P, N, G, T>(name: N, getf: G) -> Self where
     G: Fn(&T, &Message, &SyncInfo) -> Option<P> + Send + Sync + 'static,
-//    S: Fn(&T, &Message, &SyncInfo, P) -> Option<()> + Send + Sync + 'static,
     N: Into<MemberName<'static>>,
     T: Any + Send + Sync + 'static,
     P: arg::Append + arg::Arg,
@@ -64,5 +209,547 @@ impl PropInfo<'_, ()> {
             else { false }
         })), None)
     }
+
+    /// A read-write property. The setter also gets shared access, and is only called if the
+    /// new value has the correct type.
+    pub fn new_sync_rw<P, N, G, S, T>(name: N, getf: G, setf: S) -> Self where
+    G: Fn(&T, &Message, &SyncInfo) -> Option<P> + Send + Sync + 'static,
+    S: Fn(&T, &Message, &SyncInfo, P) -> Result<(), MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    P: arg::Append + arg::Arg + for<'z> arg::Get<'z>,
+    {
+        let mut r = Self::new_sync_ro(name, getf);
+        r.handlers.1 = Some(Box::new(move |data, msg, i, si| {
+            let x = data.downcast_ref().unwrap();
+            let p = i.read()?;
+            setf(x, msg, si, p)
+        }));
+        r.rw = Access::ReadWrite;
+        r
+    }
 }
 
+
+//// Mutable tree - Mut
+
+/// Handler flavor where method handlers get mutable access to the interface data,
+/// so state can be kept in plain fields.
+///
+/// Dispatching requires `&mut Crossroads`. Property getters and setters still get shared access.
+pub struct Mut;
+
+/// Method handler of the Mut flavor.
+pub enum MutMethod {
+    /// Shared access to the whole tree. Used for the standard interfaces.
+    Ref(Box<Fn(&Context, &Crossroads<Mut>, &PathData<Mut>) -> Option<Message> + Send + Sync + 'static>),
+    /// Mutable access to the interface data.
+    Mut(Box<Fn(&mut (dyn Any + Send + Sync), &Message, &Context) -> Option<Message> + Send + Sync + 'static>),
+}
+
+impl Handlers for Mut {
+    type Method = MutMethod;
+    type GetProp = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut arg::IterAppend, &Context) -> bool + Send + Sync + 'static>;
+    type SetProp = Box<Fn(&mut (dyn Any + Send + Sync), &Message, &mut arg::Iter, &Context) -> Result<(), MethodErr> + Send + Sync + 'static>;
+    type DynHandlers = Mut;
+    type Iface = Box<dyn Any + 'static + Send + Sync>;
+
+    fn no_data() -> Self::Iface { Box::new(()) }
+
+    fn make_method<F>(f: F) -> Self::Method
+    where F: Fn(&Context, &Crossroads<Self>, &PathData<Self>) -> Option<Message> + Send + Sync + 'static {
+        MutMethod::Ref(Box::new(f))
+    }
+
+    fn call_getprop(p: &Self::GetProp, iface: &Self::Iface, ctx: &Context, ia: &mut arg::IterAppend,
+        _: &Crossroads<Self>, _: &PathData<Self>) -> bool {
+        p(&**iface, ctx.message(), ia, ctx)
+    }
+
+    // Crossroads<Mut> dispatches Properties.Set itself, with mutable access to the interface data.
+    fn call_setprop(_: &Self::SetProp, _: &Self::Iface, _: &Context, _: &mut arg::Iter,
+        _: &Crossroads<Self>, _: &PathData<Self>) -> Result<(), MethodErr> {
+        Err(MethodErr::failed(&"Setting properties needs mutable access to the interface data"))
+    }
+}
+
+impl MethodInfo<'_, Mut> {
+    pub fn new_mut<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&mut T, &Message, &Context) -> Option<Message> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new(name.into(), MutMethod::Mut(Box::new(move |data, msg, ctx| {
+            let x = data.downcast_mut().unwrap();
+            f(x, msg, ctx)
+        })))
+    }
+}
+
+impl MethodInfo<'_, Mut> {
+    /// Like new_mut, but an error is turned into an error reply. Changes made to the interface
+    /// data before the error are kept.
+    pub fn new_mut_result<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&mut T, &Message, &Context) -> Result<Message, MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_mut(name, move |x: &mut T, msg, ctx| Some(f(x, msg, ctx).unwrap_or_else(|e| e.to_message(msg))))
+    }
+}
+
+impl MethodInfo<'static, Mut> {
+    /// A method with typed in and out arguments, see `MethodInfo::new_typed`.
+    pub fn new_mut_typed<IA, OA, N, F, T>(name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll,
+    F: Fn(&mut T, IA) -> Result<OA, MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_mut_typed_err(name, in_names, out_names, f)
+    }
+
+    /// Like new_mut_typed, but the handler can return any error type implementing DBusError.
+    pub fn new_mut_typed_err<IA, OA, E, N, F, T>(name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll, E: DBusError,
+    F: Fn(&mut T, IA) -> Result<OA, E> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_mut(name, move |t: &mut T, msg, _| {
+            match IA::read(&mut msg.iter_init()) {
+                Ok(ia) => typed_reply(msg, f(t, ia)),
+                Err(e) => typed_reply::<OA, _>(msg, Err(invalid_args(e))),
+            }
+        }).in_args::<IA>(in_names).out_args::<OA>(out_names)
+    }
+}
+
+impl PropInfo<'_, Mut> {
+    /// A read-only property. Getters get shared access also in the Mut flavor, since
+    /// Get, GetAll and signals only read the interface data.
+    pub fn new_mut_ro<P, N, G, T>(name: N, getf: G) -> Self where
+    G: Fn(&T, &Message, &Context) -> Option<P> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    P: arg::Append + arg::Arg,
+    {
+        Self::new(name.into(), P::signature(), Some(Box::new(move |data, msg, ia, ctx| {
+            let x = data.downcast_ref().unwrap();
+            if let Some(t) = getf(x, msg, ctx) { ia.append(t); true }
+            else { false }
+        })), None)
+    }
+
+    /// A read-write property, where the setter gets `&mut T` like methods made with new_mut.
+    /// A new value of another type is replied to with an error, without calling the setter.
+    pub fn new_mut_rw<P, N, G, S, T>(name: N, getf: G, setf: S) -> Self where
+    G: Fn(&T, &Message, &Context) -> Option<P> + Send + Sync + 'static,
+    S: Fn(&mut T, &Message, &Context, P) -> Result<(), MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    P: arg::Append + arg::Arg + for<'z> arg::Get<'z>,
+    {
+        let mut r = Self::new_mut_ro(name, getf);
+        r.handlers.1 = Some(Box::new(move |data, msg, i, ctx| {
+            let x = data.downcast_mut().unwrap();
+            let p = i.read()?;
+            setf(x, msg, ctx, p)
+        }));
+        r.rw = Access::ReadWrite;
+        r
+    }
+}
+
+//// Async tree - Async
+
+/// Handler flavor where method handlers return a future, which resolves to the reply.
+///
+/// The future must be 'static, so copy out what you need from the message and the
+/// interface data before returning it.
+pub struct Async;
+
+/// The future returned from an async method handler.
+pub type AsyncReply = Pin<Box<Future<Output=Option<Message>> + Send + 'static>>;
+
+pub struct AsyncInfo<'a> {
+    pub (crate) cr: &'a Crossroads<Async>,
+    pub (crate) pd: &'a PathData<Async>,
+    pub (crate) ctx: &'a Context<'a>,
+}
+
+impl<'a> AsyncInfo<'a> {
+    /// Messages pushed to the context are sent after the future has resolved.
+    pub fn ctx(&self) -> &'a Context<'a> { self.ctx }
+    /// All interfaces on the path being called, e g to reach the data of other interfaces.
+    pub fn path_data(&self) -> &'a PathData<Async> { self.pd }
+    pub fn crossroads(&self) -> &'a Crossroads<Async> { self.cr }
+}
+
+impl Handlers for Async {
+    type Method = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut AsyncInfo) -> AsyncReply + Send + Sync + 'static>;
+    type GetProp = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut arg::IterAppend, &mut AsyncInfo) -> bool + Send + Sync + 'static>;
+    type SetProp = Box<Fn(&(dyn Any + Send + Sync), &Message, &mut arg::Iter, &mut AsyncInfo) -> Result<(), MethodErr> + Send + Sync + 'static>;
+    type DynHandlers = Async;
+    type Iface = Box<dyn Any + 'static + Send + Sync>;
+
+    fn no_data() -> Self::Iface { Box::new(()) }
+
+    fn make_method<F>(f: F) -> Self::Method
+    where F: Fn(&Context, &Crossroads<Self>, &PathData<Self>) -> Option<Message> + Send + Sync + 'static {
+        Box::new(move |_, _, ai| Box::pin(ready(f(ai.ctx, ai.cr, ai.pd))))
+    }
+
+    fn call_getprop(p: &Self::GetProp, iface: &Self::Iface, ctx: &Context, ia: &mut arg::IterAppend,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> bool {
+        p(&**iface, ctx.message(), ia, &mut AsyncInfo { cr, pd, ctx })
+    }
+
+    fn call_setprop(p: &Self::SetProp, iface: &Self::Iface, ctx: &Context, i: &mut arg::Iter,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> Result<(), MethodErr> {
+        p(&**iface, ctx.message(), i, &mut AsyncInfo { cr, pd, ctx })
+    }
+}
+
+impl MethodInfo<'_, Async> {
+    pub fn new_async<N, F, T, R>(name: N, f: F) -> Self where
+    F: Fn(&T, &Message, &mut AsyncInfo) -> R + Send + Sync + 'static,
+    R: Future<Output=Option<Message>> + Send + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new(name.into(), Box::new(move |data, msg, ai| {
+            let x = data.downcast_ref().unwrap();
+            Box::pin(f(x, msg, ai))
+        }))
+    }
+}
+
+impl MethodInfo<'static, Async> {
+    /// A method with typed in and out arguments, see `MethodInfo::new_typed`.
+    ///
+    /// The future returned from f resolves to the out arguments.
+    pub fn new_async_typed<IA, OA, N, F, T, R>(name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll + 'static,
+    F: Fn(&T, IA) -> R + Send + Sync + 'static,
+    R: Future<Output=Result<OA, MethodErr>> + Send + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new_async_typed_err(name, in_names, out_names, f)
+    }
+
+    /// Like new_async_typed, but the future can resolve to any error type implementing DBusError.
+    pub fn new_async_typed_err<IA, OA, E, N, F, T, R>(name: N, in_names: IA::Names, out_names: OA::Names, f: F) -> Self where
+    IA: ArgAll, OA: ArgAll + 'static, E: DBusError + 'static,
+    F: Fn(&T, IA) -> R + Send + Sync + 'static,
+    R: Future<Output=Result<OA, E>> + Send + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    {
+        Self::new(name.into(), Box::new(move |data, msg, _| {
+            let x = data.downcast_ref().unwrap();
+            match IA::read(&mut msg.iter_init()) {
+                Ok(ia) => Box::pin(TypedReply { call: msg.duplicate(), reply: Box::pin(f(x, ia)) }),
+                Err(e) => Box::pin(ready(typed_reply::<OA, _>(msg, Err(invalid_args(e))))),
+            }
+        })).in_args::<IA>(in_names).out_args::<OA>(out_names)
+    }
+}
+
+struct TypedReply<R> {
+    call: Message,
+    reply: Pin<Box<R>>,
+}
+
+impl<OA: ArgAll, E: DBusError, R: Future<Output=Result<OA, E>>> Future for TypedReply<R> {
+    type Output = Option<Message>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        match self.reply.as_mut().poll(cx) {
+            task::Poll::Ready(r) => task::Poll::Ready(typed_reply(&self.call, r)),
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+}
+
+/// A future that resolves to its value at once. Like std::future::ready, which needs rustc 1.48.
+pub (crate) struct Ready<T>(Option<T>);
+
+pub (crate) fn ready<T>(t: T) -> Ready<T> { Ready(Some(t)) }
+
+impl<T> Unpin for Ready<T> {}
+
+impl<T> Future for Ready<T> {
+    type Output = T;
+    fn poll(mut self: Pin<&mut Self>, _: &mut task::Context) -> task::Poll<T> {
+        task::Poll::Ready(self.0.take().expect("Ready polled after completion"))
+    }
+}
+
+impl PropInfo<'_, Async> {
+    /// A read-only property. Property getters are synchronous, also for the Async flavor.
+    pub fn new_async_ro<P, N, G, T>(name: N, getf: G) -> Self where
+    G: Fn(&T, &Message, &AsyncInfo) -> Option<P> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    P: arg::Append + arg::Arg,
+    {
+        Self::new(name.into(), P::signature(), Some(Box::new(move |data, msg, ia, ai| {
+            let x = data.downcast_ref().unwrap();
+            if let Some(t) = getf(x, msg, ai) { ia.append(t); true }
+            else { false }
+        })), None)
+    }
+
+    /// A read-write property. Setters are synchronous, also for the Async flavor.
+    pub fn new_async_rw<P, N, G, S, T>(name: N, getf: G, setf: S) -> Self where
+    G: Fn(&T, &Message, &AsyncInfo) -> Option<P> + Send + Sync + 'static,
+    S: Fn(&T, &Message, &AsyncInfo, P) -> Result<(), MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + Sync + 'static,
+    P: arg::Append + arg::Arg + for<'z> arg::Get<'z>,
+    {
+        let mut r = Self::new_async_ro(name, getf);
+        r.handlers.1 = Some(Box::new(move |data, msg, i, ai| {
+            let x = data.downcast_ref().unwrap();
+            let p = i.read()?;
+            setf(x, msg, ai, p)
+        }));
+        r.rw = Access::ReadWrite;
+        r
+    }
+}
+
+//// Single-threaded tree - Local
+
+/// Handler flavor for single-threaded use, e g on a GUI main loop.
+///
+/// Neither the interface data nor the handlers need to be Send or Sync, so the interface data
+/// can be e g an `Rc<RefCell<T>>` shared with the rest of the application.
+pub struct Local;
+
+pub struct LocalInfo<'a> {
+    pub (crate) cr: &'a Crossroads<Local>,
+    pub (crate) pd: &'a PathData<Local>,
+    pub (crate) ctx: &'a Context<'a>,
+}
+
+impl<'a> LocalInfo<'a> {
+    pub fn ctx(&self) -> &'a Context<'a> { self.ctx }
+    /// All interfaces on the path being called, e g to reach the data of other interfaces.
+    pub fn path_data(&self) -> &'a PathData<Local> { self.pd }
+    pub fn crossroads(&self) -> &'a Crossroads<Local> { self.cr }
+}
+
+impl Handlers for Local {
+    type Method = Box<dyn Fn(&dyn Any, &Message, &mut LocalInfo) -> Option<Message> + 'static>;
+    type GetProp = Box<dyn Fn(&dyn Any, &Message, &mut arg::IterAppend, &mut LocalInfo) -> bool + 'static>;
+    type SetProp = Box<dyn Fn(&dyn Any, &Message, &mut arg::Iter, &mut LocalInfo) -> Result<(), MethodErr> + 'static>;
+    type DynHandlers = Local;
+    type Iface = Box<dyn Any + 'static>;
+
+    fn no_data() -> Self::Iface { Box::new(()) }
+
+    fn make_method<F>(f: F) -> Self::Method
+    where F: Fn(&Context, &Crossroads<Self>, &PathData<Self>) -> Option<Message> + Send + Sync + 'static {
+        Box::new(move |_, _, li| f(li.ctx, li.cr, li.pd))
+    }
+
+    fn call_getprop(p: &Self::GetProp, iface: &Self::Iface, ctx: &Context, ia: &mut arg::IterAppend,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> bool {
+        p(&**iface, ctx.message(), ia, &mut LocalInfo { cr, pd, ctx })
+    }
+
+    fn call_setprop(p: &Self::SetProp, iface: &Self::Iface, ctx: &Context, i: &mut arg::Iter,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> Result<(), MethodErr> {
+        p(&**iface, ctx.message(), i, &mut LocalInfo { cr, pd, ctx })
+    }
+}
+
+impl MethodInfo<'_, Local> {
+    pub fn new_local<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&T, &Message, &mut LocalInfo) -> Option<Message> + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + 'static,
+    {
+        Self::new(name.into(), Box::new(move |data, msg, li| {
+            let x = data.downcast_ref().unwrap();
+            f(x, msg, li)
+        }))
+    }
+
+    /// Like new_local, but an error is turned into an error reply. Neither `f` nor the
+    /// interface data needs to be Send.
+    pub fn new_local_result<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&T, &Message, &mut LocalInfo) -> Result<Message, MethodErr> + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + 'static,
+    {
+        Self::new_local(name, move |x: &T, msg, li| Some(f(x, msg, li).unwrap_or_else(|e| e.to_message(msg))))
+    }
+}
+
+impl PropInfo<'_, Local> {
+    /// A read-only property of interface data that is not Send or Sync, e g an `Rc<RefCell<T>>`.
+    pub fn new_local_ro<P, N, G, T>(name: N, getf: G) -> Self where
+    G: Fn(&T, &Message, &LocalInfo) -> Option<P> + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + 'static,
+    P: arg::Append + arg::Arg,
+    {
+        Self::new(name.into(), P::signature(), Some(Box::new(move |data, msg, ia, li| {
+            let x = data.downcast_ref().unwrap();
+            if let Some(t) = getf(x, msg, li) { ia.append(t); true }
+            else { false }
+        })), None)
+    }
+
+    /// A read-write property. The setter gets shared access, so it typically changes the value
+    /// through a `RefCell` or `Cell` in the interface data.
+    pub fn new_local_rw<P, N, G, S, T>(name: N, getf: G, setf: S) -> Self where
+    G: Fn(&T, &Message, &LocalInfo) -> Option<P> + 'static,
+    S: Fn(&T, &Message, &LocalInfo, P) -> Result<(), MethodErr> + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + 'static,
+    P: arg::Append + arg::Arg + for<'z> arg::Get<'z>,
+    {
+        let mut r = Self::new_local_ro(name, getf);
+        r.handlers.1 = Some(Box::new(move |data, msg, i, li| {
+            let x = data.downcast_ref().unwrap();
+            let p = i.read()?;
+            setf(x, msg, li, p)
+        }));
+        r.rw = Access::ReadWrite;
+        r
+    }
+}
+
+//// Parallel tree - Par
+
+/// Handler flavor for dispatching from several threads at once on a shared `&Crossroads`.
+///
+/// Every interface data is kept behind its own Mutex, which is locked while a method handler,
+/// property getter or setter for it runs. Calls to different objects can run in parallel.
+/// Don't lock the interface data of the object being called from within its handler, that deadlocks.
+pub struct Par;
+
+/// Interface data of the Par flavor, see `PathData::<Par>::lock`.
+pub struct ParIface(Box<dyn Any + Send + Sync>);
+
+// Not RefIface, as the data is only reachable through the lock, see `PathData::<Par>::lock`.
+impl AnyIface for ParIface {
+    fn downcast_mut<I: Any>(&mut self) -> Option<&mut I> {
+        self.0.downcast_mut::<Mutex<I>>().map(|m| m.get_mut().unwrap_or_else(|e| e.into_inner()))
+    }
+    fn into_inner<I: Any>(self) -> Option<I> {
+        let m: Box<Mutex<I>> = (self.0 as Box<dyn Any>).downcast().ok()?;
+        Some(m.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl<I: Any + Send> BoxIface<I> for ParIface {
+    fn box_iface(i: I) -> Self { ParIface(Box::new(Mutex::new(i))) }
+}
+
+impl ParIface {
+    pub (crate) fn lock<I: Any>(&self) -> Option<MutexGuard<I>> {
+        self.0.downcast_ref::<Mutex<I>>().map(|m| m.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+pub struct ParInfo<'a> {
+    pub (crate) cr: &'a Crossroads<Par>,
+    pub (crate) pd: &'a PathData<Par>,
+    pub (crate) ctx: &'a Context<'a>,
+}
+
+impl<'a> ParInfo<'a> {
+    pub fn ctx(&self) -> &'a Context<'a> { self.ctx }
+    /// All interfaces on the path being called, e g to reach the data of other interfaces.
+    pub fn path_data(&self) -> &'a PathData<Par> { self.pd }
+    pub fn crossroads(&self) -> &'a Crossroads<Par> { self.cr }
+}
+
+impl Handlers for Par {
+    type Method = Box<dyn Fn(&ParIface, &Message, &mut ParInfo) -> Option<Message> + Send + Sync + 'static>;
+    type GetProp = Box<dyn Fn(&ParIface, &Message, &mut arg::IterAppend, &mut ParInfo) -> bool + Send + Sync + 'static>;
+    type SetProp = Box<dyn Fn(&ParIface, &Message, &mut arg::Iter, &mut ParInfo) -> Result<(), MethodErr> + Send + Sync + 'static>;
+    type DynHandlers = Par;
+    type Iface = ParIface;
+
+    fn no_data() -> Self::Iface { ParIface::box_iface(()) }
+
+    fn make_method<F>(f: F) -> Self::Method
+    where F: Fn(&Context, &Crossroads<Self>, &PathData<Self>) -> Option<Message> + Send + Sync + 'static {
+        Box::new(move |_, _, pi| f(pi.ctx, pi.cr, pi.pd))
+    }
+
+    fn call_getprop(p: &Self::GetProp, iface: &Self::Iface, ctx: &Context, ia: &mut arg::IterAppend,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> bool {
+        p(iface, ctx.message(), ia, &mut ParInfo { cr, pd, ctx })
+    }
+
+    fn call_setprop(p: &Self::SetProp, iface: &Self::Iface, ctx: &Context, i: &mut arg::Iter,
+        cr: &Crossroads<Self>, pd: &PathData<Self>) -> Result<(), MethodErr> {
+        p(iface, ctx.message(), i, &mut ParInfo { cr, pd, ctx })
+    }
+}
+
+impl MethodInfo<'_, Par> {
+    /// A method handler with mutable access to the interface data, which is locked meanwhile.
+    pub fn new_par<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&mut T, &Message, &mut ParInfo) -> Option<Message> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + 'static,
+    {
+        Self::new(name.into(), Box::new(move |data, msg, pi| {
+            let mut x = data.lock().unwrap();
+            f(&mut x, msg, pi)
+        }))
+    }
+
+    /// Like new_par, but an error is turned into an error reply. The interface data stays
+    /// locked until the error reply is made.
+    pub fn new_par_result<N, F, T>(name: N, f: F) -> Self where
+    F: Fn(&mut T, &Message, &mut ParInfo) -> Result<Message, MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + 'static,
+    {
+        Self::new_par(name, move |x: &mut T, msg, pi| Some(f(x, msg, pi).unwrap_or_else(|e| e.to_message(msg))))
+    }
+}
+
+impl PropInfo<'_, Par> {
+    /// A read-only property. The interface data is locked while the getter runs.
+    pub fn new_par_ro<P, N, G, T>(name: N, getf: G) -> Self where
+    G: Fn(&T, &Message, &ParInfo) -> Option<P> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + 'static,
+    P: arg::Append + arg::Arg,
+    {
+        Self::new(name.into(), P::signature(), Some(Box::new(move |data, msg, ia, pi| {
+            let x = data.lock().unwrap();
+            if let Some(t) = getf(&x, msg, pi) { ia.append(t); true }
+            else { false }
+        })), None)
+    }
+
+    /// A read-write property, where the setter gets `&mut T` while the interface data is locked.
+    /// The new value is read before locking, so a value of another type never takes the lock.
+    pub fn new_par_rw<P, N, G, S, T>(name: N, getf: G, setf: S) -> Self where
+    G: Fn(&T, &Message, &ParInfo) -> Option<P> + Send + Sync + 'static,
+    S: Fn(&mut T, &Message, &ParInfo, P) -> Result<(), MethodErr> + Send + Sync + 'static,
+    N: Into<MemberName<'static>>,
+    T: Any + Send + 'static,
+    P: arg::Append + arg::Arg + for<'z> arg::Get<'z>,
+    {
+        let mut r = Self::new_par_ro(name, getf);
+        r.handlers.1 = Some(Box::new(move |data, msg, i, pi| {
+            let p = i.read()?;
+            let mut x = data.lock().unwrap();
+            setf(&mut x, msg, pi, p)
+        }));
+        r.rw = Access::ReadWrite;
+        r
+    }
+}
@@ -1,25 +1,67 @@
-use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, Signature, Message};
+use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, Signature, Message, arg};
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::any::Any;
 use super::handlers::{Handlers, DebugMethod, DebugProp};
 use super::crossroads::{Crossroads, PathData};
+use super::args::{ArgAll, msg_signature};
+use super::context::Context;
+use crate::tree::MethodErr;
+
+const DEPRECATED: &str = "org.freedesktop.DBus.Deprecated";
+const EMITS_CHANGED: &str = "org.freedesktop.DBus.Property.EmitsChangedSignal";
 
 #[derive(Default, Debug, Clone)]
 struct Annotations(Option<BTreeMap<String, String>>);
 
+impl Annotations {
+    fn insert(&mut self, name: String, value: String) {
+        self.0.get_or_insert_with(BTreeMap::new).insert(name, value);
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.as_ref().and_then(|m| m.get(name)).map(|s| &**s)
+    }
+
+    fn introspect(&self, indent: &str) -> String {
+        self.0.as_ref().map(|s| s.iter().fold("".into(), |aa, (ak, av)| {
+            format!("{}{}<annotation name=\"{}\" value=\"{}\"/>\n", aa, indent, ak, av)
+        })).unwrap_or(String::new())
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Argument<'a> {
     name: Option<Cow<'a, str>>,
     sig: Signature<'a>,
 }
 
+impl Argument<'_> {
+    fn introspect(&self, indent: &str, dir: &str) -> String {
+        let n = self.name.as_ref().map(|n| format!("name=\"{}\" ", n)).unwrap_or("".into());
+        format!("{}<arg {}type=\"{}\"{}/>\n", indent, n, self.sig, dir)
+    }
+}
+
+fn introspect_args(args: &[Argument], indent: &str, dir: &str) -> String {
+    args.iter().fold("".to_string(), |aa, az| format!("{}{}", aa, az.introspect(indent, dir)))
+}
+
+fn xml_element(indent: &str, name: &str, params: &str, contents: &str) -> String {
+    if contents.len() > 0 {
+        format!("{}<{}{}>\n{}{}</{}>\n", indent, name, params, contents, indent, name)
+    } else {
+        format!("{}<{}{}/>\n", indent, name, params)
+    }
+}
+
 #[derive(Debug)]
 pub struct IfaceInfo<'a, H: Handlers> {
     pub (crate) name: IfaceName<'a>,
     pub (crate) methods: Vec<MethodInfo<'a, H>>,
     pub (crate) props: Vec<PropInfo<'a, H>>,
     pub (crate) signals: Vec<SignalInfo<'a>>,
+    anns: Annotations,
 }
 
 #[derive(Debug)]
@@ -29,6 +71,7 @@ pub struct MethodInfo<'a, H: Handlers> {
     i_args: Vec<Argument<'a>>,
     o_args: Vec<Argument<'a>>,
     anns: Annotations,
+    check_in_args: bool,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Debug)]
@@ -56,29 +99,104 @@ pub enum Access {
     Write,
 }
 
+impl Access {
+    fn introspect(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::ReadWrite => "readwrite",
+            Access::Write => "write",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PropInfo<'a, H: Handlers> {
     pub (crate) name: MemberName<'a>,
     pub (crate) handlers: DebugProp<H>,
     anns: Annotations,
-    sig: Signature<'a>,
-    emits: EmitsChangedSignal,
+    pub (crate) sig: Signature<'a>,
+    pub (crate) emits: EmitsChangedSignal,
     auto_emit: bool,
-    rw: Access,
+    pub (crate) rw: Access,
+    read_access: Option<fn(&Context) -> bool>,
 }
 
 #[derive(Debug)]
 pub struct SignalInfo<'a> {
-    name: MemberName<'a>,
+    pub (crate) name: MemberName<'a>,
     args: Vec<Argument<'a>>,
     anns: Annotations,
 }
 
-impl<H: Handlers> MethodInfo<'_, H> {
+impl<'a, H: Handlers> MethodInfo<'a, H> {
     pub fn new(name: MemberName<'static>, f: H::Method) -> Self {
-        MethodInfo { name: name, handler: DebugMethod(f),
+        MethodInfo { name: name, handler: DebugMethod(f), check_in_args: false,
             i_args: Default::default(), o_args: Default::default(), anns: Default::default() }
     }
+
+    /// Adds an in argument, for introspection purposes.
+    ///
+    /// Once in arguments are declared, method calls with other arguments are
+    /// replied to with an InvalidArgs error without calling the handler.
+    pub fn in_arg<N: Into<Cow<'a, str>>, S: Into<Signature<'a>>>(mut self, name: N, sig: S) -> Self {
+        self.i_args.push(Argument { name: Some(name.into()), sig: sig.into() });
+        self.check_in_args = true;
+        self
+    }
+
+    /// Adds an out argument, for introspection purposes.
+    pub fn out_arg<N: Into<Cow<'a, str>>, S: Into<Signature<'a>>>(mut self, name: N, sig: S) -> Self {
+        self.o_args.push(Argument { name: Some(name.into()), sig: sig.into() });
+        self
+    }
+
+    /// Declares all in arguments, with signatures taken from the types in A.
+    ///
+    /// Also declaring zero arguments makes the arguments of method calls checked, see in_arg.
+    pub fn in_args<A: ArgAll>(mut self, names: A::Names) -> Self {
+        let mut v = vec!();
+        A::strs_sigs(names, |n, sig| v.push(Argument { name: Some(n.into()), sig: sig.into() }));
+        self.i_args.extend(v);
+        self.check_in_args = true;
+        self
+    }
+
+    /// Declares all out arguments, with signatures taken from the types in A.
+    pub fn out_args<A: ArgAll>(mut self, names: A::Names) -> Self {
+        let mut v = vec!();
+        A::strs_sigs(names, |n, sig| v.push(Argument { name: Some(n.into()), sig: sig.into() }));
+        self.o_args.extend(v);
+        self
+    }
+
+    /// Checks the arguments of a method call against the declared in arguments.
+    pub (crate) fn check_in_args(&self, msg: &Message) -> Result<(), MethodErr> {
+        if !self.check_in_args { return Ok(()) }
+        let expected = self.i_args.iter().fold(String::new(), |a, arg| a + &arg.sig);
+        let found = msg_signature(msg);
+        if expected == found { return Ok(()) }
+        Err(("org.freedesktop.DBus.Error.InvalidArgs",
+            format!("Method {} expects arguments of type '{}', not '{}'", self.name, expected, found)).into())
+    }
+
+    /// Adds an annotation, e g "org.freedesktop.DBus.Deprecated" with value "true".
+    ///
+    /// Methods annotated with "org.freedesktop.DBus.Method.NoReply" set to "true" never send a reply.
+    pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.anns.insert(name.into(), value.into()); self
+    }
+
+    /// Marks the method as deprecated.
+    pub fn deprecated(self) -> Self { self.annotate(DEPRECATED, "true") }
+
+    pub (crate) fn no_reply(&self) -> bool { self.anns.get("org.freedesktop.DBus.Method.NoReply") == Some("true") }
+
+    fn introspect(&self, indent: &str) -> String {
+        let ii = format!("{}  ", indent);
+        let contents = format!("{}{}{}", introspect_args(&self.i_args, &ii, " direction=\"in\""),
+            introspect_args(&self.o_args, &ii, " direction=\"out\""), self.anns.introspect(&ii));
+        xml_element(indent, "method", &format!(" name=\"{}\"", self.name), &contents)
+    }
 }
 
 impl<H: Handlers> PropInfo<'_, H> {
@@ -91,7 +209,85 @@ impl<H: Handlers> PropInfo<'_, H> {
             _ => unimplemented!(),
         };
         PropInfo { name, handlers: DebugProp(get, set), sig, auto_emit: true, rw: a, 
-            emits: EmitsChangedSignal::True, anns: Default::default() }
+            emits: EmitsChangedSignal::True, anns: Default::default(), read_access: None }
+    }
+
+    /// Only lets callers read the property if `f` returns true, e g for secrets or serial numbers.
+    ///
+    /// Get is replied to with an AccessDenied error, while GetAll, GetManagedObjects and
+    /// ObjectManager signals leave the property out. PropertiesChanged signals are broadcast,
+    /// so they always list the property as invalidated, without its value.
+    pub fn read_access(mut self, f: fn(&Context) -> bool) -> Self { self.read_access = Some(f); self }
+
+    pub (crate) fn readable(&self, ctx: &Context) -> bool { self.read_access.map_or(true, |f| f(ctx)) }
+
+    pub (crate) fn read_restricted(&self) -> bool { self.read_access.is_some() }
+
+    /// Sets how the property behaves in PropertiesChanged signals. Default is EmitsChangedSignal::True.
+    pub fn emits_changed(mut self, e: EmitsChangedSignal) -> Self { self.emits = e; self }
+
+    /// Adds an annotation, e g "org.freedesktop.DBus.Deprecated" with value "true".
+    ///
+    /// An "org.freedesktop.DBus.Property.EmitsChangedSignal" annotation is the same as calling emits_changed.
+    pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        let (name, value) = (name.into(), value.into());
+        if name == EMITS_CHANGED {
+            self.emits = match &*value {
+                "false" => EmitsChangedSignal::False,
+                "const" => EmitsChangedSignal::Const,
+                "invalidates" => EmitsChangedSignal::Invalidates,
+                _ => EmitsChangedSignal::True,
+            };
+        } else { self.anns.insert(name, value) }
+        self
+    }
+
+    /// Marks the property as deprecated.
+    pub fn deprecated(self) -> Self { self.annotate(DEPRECATED, "true") }
+
+    fn introspect(&self, indent: &str) -> String {
+        let ii = format!("{}  ", indent);
+        let mut anns = self.anns.clone();
+        let emits = match self.emits {
+            EmitsChangedSignal::True => None,
+            EmitsChangedSignal::False => Some("false"),
+            EmitsChangedSignal::Const => Some("const"),
+            EmitsChangedSignal::Invalidates => Some("invalidates"),
+        };
+        if let Some(e) = emits { anns.insert(EMITS_CHANGED.into(), e.into()) }
+        let params = format!(" name=\"{}\" type=\"{}\" access=\"{}\"", self.name, self.sig, self.rw.introspect());
+        xml_element(indent, "property", &params, &anns.introspect(&ii))
+    }
+}
+
+impl<'a> SignalInfo<'a> {
+    pub fn new<N: Into<MemberName<'a>>>(name: N) -> Self {
+        SignalInfo { name: name.into(), args: Default::default(), anns: Default::default() }
+    }
+
+    /// Adds an argument.
+    pub fn arg<N: Into<Cow<'a, str>>, S: Into<Signature<'a>>>(mut self, name: N, sig: S) -> Self {
+        self.args.push(Argument { name: Some(name.into()), sig: sig.into() });
+        self
+    }
+
+    /// Adds an annotation, e g "org.freedesktop.DBus.Deprecated" with value "true".
+    pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.anns.insert(name.into(), value.into()); self
+    }
+
+    /// Marks the signal as deprecated.
+    pub fn deprecated(self) -> Self { self.annotate(DEPRECATED, "true") }
+
+    /// Checks that the arguments have the declared signatures.
+    pub (crate) fn matches_args(&self, args: &[&arg::RefArg]) -> bool {
+        self.args.len() == args.len() && self.args.iter().zip(args).all(|(a, b)| *a.sig == *b.signature())
+    }
+
+    fn introspect(&self, indent: &str) -> String {
+        let ii = format!("{}  ", indent);
+        let contents = format!("{}{}", introspect_args(&self.args, &ii, ""), self.anns.introspect(&ii));
+        xml_element(indent, "signal", &format!(" name=\"{}\"", self.name), &contents)
     }
 }
 
@@ -106,8 +302,27 @@ impl<'a, H: Handlers> IfaceInfo<'a, H> {
             name: name.into(),
             methods: methods.into_iter().collect(),
             props: properties.into_iter().collect(),
-            signals: signals.into_iter().collect()
+            signals: signals.into_iter().collect(),
+            anns: Default::default(),
         }
     }
+
+    /// Adds an annotation, e g "org.freedesktop.DBus.Deprecated" with value "true".
+    pub fn annotate<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.anns.insert(name.into(), value.into()); self
+    }
+
+    /// Marks the interface as deprecated.
+    pub fn deprecated(self) -> Self { self.annotate(DEPRECATED, "true") }
+
+    pub (crate) fn introspect(&self, indent: &str) -> String {
+        let ii = format!("{}  ", indent);
+        let contents = format!("{}{}{}{}",
+            self.methods.iter().fold(String::new(), |a, m| a + &m.introspect(&ii)),
+            self.props.iter().fold(String::new(), |a, p| a + &p.introspect(&ii)),
+            self.signals.iter().fold(String::new(), |a, s| a + &s.introspect(&ii)),
+            self.anns.introspect(&ii));
+        xml_element(indent, "interface", &format!(" name=\"{}\"", self.name), &contents)
+    }
 }
 
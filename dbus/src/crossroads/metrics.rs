@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::{Message, MessageType};
+
+/// Statistics for the calls to one method, see `Crossroads::metrics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MethodMetrics {
+    /// Number of calls that reached the method handler.
+    pub calls: u64,
+    /// Number of those calls that were replied to with an error.
+    pub errors: u64,
+    /// Total time spent in the handler. For async handlers, until the reply was ready.
+    pub total_time: Duration,
+    /// The longest time spent in the handler for a single call.
+    pub max_time: Duration,
+}
+
+/// Metrics for all methods, keyed by interface and method name.
+pub type Metrics = BTreeMap<(String, String), MethodMetrics>;
+
+#[derive(Debug, Default)]
+pub (super) struct MetricsCollector(Mutex<Metrics>);
+
+impl MetricsCollector {
+    pub (super) fn record(&self, iface: &str, method: &str, reply: Option<&Message>, time: Duration) {
+        let error = reply.map(|r| r.msg_type() == MessageType::Error).unwrap_or(false);
+        let mut map = self.0.lock().unwrap();
+        let m = map.entry((iface.into(), method.into())).or_insert_with(Default::default);
+        m.calls += 1;
+        if error { m.errors += 1 }
+        m.total_time += time;
+        if time > m.max_time { m.max_time = time }
+    }
+
+    pub (super) fn get(&self) -> Metrics { self.0.lock().unwrap().clone() }
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::sync::Arc;
+use crate::Message;
+use crate::tree::MethodErr;
+use super::context::Context;
+
+/// Code that runs around every dispatched method call, e g for logging or metrics.
+///
+/// Middleware added with `Crossroads::add_middleware` runs `before` in the order it was added,
+/// and `after` in the reverse order.
+pub trait Middleware: Send + Sync + 'static {
+    /// Called before the method handler. Returning an error rejects the call: the handler
+    /// and the remaining middleware are not called, and the error is replied instead.
+    fn before(&self, _ctx: &Context) -> Result<(), MethodErr> { Ok(()) }
+
+    /// Called with the method call and the reply produced (if any), which can be changed or replaced.
+    ///
+    /// Only called if `before` of this middleware was called and returned Ok.
+    fn after(&self, _call: &Message, _reply: &mut Option<Message>) {}
+}
+
+#[derive(Default, Clone)]
+pub (super) struct Middlewares(Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "Middlewares({})", self.0.len()) }
+}
+
+impl Middlewares {
+    pub (super) fn push<M: Middleware>(&mut self, m: M) { self.0.push(Arc::new(m)) }
+
+    /// Runs the `before` functions. Returns how many of them succeeded, and the error if one failed.
+    pub (super) fn before(&self, ctx: &Context) -> (usize, Option<MethodErr>) {
+        for (i, m) in self.0.iter().enumerate() {
+            if let Err(e) = m.before(ctx) { return (i, Some(e)) }
+        }
+        (self.0.len(), None)
+    }
+
+    /// Runs the `after` functions of the first `count` middlewares, in reverse order.
+    pub (super) fn after(&self, count: usize, call: &Message, reply: &mut Option<Message>) {
+        for m in self.0[..count].iter().rev() { m.after(call, reply) }
+    }
+
+    /// The middlewares whose `after` functions should run, for running them later.
+    pub (super) fn take(&self, count: usize) -> Middlewares { Middlewares(self.0[..count].to_vec()) }
+
+    pub (super) fn len(&self) -> usize { self.0.len() }
+}
@@ -5,3 +5,30 @@ mod info;
 mod handlers;
 mod crossroads;
 mod stdimpl;
+mod args;
+mod context;
+mod builder;
+mod serve;
+mod middleware;
+mod metrics;
+mod order;
+mod trace;
+mod pool;
+mod error;
+
+pub mod testing;
+
+#[cfg(feature = "polkit")]
+pub mod polkit;
+
+pub use self::info::{IfaceInfo, MethodInfo, PropInfo, SignalInfo, EmitsChangedSignal};
+pub use self::crossroads::{Crossroads, PathData, AsyncDispatch, IntrospectCacheStats};
+pub use self::context::{Context, DeferredReply, Replier, PropChanges};
+pub use self::handlers::{Handlers, SyncInfo, Mut, MutMethod, Async, AsyncInfo, AsyncReply, Local, LocalInfo, Par, ParInfo, ParIface};
+pub use self::args::ArgAll;
+pub use self::builder::IfaceBuilder;
+pub use self::middleware::Middleware;
+pub use self::metrics::{Metrics, MethodMetrics};
+pub use self::pool::DispatchPool;
+pub use self::error::DBusError;
+pub use self::stdimpl::{DBusProperties, DBusIntrospectable, DBusObjectManager, DBusMetrics, DBusPeer};
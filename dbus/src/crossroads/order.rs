@@ -0,0 +1,74 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+
+#[derive(Debug, Default)]
+struct SenderQueue {
+    // The ticket the next dispatched call gets
+    next_ticket: u64,
+    // The ticket whose reply can be sent next
+    next_out: u64,
+    // Tickets that were dropped before their reply was sent
+    dropped: BTreeSet<u64>,
+    waiting: HashMap<u64, Waker>,
+}
+
+/// Keeps track of the order method calls arrived in, per sender, see `Crossroads::set_ordered_replies`.
+#[derive(Debug, Default)]
+pub (super) struct ReplyOrder(Mutex<HashMap<String, SenderQueue>>);
+
+impl ReplyOrder {
+    pub (super) fn ticket(self: &Arc<Self>, sender: &str) -> Ticket {
+        let mut map = self.0.lock().unwrap();
+        let q = map.entry(sender.into()).or_insert_with(Default::default);
+        q.next_ticket += 1;
+        Ticket { order: self.clone(), sender: sender.into(), seq: q.next_ticket - 1, done: false }
+    }
+
+    /// Number of senders with replies that are not sent yet.
+    pub (super) fn pending_senders(&self) -> usize { self.0.lock().unwrap().len() }
+}
+
+/// A place in the reply order of a sender.
+#[derive(Debug)]
+pub (super) struct Ticket {
+    order: Arc<ReplyOrder>,
+    sender: String,
+    seq: u64,
+    done: bool,
+}
+
+impl Ticket {
+    /// Returns true if the reply can be sent now, and then lets the next reply go.
+    /// Otherwise, `waker` is woken when it is this ticket's turn.
+    pub (super) fn try_finish(&mut self, waker: &Waker) -> bool {
+        let mut map = self.order.0.lock().unwrap();
+        let q = map.get_mut(&self.sender).unwrap();
+        if q.next_out != self.seq {
+            q.waiting.insert(self.seq, waker.clone());
+            return false
+        }
+        self.done = true;
+        Self::advance(&mut map, &self.sender);
+        true
+    }
+
+    fn advance(map: &mut HashMap<String, SenderQueue>, sender: &str) {
+        let q = map.get_mut(sender).unwrap();
+        q.next_out += 1;
+        while q.dropped.remove(&q.next_out) { q.next_out += 1 }
+        if let Some(w) = q.waiting.remove(&q.next_out) { w.wake() }
+        // Don't keep senders around that are not waiting for anything.
+        if q.next_out == q.next_ticket { map.remove(sender); }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if self.done { return }
+        let mut map = self.order.0.lock().unwrap();
+        let q = map.get_mut(&self.sender).unwrap();
+        if q.next_out == self.seq { Self::advance(&mut map, &self.sender) }
+        else { q.waiting.remove(&self.seq); q.dropped.insert(self.seq); }
+    }
+}
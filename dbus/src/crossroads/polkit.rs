@@ -0,0 +1,86 @@
+//! Checking authorization with polkit (org.freedesktop.PolicyKit1).
+//!
+//! The caller is identified by the unique name of the sender of the method call.
+
+use std::collections::HashMap;
+use crate::{Message, Connection, arg};
+use crate::tree::MethodErr;
+use super::context::Context;
+
+const AUTHORITY_NAME: &str = "org.freedesktop.PolicyKit1";
+const AUTHORITY_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+const AUTHORITY_IFACE: &str = "org.freedesktop.PolicyKit1.Authority";
+
+/// Lets polkit ask the user for a password if needed.
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// Makes a CheckAuthorization method call for the sender of the message being handled.
+///
+/// Send it on the connection the method call came from, and give the reply to `parse_reply`.
+pub fn check_authorization_call(ctx: &Context, action_id: &str, interactive: bool) -> Result<Message, MethodErr> {
+    let sender = ctx.sender().ok_or_else(|| MethodErr::access_denied(&"Method call has no sender"))?;
+    let mut subject_details = HashMap::new();
+    subject_details.insert("name", arg::Variant(sender.to_string()));
+    let subject = ("system-bus-name", subject_details);
+    let details: HashMap<&str, &str> = HashMap::new();
+    let flags = if interactive { ALLOW_USER_INTERACTION } else { 0 };
+    let m = Message::new_method_call(AUTHORITY_NAME, AUTHORITY_PATH, AUTHORITY_IFACE, "CheckAuthorization")
+        .map_err(|e| MethodErr::failed(&e))?;
+    Ok(m.append3(subject, action_id, details).append2(flags, ""))
+}
+
+/// Parses the reply to a CheckAuthorization method call.
+///
+/// Returns an AccessDenied error if the caller is not authorized.
+pub fn parse_reply(reply: &Message) -> Result<(), MethodErr> {
+    let mut reply = reply.duplicate();
+    reply.as_result()?;
+    let (authorized, challenge, _): (bool, bool, HashMap<String, String>) = reply.read1()?;
+    if authorized { return Ok(()) }
+    Err(MethodErr::access_denied(&if challenge { "Authentication is required" } else { "Not authorized" }))
+}
+
+/// Asks polkit whether the sender of the message being handled is authorized for an action.
+///
+/// This blocks until polkit replies, which can take a long time if `interactive` is set. Use
+/// `check_authorization_call` and `parse_reply` to avoid blocking dispatch.
+pub fn check_authorization(c: &Connection, ctx: &Context, action_id: &str, interactive: bool) -> Result<(), MethodErr> {
+    let m = check_authorization_call(ctx, action_id, interactive)?;
+    // Waiting for the user to type a password should not time out.
+    let timeout = if interactive { crate::TIMEOUT_INFINITE } else { -1 };
+    let reply = c.send_with_reply_and_block(m, timeout)?;
+    parse_reply(&reply)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MessageType;
+    use crate::crossroads::testing::{TestCall, TEST_SENDER};
+
+    #[test]
+    fn call_and_reply() {
+        let msg = Message::new_method_call("com.example.dbusrs.polkit", "/", "com.example.dbusrs.polkit", "Test").unwrap();
+        // No sender
+        assert!(check_authorization_call(&Context::new(&msg), "com.example.dbusrs.polkit.test", false).is_err());
+
+        let test = TestCall::new("/", "com.example.dbusrs.polkit", "Test");
+        let m = check_authorization_call(&Context::new(test.message()), "com.example.dbusrs.polkit.test", true).unwrap();
+        let (subject, action, _, flags): ((&str, HashMap<String, arg::Variant<String>>), &str, HashMap<String, String>, u32) = m.read4().unwrap();
+        assert_eq!((subject.0, &*subject.1["name"].0, action, flags), ("system-bus-name", TEST_SENDER, "com.example.dbusrs.polkit.test", ALLOW_USER_INTERACTION));
+
+        // Stands in for the call to polkit, which only needs a serial to be replied to
+        let call = TestCall::new(AUTHORITY_PATH, AUTHORITY_IFACE, "CheckAuthorization");
+        let call = call.message();
+        let reply = |authorized: bool, challenge: bool| {
+            call.method_return().append1((authorized, challenge, HashMap::<String, String>::new()))
+        };
+        assert!(parse_reply(&reply(true, false)).is_ok());
+        let e = parse_reply(&reply(false, true)).unwrap_err();
+        assert_eq!(&**e.errorname(), "org.freedesktop.DBus.Error.AccessDenied");
+        assert!(parse_reply(&reply(false, false)).is_err());
+        let err = MethodErr::failed(&"polkit is broken").to_message(call);
+        assert_eq!(err.msg_type(), MessageType::Error);
+        assert!(parse_reply(&err).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use crate::{Message, MessageType};
+use super::crossroads::Crossroads;
+
+/// Dispatches method calls on a pool of worker threads.
+///
+/// Calls to the same object path are always handled by the same thread, so they are handled
+/// one at a time and in order, while calls to different paths can be handled in parallel.
+/// Replies, and messages pushed to the context, are sent to the receiver returned from `new`.
+#[derive(Debug)]
+pub struct DispatchPool {
+    workers: Vec<(Option<mpsc::SyncSender<Message>>, Option<thread::JoinHandle<()>>)>,
+}
+
+impl DispatchPool {
+    /// Starts `threads` worker threads, each with a queue of at most `queue_len` method calls.
+    pub fn new(cr: Arc<Crossroads<()>>, threads: usize, queue_len: usize) -> (Self, mpsc::Receiver<Message>) {
+        assert!(threads > 0, "DispatchPool needs at least one thread");
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let workers = (0..threads).map(|_| {
+            let (tx, rx) = mpsc::sync_channel::<Message>(queue_len);
+            let (cr, reply_tx) = (cr.clone(), reply_tx.clone());
+            let handle = thread::spawn(move || {
+                for msg in rx {
+                    for r in cr.dispatch(&msg).unwrap_or_default() {
+                        if reply_tx.send(r).is_err() { return }
+                    }
+                }
+            });
+            (Some(tx), Some(handle))
+        }).collect();
+        (DispatchPool { workers }, reply_rx)
+    }
+
+    /// Queues a method call for dispatch. Blocks while the queue of its worker thread is full.
+    ///
+    /// Returns the message back if it is not a method call.
+    pub fn dispatch(&self, msg: Message) -> Result<(), Message> {
+        if msg.msg_type() != MessageType::MethodCall { return Err(msg) }
+        let idx = worker_index(msg.path().as_ref().map(|p| p.as_cstr().to_bytes()).unwrap_or(b""), self.workers.len());
+        // The worker only stops if the reply receiver was dropped, and then the call can be dropped, too.
+        let _ = self.workers[idx].0.as_ref().unwrap().send(msg);
+        Ok(())
+    }
+}
+
+fn worker_index(path: &[u8], workers: usize) -> usize {
+    let mut h = DefaultHasher::new();
+    path.hash(&mut h);
+    (h.finish() % workers as u64) as usize
+}
+
+impl Drop for DispatchPool {
+    /// Waits for the queued method calls to be handled.
+    fn drop(&mut self) {
+        for w in &mut self.workers { w.0.take(); }
+        for w in &mut self.workers { let _ = w.1.take().unwrap().join(); }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::crossroads::{IfaceInfo, MethodInfo, PathData};
+    use crate::crossroads::testing::TestCall;
+
+    #[test]
+    fn pool() {
+        let mut cr = Crossroads::new_sync();
+        struct Sleeper(u64);
+        cr.register::<Sleeper>(IfaceInfo::new("com.example.dbusrs.crossroads.sleeper", vec!(
+            MethodInfo::new_sync("Sleep", |x: &Sleeper, msg, _| {
+                thread::sleep(Duration::from_millis(x.0));
+                Some(msg.method_return().append1(x.0))
+            }),
+        ), vec!(), vec!()));
+        // A path that is handled by another thread than "/slow"
+        let fast = (0..).map(|i| format!("/fast{}", i))
+            .find(|p| worker_index(p.as_bytes(), 4) != worker_index(b"/slow", 4)).unwrap();
+        for (path, ms) in vec!(("/slow", 300), (&*fast, 0)) {
+            let mut pdata = PathData::new();
+            pdata.insert(Sleeper(ms));
+            cr.insert(path.to_string(), pdata);
+        }
+
+        let (pool, replies) = DispatchPool::new(Arc::new(cr), 4, 8);
+        let call = |path: &str| {
+            let call = TestCall::new(path, "com.example.dbusrs.crossroads.sleeper", "Sleep");
+            pool.dispatch(call.message().duplicate()).unwrap();
+            call.message().get_serial()
+        };
+        let slow = vec!(call("/slow"), call("/slow"));
+        let fast: Vec<_> = (0..4).map(|_| call(&fast)).collect();
+        let order: Vec<_> = (0..6).map(|_| replies.recv().unwrap().get_reply_serial().unwrap()).collect();
+        // Calls to the same path are replied to in order
+        assert_eq!(order.iter().filter(|s| slow.contains(s)).cloned().collect::<Vec<_>>(), slow);
+        assert_eq!(order.iter().filter(|s| fast.contains(s)).cloned().collect::<Vec<_>>(), fast);
+        // ...but other paths don't have to wait
+        assert_ne!(order[0], slow[0]);
+
+        let signal = Message::signal(&"/".into(), &"com.example.dbusrs.crossroads.sleeper".into(), &"Hello".into());
+        assert!(pool.dispatch(signal).is_err());
+    }
+}
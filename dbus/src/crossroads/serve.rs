@@ -0,0 +1,257 @@
+//! Serving method calls on a (blocking) Connection.
+
+use std::cell::RefCell;
+use crate::{Connection, Error, Message, NameFlag, RequestNameReply};
+use super::crossroads::Crossroads;
+use super::handlers::{Handlers, Mut};
+
+fn request_name(c: &Connection, name: &str) -> Result<(), Error> {
+    match c.register_name(name, NameFlag::DoNotQueue.value())? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(()),
+        _ => Err(Error::new_custom("org.freedesktop.DBus.Error.Failed", &format!("Could not acquire the name {}", name))),
+    }
+}
+
+fn serve_loop<Q, D, S>(c: &Connection, timeout_ms: u32, quit: Q, dispatch: D, signals: S) -> Result<(), Error>
+where Q: FnMut() -> bool, D: FnMut(&Message) -> Option<Vec<Message>>, S: FnMut() -> Vec<Message> {
+    // Otherwise libdbus replies to the method calls before we get them.
+    c.register_fallback_path("/")?;
+    let r = process(c, timeout_ms, quit, dispatch, signals);
+    c.unregister_object_path("/");
+    r
+}
+
+fn send_all<I: IntoIterator<Item=Message>>(c: &Connection, msgs: I) -> Result<(), Error> {
+    for m in msgs {
+        c.send(m).map_err(|_| Error::new_custom("org.freedesktop.DBus.Error.Failed", "Sending message failed"))?;
+    }
+    Ok(())
+}
+
+fn process<Q, D, S>(c: &Connection, timeout_ms: u32, mut quit: Q, mut dispatch: D, mut signals: S) -> Result<(), Error>
+where Q: FnMut() -> bool, D: FnMut(&Message) -> Option<Vec<Message>>, S: FnMut() -> Vec<Message> {
+    loop {
+        let q = quit();
+        send_all(c, signals())?;
+        if q { break }
+        for msg in c.incoming(timeout_ms) {
+            send_all(c, dispatch(&msg).unwrap_or_default())?;
+            let q = quit();
+            send_all(c, signals())?;
+            if q { return Ok(()) }
+        }
+        if !c.is_connected() {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection was closed"));
+        }
+    }
+    Ok(())
+}
+
+/// The unique name currently owning a name.
+fn name_owner(c: &Connection, name: &str) -> Result<String, Error> {
+    let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetNameOwner")
+        .map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e))?
+        .append1(name);
+    let r = c.send_with_reply_and_block(m, -1)?;
+    r.read1().map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()))
+}
+
+impl<H: Handlers> Crossroads<H> {
+    /// Accepts method calls from a unique or well-known name, see `set_allowed_senders`.
+    ///
+    /// A well-known name is resolved to the unique name currently owning it, so if the owner
+    /// changes later, the new owner is not allowed unless this is called again.
+    pub fn allow_sender(&mut self, c: &Connection, name: &str) -> Result<(), Error> {
+        let unique = if name.starts_with(':') { name.into() } else { name_owner(c, name)? };
+        self.add_allowed_sender(unique);
+        Ok(())
+    }
+}
+
+// How often `serve` wakes up to send deferred replies
+const REPLY_INTERVAL_MS: u32 = 100;
+
+impl Crossroads<()> {
+    /// Requests a name on the bus, then handles incoming method calls forever.
+    ///
+    /// Replies and messages pushed to the context are sent on the connection, and so are
+    /// deferred replies, within 100 ms of `reply`. Incoming messages that are not method calls
+    /// are dropped. The object path "/" must not be registered
+    /// on the connection, as all paths are registered while serving.
+    pub fn serve(&self, c: &Connection, name: &str) -> Result<(), Error> {
+        self.serve_until(c, name, REPLY_INTERVAL_MS, || false)
+    }
+
+    /// Like `serve`, but returns when `quit` returns true.
+    ///
+    /// `quit` is called after every incoming message, and at least every `timeout_ms`,
+    /// so it can e g check a channel with `try_recv`. Deferred replies are sent at the same times.
+    pub fn serve_until<Q: FnMut() -> bool>(&self, c: &Connection, name: &str, timeout_ms: u32, quit: Q) -> Result<(), Error> {
+        request_name(c, name)?;
+        serve_loop(c, timeout_ms, quit, |msg| self.dispatch(msg), || self.take_signals())
+    }
+
+    /// Like `serve_until`, but `f` gets mutable access to the Crossroads between method calls,
+    /// e g to change interfaces with `replace_iface`. Returns when `f` returns true.
+    pub fn serve_with<F: FnMut(&mut Self) -> bool>(&mut self, c: &Connection, name: &str, timeout_ms: u32, mut f: F) -> Result<(), Error> {
+        request_name(c, name)?;
+        let cr = RefCell::new(self);
+        serve_loop(c, timeout_ms, || f(&mut cr.borrow_mut()), |msg| cr.borrow().dispatch(msg), || cr.borrow().take_signals())
+    }
+}
+
+impl Crossroads<Mut> {
+    /// See `Crossroads<()>::serve`.
+    pub fn serve(&mut self, c: &Connection, name: &str) -> Result<(), Error> {
+        self.serve_until(c, name, REPLY_INTERVAL_MS, || false)
+    }
+
+    /// See `Crossroads<()>::serve_until`.
+    pub fn serve_until<Q: FnMut() -> bool>(&mut self, c: &Connection, name: &str, timeout_ms: u32, quit: Q) -> Result<(), Error> {
+        request_name(c, name)?;
+        let cr = RefCell::new(self);
+        serve_loop(c, timeout_ms, quit, |msg| cr.borrow_mut().dispatch(msg), || cr.borrow().take_signals())
+    }
+
+    /// See `Crossroads<()>::serve_with`.
+    pub fn serve_with<F: FnMut(&mut Self) -> bool>(&mut self, c: &Connection, name: &str, timeout_ms: u32, mut f: F) -> Result<(), Error> {
+        request_name(c, name)?;
+        let cr = RefCell::new(self);
+        serve_loop(c, timeout_ms, || f(&mut cr.borrow_mut()), |msg| cr.borrow_mut().dispatch(msg), || cr.borrow().take_signals())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{thread, sync::mpsc};
+    use crate::BusType;
+    use crate::crossroads::{IfaceInfo, MethodInfo, PathData};
+
+    #[test]
+    fn serve_until() {
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let name = format!("com.example.dbusrs.crossroads.serve{}", c.unique_name().replace(":", "").replace(".", "x"));
+
+        let mut cr = Crossroads::new_mut();
+        struct Counter(u32);
+        cr.register::<Counter>(IfaceInfo::new("com.example.dbusrs.crossroads.counter", vec!(
+            MethodInfo::new_mut("Next", |c: &mut Counter, msg, _| { c.0 += 1; Some(msg.method_return().append1(c.0)) }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Counter(0));
+        cr.insert("/counter", pdata);
+
+        let (tx, rx) = mpsc::channel();
+        let n = name.clone();
+        let client = thread::spawn(move || {
+            let c = Connection::get_private(BusType::Session).unwrap();
+            let call = || {
+                let m = Message::new_method_call(&*n, "/counter", "com.example.dbusrs.crossroads.counter", "Next").unwrap();
+                c.send_with_reply_and_block(m, 2000).map(|r| r.read1::<u32>().unwrap())
+            };
+            // Retry until the name is acquired
+            let mut r = call();
+            while r.is_err() {
+                thread::sleep(std::time::Duration::from_millis(10));
+                r = call();
+            }
+            assert_eq!(r.unwrap(), 1);
+            assert_eq!(call().unwrap(), 2);
+            tx.send(()).unwrap();
+        });
+        cr.serve_until(&c, &name, 100, || rx.try_recv().is_ok()).unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn deferred_reply() {
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let name = format!("com.example.dbusrs.crossroads.deferred{}", c.unique_name().replace(":", "").replace(".", "x"));
+
+        let mut cr = Crossroads::new_sync();
+        let replier = cr.replier();
+        struct Worker;
+        cr.register::<Worker>(IfaceInfo::new("com.example.dbusrs.crossroads.worker", vec!(
+            MethodInfo::new_sync("Slow", move |_: &Worker, _, si| {
+                let (token, replier) = (si.ctx().defer(), replier.clone());
+                thread::spawn(move || {
+                    thread::sleep(std::time::Duration::from_millis(50));
+                    let m = token.method_return().append1(7u32);
+                    replier.reply(token, m)
+                });
+                None
+            }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Worker);
+        cr.insert("/worker", pdata);
+
+        let (tx, rx) = mpsc::channel();
+        let n = name.clone();
+        let client = thread::spawn(move || {
+            let c = Connection::get_private(BusType::Session).unwrap();
+            let call = || {
+                let m = Message::new_method_call(&*n, "/worker", "com.example.dbusrs.crossroads.worker", "Slow").unwrap();
+                c.send_with_reply_and_block(m, 2000).map(|r| r.read1::<u32>().unwrap())
+            };
+            let mut r = call();
+            while r.is_err() {
+                thread::sleep(std::time::Duration::from_millis(10));
+                r = call();
+            }
+            assert_eq!(r.unwrap(), 7);
+            tx.send(()).unwrap();
+        });
+        cr.serve_until(&c, &name, 100, || rx.try_recv().is_ok()).unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn allow_sender() {
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let id = c.unique_name().replace(":", "").replace(".", "x");
+        let name = format!("com.example.dbusrs.crossroads.allow{}", id);
+        let mut cr = Crossroads::new_sync();
+        cr.allow_sender(&c, "org.freedesktop.DBus").unwrap();
+        cr.allow_sender(&c, ":1.5").unwrap();
+        assert!(cr.allow_sender(&c, "com.example.dbusrs.crossroads.nobody").is_err());
+
+        struct Secret;
+        cr.register::<Secret>(IfaceInfo::new("com.example.dbusrs.crossroads.secret", vec!(
+            MethodInfo::new_sync("Tell", |_: &Secret, msg, _| Some(msg.method_return().append1(42u32))),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Secret);
+        cr.insert("/secret", pdata);
+
+        let (name_tx, name_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let n = name.clone();
+        let client = thread::spawn(move || {
+            // The allowed client is known by a well-known name, the refused one by its unique name only.
+            let allowed = Connection::get_private(BusType::Session).unwrap();
+            let refused = Connection::get_private(BusType::Session).unwrap();
+            let allowed_name = format!("com.example.dbusrs.crossroads.allowed{}", id);
+            request_name(&allowed, &allowed_name).unwrap();
+            name_tx.send(allowed_name).unwrap();
+            let call = |c: &Connection| {
+                let m = Message::new_method_call(&*n, "/secret", "com.example.dbusrs.crossroads.secret", "Tell").unwrap();
+                c.send_with_reply_and_block(m, 2000).map(|r| r.read1::<u32>().unwrap())
+            };
+            // Retry until the name is acquired
+            let mut r = call(&allowed);
+            while r.is_err() {
+                thread::sleep(std::time::Duration::from_millis(10));
+                r = call(&allowed);
+            }
+            assert_eq!(r.unwrap(), 42);
+            let e = call(&refused).unwrap_err();
+            assert_eq!(e.name(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+            tx.send(()).unwrap();
+        });
+        cr.allow_sender(&c, &name_rx.recv().unwrap()).unwrap();
+        cr.serve_until(&c, &name, 100, || rx.try_recv().is_ok()).unwrap();
+        client.join().unwrap();
+    }
+}
@@ -1,26 +1,167 @@
-use super::crossroads::Crossroads;
-use super::handlers::SyncInfo;
-use super::info::{IfaceInfo, MethodInfo, PropInfo};
-use crate::arg;
+use std::ffi::CStr;
+use super::crossroads::{Crossroads, PathData, MLookup};
+use super::handlers::Handlers;
+use super::context::Context;
+use super::args::invalid_args;
+use super::info::{IfaceInfo, MethodInfo, PropInfo, SignalInfo, Access};
+use crate::tree::MethodErr;
+use crate::{arg, Message, MessageDispatcher, Path as PathName, SignalArgs};
+use crate::stdintf::org_freedesktop_dbus::{ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved};
 
 pub struct DBusProperties;
 
 impl DBusProperties {
-    pub fn register(cr: &mut Crossroads<()>) {
+    pub fn register<H: Handlers>(cr: &mut Crossroads<H>) {
         cr.register::<Self>(IfaceInfo::new("org.freedesktop.DBus.Properties",
-            vec!(MethodInfo::new_sync("Get", |_: &DBusProperties, msg, info| {
-                // FIXME, handle errors correctly here
-                let (iname, propname) = msg.read2().unwrap(); 
-                let (lookup, pinfo) = info.cr.reg_prop_lookup(info.pd, iname, propname).unwrap();
-                let handler = &pinfo.handlers.0.as_ref().unwrap();
-                let mut si = SyncInfo { cr: lookup.cr, pd: lookup.data };
-                let mut mret = msg.method_return();
-                let r = {
-                    let mut ia = arg::IterAppend::new(&mut mret);
-                    (handler)(&**lookup.iface, msg, &mut ia, &mut si)
-                };
-                if r { Some(mret) } else { None }
-            })),
+            vec!(MethodInfo::new("Get".into(), H::make_method(|ctx, cr, pd| {
+                Some(prop_get(ctx, cr, pd).unwrap_or_else(|e| e.to_message(ctx.message())))
+            })).in_arg("interface_name", "s").in_arg("property_name", "s").out_arg("value", "v"),
+            MethodInfo::new("GetAll".into(), H::make_method(|ctx, cr, pd| {
+                Some(prop_get_all(ctx, cr, pd).unwrap_or_else(|e| e.to_message(ctx.message())))
+            })).in_arg("interface_name", "s").out_arg("props", "a{sv}"),
+            MethodInfo::new("Set".into(), H::make_method(|ctx, cr, pd| {
+                Some(prop_set(ctx, cr, pd).unwrap_or_else(|e| e.to_message(ctx.message())))
+            })).in_arg("interface_name", "s").in_arg("property_name", "s").in_arg("value", "v")),
+            vec!(), vec!(SignalInfo::new("PropertiesChanged").arg("interface_name", "s")
+                .arg("changed_properties", "a{sv}").arg("invalidated_properties", "as"))
+        ));
+    }
+}
+
+pub struct DBusIntrospectable;
+
+impl DBusIntrospectable {
+    pub fn register<H: Handlers>(cr: &mut Crossroads<H>) {
+        cr.register::<Self>(IfaceInfo::new("org.freedesktop.DBus.Introspectable",
+            vec!(MethodInfo::new("Introspect".into(), H::make_method(|ctx, cr, pd| {
+                let xml = cr.introspect(&ctx.path().unwrap(), Some(pd));
+                Some(ctx.message().method_return().append1(xml))
+            })).out_arg("xml_data", "s")),
+            vec!(), vec!()
+        ));
+    }
+}
+
+pub struct DBusPeer;
+
+impl DBusPeer {
+    pub fn register<H: Handlers>(cr: &mut Crossroads<H>) {
+        cr.register::<Self>(IfaceInfo::new("org.freedesktop.DBus.Peer",
+            vec!(MethodInfo::new("Ping".into(), H::make_method(|ctx, _, _| MessageDispatcher::<()>::peer(ctx.message()))),
+            MethodInfo::new("GetMachineId".into(), H::make_method(|ctx, _, _| MessageDispatcher::<()>::peer(ctx.message())))
+                .out_arg("machine_uuid", "s")),
+            vec!(), vec!()
+        ));
+    }
+}
+
+pub struct DBusObjectManager;
+
+impl DBusObjectManager {
+    pub fn register<H: Handlers>(cr: &mut Crossroads<H>) {
+        cr.register::<Self>(IfaceInfo::new("org.freedesktop.DBus.ObjectManager",
+            vec!(MethodInfo::new("GetManagedObjects".into(), H::make_method(|ctx, cr, _| {
+                let objects = cr.managed_objects(&ctx.path().unwrap(), ctx);
+                Some(ctx.message().method_return().append1(objects))
+            })).out_arg("objpath_interfaces_and_properties", "a{oa{sa{sv}}}")),
+            vec!(), vec!(
+                SignalInfo::new("InterfacesAdded").arg("object_path", "o").arg("interfaces_and_properties", "a{sa{sv}}"),
+                SignalInfo::new("InterfacesRemoved").arg("object_path", "o").arg("interfaces", "as"),
+            )
+        ));
+    }
+
+    /// Makes an InterfacesAdded signal for all interfaces on `path`, to be sent from
+    /// the object manager at `om_path`.
+    ///
+    /// Returns None if there is nothing registered at `path`.
+    pub fn interfaces_added<H: Handlers>(cr: &Crossroads<H>, om_path: &PathName, path: &PathName) -> Option<Message> {
+        let data = cr.get_data(path.clone().into_static())?;
+        // Property getters need a message; the signal itself will have to do.
+        let m = Message::signal(om_path, &ObjectManagerInterfacesAdded::INTERFACE.into(), &ObjectManagerInterfacesAdded::NAME.into());
+        let interfaces = cr.path_props(data, &Context::new(&m).with_app_data(cr.raw_app_data()));
+        let s = ObjectManagerInterfacesAdded { object: path.clone().into_static(), interfaces: interfaces.into_iter().map(|(i, p)| (i, p.0)).collect() };
+        Some(s.to_emit_message(om_path))
+    }
+
+    /// Makes an InterfacesRemoved signal for all interfaces in `data`, which used to be at `path`,
+    /// to be sent from the object manager at `om_path`.
+    pub fn interfaces_removed<H: Handlers>(cr: &Crossroads<H>, om_path: &PathName, path: &PathName, data: &PathData<H>) -> Message {
+        let interfaces = cr.path_ifaces(data).map(|(iinfo, _)| iinfo.name.to_string()).collect();
+        let s = ObjectManagerInterfacesRemoved { object: path.clone().into_static(), interfaces };
+        s.to_emit_message(om_path)
+    }
+}
+
+pub (super) fn prop_lookup<'a, H: Handlers>(ctx: &Context<'a>, cr: &'a Crossroads<H>, pd: &'a PathData<H>) -> Result<(MLookup<'a, H>, &'a PropInfo<'static, H>), MethodErr> {
+    let (iname, propname): (&CStr, &CStr) = ctx.message().read2().map_err(invalid_args)?;
+    cr.reg_prop_lookup(pd, iname, propname)
+}
+
+fn prop_get<H: Handlers>(ctx: &Context, cr: &Crossroads<H>, pd: &PathData<H>) -> Result<Message, MethodErr> {
+    let (lookup, pinfo) = prop_lookup(ctx, cr, pd)?;
+    if pinfo.rw == Access::Write {
+        return Err(MethodErr::failed(&format!("Property {} is write only", pinfo.name)));
+    }
+    if !pinfo.readable(ctx) {
+        return Err(MethodErr::access_denied(&format!("Not allowed to read property {}", pinfo.name)));
+    }
+    let v = cr.get_prop(pd, lookup.iface, pinfo, ctx)
+        .ok_or_else(|| MethodErr::failed(&format!("Getting property {} failed", pinfo.name)))?;
+    Ok(ctx.message().method_return().append1(arg::Variant(v)))
+}
+
+fn prop_get_all<H: Handlers>(ctx: &Context, cr: &Crossroads<H>, pd: &PathData<H>) -> Result<Message, MethodErr> {
+    let msg = ctx.message();
+    let iname: &CStr = msg.read1().map_err(invalid_args)?;
+    let (iinfo, iface) = cr.path_ifaces(pd).find(|(iinfo, _)| iinfo.name.as_cstr() == iname)
+        .ok_or_else(|| MethodErr::no_interface(&iname.to_string_lossy()))?;
+    Ok(msg.method_return().append1(cr.iface_props(pd, iinfo, iface, ctx)))
+}
+
+/// The setter of a property, and the new value of a Set call, if it has the correct type.
+pub (super) fn prop_setter<'a, 'p, H: Handlers>(msg: &'a Message, pinfo: &'p PropInfo<'static, H>) -> Result<(&'p H::SetProp, arg::Iter<'a>), MethodErr> {
+    let setf = match pinfo.handlers.1 {
+        Some(ref s) if pinfo.rw != Access::Read => s,
+        _ => return Err(MethodErr::ro_property(&pinfo.name)),
+    };
+    let mut iter = arg::Iter::new(msg);
+    iter.next(); iter.next();
+    let mut subiter = iter.recurse(arg::ArgType::Variant).ok_or_else(|| MethodErr::invalid_arg(&2))?;
+    let sig = subiter.signature();
+    if *sig != *pinfo.sig {
+        return Err(("org.freedesktop.DBus.Error.InvalidArgs",
+            format!("Property {} has type {}, not {}", pinfo.name, pinfo.sig, sig)).into());
+    }
+    Ok((setf, subiter))
+}
+
+/// Signals the change of a property that was set, and replies to the Set call.
+pub (super) fn prop_was_set<H: Handlers>(ctx: &Context, lookup: &MLookup<H>, pinfo: &PropInfo<'static, H>) -> Message {
+    if let Some(path) = ctx.path() {
+        if let Some(s) = lookup.cr.iface_props_changed(&path, lookup.data, lookup.iinfo, lookup.iface, Some(&*pinfo.name)) { ctx.push_msg(s) }
+    }
+    ctx.message().method_return()
+}
+
+fn prop_set<H: Handlers>(ctx: &Context, cr: &Crossroads<H>, pd: &PathData<H>) -> Result<Message, MethodErr> {
+    let (lookup, pinfo) = prop_lookup(ctx, cr, pd)?;
+    let (setf, mut subiter) = prop_setter(ctx.message(), pinfo)?;
+    H::call_setprop(setf, lookup.iface, ctx, &mut subiter, cr, pd)?;
+    Ok(prop_was_set(ctx, &lookup, pinfo))
+}
+
+/// Exports the metrics collected by `Crossroads::enable_metrics`.
+pub struct DBusMetrics;
+
+impl DBusMetrics {
+    pub fn register<H: Handlers>(cr: &mut Crossroads<H>) {
+        cr.register::<Self>(IfaceInfo::new("com.github.diwic.dbusrs.Metrics",
+            vec!(MethodInfo::new("GetMetrics".into(), H::make_method(|ctx, cr, _| {
+                let m: Vec<_> = cr.metrics().into_iter().map(|((i, m), v)| (i, m, v.calls, v.errors,
+                    v.total_time.as_micros() as u64, v.max_time.as_micros() as u64)).collect();
+                Some(ctx.message().method_return().append1(m))
+            })).out_arg("metrics", "a(sstttt)")),
             vec!(), vec!()
         ));
     }
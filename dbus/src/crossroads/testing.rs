@@ -0,0 +1,201 @@
+//! Unit testing services without a running bus.
+//!
+//! Method calls made with `TestCall` get a serial and a sender, like calls coming from the bus,
+//! so that they can be dispatched directly to a Crossroads instance.
+//!
+//! ```
+//! use dbus::crossroads::{Crossroads, IfaceInfo, MethodInfo, PathData};
+//! use dbus::crossroads::testing::TestCall;
+//!
+//! struct Counter(u32);
+//! let mut cr = Crossroads::new_mut();
+//! cr.register::<Counter>(IfaceInfo::new("com.example.Counter", vec!(
+//!     MethodInfo::new_mut("Add", |c: &mut Counter, msg, _| {
+//!         c.0 += msg.read1::<u32>().unwrap();
+//!         Some(msg.method_return().append1(c.0))
+//!     }),
+//! ), vec!(), vec!()));
+//! let mut pdata = PathData::new();
+//! pdata.insert(Counter(1));
+//! cr.insert("/counter", pdata);
+//!
+//! let (total,): (u32,) = TestCall::new("/counter", "com.example.Counter", "Add").args((5u32,)).dispatch(&mut cr).ok();
+//! assert_eq!(total, 6);
+//! ```
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::future::Future;
+use std::pin::Pin;
+use std::{task, thread};
+use crate::{Path as PathName, Interface as IfaceName, Member as MemberName, BusName, Message, MessageType, Error, arg};
+use super::crossroads::Crossroads;
+use super::handlers::{Mut, Async, Local, Par};
+use super::args::ArgAll;
+
+/// The sender of a `TestCall`, unless changed with `TestCall::sender`.
+pub const TEST_SENDER: &str = ":1.99";
+
+const TEST_DESTINATION: &str = "com.github.diwic.dbusrs.Test";
+
+static SERIAL: AtomicUsize = AtomicUsize::new(1);
+
+/// A method call to dispatch with `TestCall::dispatch`.
+#[derive(Debug)]
+pub struct TestCall(Message);
+
+impl TestCall {
+    /// Makes a method call with a new serial, sent from `TEST_SENDER`.
+    ///
+    /// Panics if the path, interface or method name is not valid.
+    pub fn new<'p, 'i, 'm, P, I, M>(path: P, iface: I, method: M) -> Self
+    where P: Into<PathName<'p>>, I: Into<IfaceName<'i>>, M: Into<MemberName<'m>> {
+        let mut msg = Message::new_method_call(TEST_DESTINATION, path, iface, method).unwrap();
+        crate::message::message_set_serial(&mut msg, SERIAL.fetch_add(1, Ordering::SeqCst) as u32);
+        TestCall(msg).sender(TEST_SENDER)
+    }
+
+    /// Appends in arguments, as a tuple.
+    pub fn args<A: ArgAll>(mut self, args: A) -> Self {
+        args.append(&mut arg::IterAppend::new(&mut self.0));
+        self
+    }
+
+    /// Appends one in argument.
+    pub fn append1<A: arg::Append>(self, a: A) -> Self { TestCall(self.0.append1(a)) }
+
+    /// Sets the unique name the call is sent from.
+    pub fn sender(mut self, name: &str) -> Self {
+        crate::message::message_set_sender(&mut self.0, &BusName::new(name).unwrap());
+        self
+    }
+
+    /// Sets the NO_REPLY_EXPECTED flag.
+    pub fn no_reply(self) -> Self { self.0.set_no_reply(true); self }
+
+    pub fn message(&self) -> &Message { &self.0 }
+
+    /// Dispatches the call. Async handlers are run to completion on this thread.
+    pub fn dispatch<C: TestDispatch>(&self, cr: &mut C) -> TestReply {
+        let msgs = cr.test_dispatch(&self.0).expect("TestCall is a method call");
+        let serial = self.0.get_serial();
+        let is_reply = |m: &Message| m.get_reply_serial() == Some(serial)
+            && (m.msg_type() == MessageType::MethodReturn || m.msg_type() == MessageType::Error);
+        let (replies, others): (Vec<_>, Vec<_>) = msgs.into_iter().partition(is_reply);
+        TestReply { reply: replies.into_iter().next(), others }
+    }
+}
+
+/// The Crossroads flavors that `TestCall` can dispatch to.
+pub trait TestDispatch {
+    fn test_dispatch(&mut self, msg: &Message) -> Option<Vec<Message>>;
+}
+
+impl TestDispatch for Crossroads<()> {
+    fn test_dispatch(&mut self, msg: &Message) -> Option<Vec<Message>> { self.dispatch(msg) }
+}
+
+impl TestDispatch for Crossroads<Mut> {
+    fn test_dispatch(&mut self, msg: &Message) -> Option<Vec<Message>> { self.dispatch(msg) }
+}
+
+impl TestDispatch for Crossroads<Local> {
+    fn test_dispatch(&mut self, msg: &Message) -> Option<Vec<Message>> { self.dispatch(msg) }
+}
+
+impl TestDispatch for Crossroads<Par> {
+    fn test_dispatch(&mut self, msg: &Message) -> Option<Vec<Message>> { self.dispatch(msg) }
+}
+
+impl TestDispatch for Crossroads<Async> {
+    fn test_dispatch(&mut self, msg: &Message) -> Option<Vec<Message>> { self.dispatch(msg).map(block_on) }
+}
+
+fn block_on<F: Future + Unpin>(mut f: F) -> F::Output {
+    // The waker unparks this thread.
+    fn clone(p: *const ()) -> task::RawWaker {
+        let t = unsafe { Arc::from_raw(p as *const thread::Thread) };
+        let t2 = t.clone();
+        std::mem::forget(t);
+        task::RawWaker::new(Arc::into_raw(t2) as *const (), &VTABLE)
+    }
+    fn wake(p: *const ()) { unsafe { Arc::from_raw(p as *const thread::Thread) }.unpark() }
+    fn wake_by_ref(p: *const ()) { unsafe { &*(p as *const thread::Thread) }.unpark() }
+    fn drop(p: *const ()) { unsafe { Arc::from_raw(p as *const thread::Thread) }; }
+    static VTABLE: task::RawWakerVTable = task::RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+    let raw = task::RawWaker::new(Arc::into_raw(Arc::new(thread::current())) as *const (), &VTABLE);
+    let waker = unsafe { task::Waker::from_raw(raw) };
+    let mut cx = task::Context::from_waker(&waker);
+    loop {
+        if let task::Poll::Ready(r) = Pin::new(&mut f).poll(&mut cx) { return r }
+        thread::park();
+    }
+}
+
+/// What a Crossroads instance returned for a `TestCall`.
+#[derive(Debug)]
+pub struct TestReply {
+    reply: Option<Message>,
+    others: Vec<Message>,
+}
+
+impl TestReply {
+    /// The method return or error, if any.
+    pub fn reply(&self) -> Option<&Message> { self.reply.as_ref() }
+
+    /// The error, if the call was replied to with one.
+    pub fn error(&self) -> Option<Error> { self.reply.as_ref().and_then(|r| r.set_error_from_msg().err()) }
+
+    /// Reads the out arguments of a method return, as a tuple.
+    ///
+    /// Panics if there is no reply, the reply is an error, or the arguments are of other types.
+    pub fn ok<R: ArgAll>(&self) -> R {
+        let reply = self.reply.as_ref().expect("Method call was not replied to");
+        if let Some(e) = self.error() {
+            panic!("Method call failed: {}: {}", e.name().unwrap_or(""), e.message().unwrap_or(""));
+        }
+        R::read(&mut arg::Iter::new(reply)).unwrap_or_else(|e| panic!("Unexpected reply: {}", e))
+    }
+
+    /// Other messages returned from dispatch, typically signals.
+    pub fn signals(&self) -> &[Message] { &self.others }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::MethodErr;
+    use crate::crossroads::{IfaceInfo, MethodInfo, PathData};
+    use crate::crossroads::handlers;
+
+    #[test]
+    fn dispatch() {
+        let mut cr = Crossroads::new_async();
+        struct Score(u16);
+        cr.register::<Score>(IfaceInfo::new("com.example.dbusrs.crossroads.score", vec!(
+            MethodInfo::new_async_typed("Add", ("points",), ("total",), |x: &Score, (b,): (u16,)| {
+                handlers::ready(if b > 100 { Err(MethodErr::failed(&"Too many points")) } else { Ok((x.0 + b,)) })
+            }),
+            MethodInfo::new_async("Sender", |_: &Score, msg, _| {
+                handlers::ready(Some(msg.method_return().append1(msg.sender().unwrap().to_string())))
+            }),
+        ), vec!(), vec!()));
+        let mut pdata = PathData::new();
+        pdata.insert(Score(7));
+        cr.insert("/", pdata);
+
+        let add = |points: u16| TestCall::new("/", "com.example.dbusrs.crossroads.score", "Add").append1(points);
+        let (total,): (u16,) = add(5).dispatch(&mut cr).ok();
+        assert_eq!(total, 12);
+        let r = add(500).dispatch(&mut cr);
+        assert_eq!(r.error().unwrap().message(), Some("Too many points"));
+        assert!(r.signals().is_empty());
+        assert!(add(5).no_reply().dispatch(&mut cr).reply().is_none());
+
+        let mut sender = |c: TestCall| c.dispatch(&mut cr).ok::<(String,)>().0;
+        assert_eq!(sender(TestCall::new("/", "com.example.dbusrs.crossroads.score", "Sender")), TEST_SENDER);
+        assert_eq!(sender(TestCall::new("/", "com.example.dbusrs.crossroads.score", "Sender").sender(":1.5")), ":1.5");
+        assert_ne!(add(1).message().get_serial(), add(1).message().get_serial());
+    }
+}
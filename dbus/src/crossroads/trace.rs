@@ -0,0 +1,63 @@
+//! Spans and events around dispatch, with the "tracing" feature. Without it, this does nothing.
+
+use crate::Message;
+
+/// The span of one dispatched method call, with its path, interface, member, sender and serial.
+#[derive(Debug, Clone)]
+pub (super) struct DispatchSpan(#[cfg(feature = "tracing")] tracing::Span);
+
+impl Default for DispatchSpan {
+    #[cfg(feature = "tracing")]
+    fn default() -> Self { DispatchSpan(tracing::Span::none()) }
+    #[cfg(not(feature = "tracing"))]
+    fn default() -> Self { DispatchSpan() }
+}
+
+impl DispatchSpan {
+    #[cfg(feature = "tracing")]
+    pub (super) fn new(msg: &Message) -> Self {
+        let s = |x: Option<&str>| x.unwrap_or("").to_string();
+        DispatchSpan(tracing::debug_span!("dbus_dispatch",
+            path = %s(msg.path().as_ref().map(|x| &**x)),
+            interface = %s(msg.interface().as_ref().map(|x| &**x)),
+            member = %s(msg.member().as_ref().map(|x| &**x)),
+            sender = %s(msg.sender().as_ref().map(|x| &**x)),
+            serial = msg.get_serial(),
+        ))
+    }
+    #[cfg(not(feature = "tracing"))]
+    pub (super) fn new(_: &Message) -> Self { DispatchSpan() }
+
+    /// Runs `f` inside the span.
+    #[cfg(feature = "tracing")]
+    pub (super) fn in_scope<R, F: FnOnce() -> R>(&self, f: F) -> R { self.0.in_scope(f) }
+    #[cfg(not(feature = "tracing"))]
+    pub (super) fn in_scope<R, F: FnOnce() -> R>(&self, f: F) -> R { f() }
+
+    /// Emits events for the reply and other messages returned from dispatch.
+    #[cfg(feature = "tracing")]
+    pub (super) fn replied(&self, msgs: &[Message]) {
+        use crate::MessageType;
+        let _e = self.0.enter();
+        if !msgs.iter().any(|m| m.get_reply_serial().is_some()) { tracing::debug!("no reply") }
+        for m in msgs {
+            let size = crate::message::message_marshalled_len(m);
+            match (m.msg_type(), m.set_error_from_msg()) {
+                (MessageType::Error, Err(e)) => tracing::warn!(error = %e.name().unwrap_or(""),
+                    message = %e.message().unwrap_or(""), size, "replied with error"),
+                (MessageType::MethodReturn, _) => tracing::debug!(size, "replied"),
+                _ => tracing::debug!(size, member = %m.member().as_ref().map(|x| &**x).unwrap_or(""), "sending message"),
+            }
+        }
+    }
+    #[cfg(not(feature = "tracing"))]
+    pub (super) fn replied(&self, _: &[Message]) {}
+}
+
+/// Runs a synchronous dispatch inside a span.
+pub (super) fn traced<F: FnOnce() -> Option<Vec<Message>>>(msg: &Message, f: F) -> Option<Vec<Message>> {
+    let span = DispatchSpan::new(msg);
+    let r = span.in_scope(f);
+    if let Some(r) = &r { span.replied(r) }
+    r
+}
@@ -0,0 +1,130 @@
+use crate::{Connection, ConnectionItem, Watch};
+use glib::{self, IOCondition, Continue, SourceId};
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::time::Instant;
+
+/// Runs the I/O of a connection from a glib main loop, e g the one of a GTK application.
+///
+/// The watches and timeouts of the connection become sources of the default MainContext, and
+/// incoming items are given to the callback, so no extra thread is needed for D-Bus. The
+/// sources are removed when this struct is dropped.
+///
+/// The thread must own the default MainContext, as the GTK main thread does. The sources are
+/// updated after the connection has been processed; after starting a method call from outside
+/// the callback, call `update` so that its timeout is picked up.
+///
+/// This needs the "glib" feature.
+pub struct GlibSource {
+    state: Rc<State>,
+}
+
+type ItemCallback = Box<FnMut(&Connection, ConnectionItem)>;
+
+struct State {
+    conn: Rc<Connection>,
+    cb: RefCell<ItemCallback>,
+    fds: RefCell<Vec<(Watch, SourceId)>>,
+    timeout: RefCell<Option<SourceId>>,
+}
+
+impl GlibSource {
+    /// Attaches the connection to the default MainContext, with a callback for incoming items.
+    ///
+    /// Panics if the thread does not own the default MainContext.
+    pub fn new<F: FnMut(&Connection, ConnectionItem) + 'static>(conn: Rc<Connection>, f: F) -> GlibSource {
+        assert!(glib::MainContext::default().is_owner(), "The default MainContext is not owned by this thread");
+        let state = Rc::new(State { conn, cb: RefCell::new(Box::new(f)), fds: RefCell::new(vec!()), timeout: RefCell::new(None) });
+        update(&state);
+        GlibSource { state }
+    }
+
+    /// The connection.
+    pub fn connection(&self) -> &Rc<Connection> { &self.state.conn }
+
+    /// Updates the sources from the current watches and timeouts of the connection.
+    pub fn update(&self) { update(&self.state) }
+}
+
+impl Drop for GlibSource {
+    fn drop(&mut self) {
+        for (_, id) in self.state.fds.borrow_mut().drain(..) { glib::source_remove(id) }
+        if let Some(id) = self.state.timeout.borrow_mut().take() { glib::source_remove(id) }
+    }
+}
+
+fn process(state: &Rc<State>, readable: bool, writable: bool) {
+    let items: Vec<_> = state.conn.process_io(readable, writable).collect();
+    for item in items { (*state.cb.borrow_mut())(&state.conn, item) }
+    update(state);
+}
+
+fn update(state: &Rc<State>) {
+    let watches = state.conn.watch_fds();
+    let mut fds = state.fds.borrow_mut();
+    let (keep, remove): (Vec<_>, Vec<_>) = fds.drain(..).partition(|(w, _)| watches.contains(w));
+    *fds = keep;
+    for (_, id) in remove { glib::source_remove(id) }
+    for w in watches {
+        if fds.iter().any(|(ww, _)| *ww == w) { continue }
+        let mut cond = IOCondition::ERR | IOCondition::HUP;
+        if w.readable() { cond |= IOCondition::IN }
+        if w.writable() { cond |= IOCondition::OUT }
+        let weak = Rc::downgrade(state);
+        let id = glib::source::unix_fd_add_local(w.fd(), cond, move |_, c| {
+            with_state(&weak, |s| process(s, c.intersects(IOCondition::IN | IOCondition::ERR | IOCondition::HUP), c.contains(IOCondition::OUT)))
+        });
+        fds.push((w, id));
+    }
+    drop(fds);
+
+    if let Some(id) = state.timeout.borrow_mut().take() { glib::source_remove(id) }
+    if let Some(t) = state.conn.next_timeout() {
+        let ms = t.saturating_duration_since(Instant::now()).as_millis() as u32;
+        let weak = Rc::downgrade(state);
+        let id = glib::source::timeout_add_local(ms, move || {
+            with_state(&weak, |s| {
+                // Removed by glib, as we return false.
+                s.timeout.borrow_mut().take();
+                process(s, false, false);
+            });
+            Continue(false)
+        });
+        *state.timeout.borrow_mut() = Some(id);
+    }
+}
+
+fn with_state<F: FnOnce(&Rc<State>)>(weak: &Weak<State>, f: F) -> Continue {
+    match weak.upgrade() {
+        Some(s) => { f(&s); Continue(true) },
+        None => Continue(false),
+    }
+}
+
+#[test]
+fn glib_method_call() {
+    use crate::{BusType, Message};
+    use std::cell::Cell;
+    let ctx = glib::MainContext::default();
+    assert!(ctx.acquire());
+    let c = Rc::new(Connection::get_private(BusType::Session).unwrap());
+    c.register_object_path("/glib").unwrap();
+    let got = Rc::new(Cell::new(false));
+    let got2 = got.clone();
+    let source = GlibSource::new(c.clone(), move |c, item| {
+        if let ConnectionItem::MethodCall(m) = item {
+            assert_eq!(&*m.member().unwrap(), "Glib");
+            c.send(m.method_return().append1("Hi")).unwrap();
+            got2.set(true);
+        }
+    });
+    let m = Message::new_method_call(c.unique_name(), "/glib", "com.example.dbusrs.Glib", "Glib").unwrap();
+    c.send(m).unwrap();
+    let start = Instant::now();
+    while !got.get() {
+        assert!(start.elapsed().as_secs() < 5, "No method call from the main loop");
+        ctx.iteration(true);
+    }
+    drop(source);
+    ctx.release();
+}
@@ -12,6 +12,10 @@
 //! In addition to this crate, there are two companion crates, dbus-codegen for generating Rust
 //! code from D-Bus introspection data, and dbus-tokio for integrating D-Bus with [Tokio](http://tokio.rs).
 //! However, at the time of this writing, these are far less mature than this crate. 
+//!
+//! The crate also builds on Windows, where libdbus connects over "autolaunch:" and "tcp:"
+//! addresses, see `Address`. What needs unix is left out there: passing file descriptors
+//! (`OwnedFd`), `Server`, `ConnectionLoop` and the poll helpers of `Watch`.
 
 #![warn(missing_docs)]
 
@@ -21,13 +25,17 @@ pub use ffi::DBusBusType as BusType;
 pub use connection::DBusNameFlag as NameFlag;
 pub use ffi::DBusRequestNameReply as RequestNameReply;
 pub use ffi::DBusReleaseNameReply as ReleaseNameReply;
+pub use ffi::DBusStartReply as StartReply;
 pub use ffi::DBusMessageType as MessageType;
 
-pub use message::{Message, MessageItem, MessageItemArray, FromMessageItem, OwnedFd, ArrayError, ConnPath};
-pub use connection::{Connection, ConnectionItems, ConnectionItem, ConnMsgs, MsgHandler, MsgHandlerResult, MsgHandlerType, MessageCallback};
+pub use message::{Message, MessageItem, MessageItemArray, FromMessageItem, ArrayError, ConnPath, TIMEOUT_INFINITE};
+#[cfg(unix)]
+pub use message::OwnedFd;
+pub use connection::{Connection, ConnectionItems, ConnectionItem, ConnMsgs, MsgHandler, MsgHandlerResult, MsgHandlerType, MessageCallback, Token, CLOSING_ERROR, is_disconnected};
 pub use prop::PropHandler;
 pub use prop::Props;
 pub use watch::{Watch, WatchEvent};
+pub use stats::{ConnectionStats, MessageCounts};
 pub use signalargs::SignalArgs;
 
 /// A TypeSig describes the type of a MessageItem.
@@ -43,17 +51,69 @@ extern crate libdbus_sys as ffi;
 mod message;
 mod prop;
 mod watch;
+mod timeout;
+mod stats;
 mod connection;
 mod signalargs;
 
 mod connection2;
 mod dispatcher;
-pub use connection2::TxRx;
+pub use connection2::{TxRx, QueueLimits, QueuePolicy};
 pub use dispatcher::{MessageDispatcher, MessageDispatcherConfig};
 
 mod matchrule;
 pub use matchrule::MatchRule;
 
+mod address;
+pub use address::Address;
+
+// Waits with poll(2), like ConnectionLoop below.
+#[cfg(unix)]
+mod server;
+#[cfg(unix)]
+pub use server::Server;
+
+mod names;
+pub use names::{NameEvent, NameOwnership, NameGuard, NameWatcher};
+
+mod credentials;
+pub use credentials::ConnectionCredentials;
+
+mod monitor;
+pub use monitor::Monitor;
+
+mod reconnect;
+pub use reconnect::{Reconnecting, ReconnectItem};
+
+mod proxy;
+pub use proxy::{Proxy, ProxyBuilder};
+
+mod pipeline;
+pub use pipeline::Pipeline;
+
+#[cfg(unix)]
+mod connloop;
+#[cfg(unix)]
+pub use connloop::ConnectionLoop;
+
+#[cfg(feature = "glib")]
+extern crate glib;
+#[cfg(feature = "glib")]
+mod glib_source;
+#[cfg(feature = "glib")]
+pub use glib_source::GlibSource;
+
+#[cfg(feature = "calloop")]
+extern crate calloop;
+#[cfg(feature = "calloop")]
+mod calloop_source;
+#[cfg(feature = "calloop")]
+pub use calloop_source::CalloopSource;
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
 mod strings;
 pub use strings::{Signature, Path, Interface, Member, ErrorName, BusName};
 
@@ -63,6 +123,8 @@ pub mod stdintf;
 
 pub mod tree;
 
+pub mod crossroads;
+
 static INITDBUS: std::sync::Once = std::sync::ONCE_INIT;
 
 fn init_dbus() {
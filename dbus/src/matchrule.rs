@@ -1,11 +1,25 @@
-use crate::{Message, MessageType, BusName, Path, Interface, Member};
+use crate::{Message, MessageType, BusName, Path, Interface, Member, Error};
+use crate::arg::ArgType;
+use std::{fmt, str};
 
 
 #[derive(Clone, Debug, Default)]
-/// A "match rule", that can match Messages on its headers.
+/// A "match rule", that can match Messages on its headers and string arguments.
 ///
 /// A field set to "None" means no filter for that header, 
 /// a field set to "Some(_)" must match exactly.
+///
+/// Display gives the rule string to use with `Connection::add_match`, and FromStr parses one
+/// back. Both handle the quoting and escaping of the rule syntax.
+///
+/// # Example
+///
+/// ```
+/// use dbus::MatchRule;
+/// let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged").with_arg(0, "com.example.Foo");
+/// assert_eq!(rule.to_string(), "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='com.example.Foo'");
+/// assert_eq!(rule.to_string().parse::<MatchRule>().unwrap().to_string(), rule.to_string());
+/// ```
 pub struct MatchRule<'a> {
     /// Match on message type (you typically want to do this)
     pub msg_type: Option<MessageType>,
@@ -17,6 +31,25 @@ pub struct MatchRule<'a> {
     pub interface: Option<Interface<'a>>,
     /// Match on message member (signal or method name)
     pub member: Option<Member<'a>>,
+    /// Match on message destination
+    pub destination: Option<BusName<'a>>,
+    /// Match on message object path, or paths below it
+    pub path_namespace: Option<Path<'a>>,
+    /// Match on string arguments, by argument number (0 to 63) and value
+    pub args: Vec<(u8, String)>,
+    /// Match on string or object path arguments, by argument number (0 to 63) and path.
+    ///
+    /// A value ending with "/" also matches paths below it, and the other way around.
+    pub arg_paths: Vec<(u8, String)>,
+    /// Match on argument 0 being a bus name or interface in this namespace, e g "com.example"
+    /// matches "com.example" and "com.example.Foo". The bus does not allow this together with
+    /// argument 0 in `args`.
+    pub arg0_namespace: Option<String>,
+    /// Also match messages that are not addressed to this connection (legacy eavesdropping).
+    ///
+    /// Prefer `Connection::become_monitor` where the bus has it. Receiving the messages needs
+    /// `Connection::enable_eavesdropping`, and the bus policy must allow it.
+    pub eavesdrop: bool,
     _more_fields_may_come: (),
 }
 
@@ -31,38 +64,161 @@ fn msg_type_str(m: MessageType) -> &'static str {
     }
 }
 
+const INVALID: &str = "org.freedesktop.DBus.Error.MatchRuleInvalid";
+
+fn invalid(msg: &str) -> Error { Error::new_custom(INVALID, msg) }
+
+// Quotes a value. Inside quotes, nothing is escaped, so an apostrophe ends the quote,
+// is written as \' and starts a new quote.
+fn quote(v: &str) -> String { format!("'{}'", v.replace('\'', "'\\''")) }
+
+// Splits a rule into keys and unquoted values.
+fn split_rule(s: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut r = vec!();
+    let mut c = s.chars().peekable();
+    loop {
+        while c.peek().filter(|x| x.is_whitespace()).is_some() { c.next(); }
+        if c.peek().is_none() { return Ok(r) }
+        let key: String = c.by_ref().take_while(|&x| x != '=').collect();
+        let key = key.trim();
+        if key.is_empty() { return Err(invalid("Missing key in match rule")) }
+        let (mut value, mut quoted) = (String::new(), false);
+        loop {
+            match c.next() {
+                None if quoted => return Err(invalid("Unterminated quote in match rule")),
+                None => break,
+                Some('\'') => quoted = !quoted,
+                Some('\\') if !quoted && c.peek() == Some(&'\'') => { c.next(); value.push('\'') },
+                Some(',') if !quoted => break,
+                Some(x) => value.push(x),
+            }
+        }
+        r.push((key.into(), value));
+    }
+}
+
+fn arg_number(s: &str) -> Result<u8, Error> {
+    match s.parse::<u8>() {
+        Ok(n) if n < 64 => Ok(n),
+        _ => Err(invalid(&format!("Invalid argument number {:?}", s))),
+    }
+}
+
+fn set_arg(v: &mut Vec<(u8, String)>, n: u8, value: &str) {
+    assert!(n < 64, "Argument number {} is too large", n);
+    v.retain(|x| x.0 != n);
+    v.push((n, value.into()));
+}
+
+// The string value of argument n, if it is a string or an object path.
+fn arg_str(msg: &Message, n: u8, paths: bool) -> Option<String> {
+    let mut i = msg.iter_init();
+    for _ in 0..n { if !i.next() { return None } }
+    match i.arg_type() {
+        ArgType::String => i.get::<&str>().map(String::from),
+        ArgType::ObjectPath if paths => i.get::<Path>().map(|p| p.to_string()),
+        _ => None,
+    }
+}
+
+fn path_in_namespace(p: &str, ns: &str) -> bool {
+    ns == "/" || p == ns || (p.starts_with(ns) && p.as_bytes()[ns.len()] == b'/')
+}
 
 impl<'a> MatchRule<'a> {
     /// Make a string which you can use in the call to "add_match".
     ///
     /// Panics: if msg_type is set to Some(MessageType::Invalid)
     pub fn match_str(&self) -> String {
-        let mut v = vec!();
-        if let Some(x) = self.msg_type { v.push(("type", msg_type_str(x))) };
-        if let Some(ref x) = self.sender { v.push(("sender", &x)) };
-        if let Some(ref x) = self.path { v.push(("path", &x)) };
-        if let Some(ref x) = self.interface { v.push(("interface", &x)) };
-        if let Some(ref x) = self.member { v.push(("member", &x)) };
-
-        // For now we don't need to worry about internal quotes in strings as those are not valid names. 
-        // If we start matching against arguments, we need to worry.
-        let v: Vec<_> = v.into_iter().map(|(k, v)| format!("{}='{}'", k, v)).collect();
+        let mut v: Vec<(String, &str)> = vec!();
+        if let Some(x) = self.msg_type { v.push(("type".into(), msg_type_str(x))) };
+        if let Some(ref x) = self.sender { v.push(("sender".into(), x)) };
+        if let Some(ref x) = self.path { v.push(("path".into(), x)) };
+        if let Some(ref x) = self.interface { v.push(("interface".into(), x)) };
+        if let Some(ref x) = self.member { v.push(("member".into(), x)) };
+        if let Some(ref x) = self.destination { v.push(("destination".into(), x)) };
+        if let Some(ref x) = self.path_namespace { v.push(("path_namespace".into(), x)) };
+        for (n, x) in &self.args { v.push((format!("arg{}", n), x)) };
+        for (n, x) in &self.arg_paths { v.push((format!("arg{}path", n), x)) };
+        if let Some(ref x) = self.arg0_namespace { v.push(("arg0namespace".into(), x)) };
+        if self.eavesdrop { v.push(("eavesdrop".into(), "true")) };
+
+        let v: Vec<_> = v.into_iter().map(|(k, v)| format!("{}={}", k, quote(v))).collect();
         v.join(",")
     }
 
     /// Returns whether or not the message matches the rule.
+    ///
+    /// Eavesdrop is not checked, as it is about which messages the bus sends us.
     pub fn matches(&self, msg: &Message) -> bool {
         if let Some(x) = self.msg_type { if x != msg.msg_type() { return false; }};
         if self.sender.is_some() && msg.sender() != self.sender { return false };
         if self.path.is_some() && msg.path() != self.path { return false };
         if self.interface.is_some() && msg.interface() != self.interface { return false };
         if self.member.is_some() && msg.member() != self.member { return false };
+        if self.destination.is_some() && msg.destination() != self.destination { return false };
+        if let Some(ref ns) = self.path_namespace {
+            if msg.path().filter(|p| path_in_namespace(p, ns)).is_none() { return false }
+        }
+        for (n, x) in &self.args {
+            if arg_str(msg, *n, false).as_ref() != Some(x) { return false }
+        }
+        for (n, x) in &self.arg_paths {
+            let a = match arg_str(msg, *n, true) { Some(a) => a, None => return false };
+            let prefix = |a: &str, b: &str| a.ends_with('/') && b.starts_with(a);
+            if a != *x && !prefix(&a, x) && !prefix(x, &a) { return false }
+        }
+        if let Some(ref ns) = self.arg0_namespace {
+            let a = match arg_str(msg, 0, false) { Some(a) => a, None => return false };
+            if a != *ns && !(a.starts_with(&**ns) && a.as_bytes()[ns.len()] == b'.') { return false }
+        }
         true
     }
 
     /// Create a new struct which matches every message.
     pub fn new() -> Self { Default::default() }
 
+    /// Create a new struct which matches signals with this interface and member.
+    pub fn new_signal<I: Into<Interface<'a>>, N: Into<Member<'a>>>(intf: I, name: N) -> Self {
+        MatchRule::new().with_type(MessageType::Signal).with_interface(intf).with_member(name)
+    }
+
+    /// Create a new struct which matches method calls with this interface and member.
+    pub fn new_method_call<I: Into<Interface<'a>>, N: Into<Member<'a>>>(intf: I, name: N) -> Self {
+        MatchRule::new().with_type(MessageType::MethodCall).with_interface(intf).with_member(name)
+    }
+
+    /// Builder method that sets the message type.
+    pub fn with_type(mut self, ty: MessageType) -> Self { self.msg_type = Some(ty); self }
+    /// Builder method that sets the sender.
+    pub fn with_sender<S: Into<BusName<'a>>>(mut self, sender: S) -> Self { self.sender = Some(sender.into()); self }
+    /// Builder method that sets the destination.
+    pub fn with_destination<S: Into<BusName<'a>>>(mut self, dest: S) -> Self { self.destination = Some(dest.into()); self }
+    /// Builder method that sets the object path.
+    pub fn with_path<P: Into<Path<'a>>>(mut self, path: P) -> Self { self.path = Some(path.into()); self }
+    /// Builder method that matches the object path and the paths below it.
+    pub fn with_path_namespace<P: Into<Path<'a>>>(mut self, path: P) -> Self { self.path_namespace = Some(path.into()); self }
+    /// Builder method that sets the interface.
+    pub fn with_interface<I: Into<Interface<'a>>>(mut self, intf: I) -> Self { self.interface = Some(intf.into()); self }
+    /// Builder method that sets the member.
+    pub fn with_member<N: Into<Member<'a>>>(mut self, name: N) -> Self { self.member = Some(name.into()); self }
+
+    /// Builder method that matches string argument n (0 to 63).
+    ///
+    /// Panics if n is larger than 63.
+    pub fn with_arg(mut self, n: u8, value: &str) -> Self { set_arg(&mut self.args, n, value); self }
+
+    /// Builder method that matches argument n (0 to 63) as a path, see `arg_paths`.
+    ///
+    /// Panics if n is larger than 63.
+    pub fn with_arg_path(mut self, n: u8, path: &str) -> Self { set_arg(&mut self.arg_paths, n, path); self }
+
+    /// Builder method that matches argument 0 being in a namespace, see `arg0_namespace`.
+    pub fn with_arg0_namespace(mut self, ns: &str) -> Self { self.arg0_namespace = Some(ns.into()); self }
+
+    /// Builder method that also matches messages not addressed to us, see `eavesdrop`.
+    pub fn with_eavesdrop(mut self) -> Self { self.eavesdrop = true; self }
+
     /// Returns a clone with no static references
     pub fn into_static(&self) -> MatchRule<'static> {
         MatchRule {
@@ -71,7 +227,89 @@ impl<'a> MatchRule<'a> {
             path: self.path.as_ref().map(|x| x.clone().into_static()),
             interface: self.interface.as_ref().map(|x| x.clone().into_static()),
             member: self.member.as_ref().map(|x| x.clone().into_static()),
+            destination: self.destination.as_ref().map(|x| x.clone().into_static()),
+            path_namespace: self.path_namespace.as_ref().map(|x| x.clone().into_static()),
+            args: self.args.clone(),
+            arg_paths: self.arg_paths.clone(),
+            arg0_namespace: self.arg0_namespace.clone(),
+            eavesdrop: self.eavesdrop,
             _more_fields_may_come: (),
         }
     }
-} 
+}
+
+impl<'a> fmt::Display for MatchRule<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.match_str()) }
+}
+
+impl str::FromStr for MatchRule<'static> {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut r = MatchRule::new();
+        for (k, v) in split_rule(s)? {
+            let e = |x: String| invalid(&format!("Invalid {}: {}", k, x));
+            match &*k {
+                "type" => r.msg_type = Some(match &*v {
+                    "signal" => MessageType::Signal,
+                    "method_call" => MessageType::MethodCall,
+                    "method_return" => MessageType::MethodReturn,
+                    "error" => MessageType::Error,
+                    _ => return Err(e(v)),
+                }),
+                "sender" => r.sender = Some(BusName::new(v).map_err(e)?),
+                "destination" => r.destination = Some(BusName::new(v).map_err(e)?),
+                "path" => r.path = Some(Path::new(v).map_err(e)?),
+                "path_namespace" => r.path_namespace = Some(Path::new(v).map_err(e)?),
+                "interface" => r.interface = Some(Interface::new(v).map_err(e)?),
+                "member" => r.member = Some(Member::new(v).map_err(e)?),
+                "eavesdrop" => r.eavesdrop = match &*v { "true" => true, "false" => false, _ => return Err(e(v)) },
+                "arg0namespace" => r.arg0_namespace = Some(v),
+                _ if k.starts_with("arg") && k.ends_with("path") => set_arg(&mut r.arg_paths, arg_number(&k[3..k.len()-4])?, &v),
+                _ if k.starts_with("arg") => set_arg(&mut r.args, arg_number(&k[3..])?, &v),
+                _ => return Err(invalid(&format!("Unknown key {:?} in match rule", k))),
+            }
+        }
+        Ok(r)
+    }
+}
+
+#[test]
+fn match_rule_strings() {
+    let r = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+        .with_sender("org.freedesktop.DBus").with_path_namespace("/org/example").with_arg(2, "it's")
+        .with_arg_path(1, "/a/").with_arg0_namespace("com.example").with_destination(":1.5");
+    let s = r.to_string();
+    assert_eq!(s, "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus.Properties',\
+        member='PropertiesChanged',destination=':1.5',path_namespace='/org/example',arg2='it'\\''s',arg1path='/a/',\
+        arg0namespace='com.example'");
+    let r2: MatchRule = s.parse().unwrap();
+    assert_eq!(r2.to_string(), s);
+    assert_eq!(r2.args, vec!((2, "it's".to_string())));
+    // The bus understands the escaping too.
+    crate::Connection::get_private(crate::BusType::Session).unwrap().add_match(&s).unwrap();
+
+    let r: MatchRule = "type=signal, arg2='a,b',arg3=don\\'t,eavesdrop='true'".parse().unwrap();
+    assert_eq!(r.args, vec!((2, "a,b".to_string()), (3, "don't".to_string())));
+    assert!(r.eavesdrop);
+    for bad in &["type='foo'", "arg64='x'", "argx='x'", "path='no/slash'", "foo='bar'", "member='unterminated", "='x'"] {
+        assert_eq!(bad.parse::<MatchRule>().unwrap_err().name(), Some(INVALID), "{}", bad);
+    }
+}
+
+#[test]
+fn match_rule_matches() {
+    let m = Message::new_signal("/org/example/foo", "com.example.Foo", "Changed").unwrap()
+        .append2("com.example.Bar", Path::new("/a/b").unwrap());
+    assert!(MatchRule::new_signal("com.example.Foo", "Changed").matches(&m));
+    assert!(MatchRule::new().with_path_namespace("/org/example").matches(&m));
+    assert!(MatchRule::new().with_path_namespace("/").matches(&m));
+    assert!(!MatchRule::new().with_path_namespace("/org/ex").matches(&m));
+    assert!(MatchRule::new().with_arg(0, "com.example.Bar").matches(&m));
+    assert!(!MatchRule::new().with_arg(1, "/a/b").matches(&m));
+    assert!(MatchRule::new().with_arg_path(1, "/a/").matches(&m));
+    assert!(MatchRule::new().with_arg_path(1, "/a/b").matches(&m));
+    assert!(!MatchRule::new().with_arg_path(1, "/a").matches(&m));
+    assert!(MatchRule::new().with_arg0_namespace("com.example").matches(&m));
+    assert!(!MatchRule::new().with_arg0_namespace("com.ex").matches(&m));
+    assert!(!MatchRule::new().with_arg(2, "x").matches(&m));
+}
@@ -2,7 +2,8 @@ use std::borrow::Cow;
 use std::{fmt, mem, ptr, ops};
 use super::{ffi, Error, MessageType, Signature, libc, to_c_str, c_str_to_slice, init_dbus};
 use super::{BusName, Path, Interface, Member, ErrorName, Connection, SignalArgs};
-use std::os::unix::io::{RawFd, AsRawFd};
+#[cfg(unix)]
+use std::os::unix::io::{RawFd, AsRawFd, FromRawFd, IntoRawFd};
 use std::ffi::CStr;
 use std::os::raw::{c_void, c_char, c_int};
 
@@ -24,11 +25,13 @@ fn new_dbus_message_iter() -> ffi::DBusMessageIter { unsafe { mem::zeroed() }}
 
 /// An RAII wrapper around Fd to ensure that file descriptor is closed
 /// when the scope ends.
+#[cfg(unix)]
 #[derive(Debug, PartialEq, PartialOrd)]
 pub struct OwnedFd {
     fd: RawFd
 }
 
+#[cfg(unix)]
 impl OwnedFd {
     /// Create a new OwnedFd from a RawFd.
     pub fn new(fd: RawFd) -> OwnedFd {
@@ -43,18 +46,41 @@ impl OwnedFd {
     }
 }
 
+#[cfg(unix)]
 impl Drop for OwnedFd {
     fn drop(&mut self) {
         unsafe { libc::close(self.fd); }
     }
 }
 
+#[cfg(unix)]
 impl Clone for OwnedFd {
     fn clone(&self) -> OwnedFd {
         OwnedFd::new(unsafe { libc::dup(self.fd) } ) // FIXME: handle errors
     }
 }
 
+#[cfg(unix)]
+impl From<::std::fs::File> for OwnedFd {
+    fn from(f: ::std::fs::File) -> OwnedFd { OwnedFd::new(f.into_raw_fd()) }
+}
+
+#[cfg(unix)]
+impl From<OwnedFd> for ::std::fs::File {
+    fn from(f: OwnedFd) -> ::std::fs::File { unsafe { ::std::fs::File::from_raw_fd(f.into_fd()) } }
+}
+
+#[cfg(unix)]
+impl From<::std::os::unix::io::OwnedFd> for OwnedFd {
+    fn from(f: ::std::os::unix::io::OwnedFd) -> OwnedFd { OwnedFd::new(f.into_raw_fd()) }
+}
+
+#[cfg(unix)]
+impl From<OwnedFd> for ::std::os::unix::io::OwnedFd {
+    fn from(f: OwnedFd) -> ::std::os::unix::io::OwnedFd { unsafe { ::std::os::unix::io::OwnedFd::from_raw_fd(f.into_fd()) } }
+}
+
+#[cfg(unix)]
 impl AsRawFd for OwnedFd {
     fn as_raw_fd(&self) -> RawFd {
         self.fd
@@ -161,6 +187,7 @@ pub enum MessageItem {
     Double(f64),
     /// D-Bus allows for sending file descriptors, which can be used to
     /// set up SHM, unix pipes, or other communication channels.
+    #[cfg(unix)]
     UnixFd(OwnedFd),
 }
 
@@ -234,6 +261,7 @@ impl MessageItem {
             MessageItem::Variant(_) => <Variant<u8> as Arg>::signature(),
             MessageItem::DictEntry(_, _) => { panic!("Dict entries are only valid inside arrays, and therefore has no signature on their own") },
             MessageItem::ObjectPath(_) => <Path as Arg>::signature(),
+            #[cfg(unix)]
             MessageItem::UnixFd(_) => <OwnedFd as Arg>::signature(),
         }
     }
@@ -263,6 +291,7 @@ impl MessageItem {
             &MessageItem::Variant(_) => ffi::DBUS_TYPE_VARIANT,
             &MessageItem::DictEntry(_,_) => ffi::DBUS_TYPE_DICT_ENTRY,
             &MessageItem::ObjectPath(_) => ffi::DBUS_TYPE_OBJECT_PATH,
+            #[cfg(unix)]
             &MessageItem::UnixFd(_) => ffi::DBUS_TYPE_UNIX_FD,
         };
         s as i32
@@ -361,6 +390,7 @@ impl MessageItem {
                 let o = Path::new(c_str_to_slice(&c).expect("D-Bus object path error")).ok().expect("D-Bus object path error");
                 Some(MessageItem::ObjectPath(o))
             },
+            #[cfg(unix)]
             ffi::DBUS_TYPE_UNIX_FD => Some(MessageItem::UnixFd(OwnedFd::new(iter_get_basic(i)))),
             ffi::DBUS_TYPE_BOOLEAN => Some(MessageItem::Bool(iter_get_basic::<u32>(i) != 0)),
             ffi::DBUS_TYPE_BYTE => Some(MessageItem::Byte(iter_get_basic(i))),
@@ -407,6 +437,7 @@ impl MessageItem {
             &MessageItem::UInt16(b) => self.iter_append_basic(i, b),
             &MessageItem::UInt32(b) => self.iter_append_basic(i, b),
             &MessageItem::UInt64(b) => self.iter_append_basic(i, b),
+            #[cfg(unix)]
             &MessageItem::UnixFd(ref b) => self.iter_append_basic(i, b.as_raw_fd()),
             &MessageItem::Double(b) => self.iter_append_basic(i, b),
             &MessageItem::Array(ref a) => iter_append_array(i, &a.v, a.element_signature()),
@@ -490,6 +521,7 @@ impl From<String> for MessageItem { fn from(i: String) -> MessageItem { MessageI
 
 impl From<Path<'static>> for MessageItem { fn from(i: Path<'static>) -> MessageItem { MessageItem::ObjectPath(i) } }
 
+#[cfg(unix)]
 impl From<OwnedFd> for MessageItem { fn from(i: OwnedFd) -> MessageItem { MessageItem::UnixFd(i) } }
 
 /// Create a `MessageItem::Variant`
@@ -546,6 +578,7 @@ impl<'a> FromMessageItem<'a> for &'a [MessageItem] {
     fn from(i: &'a MessageItem) -> Result<&'a [MessageItem],()> { i.inner::<&Vec<MessageItem>>().map(|s| &**s) }
 }
 
+#[cfg(unix)]
 impl<'a> FromMessageItem<'a> for &'a OwnedFd {
     fn from(i: &'a MessageItem) -> Result<&'a OwnedFd,()> { if let &MessageItem::UnixFd(ref b) = i { Ok(b) } else { Err(()) } }
 }
@@ -642,6 +675,18 @@ impl Message {
         Message { msg: ptr}
     }
 
+    /// Creates a copy of this message, including its body.
+    ///
+    /// The copy keeps the serial of this message, so replies can be made from it.
+    pub fn duplicate(&self) -> Message {
+        let ptr = unsafe { ffi::dbus_message_copy(self.msg) };
+        if ptr == ptr::null_mut() { panic!("D-Bus error: dbus_message_copy failed") }
+        let mut m = Message { msg: ptr };
+        let serial = self.get_serial();
+        if serial != 0 { unsafe { ffi::dbus_message_set_serial(m.msg, serial) } };
+        m
+    }
+
     /// Get the MessageItems that make up the message.
     ///
     /// Note: use `iter_init` or `get1`/`get2`/etc instead for faster access to the arguments.
@@ -962,15 +1007,42 @@ pub struct ConnPath<'a, C> {
     /// Object path on the destination
     pub path: Path<'a>,
     /// Timeout in milliseconds for blocking method calls
+    ///
+    /// -1 means the default timeout of the connection, and `TIMEOUT_INFINITE` means no timeout.
     pub timeout: i32,
 }
 
+/// A timeout for blocking method calls that never expires, e g for a call that installs packages.
+pub const TIMEOUT_INFINITE: i32 = ffi::DBUS_TIMEOUT_INFINITE;
+
 impl<'a, C: ::std::ops::Deref<Target=Connection>> ConnPath<'a, C> {
     /// Make a D-Bus method call, where you can append arguments inside the closure.
     pub fn method_call_with_args<F: FnOnce(&mut Message)>(&self, i: &Interface, m: &Member, f: F) -> Result<Message, Error> {
+        self.method_call_with_timeout(i, m, self.timeout, f)
+    }
+
+    /// Make a D-Bus method call with its own timeout, instead of the one of the ConnPath.
+    pub fn method_call_with_timeout<F: FnOnce(&mut Message)>(&self, i: &Interface, m: &Member, timeout_ms: i32, f: F) -> Result<Message, Error> {
         let mut msg = Message::method_call(&self.dest, &self.path, i, m);
         f(&mut msg);
-        self.conn.send_with_reply_and_block(msg, self.timeout)
+        self.conn.send_with_reply_and_block(msg, timeout_ms)
+    }
+
+    /// Make a D-Bus method call without waiting for, or getting, a reply.
+    ///
+    /// See `Connection::method_call_no_reply`.
+    pub fn method_call_no_reply<F: FnOnce(&mut Message)>(&self, i: &Interface, m: &Member, f: F) -> Result<u32, Error> {
+        let mut msg = Message::method_call(&self.dest, &self.path, i, m);
+        f(&mut msg);
+        self.conn.method_call_no_reply(msg)
+    }
+
+    /// The same destination and path, with another timeout.
+    ///
+    /// This is for making a single slow call through generated or standard interface methods,
+    /// e g `p.with_timeout(TIMEOUT_INFINITE).get("com.example.Foo", "Bar")`.
+    pub fn with_timeout(&self, timeout_ms: i32) -> ConnPath<'_, &Connection> {
+        ConnPath { conn: &*self.conn, dest: self.dest.clone(), path: self.path.clone(), timeout: timeout_ms }
     }
 
     /// Emit a D-Bus signal, where you can append arguments inside the closure.
@@ -987,12 +1059,44 @@ impl<'a, C: ::std::ops::Deref<Target=Connection>> ConnPath<'a, C> {
     }
 }
 
-// For purpose of testing the library only.
-#[cfg(test)]
+// For purpose of testing the library, and for crossroads::testing.
 pub (crate) fn message_set_serial(m: &mut Message, s: u32) {
     unsafe { ffi::dbus_message_set_serial(m.msg, s) };
 }
 
+pub (crate) fn message_set_reply_serial(m: &mut Message, s: u32) {
+    assert!(unsafe { ffi::dbus_message_set_reply_serial(m.msg, s) } != 0);
+}
+
+pub (crate) fn message_set_sender(m: &mut Message, sender: &BusName) {
+    unsafe { ffi::dbus_message_set_sender(m.msg, sender.as_ref().as_ptr()) };
+}
+
+/// An error reply to the method call with this serial, that did not come from the other side.
+pub (crate) fn message_error_reply(reply_serial: u32, error_name: &str, error_message: &str) -> Message {
+    let ptr = unsafe { ffi::dbus_message_new(ffi::DBusMessageType::Error as c_int) };
+    if ptr == ptr::null_mut() { panic!("D-Bus error: dbus_message_new failed") }
+    let en = to_c_str(error_name);
+    unsafe {
+        ffi::dbus_message_set_reply_serial(ptr, reply_serial);
+        ffi::dbus_message_set_error_name(ptr, en.as_ptr());
+    }
+    Message { msg: ptr }.append1(error_message)
+}
+
+/// The size of the message on the wire.
+pub (crate) fn message_marshalled_len(m: &Message) -> usize {
+    let (mut data, mut len) = (ptr::null_mut(), 0);
+    if unsafe { ffi::dbus_message_marshal(m.msg, &mut data, &mut len) } == 0 { return 0 }
+    unsafe { ffi::dbus_free(data as *mut c_void) };
+    len as usize
+}
+
+#[cfg(test)]
+pub (crate) fn message_clear_interface(m: &mut Message) {
+    unsafe { ffi::dbus_message_set_interface(m.msg, ptr::null()) };
+}
+
 #[cfg(test)]
 mod test {
     extern crate tempdir;
@@ -1016,7 +1120,7 @@ mod test {
         let mut file = OpenOptions::new().create(true).read(true).write(true).open(&filename).unwrap();
         file.write_all(b"z").unwrap();
         file.seek(SeekFrom::Start(0)).unwrap();
-        let ofd = OwnedFd::new(file.as_raw_fd());
+        let ofd = OwnedFd::from(file);
         m.append_items(&[MessageItem::UnixFd(ofd.clone())]);
         println!("Sending {:?}", m.get_items());
         c.send(m).unwrap();
@@ -1035,6 +1139,48 @@ mod test {
         }}
     }
 
+    #[test]
+    fn fd_args() {
+        use std::io::prelude::*;
+        use std::os::unix::io::AsFd;
+        use std::collections::HashMap;
+        use crate::arg::{self, Variant, RefArg};
+
+        let c = Connection::get_private(BusType::Session).unwrap();
+        assert!(c.can_send_unix_fd());
+        c.register_object_path("/hello").unwrap();
+        let tempdir = tempdir::TempDir::new("dbus-rs-test").unwrap();
+        let file = |n: &str, data: &[u8]| {
+            let path = tempdir.path().join(n);
+            ::std::fs::write(&path, data).unwrap();
+            ::std::fs::File::open(path).unwrap()
+        };
+        let read = |f: &mut ::std::fs::File| { let mut s = String::new(); f.read_to_string(&mut s).unwrap(); s };
+
+        let borrowed = file("b", b"borrowed");
+        let mut dict = HashMap::new();
+        dict.insert("d".to_string(), Variant(Box::new(OwnedFd::from(file("d", b"dict"))) as Box<RefArg>));
+        let m = Message::new_method_call(&c.unique_name(), "/hello", "com.example.hello", "Fds").unwrap()
+            .append3(file("a", b"file"), ::std::os::unix::io::OwnedFd::from(file("s", b"std")), borrowed.as_fd())
+            .append1(dict);
+        c.send(m).unwrap();
+
+        for n in c.incoming(1000) {
+            if n.msg_type() != MessageType::MethodCall { continue }
+            let (mut f, s, b): (::std::fs::File, ::std::os::unix::io::OwnedFd, ::std::fs::File) = n.read3().unwrap();
+            assert_eq!(read(&mut f), "file");
+            assert_eq!(read(&mut s.into()), "std");
+            assert_eq!(read(&mut b.into()), "borrowed");
+            let mut i = n.iter_init();
+            i.next(); i.next(); i.next();
+            let d: HashMap<String, Variant<Box<RefArg>>> = i.read().unwrap();
+            let fd = arg::cast::<OwnedFd>(&*d["d"].0).unwrap().clone();
+            assert_eq!(read(&mut fd.into()), "dict");
+            return
+        }
+        panic!("No method call");
+    }
+
     #[test]
     fn message_types() {
         let c = Connection::get_private(BusType::Session).unwrap();
@@ -1139,6 +1285,26 @@ mod test {
         c.send(m).unwrap();
     }
 
+    #[test]
+    fn call_timeout() {
+        use super::TIMEOUT_INFINITE;
+        use crate::stdintf::org_freedesktop_dbus::Peer;
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let p = c.with_path("org.freedesktop.DBus", "/org/freedesktop/DBus", 1000);
+        let p2 = p.with_timeout(TIMEOUT_INFINITE);
+        assert_eq!(p2.timeout, TIMEOUT_INFINITE);
+        assert_eq!(p2.dest, p.dest);
+        p2.ping().unwrap();
+
+        // Nobody answers these, so only the timeout ends the calls.
+        let slow = c.with_path(c.unique_name(), "/", 10000);
+        let (i, m) = ("com.example.dbusrs.Slow".into(), "Wait".into());
+        let start = ::std::time::Instant::now();
+        assert!(slow.method_call_with_timeout(&i, &m, 20, |_| {}).is_err());
+        assert!(slow.with_timeout(20).method_call_with_args(&i, &m, |_| {}).is_err());
+        assert!(start.elapsed() < ::std::time::Duration::from_secs(5));
+    }
+
     #[test]
     fn set_valid_destination() {
         let mut m = Message::new_method_call("org.test.rust", "/", "org.test.rust", "Test").unwrap();
@@ -1149,4 +1315,15 @@ mod test {
         m.set_no_reply(true);
         assert!(m.get_no_reply());
     }
+
+    #[test]
+    fn duplicate() {
+        let mut m = Message::new_method_call("org.test.rust", "/", "org.test.rust", "Test").unwrap().append1(5u8);
+        super::message_set_serial(&mut m, 57);
+        let m2 = m.duplicate();
+        drop(m);
+        assert_eq!(m2.get_serial(), 57);
+        assert_eq!(m2.read1::<u8>().unwrap(), 5);
+        assert_eq!(m2.method_return().get_reply_serial(), Some(57));
+    }
 }
@@ -0,0 +1,96 @@
+use crate::{Connection, ConnMsgs, Message, Error};
+
+/// A connection that has become a monitor, see `Connection::become_monitor`.
+///
+/// A monitor receives copies of the messages that match its rules, including method calls and
+/// replies between other peers, like dbus-monitor does. It cannot send anything: the bus
+/// disconnects monitors that do. That is why `Monitor` only hands out incoming messages.
+pub struct Monitor {
+    conn: Connection,
+}
+
+impl Connection {
+    /// Makes the connection a monitor, receiving messages matching any of the rules (or all
+    /// messages, if there are no rules).
+    ///
+    /// This calls org.freedesktop.DBus.Monitoring.BecomeMonitor, which needs dbus-daemon 1.9.10
+    /// or later, and on the system bus, to be root. The connection starts over as a monitor, so
+    /// names it owned and match rules it added are gone. Older buses only have eavesdropping,
+    /// see `enable_eavesdropping`.
+    pub fn become_monitor(self, rules: &[&str]) -> Result<Monitor, Error> {
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus.Monitoring", "BecomeMonitor")
+            .map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e))?
+            .append2(rules, 0u32);
+        self.send_with_reply_and_block(m, -1)?;
+        // Claim every message, so that libdbus does not reply to method calls meant for others.
+        let mut old_cb = self.replace_message_callback(None).unwrap();
+        self.replace_message_callback(Some(Box::new(move |c, m| { old_cb(c, m); true })));
+        Ok(Monitor { conn: self })
+    }
+
+    /// Opts in to receiving messages addressed to other connections, from match rules with
+    /// `MatchRule::eavesdrop` set. This is for buses without `become_monitor`.
+    ///
+    /// Such messages are returned from `iter` and `incoming` like others, but libdbus is kept
+    /// from handling them itself, e g from replying with an error to method calls meant for
+    /// someone else. Unlike a monitor, the connection can still send, so do not reply either.
+    pub fn enable_eavesdropping(&self) {
+        let mut old_cb = self.replace_message_callback(None).unwrap();
+        self.replace_message_callback(Some(Box::new(move |c, m| {
+            let ours = match m.destination() { None => true, Some(d) => *d == *c.unique_name() };
+            old_cb(c, m) || !ours
+        })));
+    }
+}
+
+impl Monitor {
+    /// The messages captured, waiting up to timeout_ms milliseconds for each.
+    ///
+    /// The iterator ends on timeout, or when the connection is closed.
+    pub fn incoming(&self, timeout_ms: u32) -> ConnMsgs<&Connection> {
+        ConnMsgs { conn: &self.conn, timeout_ms: Some(timeout_ms) }
+    }
+
+    /// Whether the connection to the bus is still open.
+    pub fn is_connected(&self) -> bool { self.conn.is_connected() }
+
+    /// The unique name of the monitor on the bus.
+    pub fn unique_name(&self) -> String { self.conn.unique_name() }
+}
+
+#[test]
+fn monitor_method_calls() {
+    use crate::{BusType, MessageType};
+    let name = format!("com.example.dbusrs.monitored{}", ::std::process::id());
+    let rule = format!("type='method_call',destination='{}'", name);
+    let mon = Connection::get_private(BusType::Session).unwrap().become_monitor(&[&rule]).unwrap();
+
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let m = Message::new_method_call(&*name, "/hello", "com.example.dbusrs.Monitor", "Hello").unwrap();
+    c.send(m).unwrap();
+    let seen = mon.incoming(1000).find(|m| m.msg_type() == MessageType::MethodCall).unwrap();
+    assert_eq!(&*seen.member().unwrap(), "Hello");
+    assert_eq!(&*seen.destination().unwrap(), &*name);
+    assert!(mon.is_connected());
+}
+
+#[test]
+fn eavesdrop() {
+    use crate::{BusType, MessageType, MatchRule};
+    let name = format!("com.example.dbusrs.eavesdropped{}", ::std::process::id());
+    let c = Connection::get_private(BusType::Session).unwrap();
+    c.enable_eavesdropping();
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.member = Some("Greet".into());
+    rule.eavesdrop = true;
+    assert_eq!(rule.match_str(), "type='method_call',member='Greet',eavesdrop='true'");
+    c.add_match(&rule.match_str()).unwrap();
+
+    let service = Connection::get_private(BusType::Session).unwrap();
+    service.register_name(&name, 0).unwrap();
+    let client = Connection::get_private(BusType::Session).unwrap();
+    client.send(Message::new_method_call(&*name, "/hello", "com.example.dbusrs.Eavesdrop", "Greet").unwrap()).unwrap();
+    let seen = c.incoming(1000).find(|m| m.msg_type() == MessageType::MethodCall).unwrap();
+    assert_eq!(&*seen.destination().unwrap(), &*name);
+}
@@ -0,0 +1,320 @@
+//! Owning well-known names on the bus.
+
+use crate::{Connection, Message, MessageType, MsgHandler, MsgHandlerType, MsgHandlerResult};
+use crate::{Error, RequestNameReply, ReleaseNameReply};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::ops;
+use std::time::Instant;
+
+/// A change in which names the connection owns, sent by the bus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameEvent {
+    /// The connection became the primary owner of the name, e g after waiting in the queue.
+    Acquired(String),
+    /// The connection is no longer the primary owner of the name, e g because another
+    /// connection replaced it.
+    Lost(String),
+}
+
+impl NameEvent {
+    /// If the message is a NameAcquired or NameLost signal from the bus, returns what it tells.
+    pub fn from_message(m: &Message) -> Option<NameEvent> {
+        if m.msg_type() != MessageType::Signal { return None }
+        if m.sender() != Some("org.freedesktop.DBus".into()) { return None }
+        if m.interface() != Some("org.freedesktop.DBus".into()) { return None }
+        let name: String = m.read1().ok()?;
+        match &*m.member()? {
+            "NameAcquired" => Some(NameEvent::Acquired(name)),
+            "NameLost" => Some(NameEvent::Lost(name)),
+            _ => None,
+        }
+    }
+}
+
+/// A MsgHandler that calls a closure for every `NameEvent`, see `Connection::on_name_event`.
+///
+/// The signals are not marked as handled, so they still show up in `Connection::iter`.
+pub struct NameOwnership<F>(F);
+
+impl<F: FnMut(NameEvent)> NameOwnership<F> {
+    /// Creates a handler calling the closure.
+    pub fn new(f: F) -> Self { NameOwnership(f) }
+}
+
+impl<F: FnMut(NameEvent)> MsgHandler for NameOwnership<F> {
+    fn handler_type(&self) -> MsgHandlerType { MsgHandlerType::MsgType(MessageType::Signal) }
+    fn handle_msg(&mut self, msg: &Message) -> Option<MsgHandlerResult> {
+        if let Some(e) = NameEvent::from_message(msg) { (self.0)(e) }
+        None
+    }
+}
+
+/// A name requested with `Connection::request_name`, which is released when this is dropped.
+///
+/// Releasing on drop is best effort, errors (e g because the connection is gone) are ignored.
+/// Call `release` to see them.
+#[derive(Debug)]
+pub struct NameGuard<'a> {
+    conn: &'a Connection,
+    name: String,
+    reply: RequestNameReply,
+    released: bool,
+}
+
+impl<'a> NameGuard<'a> {
+    /// The name requested.
+    pub fn name(&self) -> &str { &self.name }
+
+    /// What the bus replied to the request.
+    ///
+    /// With `RequestNameReply::Exists`, the name is neither owned nor queued for, and there is
+    /// nothing to release.
+    pub fn reply(&self) -> RequestNameReply { self.reply }
+
+    /// Releases the name now, or leaves the queue for it.
+    pub fn release(mut self) -> Result<ReleaseNameReply, Error> {
+        self.released = true;
+        self.conn.release_name(&self.name)
+    }
+}
+
+impl<'a> Drop for NameGuard<'a> {
+    fn drop(&mut self) {
+        if !self.released { let _ = self.conn.release_name(&self.name); }
+    }
+}
+
+impl Connection {
+    /// Requests a name, with the flags spelled out. See `register_name` for the raw flags version.
+    ///
+    /// The name is released when the returned guard is dropped.
+    ///
+    /// allow_replacement lets a later request with replace_existing take over the name.
+    /// do_not_queue makes the request fail with `RequestNameReply::Exists`, instead of waiting
+    /// in the queue with `RequestNameReply::InQueue`, if someone else owns the name. A queued
+    /// request is granted later with a `NameEvent::Acquired`, see `on_name_event`.
+    pub fn request_name(&self, name: &str, allow_replacement: bool, replace_existing: bool, do_not_queue: bool)
+        -> Result<NameGuard<'_>, Error> {
+        use crate::NameFlag;
+        let flags = [(allow_replacement, NameFlag::AllowReplacement), (replace_existing, NameFlag::ReplaceExisting),
+            (do_not_queue, NameFlag::DoNotQueue)];
+        let reply = self.register_name(name, flags.iter().filter(|f| f.0).fold(0, |a, f| a | f.1.value()))?;
+        Ok(NameGuard { conn: self, name: name.into(), reply, released: reply == RequestNameReply::Exists })
+    }
+
+    /// Waits for a name to get an owner, for at most timeout_ms milliseconds, or forever if it is -1.
+    ///
+    /// Returns the unique name of the owner, or None on timeout. This is for clients that start
+    /// before the service they need, e g during boot. While waiting, incoming messages go to the
+    /// message handlers, but are otherwise dropped, so this is best called before other messages
+    /// are expected.
+    pub fn wait_for_name(&self, name: &str, timeout_ms: i32) -> Result<Option<String>, Error> {
+        let owner = Rc::new(RefCell::new(None));
+        let o2 = owner.clone();
+        let _w = NameWatcher::new(self, name, move |o| if let Some(o) = o { *o2.borrow_mut() = Some(o.to_string()) })?;
+        let start = Instant::now();
+        loop {
+            if let Some(o) = owner.borrow_mut().take() { return Ok(Some(o)) }
+            let left = if timeout_ms < 0 { -1 } else {
+                let e = start.elapsed();
+                let left = timeout_ms as i64 - (e.as_secs() as i64 * 1000 + e.subsec_millis() as i64);
+                if left <= 0 { return Ok(None) }
+                left as i32
+            };
+            if self.iter(left).next().is_none() {
+                return Err(Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection closed while waiting for name"))
+            }
+        }
+    }
+
+    /// Calls the closure when the connection acquires or loses a name, while processing incoming messages.
+    pub fn on_name_event<F: FnMut(NameEvent) + 'static>(&self, f: F) {
+        self.add_handler(NameOwnership::new(f))
+    }
+}
+
+type OwnerCallback = Box<FnMut(Option<&str>)>;
+
+// owner is None until the reply to GetNameOwner arrives.
+struct WatchState {
+    owner: Option<Option<String>>,
+    cb: OwnerCallback,
+}
+
+impl WatchState {
+    fn set(&mut self, owner: Option<String>) {
+        if self.owner.as_ref() == Some(&owner) { return }
+        (self.cb)(owner.as_ref().map(|s| &**s));
+        self.owner = Some(owner);
+    }
+}
+
+/// Tracks whether a well-known name has an owner, and which unique name that is.
+///
+/// The callback is called with the unique name when the name appears or changes owner, and
+/// with None when it disappears. It is first called when the current owner is known, which
+/// is when the connection has processed the reply to GetNameOwner, e g in `Connection::iter`.
+///
+/// Changes before that are part of the reply and not reported on their own, so that the
+/// callback never sees an older owner than the one before.
+///
+/// C is some way to access the connection, e g a &Connection or Rc<Connection>.
+pub struct NameWatcher<C: ops::Deref<Target=Connection>> {
+    conn: C,
+    name: String,
+    rule: String,
+    state: Rc<RefCell<WatchState>>,
+}
+
+struct WatchHandler {
+    name: String,
+    serial: u32,
+    state: Weak<RefCell<WatchState>>,
+}
+
+impl MsgHandler for WatchHandler {
+    fn handler_type(&self) -> MsgHandlerType { MsgHandlerType::All }
+    fn handle_msg(&mut self, msg: &Message) -> Option<MsgHandlerResult> {
+        let state = match self.state.upgrade() {
+            Some(s) => s,
+            None => return Some(MsgHandlerResult { handled: false, done: true, reply: vec!() }),
+        };
+        let mut state = state.borrow_mut();
+        if msg.get_reply_serial() == Some(self.serial) {
+            // An error means that nobody owns the name.
+            state.set(msg.read1::<&str>().ok().filter(|_| msg.msg_type() == MessageType::MethodReturn).map(String::from));
+            return Some(MsgHandlerResult { handled: true, done: false, reply: vec!() })
+        }
+        if state.owner.is_none() || msg.msg_type() != MessageType::Signal { return None }
+        if msg.sender() != Some("org.freedesktop.DBus".into()) || msg.member() != Some("NameOwnerChanged".into()) { return None }
+        if let Ok((name, _, new_owner)) = msg.read3::<&str, &str, &str>() {
+            if name == self.name { state.set(Some(new_owner).filter(|x| !x.is_empty()).map(String::from)) }
+        }
+        None
+    }
+}
+
+impl<C: ops::Deref<Target=Connection>> NameWatcher<C> {
+    /// Starts watching the name, calling the closure on changes while processing incoming messages.
+    pub fn new<F: FnMut(Option<&str>) + 'static>(conn: C, name: &str, f: F) -> Result<Self, Error> {
+        // Add the match before asking, so that no change falls between the reply and the signals.
+        let rule = crate::MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+            .with_sender("org.freedesktop.DBus").with_arg(0, name).match_str();
+        conn.add_match(&rule)?;
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetNameOwner")
+            .map_err(|e| Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e))?.append1(name);
+        let serial = conn.send(m).map_err(|_| Error::new_custom("org.freedesktop.DBus.Error.Failed", "Sending GetNameOwner failed"))?;
+        let state = Rc::new(RefCell::new(WatchState { owner: None, cb: Box::new(f) }));
+        conn.add_handler(WatchHandler { name: name.into(), serial, state: Rc::downgrade(&state) });
+        Ok(NameWatcher { conn, name: name.into(), rule, state })
+    }
+
+    /// The name watched.
+    pub fn name(&self) -> &str { &self.name }
+
+    /// The unique name of the owner, or None if the name has no owner or the owner is not known yet.
+    pub fn owner(&self) -> Option<String> { self.state.borrow().owner.clone().and_then(|x| x) }
+
+    /// Whether the current owner is known yet, see `NameWatcher`.
+    pub fn is_resolved(&self) -> bool { self.state.borrow().owner.is_some() }
+}
+
+impl<C: ops::Deref<Target=Connection>> Drop for NameWatcher<C> {
+    fn drop(&mut self) { let _ = self.conn.remove_match(&self.rule); }
+}
+
+#[test]
+fn queued_name() {
+    use crate::BusType;
+    use std::{cell, rc};
+    let name = format!("com.example.dbusrs.queued{}", ::std::process::id());
+    let c1 = Connection::get_private(BusType::Session).unwrap();
+    let c2 = Connection::get_private(BusType::Session).unwrap();
+    let g1 = c1.request_name(&name, false, false, false).unwrap();
+    assert_eq!(g1.reply(), RequestNameReply::PrimaryOwner);
+    assert_eq!(c2.request_name(&name, false, false, true).unwrap().reply(), RequestNameReply::Exists);
+    let g2 = c2.request_name(&name, false, false, false).unwrap();
+    assert_eq!(g2.reply(), RequestNameReply::InQueue);
+
+    let events = rc::Rc::new(cell::RefCell::new(vec!()));
+    let e2 = events.clone();
+    c2.on_name_event(move |e| e2.borrow_mut().push(e));
+    drop(g1);
+    for _ in 0..20 {
+        c2.iter(100).next();
+        if events.borrow().contains(&NameEvent::Acquired(name.clone())) { return }
+    }
+    panic!("No NameAcquired, got {:?}", events.borrow());
+}
+
+#[test]
+fn name_guard() {
+    use crate::BusType;
+    let name = format!("com.example.dbusrs.guard{}", ::std::process::id());
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let c2 = Connection::get_private(BusType::Session).unwrap();
+    {
+        let g = c.request_name(&name, false, false, true).unwrap();
+        assert_eq!(g.name(), name);
+        assert_eq!(c2.request_name(&name, false, false, true).unwrap().reply(), RequestNameReply::Exists);
+    }
+    let g = c2.request_name(&name, false, false, true).unwrap();
+    assert_eq!(g.reply(), RequestNameReply::PrimaryOwner);
+    assert_eq!(g.release().unwrap(), ReleaseNameReply::Released);
+    assert_eq!(c.release_name(&name).unwrap(), ReleaseNameReply::NonExistent);
+}
+
+#[test]
+fn name_watcher() {
+    use crate::BusType;
+    let name = format!("com.example.dbusrs.watched{}", ::std::process::id());
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let c2 = Connection::get_private(BusType::Session).unwrap();
+    let _g = c2.request_name(&name, true, false, false).unwrap();
+
+    let seen = Rc::new(RefCell::new(vec!()));
+    let s2 = seen.clone();
+    let w = NameWatcher::new(&c, &name, move |o| s2.borrow_mut().push(o.map(String::from))).unwrap();
+    assert!(!w.is_resolved());
+    let wait = |n| for _ in 0..20 { if seen.borrow().len() >= n { break }; c.iter(100).next(); };
+    wait(1);
+    assert!(w.is_resolved());
+    assert_eq!(w.owner(), Some(c2.unique_name()));
+
+    let c3 = Connection::get_private(BusType::Session).unwrap();
+    let c3_name = c3.unique_name();
+    let g3 = c3.request_name(&name, false, true, false).unwrap();
+    wait(2);
+    assert_eq!(w.owner(), Some(c3_name.clone()));
+    drop(g3);
+    // The name goes back to c2, which is still in the queue.
+    wait(3);
+    assert_eq!(w.owner(), Some(c2.unique_name()));
+    drop(_g);
+    wait(4);
+    assert_eq!(w.owner(), None);
+    assert_eq!(*seen.borrow(), vec!(Some(c2.unique_name()), Some(c3_name), Some(c2.unique_name()), None));
+}
+
+#[test]
+fn wait_for_name() {
+    use crate::BusType;
+    let name = format!("com.example.dbusrs.waited{}", ::std::process::id());
+    let c = Connection::get_private(BusType::Session).unwrap();
+    assert_eq!(c.wait_for_name(&name, 50).unwrap(), None);
+
+    let n2 = name.clone();
+    let t = ::std::thread::spawn(move || {
+        let c2 = Connection::get_private(BusType::Session).unwrap();
+        ::std::thread::sleep(::std::time::Duration::from_millis(100));
+        c2.register_name(&n2, 0).unwrap();
+        let u = c2.unique_name();
+        ::std::thread::sleep(::std::time::Duration::from_millis(500));
+        u
+    });
+    let owner = c.wait_for_name(&name, 5000).unwrap();
+    assert_eq!(owner, Some(t.join().unwrap()));
+    // Already owned names are found at once.
+    assert_eq!(c.wait_for_name("org.freedesktop.DBus", 1000).unwrap(), Some("org.freedesktop.DBus".into()));
+}
@@ -0,0 +1,108 @@
+use crate::{Connection, Message, Error};
+use crate::connection::{conn_handle, conn_stats};
+use crate::stats::Stats;
+use std::ptr;
+use std::os::raw::c_int;
+
+/// Method calls sent back to back, with the replies collected afterwards, see `Connection::pipeline`.
+///
+/// Waiting for each reply before sending the next call costs a round trip per call. Here, all
+/// calls are written before waiting for any reply, so fetching a hundred properties costs
+/// about as much as fetching one.
+///
+/// While waiting, other incoming messages are queued on the connection, and returned from
+/// `iter` and `incoming` afterwards.
+pub struct Pipeline<'a> {
+    conn: &'a Connection,
+    calls: Vec<PendingCall<'a>>,
+}
+
+struct PendingCall<'a>(*mut ffi::DBusPendingCall, &'a Stats);
+
+impl<'a> Drop for PendingCall<'a> {
+    fn drop(&mut self) {
+        if self.0.is_null() { return }
+        unsafe {
+            ffi::dbus_pending_call_cancel(self.0);
+            ffi::dbus_pending_call_unref(self.0);
+        }
+        self.1.call_finished();
+    }
+}
+
+impl Connection {
+    /// Starts a pipeline of method calls.
+    pub fn pipeline(&self) -> Pipeline<'_> { Pipeline { conn: self, calls: vec!() } }
+}
+
+impl<'a> Pipeline<'a> {
+    /// Queues a method call for sending, without waiting for the reply.
+    ///
+    /// Returns its index in the `replies`. A timeout_ms of -1 means the `default_timeout` of
+    /// the connection, and `TIMEOUT_INFINITE` means no timeout.
+    pub fn call(&mut self, msg: Message, timeout_ms: i32) -> Result<usize, Error> {
+        let timeout_ms = if timeout_ms == -1 { self.conn.default_timeout() } else { timeout_ms };
+        let mut p = ptr::null_mut();
+        if unsafe { ffi::dbus_connection_send_with_reply(conn_handle(self.conn), msg.ptr(), &mut p, timeout_ms as c_int) } == 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Sending method call failed"))
+        }
+        // libdbus gives us no pending call if the connection is closed.
+        if p.is_null() { return Err(Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "Connection is closed")) }
+        let stats = conn_stats(self.conn);
+        stats.sent(&msg, None);
+        stats.call_started();
+        self.calls.push(PendingCall(p, stats));
+        Ok(self.calls.len() - 1)
+    }
+
+    /// The number of calls queued.
+    pub fn len(&self) -> usize { self.calls.len() }
+
+    /// Whether no calls are queued.
+    pub fn is_empty(&self) -> bool { self.calls.is_empty() }
+
+    /// Sends the calls, and waits until all of them are replied to or have timed out.
+    ///
+    /// The replies are in the order of the calls. An error reply, or a timeout, is an Err.
+    pub fn replies(self) -> Vec<Result<Message, Error>> {
+        unsafe { ffi::dbus_connection_flush(conn_handle(self.conn)) };
+        self.calls.into_iter().map(|mut p| {
+            unsafe { ffi::dbus_pending_call_block(p.0) };
+            let r = unsafe { ffi::dbus_pending_call_steal_reply(p.0) };
+            unsafe { ffi::dbus_pending_call_unref(p.0) };
+            p.0 = ptr::null_mut();
+            p.1.call_finished();
+            if r.is_null() { return Err(Error::new_custom("org.freedesktop.DBus.Error.NoReply", "No reply")) }
+            let mut m = Message::from_ptr(r, false);
+            p.1.received(&m);
+            m.as_result()?;
+            Ok(m)
+        }).collect()
+    }
+}
+
+#[test]
+fn pipelined_calls() {
+    use crate::BusType;
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let mut p = c.pipeline();
+    assert!(p.is_empty());
+    let mut names = vec!();
+    for i in 0..50 {
+        let name = if i == 25 { "com.example.dbusrs.nobody".to_string() } else { c.unique_name() };
+        let m = Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetNameOwner")
+            .unwrap().append1(&name);
+        assert_eq!(p.call(m, 2000).unwrap(), i);
+        names.push(name);
+    }
+    assert_eq!(p.len(), 50);
+    let replies = p.replies();
+    assert_eq!(replies.len(), 50);
+    for (i, r) in replies.into_iter().enumerate() {
+        if i == 25 {
+            assert_eq!(r.unwrap_err().name(), Some("org.freedesktop.DBus.Error.NameHasNoOwner"));
+        } else {
+            assert_eq!(r.unwrap().read1::<&str>().unwrap(), names[i]);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{Connection, Message, Error, BusName, Path, Interface, Member, arg};
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// A remote object: a destination, a path on it, and a timeout for the blocking method calls.
+///
+/// Arguments and return values are tuples (see `arg::AppendAll` and `arg::ReadAll`), so that
+/// simple client code does not need dbus-codegen, e g:
+///
+/// ```rust,no_run
+/// use dbus::{Connection, BusType, Proxy};
+///
+/// let c = Connection::get_private(BusType::Session).unwrap();
+/// let p = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", 5000, &c);
+/// let (names,): (Vec<String>,) = p.method_call("org.freedesktop.DBus", "ListNames", ()).unwrap();
+/// let (owner,): (String,) = p.method_call("org.freedesktop.DBus", "GetNameOwner", ("org.freedesktop.DBus",)).unwrap();
+/// let features: Vec<String> = p.get("org.freedesktop.DBus", "Features").unwrap();
+/// ```
+///
+/// To set the defaults once, and make proxies for several paths on the same peer, use `ProxyBuilder`.
+#[derive(Clone, Debug)]
+pub struct Proxy<'a, C> {
+    /// Destination, i e what D-Bus service you're communicating with
+    pub destination: BusName<'a>,
+    /// Object path on the destination
+    pub path: Path<'a>,
+    /// Interface for `call`, which has no interface argument
+    pub interface: Option<Interface<'a>>,
+    /// Timeout in milliseconds for method calls
+    ///
+    /// -1 means the default timeout of the connection, and `TIMEOUT_INFINITE` means no timeout.
+    pub timeout: i32,
+    /// Some way to access the connection, e g a &Connection or Rc<Connection>
+    pub connection: C,
+}
+
+/// Sets destination, path, interface and timeout once, for the proxies of a client.
+///
+/// ```rust,no_run
+/// use dbus::{Connection, BusType, ProxyBuilder};
+///
+/// let c = Connection::get_private(BusType::System).unwrap();
+/// let nm = ProxyBuilder::new("org.freedesktop.NetworkManager")
+///     .path("/org/freedesktop/NetworkManager")
+///     .interface("org.freedesktop.NetworkManager")
+///     .timeout(5000)
+///     .build(&c);
+/// let (devices,): (Vec<dbus::Path>,) = nm.call("GetDevices", ()).unwrap();
+/// for d in devices {
+///     let device = nm.with_path(d);
+///     let name: String = device.get("org.freedesktop.NetworkManager.Device", "Interface").unwrap();
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ProxyBuilder<'a> {
+    destination: BusName<'a>,
+    path: Path<'a>,
+    interface: Option<Interface<'a>>,
+    timeout: i32,
+}
+
+impl<'a> ProxyBuilder<'a> {
+    /// Starts with a destination, the path "/", no interface and the default timeout of the connection.
+    pub fn new<D: Into<BusName<'a>>>(destination: D) -> Self {
+        ProxyBuilder { destination: destination.into(), path: "/".into(), interface: None, timeout: -1 }
+    }
+
+    /// Sets the object path.
+    pub fn path<P: Into<Path<'a>>>(mut self, path: P) -> Self { self.path = path.into(); self }
+
+    /// Sets the interface used by `Proxy::call`.
+    pub fn interface<I: Into<Interface<'a>>>(mut self, interface: I) -> Self { self.interface = Some(interface.into()); self }
+
+    /// Sets the timeout in milliseconds, see `Proxy::timeout`.
+    pub fn timeout(mut self, timeout_ms: i32) -> Self { self.timeout = timeout_ms; self }
+
+    /// Makes a proxy using a connection.
+    pub fn build<C: Deref<Target=Connection>>(&self, connection: C) -> Proxy<'a, C> {
+        Proxy { destination: self.destination.clone(), path: self.path.clone(), interface: self.interface.clone(),
+            timeout: self.timeout, connection }
+    }
+}
+
+fn invalid_args<E: ToString>(e: E) -> Error { Error::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()) }
+
+impl<'a, C: Deref<Target=Connection>> Proxy<'a, C> {
+    /// Creates a new proxy for a destination and path.
+    pub fn new<D: Into<BusName<'a>>, P: Into<Path<'a>>>(destination: D, path: P, timeout_ms: i32, connection: C) -> Self {
+        Proxy { destination: destination.into(), path: path.into(), interface: None, timeout: timeout_ms, connection }
+    }
+
+    /// The same destination, interface, timeout and connection, for another path.
+    ///
+    /// This is cheap when the connection is, e g a &Connection or Rc<Connection>.
+    pub fn with_path<P: Into<Path<'a>>>(&self, path: P) -> Self where C: Clone {
+        Proxy { destination: self.destination.clone(), path: path.into(), interface: self.interface.clone(),
+            timeout: self.timeout, connection: self.connection.clone() }
+    }
+
+    /// Calls a method of the interface of the proxy, see `method_call`.
+    pub fn call<A: arg::AppendAll, R: arg::ReadAll>(&self, member: &str, args: A) -> Result<R, Error> {
+        let i = self.interface.as_ref().ok_or_else(|| Error::new_custom("org.freedesktop.DBus.Error.UnknownInterface", "Proxy has no interface"))?;
+        self.method_call(i, member, args)
+    }
+
+    /// Calls a method, and waits for the reply.
+    ///
+    /// The arguments and the return values are tuples, e g `(5u32, "Hello")`, or `()` for none.
+    /// A reply with other types than R is an InvalidArgs error.
+    pub fn method_call<A: arg::AppendAll, R: arg::ReadAll>(&self, interface: &str, member: &str, args: A) -> Result<R, Error> {
+        let i = Interface::new(interface).map_err(invalid_args)?;
+        let m = Member::new(member).map_err(invalid_args)?;
+        let mut msg = Message::method_call(&self.destination, &self.path, &i, &m);
+        args.append(&mut arg::IterAppend::new(&mut msg));
+        let reply = self.connection.send_with_reply_and_block(msg, self.timeout)?;
+        R::read(&mut reply.iter_init()).map_err(invalid_args)
+    }
+
+    /// Calls a method without waiting for, or getting, a reply.
+    ///
+    /// See `Connection::method_call_no_reply`.
+    pub fn method_call_no_reply<A: arg::AppendAll>(&self, interface: &str, member: &str, args: A) -> Result<(), Error> {
+        let i = Interface::new(interface).map_err(invalid_args)?;
+        let m = Member::new(member).map_err(invalid_args)?;
+        let mut msg = Message::method_call(&self.destination, &self.path, &i, &m);
+        args.append(&mut arg::IterAppend::new(&mut msg));
+        self.connection.method_call_no_reply(msg).map(|_| ())
+    }
+
+    /// Gets a property, with org.freedesktop.DBus.Properties.Get.
+    pub fn get<T: for<'b> arg::Get<'b>>(&self, interface: &str, name: &str) -> Result<T, Error> {
+        let (v,): (arg::Variant<T>,) = self.method_call("org.freedesktop.DBus.Properties", "Get", (interface, name))?;
+        Ok(v.0)
+    }
+
+    /// Sets a property, with org.freedesktop.DBus.Properties.Set.
+    pub fn set<T: arg::Arg + arg::Append>(&self, interface: &str, name: &str, value: T) -> Result<(), Error> {
+        self.method_call("org.freedesktop.DBus.Properties", "Set", (interface, name, arg::Variant(value)))
+    }
+
+    /// Gets all properties of an interface, with org.freedesktop.DBus.Properties.GetAll.
+    pub fn get_all(&self, interface: &str) -> Result<HashMap<String, arg::Variant<Box<arg::RefArg>>>, Error> {
+        let (props,) = self.method_call("org.freedesktop.DBus.Properties", "GetAll", (interface,))?;
+        Ok(props)
+    }
+}
+
+#[test]
+fn proxy_calls() {
+    use crate::BusType;
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let p = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", 2000, &c);
+    let (owner,): (String,) = p.method_call("org.freedesktop.DBus", "GetNameOwner", ("org.freedesktop.DBus",)).unwrap();
+    assert_eq!(owner, "org.freedesktop.DBus");
+    let (names,): (Vec<String>,) = p.method_call("org.freedesktop.DBus", "ListNames", ()).unwrap();
+    assert!(names.contains(&c.unique_name()));
+    let r: Result<(u32,), _> = p.method_call("org.freedesktop.DBus", "ListNames", ());
+    assert_eq!(r.unwrap_err().name(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+    assert!(p.method_call::<_, ()>("not an interface", "ListNames", ()).is_err());
+
+    let features: Vec<String> = p.get("org.freedesktop.DBus", "Features").unwrap();
+    let all = p.get_all("org.freedesktop.DBus").unwrap();
+    assert_eq!(all["Features"].0.as_iter().unwrap().count(), features.len());
+    assert!(p.set("org.freedesktop.DBus", "Features", vec!("x")).is_err());
+    p.method_call_no_reply("org.freedesktop.DBus", "GetId", ()).unwrap();
+}
+
+#[test]
+fn proxy_builder() {
+    use crate::BusType;
+    let c = Connection::get_private(BusType::Session).unwrap();
+    let b = ProxyBuilder::new("org.freedesktop.DBus").path("/org/freedesktop/DBus").timeout(2000);
+    assert!(b.build(&c).call::<_, (String,)>("GetId", ()).is_err());
+    let p = b.interface("org.freedesktop.DBus").build(&c);
+    let (id,): (String,) = p.call("GetId", ()).unwrap();
+    assert_eq!(id.len(), 32);
+
+    let root = p.with_path("/");
+    assert_eq!(&*root.path, "/");
+    assert_eq!(root.timeout, 2000);
+    let (id2,): (String,) = root.call("GetId", ()).unwrap();
+    assert_eq!(id, id2);
+}
@@ -0,0 +1,178 @@
+use crate::{Connection, ConnectionItem, BusType, Error, RequestNameReply, ReleaseNameReply};
+use crate::connection::is_disconnected;
+use std::{thread, cmp};
+use std::time::Duration;
+
+/// What `Reconnecting::next_item` returned.
+#[derive(Debug)]
+pub enum ReconnectItem {
+    /// An item from the current connection.
+    Item(ConnectionItem),
+    /// The connection dropped, and a new one is up, with the names and match rules back in place.
+    ///
+    /// Replies to calls made on the old connection will not arrive, and signals sent while
+    /// disconnected are lost, so this is the time to refresh cached state.
+    Reconnected,
+}
+
+/// A connection to a bus, that connects again when the connection drops.
+///
+/// Names requested and match rules added through this struct (rather than through the
+/// `Connection` directly) are requested and added again on the new connection, which also
+/// gets the `default_timeout` of the old one. Message handlers, registered object paths and
+/// callbacks are not, since they belong to the old Connection; set them up again on
+/// `ReconnectItem::Reconnected`.
+///
+/// Only the blocking `Connection` can be wrapped. There is no reconnecting wrapper for the
+/// non-blocking `TxRx`, which does not keep track of names or match rules; when its
+/// `is_connected` returns false, open a new TxRx and send RequestName and AddMatch again.
+pub struct Reconnecting {
+    connect: Box<Fn() -> Result<Connection, Error>>,
+    conn: Connection,
+    names: Vec<(String, u32)>,
+    matches: Vec<String>,
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl Reconnecting {
+    /// Connects to a bus, and to the same bus again when the connection drops.
+    pub fn new(bus: BusType) -> Result<Self, Error> { Self::with_connect(move || Connection::get_private(bus)) }
+
+    /// Connects with `connect`, e g to open and register a connection to a bus at an address.
+    pub fn with_connect<F: Fn() -> Result<Connection, Error> + 'static>(connect: F) -> Result<Self, Error> {
+        let conn = connect()?;
+        Ok(Reconnecting { connect: Box::new(connect), conn, names: vec!(), matches: vec!(),
+            initial_delay: Duration::from_millis(100), max_delay: Duration::from_secs(30), max_attempts: None })
+    }
+
+    /// Sets the delay before reconnecting, which doubles after each failed attempt, up to max.
+    ///
+    /// The defaults are 100 ms and 30 s.
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) {
+        self.initial_delay = initial;
+        self.max_delay = max;
+    }
+
+    /// Gives up reconnecting after this many failed attempts, and returns the last error.
+    ///
+    /// The default is to never give up.
+    pub fn set_max_attempts(&mut self, attempts: Option<u32>) { self.max_attempts = attempts }
+
+    /// The current connection, which is replaced when reconnecting.
+    pub fn connection(&self) -> &Connection { &self.conn }
+
+    /// Requests a name, and requests it again after reconnecting. See `Connection::register_name`.
+    pub fn register_name(&mut self, name: &str, flags: u32) -> Result<RequestNameReply, Error> {
+        let r = self.conn.register_name(name, flags)?;
+        self.names.retain(|n| n.0 != name);
+        self.names.push((name.into(), flags));
+        Ok(r)
+    }
+
+    /// Releases a name, which is then not requested after reconnecting.
+    pub fn release_name(&mut self, name: &str) -> Result<ReleaseNameReply, Error> {
+        self.names.retain(|n| n.0 != name);
+        self.conn.release_name(name)
+    }
+
+    /// Adds a match rule, and adds it again after reconnecting. See `Connection::add_match`.
+    pub fn add_match(&mut self, rule: &str) -> Result<(), Error> {
+        self.conn.add_match(rule)?;
+        self.matches.push(rule.into());
+        Ok(())
+    }
+
+    /// Removes a match rule, which is then not added after reconnecting.
+    pub fn remove_match(&mut self, rule: &str) -> Result<(), Error> {
+        if let Some(i) = self.matches.iter().position(|m| m == rule) { self.matches.remove(i); }
+        self.conn.remove_match(rule)
+    }
+
+    /// Returns the next incoming item, like `Connection::iter`, or reconnects if the connection has dropped.
+    ///
+    /// Blocking: while reconnecting, until a connection is up again or max attempts are reached.
+    pub fn next_item(&mut self, timeout_ms: i32) -> Result<ReconnectItem, Error> {
+        if self.conn.is_connected() {
+            match self.conn.iter(timeout_ms).next() {
+                Some(ConnectionItem::Signal(ref m)) if is_disconnected(m) => {},
+                Some(item) => return Ok(ReconnectItem::Item(item)),
+                None => {},
+            }
+        }
+        self.reconnect()?;
+        Ok(ReconnectItem::Reconnected)
+    }
+
+    /// Reconnects now, with backoff, and requests the names and adds the match rules again.
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        let (mut delay, mut attempts) = (self.initial_delay, 0);
+        loop {
+            thread::sleep(delay);
+            attempts += 1;
+            match self.try_reconnect() {
+                Ok(()) => return Ok(()),
+                Err(e) => if self.max_attempts.filter(|&m| attempts >= m).is_some() { return Err(e) },
+            }
+            delay = cmp::min(delay * 2, self.max_delay);
+        }
+    }
+
+    fn try_reconnect(&mut self) -> Result<(), Error> {
+        let c = (self.connect)()?;
+        c.set_default_timeout(self.conn.default_timeout());
+        for (name, flags) in &self.names { c.register_name(name, *flags)?; }
+        for rule in &self.matches { c.add_match(rule)?; }
+        self.conn = c;
+        Ok(())
+    }
+}
+
+#[test]
+fn reconnect_to_new_bus() {
+    extern crate tempdir;
+    use std::process::{Command, Child};
+    let dir = tempdir::TempDir::new("dbus-rs-reconnect").unwrap();
+    let address = format!("unix:path={}", dir.path().join("bus").display());
+    let start_bus = || -> Child {
+        let c = Command::new("dbus-daemon").args(&["--session", "--nofork", "--nopidfile", &format!("--address={}", address)])
+            .spawn().expect("dbus-daemon not found");
+        for _ in 0..100 {
+            if dir.path().join("bus").exists() { return c }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("dbus-daemon did not start");
+    };
+    let mut bus = start_bus();
+
+    let a2 = address.clone();
+    let mut r = Reconnecting::with_connect(move || {
+        let c = Connection::open_private(&a2)?;
+        c.register()?;
+        Ok(c)
+    }).unwrap();
+    r.set_backoff(Duration::from_millis(20), Duration::from_millis(200));
+    r.set_max_attempts(Some(50));
+    r.register_name("com.example.dbusrs.reconnect", 0).unwrap();
+    r.add_match("type='signal',interface='com.example.dbusrs.Reconnect'").unwrap();
+
+    bus.kill().unwrap();
+    bus.wait().unwrap();
+    ::std::fs::remove_file(dir.path().join("bus")).unwrap();
+    let mut bus = start_bus();
+
+    loop {
+        match r.next_item(100).unwrap() {
+            ReconnectItem::Reconnected => break,
+            ReconnectItem::Item(_) => {},
+        }
+    }
+    assert!(r.connection().is_connected());
+    let m = crate::Message::new_method_call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "GetNameOwner")
+        .unwrap().append1("com.example.dbusrs.reconnect");
+    let owner: String = r.connection().send_with_reply_and_block(m, 2000).unwrap().read1().unwrap();
+    assert_eq!(owner, r.connection().unique_name());
+    bus.kill().unwrap();
+    bus.wait().unwrap();
+}
@@ -0,0 +1,250 @@
+use crate::{Connection, Error, Watch, to_c_str, c_str_to_slice, init_dbus};
+use crate::watch::WatchList;
+
+use std::{ptr, mem};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::os::raw::{c_void, c_uint};
+use std::os::unix::io::RawFd;
+
+/// Listens for direct connections from peers, e g on "unix:path=/run/foo" or "unix:tmpdir=/tmp".
+///
+/// This is for hosting a private peer-to-peer endpoint, not for connecting to a bus. Clients
+/// connect with `Connection::open_peer(&server.address())`. libdbus does the server side of the
+/// authentication on the accepted connections, and by default only lets in clients running as
+/// the same user as the server.
+pub struct Server {
+    ptr: *mut ffi::DBusServer,
+    watches: Box<WatchList>,
+    // Connections accepted but not yet returned from accept
+    accepted: Box<RefCell<VecDeque<*mut ffi::DBusConnection>>>,
+    allow_anonymous: Cell<bool>,
+}
+
+extern "C" fn new_connection_cb(_: *mut ffi::DBusServer, conn: *mut ffi::DBusConnection, data: *mut c_void) {
+    let accepted: &RefCell<VecDeque<*mut ffi::DBusConnection>> = unsafe { mem::transmute(data) };
+    // libdbus closes the connection after we return, unless we keep a reference to it.
+    accepted.borrow_mut().push_back(unsafe { ffi::dbus_connection_ref(conn) });
+}
+
+impl Server {
+    /// Starts listening on an address.
+    pub fn listen(address: &str) -> Result<Server, Error> {
+        init_dbus();
+        let mut e = Error::empty();
+        let ptr = unsafe { ffi::dbus_server_listen(to_c_str(address).as_ptr(), e.get_mut()) };
+        if ptr == ptr::null_mut() {
+            return Err(e)
+        }
+        let accepted = Box::new(RefCell::new(VecDeque::new()));
+        unsafe { ffi::dbus_server_set_new_connection_function(ptr, Some(new_connection_cb), &*accepted as *const _ as *mut _, None) };
+        let watches = WatchList::new_server(ptr);
+        Ok(Server { ptr, watches, accepted, allow_anonymous: Cell::new(false) })
+    }
+
+    /// Listens on the sockets systemd passed to the process, when the service is socket activated.
+    ///
+    /// Checks LISTEN_PID and LISTEN_FDS, and then lets libdbus pick up the sockets through its
+    /// "systemd:" transport. This needs a libdbus built with systemd support, which it is on
+    /// distributions that use systemd.
+    pub fn from_systemd() -> Result<Server, Error> {
+        let var = |n| ::std::env::var(n).ok();
+        systemd_fd_count(var("LISTEN_PID").as_ref().map(|x| &**x), var("LISTEN_FDS").as_ref().map(|x| &**x), ::std::process::id())?;
+        Server::listen("systemd:")
+    }
+
+    /// The address clients can connect to.
+    ///
+    /// This is more specific than the address listened on, e g a "unix:tmpdir=/tmp" server
+    /// has an address with the actual path of the socket. It also has the guid of the server.
+    pub fn address(&self) -> String {
+        let c = unsafe { ffi::dbus_server_get_address(self.ptr) };
+        let r = c_str_to_slice(&(c as *const _)).unwrap_or("").to_string();
+        unsafe { ffi::dbus_free(c as *mut c_void) };
+        r
+    }
+
+    /// Restricts the authentication mechanisms clients can use, e g to `&["DBUS_COOKIE_SHA1"]`.
+    ///
+    /// By default, all mechanisms libdbus knows about are allowed. Clients using libdbus (like
+    /// `Connection::open_peer`) try EXTERNAL first, and fall back to DBUS_COOKIE_SHA1 and then
+    /// ANONYMOUS by themselves. DBUS_COOKIE_SHA1 works over TCP too, and proves that the client
+    /// can read the server's user's keyring in ~/.dbus-keyrings.
+    pub fn set_auth_mechanisms(&self, mechanisms: &[&str]) -> Result<(), Error> {
+        let m: Vec<_> = mechanisms.iter().map(|m| to_c_str(m)).collect();
+        let mut p: Vec<_> = m.iter().map(|m| m.as_ptr()).collect();
+        p.push(ptr::null());
+        if unsafe { ffi::dbus_server_set_auth_mechanisms(self.ptr, p.as_mut_ptr()) } == 0 {
+            return Err(Error::new_custom("org.freedesktop.DBus.Error.NoMemory", "Setting auth mechanisms failed"))
+        }
+        Ok(())
+    }
+
+    /// Lets clients that connect from now on authenticate with the ANONYMOUS mechanism.
+    ///
+    /// Off by default, as anyone who can reach the address can then connect. Anonymous clients
+    /// have no user id, so there is nothing to check authorization against.
+    pub fn set_allow_anonymous(&self, value: bool) { self.allow_anonymous.set(value) }
+
+    /// Whether the server is still listening.
+    pub fn is_connected(&self) -> bool { unsafe { ffi::dbus_server_get_is_connected(self.ptr) != 0 } }
+
+    /// Async I/O: the file descriptors to watch for incoming connections.
+    pub fn watch_fds(&self) -> Vec<Watch> { self.watches.get_enabled_fds() }
+
+    /// Async I/O: handles an event on one of the `watch_fds`. Accepted connections are then
+    /// returned from `accept`, without waiting.
+    pub fn watch_handle(&self, fd: RawFd, flags: c_uint) { self.watches.watch_handle(fd, flags) }
+
+    /// Waits for a client to connect, for at most timeout_ms milliseconds, or forever if it is -1.
+    ///
+    /// Returns None on timeout. The accepted connection is not a bus, see `Connection::open_peer`.
+    /// The authentication with the client is done when the connection first reads and writes,
+    /// e g in `Connection::iter`.
+    pub fn accept(&self, timeout_ms: i32) -> Result<Option<Connection>, Error> {
+        if self.accepted.borrow().is_empty() {
+            let mut fds: Vec<_> = self.watch_fds().iter().map(|w| w.to_pollfd()).collect();
+            let r = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+            if r < 0 {
+                let e = ::std::io::Error::last_os_error();
+                return Err(Error::new_custom("org.freedesktop.DBus.Error.IOError", &e.to_string()))
+            }
+            for pfd in fds.iter().filter(|pfd| pfd.revents != 0) {
+                self.watch_handle(pfd.fd, crate::WatchEvent::from_revents(pfd.revents));
+            }
+        }
+        match self.accepted.borrow_mut().pop_front() {
+            Some(c) => {
+                // The handshake has not started yet, as the connection has not done any I/O.
+                let c = Connection::conn_from_ptr(c, false)?;
+                c.set_allow_anonymous(self.allow_anonymous.get());
+                Ok(Some(c))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Stops listening. Connections accepted so far are not affected.
+    pub fn disconnect(&self) { unsafe { ffi::dbus_server_disconnect(self.ptr) } }
+}
+
+/// The number of sockets passed from systemd, given LISTEN_PID, LISTEN_FDS and our pid.
+fn systemd_fd_count(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Result<u32, Error> {
+    let e = |m: &str| Err(Error::new_custom("org.freedesktop.DBus.Error.FileNotFound", m));
+    match listen_pid.map(str::parse::<u32>) {
+        None => return e("Not socket activated, LISTEN_PID is not set"),
+        Some(Ok(p)) if p == pid => {},
+        // A parent process was socket activated, and did not unset the variables.
+        Some(_) => return e("Not socket activated, LISTEN_PID is not our pid"),
+    }
+    match listen_fds.map(str::parse::<u32>) {
+        Some(Ok(n)) if n > 0 => Ok(n),
+        _ => e("No sockets passed from systemd, LISTEN_FDS is not set or 0"),
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::dbus_server_disconnect(self.ptr);
+            ffi::dbus_server_set_new_connection_function(self.ptr, None, ptr::null_mut(), None);
+            ffi::dbus_server_set_watch_functions(self.ptr, None, None, None, ptr::null_mut(), None);
+        }
+        for c in self.accepted.borrow_mut().drain(..) {
+            unsafe {
+                ffi::dbus_connection_close(c);
+                ffi::dbus_connection_unref(c);
+            }
+        }
+        unsafe { ffi::dbus_server_unref(self.ptr) };
+    }
+}
+
+#[test]
+fn systemd_fds() {
+    assert_eq!(systemd_fd_count(Some("55"), Some("2"), 55).unwrap(), 2);
+    assert!(systemd_fd_count(None, Some("2"), 55).is_err());
+    assert!(systemd_fd_count(Some("54"), Some("2"), 55).is_err());
+    assert!(systemd_fd_count(Some("55"), Some("0"), 55).is_err());
+    assert!(systemd_fd_count(Some("55"), None, 55).is_err());
+    assert!(systemd_fd_count(Some("x"), Some("1"), 55).is_err());
+}
+
+#[test]
+fn server_accept() {
+    extern crate tempdir;
+    use crate::{ConnectionItem, Message};
+    use std::thread;
+
+    let dir = tempdir::TempDir::new("dbus-rs-server").unwrap();
+    let server = Server::listen(&format!("unix:tmpdir={}", dir.path().display())).unwrap();
+    assert!(server.is_connected());
+    assert!(server.accept(0).unwrap().is_none());
+
+    let address = server.address();
+    assert!(address.starts_with("unix:path="));
+    let client = thread::spawn(move || {
+        let c = Connection::open_peer(&address).unwrap();
+        let m = Message::new_method_call("com.example.dbusrs.server", "/hello", "com.example.dbusrs.Server", "Hello").unwrap();
+        let r = c.send_with_reply_and_block(m, 2000).unwrap();
+        r.read1::<String>().unwrap()
+    });
+
+    let c = server.accept(2000).unwrap().unwrap();
+    assert!(!c.is_bus());
+    c.register_object_path("/hello").unwrap();
+    let mut timeouts = 0;
+    for item in c.iter(100) {
+        match item {
+            ConnectionItem::MethodCall(m) => {
+                assert_eq!(&*m.member().unwrap(), "Hello");
+                c.send(m.method_return().append1("Hi")).unwrap();
+                break;
+            },
+            ConnectionItem::Nothing => { timeouts += 1; assert!(timeouts < 50, "No method call from the client") },
+            _ => {},
+        }
+    }
+    assert_eq!(client.join().unwrap(), "Hi");
+}
+
+#[cfg(test)]
+fn call_peer(server: &Server) -> Result<String, Error> {
+    use crate::{ConnectionItem, Message};
+    let address = server.address();
+    let client = ::std::thread::spawn(move || {
+        let c = Connection::open_peer(&address)?;
+        let m = Message::new_method_call("com.example.dbusrs.server", "/hello", "com.example.dbusrs.Server", "Hello").unwrap();
+        c.send_with_reply_and_block(m, 2000)
+    });
+    let c = server.accept(2000)?.unwrap();
+    c.register_object_path("/hello").unwrap();
+    for (i, item) in c.iter(100).enumerate() {
+        match item {
+            ConnectionItem::MethodCall(m) => { c.send(m.method_return().append1("Hi")).unwrap(); break },
+            _ if i > 20 => break,
+            _ => {},
+        }
+    }
+    Ok(client.join().unwrap()?.read1::<String>().unwrap())
+}
+
+#[test]
+fn cookie_auth() {
+    extern crate tempdir;
+    let dir = tempdir::TempDir::new("dbus-rs-server").unwrap();
+    let server = Server::listen(&format!("unix:tmpdir={}", dir.path().display())).unwrap();
+    server.set_auth_mechanisms(&["DBUS_COOKIE_SHA1"]).unwrap();
+    assert_eq!(call_peer(&server).unwrap(), "Hi");
+}
+
+#[test]
+fn anonymous_auth() {
+    extern crate tempdir;
+    let dir = tempdir::TempDir::new("dbus-rs-server").unwrap();
+    let server = Server::listen(&format!("unix:tmpdir={}", dir.path().display())).unwrap();
+    server.set_auth_mechanisms(&["ANONYMOUS"]).unwrap();
+    assert!(call_peer(&server).is_err());
+    server.set_allow_anonymous(true);
+    assert_eq!(call_peer(&server).unwrap(), "Hi");
+}
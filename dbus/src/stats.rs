@@ -0,0 +1,101 @@
+use crate::{Message, MessageType};
+use crate::message::message_marshalled_len;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+/// Counters of the messages in one direction, see `ConnectionStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageCounts {
+    /// Messages of all types
+    pub messages: u64,
+    /// Size of the messages on the wire
+    pub bytes: u64,
+    /// Method calls
+    pub method_calls: u64,
+    /// Method returns
+    pub method_returns: u64,
+    /// Signals
+    pub signals: u64,
+    /// Error replies
+    pub errors: u64,
+}
+
+impl MessageCounts {
+    fn add(&mut self, m: &Message) {
+        self.messages += 1;
+        self.bytes += message_marshalled_len(m) as u64;
+        match m.msg_type() {
+            MessageType::MethodCall => self.method_calls += 1,
+            MessageType::MethodReturn => self.method_returns += 1,
+            MessageType::Signal => self.signals += 1,
+            MessageType::Error => self.errors += 1,
+            MessageType::Invalid => {},
+        }
+    }
+}
+
+/// Counters of the traffic on a connection since it was made, see `Connection::stats`.
+///
+/// E g for a long-running service to export as metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Messages sent
+    pub sent: MessageCounts,
+    /// Messages received, including replies to blocking method calls
+    pub received: MessageCounts,
+    /// Method calls waiting for a reply now
+    pub pending_replies: usize,
+    /// The most method calls that have been waiting for a reply at the same time
+    pub max_pending_replies: usize,
+}
+
+/// Note - internal struct, the counters of a connection.
+#[derive(Debug, Default)]
+pub struct Stats {
+    counts: Cell<ConnectionStats>,
+    // Serials of method calls sent with `send`, until their reply is received.
+    sent_calls: RefCell<HashSet<u32>>,
+    // Blocking and pipelined method calls, which do not have their replies dispatched.
+    blocked_calls: Cell<usize>,
+}
+
+impl Stats {
+    pub fn get(&self) -> ConnectionStats { self.counts.get() }
+
+    /// A message was sent; with the serial, if a reply to it will be received.
+    pub fn sent(&self, m: &Message, reply_serial: Option<u32>) {
+        let mut c = self.counts.get();
+        c.sent.add(m);
+        self.counts.set(c);
+        if let Some(s) = reply_serial { self.sent_calls.borrow_mut().insert(s); }
+        self.update_pending();
+    }
+
+    pub fn received(&self, m: &Message) {
+        let mut c = self.counts.get();
+        c.received.add(m);
+        self.counts.set(c);
+        if let Some(s) = m.get_reply_serial() {
+            if self.sent_calls.borrow_mut().remove(&s) { self.update_pending() }
+        }
+    }
+
+    /// A blocking or pipelined method call starts waiting for its reply.
+    pub fn call_started(&self) {
+        self.blocked_calls.set(self.blocked_calls.get() + 1);
+        self.update_pending();
+    }
+
+    /// A blocking or pipelined method call got its reply, or stopped waiting for it.
+    pub fn call_finished(&self) {
+        self.blocked_calls.set(self.blocked_calls.get() - 1);
+        self.update_pending();
+    }
+
+    fn update_pending(&self) {
+        let mut c = self.counts.get();
+        c.pending_replies = self.sent_calls.borrow().len() + self.blocked_calls.get();
+        if c.pending_replies > c.max_pending_replies { c.max_pending_replies = c.pending_replies }
+        self.counts.set(c);
+    }
+}
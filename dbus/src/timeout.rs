@@ -0,0 +1,62 @@
+use ffi;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use std::os::raw::c_void;
+
+/// Note - internal struct, not to be used outside API. Moving it outside its box will break things.
+///
+/// Keeps track of libdbus's timeouts (e g for method calls not replied to), and when they are due.
+pub struct TimeoutList {
+    // None means disabled
+    timeouts: RefCell<Vec<(*mut ffi::DBusTimeout, Option<Instant>)>>,
+}
+
+fn deadline(t: *mut ffi::DBusTimeout, now: Instant) -> Option<Instant> {
+    if unsafe { ffi::dbus_timeout_get_enabled(t) } == 0 { return None }
+    let ms = unsafe { ffi::dbus_timeout_get_interval(t) };
+    Some(now + Duration::from_millis(ms.max(0) as u64))
+}
+
+impl TimeoutList {
+    pub fn new(conn: *mut ffi::DBusConnection) -> Box<TimeoutList> {
+        let t = Box::new(TimeoutList { timeouts: RefCell::new(vec!()) });
+        if unsafe { ffi::dbus_connection_set_timeout_functions(conn,
+            Some(add_timeout_cb), Some(remove_timeout_cb), Some(toggled_timeout_cb), &*t as *const _ as *mut _, None) } == 0 {
+            panic!("dbus_connection_set_timeout_functions failed");
+        }
+        t
+    }
+
+    /// The earliest time an enabled timeout is due.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timeouts.borrow().iter().filter_map(|t| t.1).min()
+    }
+
+    /// Handles the timeouts that are due. A timeout handled is due again after its interval,
+    /// unless libdbus removes or disables it, which it does for method calls that timed out.
+    pub fn handle_due(&self) {
+        let now = Instant::now();
+        let due: Vec<_> = self.timeouts.borrow().iter().filter(|t| t.1.filter(|&d| d <= now).is_some()).map(|t| t.0).collect();
+        for t in due {
+            // The list must not be borrowed here, since handling can add and remove timeouts.
+            if let Some(e) = self.timeouts.borrow_mut().iter_mut().find(|e| e.0 == t) { e.1 = deadline(t, now) }
+            unsafe { ffi::dbus_timeout_handle(t) };
+        }
+    }
+}
+
+extern "C" fn add_timeout_cb(t: *mut ffi::DBusTimeout, data: *mut c_void) -> u32 {
+    let tlist: &TimeoutList = unsafe { &*(data as *const TimeoutList) };
+    tlist.timeouts.borrow_mut().push((t, deadline(t, Instant::now())));
+    1
+}
+
+extern "C" fn remove_timeout_cb(t: *mut ffi::DBusTimeout, data: *mut c_void) {
+    let tlist: &TimeoutList = unsafe { &*(data as *const TimeoutList) };
+    tlist.timeouts.borrow_mut().retain(|e| e.0 != t);
+}
+
+extern "C" fn toggled_timeout_cb(t: *mut ffi::DBusTimeout, data: *mut c_void) {
+    let tlist: &TimeoutList = unsafe { &*(data as *const TimeoutList) };
+    if let Some(e) = tlist.timeouts.borrow_mut().iter_mut().find(|e| e.0 == t) { e.1 = deadline(t, Instant::now()) }
+}
@@ -34,10 +34,18 @@ impl MethodErr {
     pub fn no_method<T: fmt::Display>(a: &T) -> MethodErr {
         ("org.freedesktop.DBus.Error.UnknownMethod", format!("Unknown method {}", a)).into()
     }
+    /// Create a MethodErr that the object path was unknown.
+    pub fn no_path<T: fmt::Display>(a: &T) -> MethodErr {
+        ("org.freedesktop.DBus.Error.UnknownObject", format!("Unknown object path {}", a)).into()
+    }
     /// Create a MethodErr that the Property was unknown.
     pub fn no_property<T: fmt::Display>(a: &T) -> MethodErr {
         ("org.freedesktop.DBus.Error.UnknownProperty", format!("Unknown property {}", a)).into()
     }
+    /// Create a MethodErr that the caller is not allowed to make the call.
+    pub fn access_denied<T: fmt::Display>(a: &T) -> MethodErr {
+        ("org.freedesktop.DBus.Error.AccessDenied", a.to_string()).into()
+    }
     /// Create a MethodErr that the Property was read-only.
     pub fn ro_property<T: fmt::Display>(a: &T) -> MethodErr {
         ("org.freedesktop.DBus.Error.PropertyReadOnly", format!("Property {} is read only", a)).into()
@@ -1,12 +1,18 @@
 use ffi;
+#[cfg(unix)]
 use libc;
 use super::Connection;
 
 use std::mem;
 use std::sync::{Mutex, RwLock};
-use std::os::unix::io::{RawFd, AsRawFd};
 use std::os::raw::{c_void, c_uint};
 
+#[cfg(unix)]
+pub(crate) use std::os::unix::io::{RawFd, AsRawFd};
+// libdbus watches sockets on Windows, rather than file descriptors.
+#[cfg(windows)]
+pub(crate) use std::os::windows::io::{RawSocket as RawFd, AsRawSocket};
+
 /// A file descriptor to watch for incoming events (for async I/O).
 ///
 /// # Example
@@ -50,6 +56,7 @@ pub enum WatchEvent {
     Hangup = ffi::DBUS_WATCH_HANGUP as isize,
 }
 
+#[cfg(unix)]
 impl WatchEvent {
     /// After running poll, this transforms the revents into a parameter you can send into `Connection::watch_handle`
     pub fn from_revents(revents: libc::c_short) -> c_uint {
@@ -70,13 +77,14 @@ pub struct Watch {
 }
 
 impl Watch {
-    /// Get the RawFd this Watch is for
+    /// Get the RawFd this Watch is for (the RawSocket, on Windows)
     pub fn fd(&self) -> RawFd { self.fd }
     /// Add POLLIN to events to listen for
     pub fn readable(&self) -> bool { self.read }
     /// Add POLLOUT to events to listen for
     pub fn writable(&self) -> bool { self.write }
     /// Returns the current watch as a libc::pollfd, to use with libc::poll
+    #[cfg(unix)]
     pub fn to_pollfd(&self) -> libc::pollfd {
         libc::pollfd { fd: self.fd, revents: 0, events: libc::POLLERR + libc::POLLHUP + 
             if self.readable() { libc::POLLIN } else { 0 } +
@@ -85,7 +93,7 @@ impl Watch {
     }
 
     pub (crate) unsafe fn from_raw(watch: *mut ffi::DBusWatch) -> Self {
-        let mut w = Watch { fd: ffi::dbus_watch_get_unix_fd(watch), read: false, write: false};
+        let mut w = Watch { fd: watch_fd(watch), read: false, write: false};
         let enabled = ffi::dbus_watch_get_enabled(watch) != 0;
         if enabled {
             let flags = ffi::dbus_watch_get_flags(watch);
@@ -97,10 +105,22 @@ impl Watch {
 
 }
 
+#[cfg(unix)]
 impl AsRawFd for Watch {
     fn as_raw_fd(&self) -> RawFd { self.fd }
 }
 
+#[cfg(windows)]
+impl AsRawSocket for Watch {
+    fn as_raw_socket(&self) -> RawFd { self.fd }
+}
+
+#[cfg(unix)]
+unsafe fn watch_fd(watch: *mut ffi::DBusWatch) -> RawFd { ffi::dbus_watch_get_unix_fd(watch) }
+
+#[cfg(windows)]
+unsafe fn watch_fd(watch: *mut ffi::DBusWatch) -> RawFd { ffi::dbus_watch_get_socket(watch) as RawFd }
+
 /// Note - internal struct, not to be used outside API. Moving it outside its box will break things.
 pub struct WatchList {
     watches: RwLock<Vec<*mut ffi::DBusWatch>>,
@@ -118,11 +138,24 @@ impl WatchList {
         w
     }
 
+    #[cfg(unix)]
+    pub fn new_server(s: *mut ffi::DBusServer) -> Box<WatchList> {
+        let w = Box::new(WatchList { on_update: Mutex::new(Box::new(|_| {})), watches: RwLock::new(vec!()), enabled_fds: Mutex::new(vec!()) });
+        if unsafe { ffi::dbus_server_set_watch_functions(s,
+            Some(add_watch_cb), Some(remove_watch_cb), Some(toggled_watch_cb), &*w as *const _ as *mut _, None) } == 0 {
+            panic!("dbus_server_set_watch_functions failed");
+        }
+        w
+    }
+
     pub fn set_on_update(&self, on_update: Box<Fn(Watch) + Send>) { *self.on_update.lock().unwrap() = on_update; }
 
     pub fn watch_handle(&self, fd: RawFd, flags: c_uint) {
         // println!("watch_handle {} flags {}", fd, flags);
-        for &q in self.watches.read().unwrap().iter() {
+        // Not kept locked, since libdbus removes the watches while handling a hangup.
+        let watches = self.watches.read().unwrap().clone();
+        for q in watches {
+            if !self.watches.read().unwrap().contains(&q) { continue };
             let w = self.get_watch(q);
             if w.fd != fd { continue };
             if unsafe { ffi::dbus_watch_handle(q, flags) } == 0 {
@@ -137,7 +170,7 @@ impl WatchList {
     }
 
     fn get_watch(&self, watch: *mut ffi::DBusWatch) -> Watch {
-        let mut w = Watch { fd: unsafe { ffi::dbus_watch_get_unix_fd(watch) }, read: false, write: false};
+        let mut w = Watch { fd: unsafe { watch_fd(watch) }, read: false, write: false};
         let enabled = self.watches.read().unwrap().contains(&watch) && unsafe { ffi::dbus_watch_get_enabled(watch) != 0 };
         let flags = unsafe { ffi::dbus_watch_get_flags(watch) };
         if enabled {
@@ -5,6 +5,7 @@ pub type DBusMessage = c_void;
 pub type DBusWatch = c_void;
 pub type DBusPendingCall = c_void;
 pub type DBusTimeout = c_void;
+pub type DBusServer = c_void;
 
 #[repr(C)]
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -62,6 +63,13 @@ pub enum DBusReleaseNameReply {
     NotOwner = 3,
 }
 
+#[repr(C)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DBusStartReply {
+    Success = 1,
+    AlreadyRunning = 2,
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum DBusHandlerResult {
@@ -138,6 +146,8 @@ pub type DBusPendingCallNotifyFunction = Option<extern fn(pending: *mut DBusPend
 
 pub type DBusFreeFunction = Option<extern fn(memory: *mut c_void)>;
 
+pub type DBusNewConnectionFunction = Option<extern fn(server: *mut DBusServer, conn: *mut DBusConnection, user_data: *mut c_void)>;
+
 #[repr(C)]
 pub struct DBusObjectPathVTable {
     pub unregister_function: Option<extern fn(conn: *mut DBusConnection, user_data: *mut c_void)>,
@@ -160,14 +170,19 @@ extern "C" {
     pub fn dbus_bus_remove_match(conn: *mut DBusConnection, rule: *const c_char,
         error: *mut DBusError);
     pub fn dbus_bus_register(conn: *mut DBusConnection, error: *mut DBusError) -> u32;
+    pub fn dbus_bus_start_service_by_name(conn: *mut DBusConnection, name: *const c_char,
+        flags: u32, result: *mut u32, error: *mut DBusError) -> u32;
 
     pub fn dbus_connection_close(conn: *mut DBusConnection);
     pub fn dbus_connection_dispatch(conn: *mut DBusConnection) -> DBusDispatchStatus;
     pub fn dbus_connection_flush(conn: *mut DBusConnection);
     pub fn dbus_connection_open_private(address: *const c_char, error: *mut DBusError) -> *mut DBusConnection;
+    pub fn dbus_connection_ref(conn: *mut DBusConnection) -> *mut DBusConnection;
     pub fn dbus_connection_unref(conn: *mut DBusConnection);
     pub fn dbus_connection_get_is_connected(conn: *mut DBusConnection) -> u32;
+    pub fn dbus_connection_can_send_type(conn: *mut DBusConnection, t: c_int) -> u32;
     pub fn dbus_connection_set_exit_on_disconnect(conn: *mut DBusConnection, enable: u32);
+    pub fn dbus_connection_set_allow_anonymous(conn: *mut DBusConnection, value: u32);
     pub fn dbus_connection_send_with_reply_and_block(conn: *mut DBusConnection,
         message: *mut DBusMessage, timeout_milliseconds: c_int, error: *mut DBusError) -> *mut DBusMessage;
     pub fn dbus_connection_send_with_reply(conn: *mut DBusConnection,
@@ -180,6 +195,9 @@ extern "C" {
     pub fn dbus_connection_try_register_object_path(conn: *mut DBusConnection,
         path: *const c_char, vtable: *const DBusObjectPathVTable, user_data: *mut c_void,
         error: *mut DBusError) -> u32;
+    pub fn dbus_connection_try_register_fallback(conn: *mut DBusConnection,
+        path: *const c_char, vtable: *const DBusObjectPathVTable, user_data: *mut c_void,
+        error: *mut DBusError) -> u32;
     pub fn dbus_connection_unregister_object_path(conn: *mut DBusConnection,
         path: *const c_char) -> u32;
     pub fn dbus_connection_list_registered(conn: *mut DBusConnection,
@@ -206,6 +224,7 @@ extern "C" {
     pub fn dbus_set_error(error: *mut DBusError, name: *const c_char, message: *const c_char, ...);
     pub fn dbus_set_error_from_message(error: *mut DBusError, message: *mut DBusMessage) -> u32;
 
+    pub fn dbus_message_new(message_type: c_int) -> *mut DBusMessage;
     pub fn dbus_message_new_method_call(destination: *const c_char, path: *const c_char,
         iface: *const c_char, method: *const c_char) -> *mut DBusMessage;
     pub fn dbus_message_new_method_return(message: *mut DBusMessage) -> *mut DBusMessage;
@@ -213,6 +232,7 @@ extern "C" {
         error_name: *const c_char, error_message: *const c_char) -> *mut DBusMessage;
     pub fn dbus_message_new_signal(path: *const c_char,
         iface: *const c_char, name: *const c_char) -> *mut DBusMessage;
+    pub fn dbus_message_copy(message: *const DBusMessage) -> *mut DBusMessage;
     pub fn dbus_message_ref(message: *mut DBusMessage) -> *mut DBusMessage;
     pub fn dbus_message_unref(message: *mut DBusMessage);
     pub fn dbus_message_get_type(message: *mut DBusMessage) -> c_int;
@@ -226,7 +246,11 @@ extern "C" {
     pub fn dbus_message_get_member(message: *mut DBusMessage) -> *const c_char;
     pub fn dbus_message_get_sender(message: *mut DBusMessage) -> *const c_char;
     pub fn dbus_message_set_serial(message: *mut DBusMessage, serial: u32);
+    pub fn dbus_message_set_reply_serial(message: *mut DBusMessage, reply_serial: u32) -> u32;
+    pub fn dbus_message_set_error_name(message: *mut DBusMessage, name: *const c_char) -> u32;
     pub fn dbus_message_set_destination(message: *mut DBusMessage, destination: *const c_char) -> u32;
+    pub fn dbus_message_set_interface(message: *mut DBusMessage, iface: *const c_char) -> u32;
+    pub fn dbus_message_set_sender(message: *mut DBusMessage, sender: *const c_char) -> u32;
     pub fn dbus_message_get_no_reply(message: *mut DBusMessage) -> u32;
     pub fn dbus_message_set_no_reply(message: *mut DBusMessage, no_reply: u32);
     pub fn dbus_message_get_auto_start(message: *mut DBusMessage) -> u32;
@@ -265,6 +289,7 @@ extern "C" {
     pub fn dbus_watch_get_enabled(watch: *mut DBusWatch) -> u32;
     pub fn dbus_watch_get_flags(watch: *mut DBusWatch) -> c_uint;
     pub fn dbus_watch_get_unix_fd(watch: *mut DBusWatch) -> c_int;
+    pub fn dbus_watch_get_socket(watch: *mut DBusWatch) -> c_int;
     pub fn dbus_watch_handle(watch: *mut DBusWatch, flags: c_uint) -> u32;
     pub fn dbus_watch_get_data(watch: *mut DBusWatch) -> *mut c_void;
     pub fn dbus_watch_set_data(watch: *mut DBusWatch, user_data: *mut c_void,
@@ -277,11 +302,29 @@ extern "C" {
     pub fn dbus_timeout_set_data(timeout: *mut DBusTimeout, user_data: *mut c_void,
         free_data_function: DBusFreeFunction);
 
+    pub fn dbus_server_listen(address: *const c_char, error: *mut DBusError) -> *mut DBusServer;
+    pub fn dbus_server_unref(server: *mut DBusServer);
+    pub fn dbus_server_disconnect(server: *mut DBusServer);
+    pub fn dbus_server_get_is_connected(server: *mut DBusServer) -> u32;
+    pub fn dbus_server_get_address(server: *mut DBusServer) -> *mut c_char;
+    pub fn dbus_server_set_new_connection_function(server: *mut DBusServer, function: DBusNewConnectionFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction);
+    pub fn dbus_server_set_watch_functions(server: *mut DBusServer, add_function: DBusAddWatchFunction,
+        remove_function: DBusRemoveWatchFunction, toggled_function: DBusWatchToggledFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction) -> u32;
+    pub fn dbus_server_set_auth_mechanisms(server: *mut DBusServer, mechanisms: *mut *const c_char) -> u32;
+    pub fn dbus_server_set_timeout_functions(server: *mut DBusServer, add_function: DBusAddTimeoutFunction,
+        remove_function: DBusRemoveTimeoutFunction, toggled_function: DBusTimeoutToggledFunction,
+        data: *mut c_void, free_data_function: DBusFreeFunction) -> u32;
+
     pub fn dbus_pending_call_ref(pending: *mut DBusPendingCall) -> *mut DBusPendingCall;
     pub fn dbus_pending_call_unref(pending: *mut DBusPendingCall);
     pub fn dbus_pending_call_set_notify(pending: *mut DBusPendingCall, n: DBusPendingCallNotifyFunction,
         user_data: *mut c_void, free_user_data: DBusFreeFunction) -> u32;
     pub fn dbus_pending_call_steal_reply(pending: *mut DBusPendingCall) -> *mut DBusMessage;
+    pub fn dbus_pending_call_block(pending: *mut DBusPendingCall);
+    pub fn dbus_pending_call_cancel(pending: *mut DBusPendingCall);
+    pub fn dbus_pending_call_get_completed(pending: *mut DBusPendingCall) -> u32;
 
     pub fn dbus_message_marshal(msg: *mut DBusMessage, marshalled_data_p: *mut *mut c_char, len_p: *mut c_int) -> u32;
     pub fn dbus_message_demarshal(s: *const c_char, len: c_int, error: *mut DBusError) -> *mut DBusMessage;